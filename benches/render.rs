@@ -0,0 +1,48 @@
+use std::hint::black_box;
+
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use lilac::{
+    auth::Secret,
+    render::Rasterizer,
+    tui::{self, AppState},
+};
+
+const RESOLUTIONS: [(&str, u32, u32); 3] = [
+    ("1080p", 1920, 1080),
+    ("1440p", 2560, 1440),
+    ("4k", 3840, 2160),
+];
+
+// The full per-frame path of the lock screen: lay out the TUI into a cell buffer, then
+// rasterize it into ARGB pixels.
+fn full_frame(c: &mut Criterion) {
+    let mut rasterizer = Rasterizer::default();
+    let mut group = c.benchmark_group("full_frame");
+
+    for animated in [true, false] {
+        let mut state = AppState {
+            username: "lilac".to_string(),
+            password: Secret::from("hunter2"),
+            reduced_motion: !animated,
+            tick: 1_000,
+            ..AppState::default()
+        };
+        let mode = if animated { "animated" } else { "static" };
+
+        for (name, width, height) in RESOLUTIONS {
+            let (cols, rows) = rasterizer.grid_dims(width, height);
+            let mut pixels = vec![0u8; (width * height * 4) as usize];
+            group.bench_function(BenchmarkId::new(mode, name), |b| {
+                b.iter(|| {
+                    let buffer = tui::render_to_buffer(&mut state, cols, rows);
+                    rasterizer.rasterize(&buffer, &mut pixels, width, height);
+                    black_box(&pixels);
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, full_frame);
+criterion_main!(benches);
@@ -0,0 +1,337 @@
+use std::time::Duration;
+
+mod pam;
+mod secret;
+
+pub use pam::{DEFAULT_SERVICE, Pam};
+pub use secret::Secret;
+
+/// The message styles a PAM conversation can send, mirroring `PAM_PROMPT_ECHO_OFF`,
+/// `PAM_PROMPT_ECHO_ON`, `PAM_ERROR_MSG` and `PAM_TEXT_INFO`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PromptStyle {
+    // secret input such as a password, never shown
+    EchoOff,
+    // visible input such as a username or a one-time code
+    EchoOn,
+    ErrorMsg,
+    TextInfo,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Prompt {
+    pub style: PromptStyle,
+    pub text: String,
+}
+
+impl Prompt {
+    pub fn new(style: PromptStyle, text: impl Into<String>) -> Self {
+        Self {
+            style,
+            text: text.into(),
+        }
+    }
+
+    /// Whether the response to this prompt should be masked. Follows PAM's intent rather than
+    /// assuming every answer is a password.
+    pub fn is_masked(&self) -> bool {
+        self.style == PromptStyle::EchoOff
+    }
+
+    /// Maps the prompt text a PAM module sent to the label shown next to the input field.
+    /// Well known prompts in a few languages collapse to a short label; anything else is shown
+    /// as sent, minus the trailing colon.
+    pub fn label(&self) -> String {
+        let text = self.text.trim().trim_end_matches([':', '：']).trim_end();
+        let lower = text.to_lowercase();
+
+        // codes come first: a TOTP module asks for a "one-time password"
+        let known = [
+            (
+                "Code",
+                &["verification code", "one-time", "otp", "token", "验证码"][..],
+            ),
+            (
+                "Password",
+                &[
+                    "password",
+                    "passwort",
+                    "mot de passe",
+                    "contraseña",
+                    "密码",
+                    "パスワード",
+                ][..],
+            ),
+            ("Username", &["login", "username", "用户名"][..]),
+        ];
+        for (label, needles) in known {
+            if needles.iter().any(|needle| lower.contains(needle)) {
+                return label.to_string();
+            }
+        }
+
+        if text.is_empty() {
+            "Password".to_string()
+        } else {
+            text.to_string()
+        }
+    }
+}
+
+/// Why an authentication attempt didn't unlock the session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuthError {
+    // wrong username or password; deliberately vague to avoid user enumeration
+    Failed,
+    // the credentials were accepted but PAM reported PAM_NEW_AUTHTOK_REQD
+    PasswordExpired,
+    // PAM itself couldn't be used (missing service file, module error, ...)
+    Unavailable(String),
+}
+
+impl AuthError {
+    /// The message shown in the login box, short enough to fit on one line of it.
+    pub fn message(&self) -> &str {
+        match self {
+            Self::Failed => "Authentication failed.",
+            // lilac can't change it, and "Error: " leaves little room to say so
+            Self::PasswordExpired => "Expired; change elsewhere.",
+            Self::Unavailable(_) => "Authentication unavailable.",
+        }
+    }
+}
+
+impl std::fmt::Display for AuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unavailable(reason) => write!(f, "authentication unavailable: {reason}"),
+            _ => f.write_str(self.message()),
+        }
+    }
+}
+
+impl std::error::Error for AuthError {}
+
+/// The user's side of an authentication, for whatever an authenticator asks beyond the
+/// submitted password: a second factor, a changed password, or messages to show on the way.
+pub trait Conversation {
+    /// The user's answer to an `EchoOn` or `EchoOff` prompt. `None` once they gave up, which
+    /// aborts the attempt.
+    fn answer(&mut self, prompt: &Prompt) -> Option<Secret>;
+
+    /// Shows an `ErrorMsg` or `TextInfo` message; nothing is answered.
+    fn show(&mut self, message: &Prompt);
+}
+
+/// A conversation with nothing to add to the submitted password: every further prompt is
+/// refused and messages are dropped.
+pub struct PasswordOnly;
+
+impl Conversation for PasswordOnly {
+    fn answer(&mut self, _: &Prompt) -> Option<Secret> {
+        None
+    }
+
+    fn show(&mut self, _: &Prompt) {}
+}
+
+/// Checks a username and password, asking `conversation` for anything else the backend
+/// prompts for. Implemented over PAM for real sessions and by `MockAuthenticator` for the demo
+/// and tests.
+pub trait Authenticator {
+    fn authenticate(
+        &mut self,
+        username: &str,
+        password: &str,
+        conversation: &mut dyn Conversation,
+    ) -> Result<(), AuthError>;
+}
+
+/// A scripted authenticator for the demo and tests.
+#[derive(Debug, Clone)]
+pub enum MockAuthenticator {
+    Accept,
+    Reject,
+    // accepts any username together with this password
+    Password(String),
+    // like `Password`, then prompts for a one-time code the way a second factor module would
+    SecondFactor { password: String, code: String },
+    // like `Password`, but the account's password has expired, as pam_acct_mgmt reports
+    Expired(String),
+    // answers like the inner authenticator, but only after blocking for the duration, the way
+    // a slow PAM module or network backed auth would
+    Delayed(Duration, Box<MockAuthenticator>),
+}
+
+impl Authenticator for MockAuthenticator {
+    #[allow(clippy::only_used_in_recursion)]
+    fn authenticate(
+        &mut self,
+        username: &str,
+        password: &str,
+        conversation: &mut dyn Conversation,
+    ) -> Result<(), AuthError> {
+        match self {
+            Self::Accept => Ok(()),
+            Self::Reject => Err(AuthError::Failed),
+            Self::Password(expected) if password == expected => Ok(()),
+            Self::Password(_) => Err(AuthError::Failed),
+            Self::Expired(expected) if password == expected => Err(AuthError::PasswordExpired),
+            Self::Expired(_) => Err(AuthError::Failed),
+            Self::SecondFactor {
+                password: expected,
+                code,
+            } => {
+                if password != expected.as_str() {
+                    return Err(AuthError::Failed);
+                }
+                conversation.show(&Prompt::new(PromptStyle::TextInfo, "A code was sent."));
+                let prompt = Prompt::new(PromptStyle::EchoOn, "Verification code: ");
+                match conversation.answer(&prompt) {
+                    Some(answer) if answer.as_str() == code.as_str() => Ok(()),
+                    _ => Err(AuthError::Failed),
+                }
+            }
+            Self::Delayed(delay, inner) => {
+                std::thread::sleep(*delay);
+                inner.authenticate(username, password, conversation)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // inside the login box's borders, after the "Error: " the box puts in front
+    const ERROR_LINE_WIDTH: usize = 34 - "Error: ".len();
+
+    #[test]
+    fn messages_are_sentences_that_fit_the_login_box() {
+        let errors = [
+            AuthError::Failed,
+            AuthError::PasswordExpired,
+            AuthError::Unavailable("no such service".to_string()),
+        ];
+        for error in errors {
+            let message = error.message();
+            assert!(message.len() <= ERROR_LINE_WIDTH, "{message:?} is too long");
+            assert!(message.ends_with('.'), "{message:?}");
+        }
+    }
+
+    #[test]
+    fn prompts_map_to_labels_and_masking() {
+        use PromptStyle::*;
+        let prompts = [
+            (EchoOff, "Password: ", "Password", true),
+            (EchoOff, "Passwort:", "Password", true),
+            (EchoOff, "Mot de passe : ", "Password", true),
+            (EchoOff, "密码：", "Password", true),
+            (EchoOff, "パスワード:", "Password", true),
+            (EchoOn, "login:", "Username", false),
+            (EchoOn, "用户名：", "Username", false),
+            (EchoOn, "Verification code: ", "Code", false),
+            (EchoOn, "验证码：", "Code", false),
+            (EchoOff, "One-time password: ", "Code", true),
+            (EchoOn, "Enter OTP: ", "Code", false),
+            (EchoOff, "PIN for YubiKey: ", "PIN for YubiKey", true),
+            (EchoOff, "", "Password", true),
+        ];
+        for (style, text, label, masked) in prompts {
+            let prompt = Prompt::new(style, text);
+            assert_eq!(prompt.label(), label, "{text:?}");
+            assert_eq!(prompt.is_masked(), masked, "{text:?}");
+        }
+    }
+
+    #[test]
+    fn mock_authenticators_answer_as_scripted() {
+        let conversation = &mut PasswordOnly;
+        assert_eq!(
+            MockAuthenticator::Accept.authenticate("alice", "", conversation),
+            Ok(())
+        );
+        assert_eq!(
+            MockAuthenticator::Reject.authenticate("alice", "hunter2", conversation),
+            Err(AuthError::Failed)
+        );
+
+        let mut password = MockAuthenticator::Password("hunter2".to_string());
+        assert_eq!(
+            password.authenticate("alice", "hunter2", conversation),
+            Ok(())
+        );
+        assert_eq!(
+            password.authenticate("alice", "hunter3", conversation),
+            Err(AuthError::Failed)
+        );
+
+        let delay = Duration::from_millis(20);
+        let mut delayed = MockAuthenticator::Delayed(delay, Box::new(password));
+        let started = std::time::Instant::now();
+        assert_eq!(
+            delayed.authenticate("alice", "hunter2", conversation),
+            Ok(())
+        );
+        assert!(started.elapsed() >= delay);
+    }
+
+    // Answers every prompt with `answer`, keeping what it was shown and asked.
+    struct Scripted {
+        answer: &'static str,
+        seen: Vec<Prompt>,
+    }
+
+    impl Conversation for Scripted {
+        fn answer(&mut self, prompt: &Prompt) -> Option<Secret> {
+            self.seen.push(prompt.clone());
+            Some(Secret::from(self.answer))
+        }
+
+        fn show(&mut self, message: &Prompt) {
+            self.seen.push(message.clone());
+        }
+    }
+
+    #[test]
+    fn a_second_factor_is_asked_for_after_the_password() {
+        let mut otp = MockAuthenticator::SecondFactor {
+            password: "hunter2".to_string(),
+            code: "123456".to_string(),
+        };
+        let mut right = Scripted {
+            answer: "123456",
+            seen: Vec::new(),
+        };
+        assert_eq!(otp.authenticate("alice", "hunter2", &mut right), Ok(()));
+        let styles: Vec<_> = right.seen.iter().map(|prompt| prompt.style).collect();
+        assert_eq!(styles, [PromptStyle::TextInfo, PromptStyle::EchoOn]);
+        assert_eq!(right.seen[1].label(), "Code");
+
+        let mut wrong = Scripted {
+            answer: "654321",
+            seen: Vec::new(),
+        };
+        assert_eq!(
+            otp.authenticate("alice", "hunter2", &mut wrong),
+            Err(AuthError::Failed)
+        );
+        // giving up fails the attempt
+        assert_eq!(
+            otp.authenticate("alice", "hunter2", &mut PasswordOnly),
+            Err(AuthError::Failed)
+        );
+        // a wrong password never gets as far as the code
+        let mut unasked = Scripted {
+            answer: "123456",
+            seen: Vec::new(),
+        };
+        assert_eq!(
+            otp.authenticate("alice", "hunter3", &mut unasked),
+            Err(AuthError::Failed)
+        );
+        assert!(unasked.seen.is_empty());
+    }
+}
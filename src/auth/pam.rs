@@ -0,0 +1,213 @@
+use std::ffi::{CStr, CString};
+
+use pam_client::{Context, ConversationHandler, ErrorCode, Flag};
+
+use super::{AuthError, Authenticator, Conversation, Prompt, PromptStyle};
+
+/// The PAM service used when none is configured, i.e. `/etc/pam.d/lilac`.
+pub const DEFAULT_SERVICE: &str = "lilac";
+
+/// Authenticates against PAM: `pam_authenticate` for the password, then `pam_acct_mgmt` so
+/// expired or locked accounts are refused even with the right password.
+pub struct Pam {
+    service: String,
+}
+
+impl Pam {
+    pub fn new(service: impl Into<String>) -> Self {
+        Self {
+            service: service.into(),
+        }
+    }
+}
+
+impl Authenticator for Pam {
+    fn authenticate(
+        &mut self,
+        username: &str,
+        password: &str,
+        conversation: &mut dyn Conversation,
+    ) -> Result<(), AuthError> {
+        let handler = Handler {
+            username,
+            password: Some(password),
+            conversation,
+        };
+        let mut context =
+            Context::new(&self.service, Some(username), handler).map_err(auth_error)?;
+        context.authenticate(Flag::NONE).map_err(auth_error)?;
+        context.acct_mgmt(Flag::NONE).map_err(auth_error)
+    }
+}
+
+// Answers a PAM conversation. What the user already submitted answers the first secret prompt
+// and a prompt for the username before it; every other prompt, such as a one-time code, goes to the
+// user, and messages are passed on to be shown.
+struct Handler<'a> {
+    username: &'a str,
+    // taken by the first secret prompt, which is the password's
+    password: Option<&'a str>,
+    conversation: &'a mut dyn Conversation,
+}
+
+impl Handler<'_> {
+    // The answer goes back as a CString, which pam-client takes by value, copies for PAM and
+    // drops itself, so its bytes can't be zeroed from here. What's left to clear is PAM's copy,
+    // which is PAM's to free.
+    fn prompt(&mut self, style: PromptStyle, text: &CStr) -> Result<CString, ErrorCode> {
+        let prompt = Prompt::new(style, text.to_string_lossy());
+        let submitted = match style {
+            PromptStyle::EchoOff => self.password.take(),
+            // a module asking again later gets its answer from the user
+            _ if self.password.is_some() && prompt.label() == "Username" => Some(self.username),
+            _ => None,
+        };
+        let answer = match submitted {
+            Some(answer) => CString::new(answer),
+            // a user who gave up aborts the conversation, and with it the attempt
+            None => {
+                let answer = self
+                    .conversation
+                    .answer(&prompt)
+                    .ok_or(ErrorCode::CONV_ERR)?;
+                CString::new(answer.as_str())
+            }
+        };
+        answer.map_err(|_| ErrorCode::CONV_ERR)
+    }
+}
+
+impl ConversationHandler for Handler<'_> {
+    fn prompt_echo_on(&mut self, prompt: &CStr) -> Result<CString, ErrorCode> {
+        self.prompt(PromptStyle::EchoOn, prompt)
+    }
+
+    fn prompt_echo_off(&mut self, prompt: &CStr) -> Result<CString, ErrorCode> {
+        self.prompt(PromptStyle::EchoOff, prompt)
+    }
+
+    fn text_info(&mut self, msg: &CStr) {
+        let message = Prompt::new(PromptStyle::TextInfo, msg.to_string_lossy());
+        self.conversation.show(&message);
+    }
+
+    fn error_msg(&mut self, msg: &CStr) {
+        let message = Prompt::new(PromptStyle::ErrorMsg, msg.to_string_lossy());
+        self.conversation.show(&message);
+    }
+}
+
+fn auth_error(err: pam_client::Error) -> AuthError {
+    code_error(err.code(), err.to_string())
+}
+
+fn code_error(code: ErrorCode, reason: String) -> AuthError {
+    match code {
+        ErrorCode::NEW_AUTHTOK_REQD => AuthError::PasswordExpired,
+        ErrorCode::AUTH_ERR
+        | ErrorCode::USER_UNKNOWN
+        | ErrorCode::MAXTRIES
+        | ErrorCode::PERM_DENIED
+        | ErrorCode::ACCT_EXPIRED
+        | ErrorCode::CRED_INSUFFICIENT
+        // the user abandoned a prompt
+        | ErrorCode::CONV_ERR => AuthError::Failed,
+        _ => AuthError::Unavailable(reason),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Secret;
+
+    // Answers every prompt with `answer`, or gives up when there's none, keeping what it was
+    // shown and asked.
+    struct Recorder {
+        answer: Option<&'static str>,
+        seen: Vec<Prompt>,
+    }
+
+    impl Conversation for Recorder {
+        fn answer(&mut self, prompt: &Prompt) -> Option<Secret> {
+            self.seen.push(prompt.clone());
+            self.answer.map(Secret::from)
+        }
+
+        fn show(&mut self, message: &Prompt) {
+            self.seen.push(message.clone());
+        }
+    }
+
+    #[test]
+    fn the_password_answers_only_the_first_secret_prompt() {
+        let mut conversation = Recorder {
+            answer: Some("123456"),
+            seen: Vec::new(),
+        };
+        let mut handler = Handler {
+            username: "alice",
+            password: Some("hunter2"),
+            conversation: &mut conversation,
+        };
+
+        let login = handler.prompt_echo_on(c"login:").unwrap();
+        assert_eq!(login.as_c_str(), c"alice");
+        let password = handler.prompt_echo_off(c"Password: ").unwrap();
+        assert_eq!(password.as_c_str(), c"hunter2");
+        handler.text_info(c"Verification code:");
+        let code = handler.prompt_echo_on(c"Verification code:").unwrap();
+        assert_eq!(code.as_c_str(), c"123456");
+        let again = handler.prompt_echo_off(c"Password: ").unwrap();
+        assert_eq!(again.as_c_str(), c"123456");
+        let login_again = handler.prompt_echo_on(c"login:").unwrap();
+        assert_eq!(login_again.as_c_str(), c"123456");
+
+        let seen: Vec<_> = conversation
+            .seen
+            .iter()
+            .map(|prompt| (prompt.style, prompt.text.as_str()))
+            .collect();
+        assert_eq!(
+            seen,
+            [
+                (PromptStyle::TextInfo, "Verification code:"),
+                (PromptStyle::EchoOn, "Verification code:"),
+                (PromptStyle::EchoOff, "Password: "),
+                (PromptStyle::EchoOn, "login:"),
+            ]
+        );
+    }
+
+    #[test]
+    fn giving_up_on_a_prompt_aborts_the_conversation() {
+        let mut conversation = Recorder {
+            answer: None,
+            seen: Vec::new(),
+        };
+        let mut handler = Handler {
+            username: "alice",
+            password: None,
+            conversation: &mut conversation,
+        };
+        assert!(matches!(
+            handler.prompt_echo_on(c"Verification code:"),
+            Err(ErrorCode::CONV_ERR)
+        ));
+    }
+
+    #[test]
+    fn pam_codes_map_to_auth_errors() {
+        let error = |code| code_error(code, "module failed".to_string());
+        assert_eq!(
+            error(ErrorCode::NEW_AUTHTOK_REQD),
+            AuthError::PasswordExpired
+        );
+        assert_eq!(error(ErrorCode::AUTH_ERR), AuthError::Failed);
+        assert_eq!(error(ErrorCode::CONV_ERR), AuthError::Failed);
+        assert_eq!(
+            error(ErrorCode::SERVICE_ERR),
+            AuthError::Unavailable("module failed".to_string())
+        );
+    }
+}
@@ -0,0 +1,145 @@
+use std::fmt;
+
+use zeroize::Zeroize;
+
+// enough for any realistic password, so typing one never reallocates
+const INITIAL_CAPACITY: usize = 256;
+
+/// Text that must not outlive its use, such as a typed password. The bytes are zeroed when the
+/// secret is cleared or dropped. Growing past the capacity moves to a new allocation and zeroes
+/// the old one, so no stale copy is left behind on the heap.
+///
+/// The allocation is also `mlock`ed where the memlock limit allows, so it is never written to
+/// swap. It's never `munlock`ed: locks work on whole pages and don't nest, so unlocking one
+/// secret's pages could unlock another's that shares them. A freed page stays locked until the
+/// allocator reuses it or lilac exits, which only costs what little the secrets span.
+pub struct Secret {
+    buf: String,
+}
+
+impl Secret {
+    pub fn new() -> Self {
+        Self::with_capacity(INITIAL_CAPACITY)
+    }
+
+    fn with_capacity(capacity: usize) -> Self {
+        let buf = String::with_capacity(capacity);
+        // Safety: only covers our own allocation. Best effort, without CAP_IPC_LOCK this fails
+        // once RLIMIT_MEMLOCK is used up.
+        unsafe {
+            libc::mlock(buf.as_ptr().cast(), buf.capacity());
+        }
+        Self { buf }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.buf
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    pub fn push(&mut self, ch: char) {
+        let needed = self.buf.len() + ch.len_utf8();
+        if needed > self.buf.capacity() {
+            let mut grown = Self::with_capacity(needed.max(self.buf.capacity() * 2));
+            grown.buf.push_str(&self.buf);
+            // the old allocation is zeroed as it's dropped
+            *self = grown;
+        }
+        self.buf.push(ch);
+    }
+
+    pub fn pop(&mut self) -> Option<char> {
+        let ch = self.buf.pop()?;
+        // the removed bytes are still in the allocation past the new length. Safety: only the
+        // spare capacity is touched, so the string stays valid UTF-8.
+        unsafe { self.buf.as_mut_vec() }
+            .spare_capacity_mut()
+            .zeroize();
+        Some(ch)
+    }
+
+    /// Zeroes the contents, keeping the allocation for the next input.
+    pub fn clear(&mut self) {
+        self.buf.zeroize();
+    }
+
+    /// Hands the contents over to the caller, leaving this secret empty.
+    pub fn take(&mut self) -> Self {
+        std::mem::take(self)
+    }
+}
+
+impl Default for Secret {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl From<&str> for Secret {
+    fn from(value: &str) -> Self {
+        let mut secret = Self::with_capacity(value.len().max(INITIAL_CAPACITY));
+        secret.buf.push_str(value);
+        secret
+    }
+}
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        self.buf.zeroize();
+    }
+}
+
+// never prints the contents, so a stray `{:?}` can't leak them into a log
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Secret(..)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The bytes of the allocation past the contents, which edits must leave zeroed.
+    fn spare(secret: &mut Secret, len: usize) -> Vec<u8> {
+        // Safety: the spare capacity is only read, and every byte of it was written by zeroize.
+        unsafe { secret.buf.as_mut_vec() }.spare_capacity_mut()[..len]
+            .iter()
+            .map(|byte| unsafe { byte.assume_init() })
+            .collect()
+    }
+
+    #[test]
+    fn popped_and_cleared_bytes_are_zeroed() {
+        let mut secret = Secret::from("hunter2");
+        assert_eq!(secret.pop(), Some('2'));
+        assert_eq!(secret.as_str(), "hunter");
+        assert_eq!(spare(&mut secret, 1), [0]);
+
+        secret.clear();
+        assert!(secret.is_empty());
+        assert_eq!(spare(&mut secret, 6), [0; 6]);
+    }
+
+    #[test]
+    fn growing_keeps_the_contents() {
+        let mut secret = Secret::new();
+        let long = "x".repeat(INITIAL_CAPACITY + 1);
+        for ch in long.chars() {
+            secret.push(ch);
+        }
+        assert_eq!(secret.as_str(), long);
+    }
+
+    #[test]
+    fn taking_leaves_the_secret_empty() {
+        let mut secret = Secret::from("hunter2");
+        let taken = secret.take();
+        assert_eq!(taken.as_str(), "hunter2");
+        assert!(secret.is_empty());
+        assert_eq!(format!("{taken:?}"), "Secret(..)");
+    }
+}
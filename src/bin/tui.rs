@@ -1,22 +1,45 @@
-use std::time::{Duration, Instant};
+use std::{
+    io::Write,
+    time::{Duration, Instant},
+};
 
-use anyhow::Context;
+use anyhow::{Context, anyhow};
 use crossterm::{
     ExecutableCommand,
-    event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    event::{self, Event, KeyCode, KeyEventKind, KeyEventState, KeyModifiers},
     terminal::{EnterAlternateScreen, LeaveAlternateScreen, disable_raw_mode, enable_raw_mode},
 };
 use ratatui::{Terminal, backend::CrosstermBackend};
 
-use lilac::tui::{self, AppState, KeyInput};
+use lilac::{
+    auth::MockAuthenticator,
+    config::Config,
+    controller::{Controller, Policy},
+    env::Vars,
+    locker::LockConfig,
+    tui::{self, AppState, KeyInput},
+};
+
+// how often to ask the media player what's playing, roughly every 5s
+#[cfg(feature = "mpris")]
+const MEDIA_POLL_TICKS: u64 = 150;
+
+// how often to read the battery, roughly every 30s
+#[cfg(feature = "power")]
+const BATTERY_POLL_TICKS: u64 = 900;
+
+// how long `--simulate-auth delay` keeps the UI waiting before answering
+const SIMULATED_AUTH_DELAY: Duration = Duration::from_secs(2);
 
 fn main() -> anyhow::Result<()> {
+    let authenticator = parse_args()?;
+
     enable_raw_mode().context("enable raw mode")?;
     std::io::stdout()
         .execute(EnterAlternateScreen)
         .context("enter alternate screen")?;
 
-    let result = run_app();
+    let result = run_app(authenticator);
 
     std::io::stdout()
         .execute(LeaveAlternateScreen)
@@ -26,16 +49,74 @@ fn main() -> anyhow::Result<()> {
     result
 }
 
-fn run_app() -> anyhow::Result<()> {
+// There is no PAM here: by default submitting the demo password stands in for unlocking, and
+// `--simulate-auth accept|reject|delay|otp` scripts the outcome to exercise the feedback UI.
+// `otp` follows the password with a prompt for the code in LILAC_DEMO_CODE.
+fn parse_args() -> anyhow::Result<MockAuthenticator> {
+    let password = std::env::var("LILAC_DEMO_PASSWORD").unwrap_or_else(|_| "lilac".to_string());
+    let mut authenticator = MockAuthenticator::Password(password.clone());
+
+    let mut argv = std::env::args().skip(1);
+    while let Some(arg) = argv.next() {
+        match arg.as_str() {
+            "--simulate-auth" => {
+                let mode = argv.next().ok_or_else(|| {
+                    anyhow!("--simulate-auth requires accept, reject, delay or otp")
+                })?;
+                authenticator = match mode.as_str() {
+                    "accept" => MockAuthenticator::Accept,
+                    "reject" => MockAuthenticator::Reject,
+                    "delay" => MockAuthenticator::Delayed(
+                        SIMULATED_AUTH_DELAY,
+                        Box::new(MockAuthenticator::Password(password.clone())),
+                    ),
+                    "otp" => MockAuthenticator::SecondFactor {
+                        password: password.clone(),
+                        code: std::env::var("LILAC_DEMO_CODE")
+                            .unwrap_or_else(|_| "123456".to_string()),
+                    },
+                    _ => {
+                        return Err(anyhow!(
+                            "unknown --simulate-auth mode {mode:?}, expected accept, reject, delay or otp"
+                        ));
+                    }
+                };
+            }
+            _ => return Err(anyhow!("unrecognized argument {arg:?}")),
+        }
+    }
+    Ok(authenticator)
+}
+
+fn run_app(authenticator: MockAuthenticator) -> anyhow::Result<()> {
     let backend = CrosstermBackend::new(std::io::stdout());
     let mut terminal = Terminal::new(backend).context("create terminal")?;
 
-    let mut state = AppState::default();
+    // the same layering the locker uses, so the demo shows what the lock screen would
+    let mut config = LockConfig::from_file(&Config::load()?)?;
+    config.apply_env(&Vars::process())?;
+    let mut state = AppState {
+        locked_at: Some(Instant::now()),
+        // there's no compositor to confirm a lock here
+        ignore_input: false,
+        ..config.app_state()
+    };
+    let mut policy = Policy::new(authenticator);
+    if let Some(users) = config.allowed_users {
+        policy = policy.with_allowed_users(users);
+    }
+    policy = policy.with_min_failure_duration(config.min_failure_duration);
+    let mut controller = Controller::spawn(policy);
     let mut last_tick = Instant::now();
     let tick_rate = Duration::from_millis(33);
 
+    #[cfg(feature = "mpris")]
+    let mut media = lilac::media::Mpris::session().ok();
+    #[cfg(feature = "power")]
+    let power_supplies = std::path::Path::new(lilac::power::POWER_SUPPLY_DIR);
+
     loop {
-        terminal.draw(|frame| tui::view(frame, &state))?;
+        terminal.draw(|frame| tui::view(frame, &mut state))?;
 
         let timeout = tick_rate
             .checked_sub(last_tick.elapsed())
@@ -43,6 +124,9 @@ fn run_app() -> anyhow::Result<()> {
 
         if event::poll(timeout)? {
             if let Event::Key(key) = event::read()? {
+                // only terminals with the kitty keyboard protocol report lock keys
+                state.caps_lock = key.state.contains(KeyEventState::CAPS_LOCK);
+
                 if key.kind != KeyEventKind::Press {
                     continue;
                 }
@@ -51,19 +135,51 @@ fn run_app() -> anyhow::Result<()> {
                     break;
                 }
 
-                if key.code == KeyCode::Esc {
+                // while an attempt is being checked or asking something, Esc cancels it instead
+                if key.code == KeyCode::Esc && !state.authenticating && state.prompt.is_none() {
                     break;
                 }
 
-                if let Some(input) = map_key(key.code) {
-                    let _ = state.handle_input(input);
+                let input = if key.modifiers.contains(KeyModifiers::CONTROL)
+                    && key.code == KeyCode::Char('r')
+                {
+                    Some(KeyInput::ToggleReveal)
+                } else {
+                    map_key(key.code)
+                };
+                if let Some(input) = input {
+                    controller.handle_input(&mut state, input);
                 }
             }
         }
 
+        if controller.poll(&mut state) {
+            break;
+        }
+        // there's no machine of ours to power off, so the demo just leaves
+        if controller.take_power_off() {
+            break;
+        }
+        if state.take_bell() {
+            // BEL, which the terminal turns into a sound or a flash of its own
+            let mut stdout = std::io::stdout();
+            stdout.write_all(b"\x07")?;
+            stdout.flush()?;
+        }
+
         if last_tick.elapsed() >= tick_rate {
             state.tick();
             last_tick = Instant::now();
+
+            #[cfg(feature = "mpris")]
+            if state.tick % MEDIA_POLL_TICKS == 1 {
+                use lilac::media::MediaSource;
+                state.now_playing = media.as_mut().and_then(|media| media.now_playing());
+            }
+            #[cfg(feature = "power")]
+            if state.tick % BATTERY_POLL_TICKS == 1 {
+                state.battery = lilac::power::read_batteries(power_supplies);
+            }
         }
     }
 
@@ -77,8 +193,11 @@ fn map_key(code: KeyCode) -> Option<KeyInput> {
         KeyCode::Enter => Some(KeyInput::Enter),
         KeyCode::Backspace => Some(KeyInput::Backspace),
         KeyCode::Tab => Some(KeyInput::Tab),
+        KeyCode::BackTab => Some(KeyInput::BackTab),
         KeyCode::Up => Some(KeyInput::Up),
         KeyCode::Down => Some(KeyInput::Down),
+        KeyCode::Left => Some(KeyInput::Left),
+        KeyCode::Right => Some(KeyInput::Right),
         KeyCode::Esc => Some(KeyInput::Esc),
         _ => None,
     }
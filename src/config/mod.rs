@@ -0,0 +1,506 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{Context, anyhow};
+use ratatui::style::Color;
+use serde::Deserialize;
+
+use crate::{
+    log::TimeFormat,
+    render::parse_rgb,
+    tui::{
+        self, BackgroundKind, Fire, LockReminder, Lockout, PalettePreset, PasswordDisplay, Theme,
+    },
+};
+
+/// Settings read from `$XDG_CONFIG_HOME/lilac/config.toml`. Every key is optional and anything
+/// left out keeps its built in default. The `LILAC_*` environment and command line flags still
+/// win over the file.
+///
+/// ```toml
+/// background = "#1E1E2E"
+/// animation = "matrix"
+/// fire_palette = ["#000000", "#3B0764", "#A855F7", "#F5D0FE"]
+/// grace_period_secs = 5
+/// box_on = "focused"
+/// output = "DP-1"
+/// allowed_users = ["alice", "bob"]
+/// submit_button = true
+/// power_button = true
+/// idle_timeout_secs = 30
+///
+/// [box]
+/// background = "#313244"
+/// border = "rounded"
+///
+/// [fire]
+/// wind = 1
+/// direction = "up"
+/// intensity = 1.2
+///
+/// [lockout]
+/// threshold = 5
+/// max_secs = 60
+///
+/// [font]
+/// path = "/usr/share/fonts/TTF/FiraMono-Regular.ttf"
+/// bold_path = "/usr/share/fonts/TTF/FiraMono-Bold.ttf"
+/// size = 18.0
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    // RRGGBB shown on every surface until the rasterized UI is ready
+    pub background: Option<String>,
+    // which `BackgroundKind` is drawn behind the box, by name
+    pub animation: Option<String>,
+    #[serde(rename = "box")]
+    pub box_style: BoxStyle,
+    pub fire_palette: Option<PaletteSetting>,
+    pub fire: FireConfig,
+    pub grace_period_secs: Option<u64>,
+    // a static gradient instead of the animation; left out, the desktop's setting decides
+    pub reduced_motion: Option<bool>,
+    // an on-screen numeric keypad under the box, for touchscreens without a keyboard
+    pub pin_pad: Option<bool>,
+    // prefills the username field with whoever last unlocked, kept under $XDG_STATE_HOME/lilac
+    pub remember_user: Option<bool>,
+    // after a failed attempt, flash the box border red (on by default) and ring the bell (off)
+    pub failure_flash: Option<bool>,
+    pub failure_bell: Option<bool>,
+    // seconds locked before "Locked for ..." shows under the box, 0 to never show it
+    pub lock_reminder_secs: Option<u64>,
+    // `masked`, `fixed-dots` or `hidden`, see `PasswordDisplay`
+    pub password_display: Option<String>,
+    // an unlock button under the password field, which Tab also reaches
+    pub submit_button: Option<bool>,
+    // a power off button opposite the unlock button, which Tab also reaches
+    pub power_button: Option<bool>,
+    // pick the username from `allowed_users` with Left and Right instead of typing it
+    pub user_list: Option<bool>,
+    // ASCII art above the box, given inline or as a file; inline wins
+    pub logo: Option<String>,
+    pub logo_file: Option<PathBuf>,
+    // which monitors show the login box: `all`, `primary` or `focused`
+    pub box_on: Option<String>,
+    // name or description of the output the login box is pinned to
+    pub output: Option<String>,
+    // `prefill` or `composed`, what the first commit to each output shows
+    pub first_frame: Option<String>,
+    // seconds without input before the animation freezes, 0 to keep it running
+    pub idle_timeout_secs: Option<u64>,
+    pub locked_timeout_ms: Option<u64>,
+    // frames in a row that may find every buffer busy before the output is redrawn
+    pub commit_retry_budget: Option<u32>,
+    // shm buffers per output, 2 or 3; a third keeps the animation going while the compositor
+    // holds the other two
+    pub buffer_count: Option<usize>,
+    // the only users allowed to unlock; left out, anyone PAM accepts can
+    pub allowed_users: Option<Vec<String>>,
+    // the least time a failed attempt takes, so a rejection can't be told apart by timing
+    pub min_failure_ms: Option<u64>,
+    pub lockout: LockoutConfig,
+    // how often to try reaching the compositor, and how long to wait between tries
+    pub connect_attempts: Option<u32>,
+    pub connect_retry_ms: Option<u64>,
+    // only read by builds with the dev-autounlock feature
+    pub auto_unlock_secs: Option<u64>,
+    // where the log goes instead of $XDG_STATE_HOME/lilac/lilac.log, `-` for stderr
+    pub log_file: Option<PathBuf>,
+    // `epoch` or `rfc3339`
+    pub log_time: Option<String>,
+    pub font: FontConfig,
+}
+
+/// Either the name of a `PalettePreset` or gradient stops from coldest to hottest, see
+/// `tui::palette_from_stops`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum PaletteSetting {
+    Preset(String),
+    Stops(Vec<String>),
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FireConfig {
+    pub wind: Option<i32>,
+    pub spread: Option<u16>,
+    pub direction: Option<String>,
+    // see `Fire::intensity`
+    pub intensity: Option<f32>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LockoutConfig {
+    // failures in a row before each further one locks the fields for a while
+    pub threshold: Option<u32>,
+    // the longest the fields stay locked, however many failures there were
+    pub max_secs: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BoxStyle {
+    pub foreground: Option<String>,
+    pub background: Option<String>,
+    pub alpha: Option<u8>,
+    pub bold_labels: Option<bool>,
+    pub border: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FontConfig {
+    // a monospace TrueType or OpenType font used instead of the bundled one
+    pub path: Option<PathBuf>,
+    // its bold face, for bold labels; without one, bold is synthesized
+    pub bold_path: Option<PathBuf>,
+    pub size: Option<f32>,
+}
+
+impl Config {
+    /// Where the config file is looked for, or None when neither `XDG_CONFIG_HOME` nor `HOME`
+    /// is set.
+    pub fn path() -> Option<PathBuf> {
+        let base = std::env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+        Some(base.join("lilac").join("config.toml"))
+    }
+
+    /// Reads the config file. A missing file isn't an error, it just leaves every default.
+    pub fn load() -> anyhow::Result<Self> {
+        let Some(path) = Self::path() else {
+            return Ok(Self::default());
+        };
+        match std::fs::read_to_string(&path) {
+            Ok(text) => Self::parse(&text).with_context(|| format!("invalid {}", path.display())),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err).with_context(|| format!("failed to read {}", path.display())),
+        }
+    }
+
+    /// Reads an explicitly named config file, which unlike the default one has to exist.
+    pub fn load_from(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        Self::parse(&text).with_context(|| format!("invalid {}", path.display()))
+    }
+
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        Ok(toml::from_str(text)?)
+    }
+
+    /// The placeholder color as ARGB8888, if the file sets one.
+    pub fn background_color(&self) -> anyhow::Result<Option<u32>> {
+        self.background
+            .as_deref()
+            .map(|color| argb("background", color))
+            .transpose()
+    }
+
+    pub fn background_kind(&self) -> anyhow::Result<Option<BackgroundKind>> {
+        self.animation
+            .as_deref()
+            .map(|name| name.parse().map_err(anyhow::Error::msg))
+            .transpose()
+    }
+
+    /// Overrides the parts of `theme` the `[box]` table sets.
+    pub fn apply_theme(&self, theme: &mut Theme) -> anyhow::Result<()> {
+        let style = &self.box_style;
+        if let Some(color) = &style.foreground {
+            theme.box_fg = Color::from_u32(argb("box foreground", color)? & 0x00FF_FFFF);
+        }
+        if let Some(color) = &style.background {
+            theme.box_bg = Color::from_u32(argb("box background", color)? & 0x00FF_FFFF);
+        }
+        if let Some(alpha) = style.alpha {
+            theme.box_alpha = alpha;
+        }
+        if let Some(bold) = style.bold_labels {
+            theme.bold_labels = bold;
+        }
+        if let Some(border) = &style.border {
+            theme.border = border.parse().map_err(anyhow::Error::msg)?;
+        }
+        Ok(())
+    }
+
+    /// The palette the file picks or stretches from gradient stops, if it sets one.
+    pub fn palette(&self) -> anyhow::Result<Option<Vec<Color>>> {
+        let stops = match &self.fire_palette {
+            None => return Ok(None),
+            Some(PaletteSetting::Preset(name)) => {
+                let preset: PalettePreset = name.parse().map_err(anyhow::Error::msg)?;
+                return Ok(Some(preset.colors()));
+            }
+            Some(PaletteSetting::Stops(stops)) => stops,
+        };
+        let stops = stops
+            .iter()
+            .map(|stop| argb("fire_palette", stop))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let palette = tui::palette_from_stops(&stops)
+            .ok_or_else(|| anyhow!("fire_palette needs at least two colors"))?;
+        Ok(Some(palette))
+    }
+
+    /// Overrides the parts of `fire` the `[fire]` table sets.
+    pub fn apply_fire(&self, fire: &mut Fire) -> anyhow::Result<()> {
+        if let Some(wind) = self.fire.wind {
+            fire.wind = wind;
+        }
+        if let Some(spread) = self.fire.spread {
+            fire.spread = spread;
+        }
+        if let Some(direction) = &self.fire.direction {
+            fire.direction = direction.parse().map_err(anyhow::Error::msg)?;
+        }
+        if let Some(intensity) = self.fire.intensity {
+            if !(intensity.is_finite() && intensity > 0.0) {
+                return Err(anyhow!(
+                    "invalid fire intensity {intensity}, expected a positive number"
+                ));
+            }
+            fire.intensity = intensity;
+        }
+        Ok(())
+    }
+
+    /// Overrides the parts of `lockout` the `[lockout]` table sets.
+    pub fn apply_lockout(&self, lockout: &mut Lockout) {
+        if let Some(threshold) = self.lockout.threshold {
+            lockout.threshold = threshold;
+        }
+        if let Some(secs) = self.lockout.max_secs {
+            lockout.max_delay = Duration::from_secs(secs);
+        }
+    }
+
+    pub fn log_time(&self) -> anyhow::Result<Option<TimeFormat>> {
+        self.log_time
+            .as_deref()
+            .map(|format| format.parse().map_err(anyhow::Error::msg))
+            .transpose()
+    }
+
+    pub fn grace_period(&self) -> Option<Duration> {
+        self.grace_period_secs.map(Duration::from_secs)
+    }
+
+    pub fn lock_reminder(&self) -> Option<LockReminder> {
+        let secs = self.lock_reminder_secs?;
+        Some(LockReminder {
+            after: (secs != 0).then(|| Duration::from_secs(secs)),
+        })
+    }
+
+    pub fn password_display(&self) -> anyhow::Result<Option<PasswordDisplay>> {
+        self.password_display
+            .as_deref()
+            .map(|mode| mode.parse().map_err(anyhow::Error::msg))
+            .transpose()
+    }
+
+    /// The art above the box, read from `logo_file` when it isn't given inline.
+    pub fn logo(&self) -> anyhow::Result<Option<String>> {
+        if let Some(logo) = &self.logo {
+            return Ok(Some(logo.clone()));
+        }
+        let Some(path) = &self.logo_file else {
+            return Ok(None);
+        };
+        let logo = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read logo {}", path.display()))?;
+        Ok(Some(logo))
+    }
+
+    pub fn min_failure_duration(&self) -> Option<Duration> {
+        self.min_failure_ms.map(Duration::from_millis)
+    }
+
+    pub fn idle_after(&self) -> Option<Duration> {
+        self.idle_timeout_secs.map(Duration::from_secs)
+    }
+
+    pub fn locked_timeout(&self) -> Option<Duration> {
+        self.locked_timeout_ms.map(Duration::from_millis)
+    }
+
+    pub fn connect_retry_delay(&self) -> Option<Duration> {
+        self.connect_retry_ms.map(Duration::from_millis)
+    }
+
+    pub fn auto_unlock_after(&self) -> Option<Duration> {
+        self.auto_unlock_secs.map(Duration::from_secs)
+    }
+
+    /// The font size in pixels, which has to be a positive number.
+    pub fn font_size(&self) -> anyhow::Result<Option<f32>> {
+        let Some(size) = self.font.size else {
+            return Ok(None);
+        };
+        if !(size.is_finite() && size > 0.0) {
+            return Err(anyhow!(
+                "invalid font size {size}, expected a positive number"
+            ));
+        }
+        Ok(Some(size))
+    }
+
+    /// The bytes of the configured font, if the file names one.
+    pub fn font_bytes(&self) -> anyhow::Result<Option<Vec<u8>>> {
+        let Some(path) = &self.font.path else {
+            return Ok(None);
+        };
+        let bytes = std::fs::read(path)
+            .with_context(|| format!("failed to read font {}", path.display()))?;
+        Ok(Some(bytes))
+    }
+
+    /// The bytes of the configured bold face, if the file names one.
+    pub fn bold_font_bytes(&self) -> anyhow::Result<Option<Vec<u8>>> {
+        let Some(path) = &self.font.bold_path else {
+            return Ok(None);
+        };
+        let bytes = std::fs::read(path)
+            .with_context(|| format!("failed to read bold font {}", path.display()))?;
+        Ok(Some(bytes))
+    }
+}
+
+fn argb(key: &str, value: &str) -> anyhow::Result<u32> {
+    parse_rgb(value).ok_or_else(|| anyhow!("invalid {key} color {value:?}, expected RRGGBB"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = r##"
+background = "#1E1E2E"
+animation = "matrix"
+fire_palette = ["#000000", "#A855F7"]
+grace_period_secs = 5
+idle_timeout_secs = 30
+lock_reminder_secs = 0
+password_display = "hidden"
+submit_button = true
+power_button = true
+user_list = true
+allowed_users = ["alice"]
+min_failure_ms = 1500
+log_time = "rfc3339"
+
+[box]
+background = "#313244"
+alpha = 200
+border = "rounded"
+
+[fire]
+wind = -1
+direction = "down"
+
+[lockout]
+threshold = 5
+max_secs = 60
+
+[font]
+size = 18.0
+"##;
+
+    #[test]
+    fn a_sample_config_overrides_the_defaults() {
+        let config = Config::parse(SAMPLE).unwrap();
+        assert_eq!(config.background_color().unwrap(), Some(0xFF1E1E2E));
+        assert_eq!(
+            config.background_kind().unwrap(),
+            Some(BackgroundKind::Matrix)
+        );
+        assert_eq!(config.grace_period(), Some(Duration::from_secs(5)));
+        assert_eq!(config.idle_after(), Some(Duration::from_secs(30)));
+        assert_eq!(
+            config.min_failure_duration(),
+            Some(Duration::from_millis(1500))
+        );
+        assert_eq!(
+            config.allowed_users.as_deref(),
+            Some(&["alice".to_string()][..])
+        );
+        assert_eq!(config.font.size, Some(18.0));
+        assert_eq!(config.log_time().unwrap(), Some(TimeFormat::Rfc3339));
+        assert_eq!(config.lock_reminder().unwrap().after, None);
+        assert_eq!(
+            config.password_display().unwrap(),
+            Some(PasswordDisplay::Hidden)
+        );
+        assert_eq!(config.submit_button, Some(true));
+        assert_eq!(config.power_button, Some(true));
+        assert_eq!(config.user_list, Some(true));
+
+        let mut theme = Theme::default();
+        config.apply_theme(&mut theme).unwrap();
+        assert_eq!(theme.box_bg, Color::from_u32(0x00313244));
+        assert_eq!(theme.box_alpha, 200);
+        // left out, so still the default
+        assert_eq!(theme.box_fg, Theme::default().box_fg);
+
+        let mut fire = Fire::default();
+        config.apply_fire(&mut fire).unwrap();
+        assert_eq!(fire.wind, -1);
+        assert_eq!(fire.direction, tui::FireDirection::Down);
+        assert_eq!(fire.spread, Fire::default().spread);
+
+        let mut lockout = Lockout::default();
+        config.apply_lockout(&mut lockout);
+        assert_eq!(lockout.threshold, 5);
+        assert_eq!(lockout.max_delay, Duration::from_secs(60));
+
+        let palette = config.palette().unwrap().unwrap();
+        assert_eq!(palette.len(), tui::FIRE_PALETTE.len());
+    }
+
+    #[test]
+    fn a_palette_can_be_a_preset_name() {
+        let config = Config::parse("fire_palette = \"green\"").unwrap();
+        assert_eq!(
+            config.palette().unwrap(),
+            Some(PalettePreset::Green.colors())
+        );
+    }
+
+    #[test]
+    fn an_empty_file_keeps_every_default() {
+        let config = Config::parse("").unwrap();
+        assert_eq!(config.background_color().unwrap(), None);
+        assert!(config.palette().unwrap().is_none());
+        assert!(config.lock_reminder().is_none());
+        assert!(config.logo().unwrap().is_none());
+    }
+
+    #[test]
+    fn unknown_keys_and_bad_values_are_errors() {
+        assert!(Config::parse("grace_period = 5").is_err());
+        assert!(Config::parse("[box]\nbackground = 3").is_err());
+        let config = Config::parse("background = \"blue\"").unwrap();
+        assert!(config.background_color().is_err());
+        let config = Config::parse("fire_palette = [\"#000000\"]").unwrap();
+        assert!(config.palette().is_err());
+        let config = Config::parse("password_display = \"stars\"").unwrap();
+        assert!(config.password_display().is_err());
+        let config = Config::parse("log_time = \"local\"").unwrap();
+        assert!(config.log_time().is_err());
+        let config = Config::parse("[fire]\nintensity = 0.0").unwrap();
+        assert!(config.apply_fire(&mut Fire::default()).is_err());
+        for size in ["0.0", "-12.0", "nan", "inf"] {
+            let config = Config::parse(&format!("[font]\nsize = {size}")).unwrap();
+            assert!(config.font_size().is_err(), "{size}");
+        }
+    }
+}
@@ -0,0 +1,792 @@
+use std::{
+    sync::mpsc::{self, Receiver, Sender, TryRecvError},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    auth::{AuthError, Authenticator, Conversation, Prompt, PromptStyle, Secret},
+    tui::{AppAction, AppState, KeyInput},
+};
+
+/// The least time a failed attempt takes unless configured otherwise.
+pub const DEFAULT_MIN_FAILURE_DURATION: Duration = Duration::from_secs(1);
+
+/// Where the policy reads the time and waits, so tests can pad failures without sleeping.
+pub trait Clock: Send {
+    fn now(&self) -> Instant;
+    fn sleep(&self, duration: Duration);
+}
+
+/// The real clock, sleeping the calling thread.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+}
+
+/// Decides whether an attempt unlocks: the authenticator plus the lock's own rules on top of
+/// it. Lives on the authentication thread once handed to `Controller::spawn`.
+pub struct Policy<A> {
+    authenticator: A,
+    // when set, only these users may unlock, whatever the authenticator says
+    allowed_users: Option<Vec<String>>,
+    // every failure takes at least this long, so response time doesn't tell an unknown or
+    // disallowed user apart from a wrong password
+    min_failure_duration: Duration,
+    clock: Box<dyn Clock>,
+}
+
+impl<A: Authenticator> Policy<A> {
+    pub fn new(authenticator: A) -> Self {
+        Self {
+            authenticator,
+            allowed_users: None,
+            min_failure_duration: DEFAULT_MIN_FAILURE_DURATION,
+            clock: Box::new(SystemClock),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Box::new(clock);
+        self
+    }
+
+    pub fn with_min_failure_duration(mut self, duration: Duration) -> Self {
+        self.min_failure_duration = duration;
+        self
+    }
+
+    pub fn with_allowed_users(mut self, users: Vec<String>) -> Self {
+        self.allowed_users = Some(users);
+        self
+    }
+
+    /// Checks one attempt, blocking for as long as the authenticator takes, including while
+    /// `conversation` waits for the user to answer a prompt. Failures are padded to
+    /// `min_failure_duration` by sleeping on the policy's clock; a success returns as soon as
+    /// the authenticator does.
+    pub fn authenticate(
+        &mut self,
+        username: &str,
+        password: &str,
+        conversation: &mut dyn Conversation,
+    ) -> Result<(), AuthError> {
+        let started = self.clock.now();
+        let result = self.check(username, password, conversation);
+        if result.is_err() {
+            let elapsed = self.clock.now().saturating_duration_since(started);
+            self.clock
+                .sleep(self.min_failure_duration.saturating_sub(elapsed));
+        }
+        result
+    }
+
+    // A user outside the allowlist never reaches the authenticator, and fails with the same
+    // error as a wrong password so the message doesn't reveal which users exist.
+    fn check(
+        &mut self,
+        username: &str,
+        password: &str,
+        conversation: &mut dyn Conversation,
+    ) -> Result<(), AuthError> {
+        if let Some(allowed) = &self.allowed_users
+            && !allowed.iter().any(|user| user == username)
+        {
+            return Err(AuthError::Failed);
+        }
+        self.authenticator
+            .authenticate(username, password, conversation)
+    }
+}
+
+struct Request {
+    id: u64,
+    username: String,
+    // zeroed once the worker drops the request
+    password: Secret,
+    // the user's answers to the prompts the attempt sends back; closed if it's cancelled
+    answers: Receiver<Secret>,
+}
+
+// What the authentication thread sends the UI about an attempt, tagged with its id.
+enum Update {
+    // something to answer or show before the attempt can go on
+    Prompt(Prompt),
+    Outcome(Result<(), AuthError>),
+}
+
+// The authentication thread's side of a conversation: prompts and messages go out to the UI,
+// and the thread blocks until the answer comes back.
+struct Exchange<'a> {
+    id: u64,
+    updates: &'a Sender<(u64, Update)>,
+    answers: &'a Receiver<Secret>,
+    wake: &'a dyn Fn(),
+}
+
+impl Exchange<'_> {
+    fn send(&self, prompt: &Prompt) -> bool {
+        let sent = self.updates.send((self.id, Update::Prompt(prompt.clone())));
+        (self.wake)();
+        sent.is_ok()
+    }
+}
+
+impl Conversation for Exchange<'_> {
+    fn answer(&mut self, prompt: &Prompt) -> Option<Secret> {
+        if !self.send(prompt) {
+            return None;
+        }
+        // fails once the attempt is cancelled and its sender dropped
+        self.answers.recv().ok()
+    }
+
+    fn show(&mut self, message: &Prompt) {
+        self.send(message);
+    }
+}
+
+/// Turns the actions `AppState::handle_input` produces into authentication attempts and their
+/// outcome on the UI. Both the Wayland locker and the terminal demo drive input through this,
+/// so the demo behaves exactly like the real lock.
+///
+/// Attempts are checked on a worker thread, since PAM can take seconds to answer; the caller
+/// picks up the outcome with `poll` from its event loop, which `spawn_with_waker` can wake for
+/// it.
+pub struct Controller {
+    requests: Sender<Request>,
+    updates: Receiver<(u64, Update)>,
+    next_id: u64,
+    // the attempt whose outcome the UI is waiting for. A cancelled attempt still runs to
+    // completion on the worker, but its outcome no longer matches and is dropped.
+    pending: Option<u64>,
+    // answers the pending attempt's prompts; set while the fields are given back to answer one
+    answers: Option<Sender<Secret>>,
+    awaiting_answer: bool,
+    // the lock was dismissed within its grace period; handed out by the next `poll`
+    dismissed: bool,
+    // the power button was pressed; handed out by `take_power_off`
+    power_off: bool,
+}
+
+impl Controller {
+    /// Starts the authentication thread, which owns `policy` from then on.
+    pub fn spawn<A: Authenticator + Send + 'static>(policy: Policy<A>) -> Self {
+        Self::spawn_with_waker(policy, || {})
+    }
+
+    /// Like `spawn`, but the authentication thread calls `wake` whenever it has an outcome for
+    /// `poll`, so an event loop can sleep until then.
+    pub fn spawn_with_waker<A: Authenticator + Send + 'static>(
+        mut policy: Policy<A>,
+        wake: impl Fn() + Send + 'static,
+    ) -> Self {
+        let (requests, worker_requests) = mpsc::channel::<Request>();
+        let (worker_updates, updates) = mpsc::channel();
+        std::thread::Builder::new()
+            .name("lilac-auth".to_string())
+            .spawn(move || {
+                for request in worker_requests {
+                    let mut exchange = Exchange {
+                        id: request.id,
+                        updates: &worker_updates,
+                        answers: &request.answers,
+                        wake: &wake,
+                    };
+                    let result = policy.authenticate(
+                        &request.username,
+                        request.password.as_str(),
+                        &mut exchange,
+                    );
+                    if worker_updates
+                        .send((request.id, Update::Outcome(result)))
+                        .is_err()
+                    {
+                        break;
+                    }
+                    wake();
+                }
+            })
+            .expect("failed to spawn the authentication thread");
+
+        Self {
+            requests,
+            updates,
+            next_id: 0,
+            pending: None,
+            answers: None,
+            awaiting_answer: false,
+            dismissed: false,
+            power_off: false,
+        }
+    }
+
+    /// Whether an attempt is being checked.
+    pub fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// Feeds a key to the UI and applies whatever action it produced.
+    pub fn handle_input(&mut self, state: &mut AppState, key: KeyInput) {
+        if let Some(action) = state.handle_input(key) {
+            self.apply(state, action);
+        }
+    }
+
+    /// Applies a single action to `state`. A submit while the pending attempt is asking
+    /// something answers it; any other submit while an attempt is pending is rejected, so two
+    /// attempts never race for the same outcome.
+    pub fn apply(&mut self, state: &mut AppState, action: AppAction) {
+        match action {
+            AppAction::Submit { password, .. } if self.awaiting_answer => {
+                let sent = self
+                    .answers
+                    .as_ref()
+                    .is_some_and(|answers| answers.send(password).is_ok());
+                if !sent {
+                    self.forget(state);
+                    state.apply_auth_error(&worker_gone());
+                    return;
+                }
+                self.awaiting_answer = false;
+                state.authenticating = true;
+            }
+            AppAction::Submit { username, password } => {
+                if self.is_pending() {
+                    return;
+                }
+                let id = self.next_id;
+                self.next_id += 1;
+                let (answers, worker_answers) = mpsc::channel();
+                let request = Request {
+                    id,
+                    username,
+                    password,
+                    answers: worker_answers,
+                };
+                if self.requests.send(request).is_err() {
+                    state.apply_auth_error(&worker_gone());
+                    return;
+                }
+                self.pending = Some(id);
+                self.answers = Some(answers);
+                state.authenticating = true;
+                state.error_message = None;
+            }
+            AppAction::Cancel => self.forget(state),
+            AppAction::Dismiss => self.dismissed = true,
+            AppAction::PowerOff => self.power_off = true,
+        }
+    }
+
+    /// Whether the power button was pressed since the last call. Powering off is up to the
+    /// caller, which knows whether it can.
+    pub fn take_power_off(&mut self) -> bool {
+        std::mem::take(&mut self.power_off)
+    }
+
+    /// Applies the outcome of the pending attempt, if it has arrived, without blocking. Returns
+    /// true once the user has authenticated, or dismissed the lock within its grace period, and
+    /// the session should unlock.
+    pub fn poll(&mut self, state: &mut AppState) -> bool {
+        if std::mem::take(&mut self.dismissed) {
+            return true;
+        }
+        let Some(pending) = self.pending else {
+            // drain outcomes of cancelled attempts
+            while self.updates.try_recv().is_ok() {}
+            return false;
+        };
+
+        let result = loop {
+            match self.updates.try_recv() {
+                Ok((id, Update::Prompt(prompt))) if id == pending => self.prompt(state, prompt),
+                Ok((id, Update::Outcome(result))) if id == pending => break result,
+                Ok(_) => continue,
+                Err(TryRecvError::Empty) => return false,
+                Err(TryRecvError::Disconnected) => break Err(worker_gone()),
+            }
+        };
+
+        self.forget(state);
+        match result {
+            Ok(()) => {
+                state.password.clear();
+                state.error_message = None;
+                state.lockout.reset();
+                true
+            }
+            Err(error) => {
+                state.apply_auth_error(&error);
+                false
+            }
+        }
+    }
+
+    // Shows a message from the pending attempt, or hands the password field over to answer
+    // its prompt, labelled and masked the way PAM asked.
+    fn prompt(&mut self, state: &mut AppState, prompt: Prompt) {
+        match prompt.style {
+            PromptStyle::EchoOn | PromptStyle::EchoOff => {
+                state.apply_prompt(prompt);
+                state.authenticating = false;
+                self.awaiting_answer = true;
+            }
+            PromptStyle::ErrorMsg | PromptStyle::TextInfo => {
+                state.pam_message = Some(prompt.text);
+            }
+        }
+    }
+
+    // Stops waiting for the pending attempt and gives the fields back as they were before it.
+    // Dropping the answers sender aborts a conversation still waiting for the user.
+    fn forget(&mut self, state: &mut AppState) {
+        self.pending = None;
+        self.answers = None;
+        self.awaiting_answer = false;
+        state.authenticating = false;
+        state.prompt = None;
+        state.pam_message = None;
+    }
+}
+
+fn worker_gone() -> AuthError {
+    AuthError::Unavailable("the authentication thread has stopped".to_string())
+}
+
+/// Parses a comma separated list of usernames, ignoring blank entries.
+pub fn parse_user_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|user| !user.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::{
+        auth::{MockAuthenticator, PasswordOnly},
+        tui::FocusTarget,
+    };
+
+    // Time that only moves when something sleeps on it or advances it.
+    #[derive(Clone)]
+    struct FakeClock {
+        start: Instant,
+        elapsed: Arc<Mutex<Duration>>,
+    }
+
+    impl FakeClock {
+        fn new() -> Self {
+            Self {
+                start: Instant::now(),
+                elapsed: Arc::default(),
+            }
+        }
+
+        fn advance(&self, duration: Duration) {
+            *self.elapsed.lock().unwrap() += duration;
+        }
+
+        fn elapsed(&self) -> Duration {
+            *self.elapsed.lock().unwrap()
+        }
+    }
+
+    impl Clock for FakeClock {
+        fn now(&self) -> Instant {
+            self.start + self.elapsed()
+        }
+
+        fn sleep(&self, duration: Duration) {
+            self.advance(duration);
+        }
+    }
+
+    // A controller whose worker reports every outcome it sends on the returned receiver.
+    fn spawned(authenticator: MockAuthenticator) -> (Controller, Receiver<()>) {
+        let (woken, wakeups) = mpsc::channel();
+        let policy = Policy::new(authenticator).with_clock(FakeClock::new());
+        let controller = Controller::spawn_with_waker(policy, move || {
+            let _ = woken.send(());
+        });
+        (controller, wakeups)
+    }
+
+    // Types `text` into the focused field and presses Enter.
+    fn submit(controller: &mut Controller, state: &mut AppState, text: &str) {
+        for ch in text.chars() {
+            controller.handle_input(state, KeyInput::Char(ch));
+        }
+        controller.handle_input(state, KeyInput::Enter);
+    }
+
+    // Polls once the worker has sent something, the way the event loop does once woken.
+    fn poll_woken(
+        controller: &mut Controller,
+        wakeups: &Receiver<()>,
+        state: &mut AppState,
+    ) -> bool {
+        wakeups.recv_timeout(Duration::from_secs(5)).unwrap();
+        controller.poll(state)
+    }
+
+    fn on_password(username: &str) -> AppState {
+        AppState {
+            username: username.to_string(),
+            focused: FocusTarget::Password,
+            ..AppState::default()
+        }
+    }
+
+    #[test]
+    fn the_right_password_unlocks() {
+        let (mut controller, wakeups) = spawned(MockAuthenticator::Password("hunter2".to_string()));
+        let mut state = AppState::default();
+        // Enter on the username moves on to the password
+        submit(&mut controller, &mut state, "alice");
+        assert!(!controller.is_pending());
+        submit(&mut controller, &mut state, "hunter2");
+        assert!(controller.is_pending());
+        assert!(state.authenticating);
+
+        assert!(poll_woken(&mut controller, &wakeups, &mut state));
+        assert!(!controller.is_pending());
+        assert!(!state.authenticating);
+        assert!(state.password.is_empty());
+        assert_eq!(state.error_message, None);
+    }
+
+    #[test]
+    fn a_wrong_password_shows_the_error_and_can_be_retried() {
+        let (mut controller, wakeups) = spawned(MockAuthenticator::Password("hunter2".to_string()));
+        let mut state = on_password("alice");
+        submit(&mut controller, &mut state, "hunter3");
+        assert!(!poll_woken(&mut controller, &wakeups, &mut state));
+        assert_eq!(
+            state.error_message.as_deref(),
+            Some("Authentication failed.")
+        );
+        assert!(state.password.is_empty());
+
+        submit(&mut controller, &mut state, "hunter2");
+        assert!(poll_woken(&mut controller, &wakeups, &mut state));
+        assert_eq!(state.error_message, None);
+    }
+
+    #[test]
+    fn an_expired_password_says_so_without_unlocking() {
+        let expired = MockAuthenticator::Expired("hunter2".to_string());
+        let (mut controller, wakeups) = spawned(expired);
+        let mut state = on_password("alice");
+        submit(&mut controller, &mut state, "hunter2");
+        assert!(!poll_woken(&mut controller, &wakeups, &mut state));
+        assert_eq!(
+            state.error_message.as_deref(),
+            Some(AuthError::PasswordExpired.message())
+        );
+        // the password was right, so it doesn't count towards the lockout
+        assert_eq!(state.lockout.failures(), 0);
+        assert!(state.password.is_empty());
+    }
+
+    #[test]
+    fn enter_within_the_grace_period_unlocks_without_a_password() {
+        let (mut controller, _wakeups) = spawned(MockAuthenticator::Reject);
+        let mut state = AppState {
+            locked_at: Some(Instant::now()),
+            grace_period: Duration::from_secs(60),
+            ..on_password("alice")
+        };
+        controller.handle_input(&mut state, KeyInput::Enter);
+        assert!(!controller.is_pending());
+        assert!(controller.poll(&mut state));
+        // handed out once
+        assert!(!controller.poll(&mut state));
+    }
+
+    #[test]
+    fn the_power_button_is_handed_to_the_caller() {
+        let (mut controller, _wakeups) = spawned(MockAuthenticator::Reject);
+        let mut state = AppState {
+            power_button: true,
+            focused: FocusTarget::Power,
+            ..on_password("alice")
+        };
+        assert!(!controller.take_power_off());
+        controller.handle_input(&mut state, KeyInput::Enter);
+        assert!(!controller.is_pending());
+        assert!(!controller.poll(&mut state));
+        assert!(controller.take_power_off());
+        assert!(!controller.take_power_off());
+    }
+
+    #[test]
+    fn success_clears_the_lockout() {
+        let (mut controller, wakeups) = spawned(MockAuthenticator::Password("hunter2".to_string()));
+        let mut state = on_password("alice");
+        submit(&mut controller, &mut state, "wrong");
+        assert!(!poll_woken(&mut controller, &wakeups, &mut state));
+        assert_eq!(state.lockout.failures(), 1);
+
+        submit(&mut controller, &mut state, "hunter2");
+        assert!(poll_woken(&mut controller, &wakeups, &mut state));
+        assert_eq!(state.lockout.failures(), 0);
+        assert_eq!(state.error_message, None);
+    }
+
+    #[test]
+    fn a_second_submit_waits_for_the_first() {
+        let delayed = MockAuthenticator::Delayed(
+            Duration::from_millis(50),
+            Box::new(MockAuthenticator::Reject),
+        );
+        let (mut controller, wakeups) = spawned(delayed);
+        let mut state = on_password("alice");
+        submit(&mut controller, &mut state, "first");
+        // the fields are locked while the attempt is checked, so submit directly
+        controller.apply(
+            &mut state,
+            AppAction::Submit {
+                username: "alice".to_string(),
+                password: Secret::from("second"),
+            },
+        );
+
+        assert!(!poll_woken(&mut controller, &wakeups, &mut state));
+        assert!(!controller.is_pending());
+        // only one attempt was made
+        assert!(wakeups.recv_timeout(Duration::from_millis(200)).is_err());
+    }
+
+    #[test]
+    fn cancelling_forgets_the_attempt() {
+        let delayed = MockAuthenticator::Delayed(
+            Duration::from_millis(50),
+            Box::new(MockAuthenticator::Reject),
+        );
+        let (mut controller, wakeups) = spawned(delayed);
+        let mut state = on_password("alice");
+        submit(&mut controller, &mut state, "hunter2");
+        controller.handle_input(&mut state, KeyInput::Esc);
+        assert!(!controller.is_pending());
+        assert!(!state.authenticating);
+
+        // the abandoned attempt's outcome is dropped
+        assert!(!poll_woken(&mut controller, &wakeups, &mut state));
+        assert_eq!(state.error_message, None);
+    }
+
+    fn second_factor() -> MockAuthenticator {
+        MockAuthenticator::SecondFactor {
+            password: "hunter2".to_string(),
+            code: "123456".to_string(),
+        }
+    }
+
+    // Polls until the pending attempt hands the fields back to answer a prompt.
+    fn poll_until_asked(controller: &mut Controller, wakeups: &Receiver<()>, state: &mut AppState) {
+        while state.authenticating {
+            assert!(!poll_woken(controller, wakeups, state));
+        }
+    }
+
+    // Polls until the pending attempt has its outcome, through the wakeups its prompts left.
+    fn poll_until_done(
+        controller: &mut Controller,
+        wakeups: &Receiver<()>,
+        state: &mut AppState,
+    ) -> bool {
+        loop {
+            let unlocked = poll_woken(controller, wakeups, state);
+            if unlocked || !controller.is_pending() {
+                return unlocked;
+            }
+        }
+    }
+
+    #[test]
+    fn a_second_factor_is_asked_for_and_answered_in_the_password_field() {
+        let (mut controller, wakeups) = spawned(second_factor());
+        let mut state = on_password("alice");
+        submit(&mut controller, &mut state, "hunter2");
+        poll_until_asked(&mut controller, &wakeups, &mut state);
+        assert!(controller.is_pending());
+        assert_eq!(state.pam_message.as_deref(), Some("A code was sent."));
+        assert_eq!(state.password_label(), "Code: ");
+        assert_eq!(state.focused, FocusTarget::Password);
+        // a code is typed in the clear, since PAM asked with echo on
+        state.password = Secret::from("123456");
+        assert_eq!(state.password_display().as_str(), "123456");
+        state.password.clear();
+
+        submit(&mut controller, &mut state, "123456");
+        assert!(state.authenticating);
+        assert!(poll_until_done(&mut controller, &wakeups, &mut state));
+        assert_eq!(state.prompt, None);
+        assert_eq!(state.pam_message, None);
+    }
+
+    #[test]
+    fn a_wrong_second_factor_fails_the_attempt() {
+        let (mut controller, wakeups) = spawned(second_factor());
+        let mut state = on_password("alice");
+        submit(&mut controller, &mut state, "hunter2");
+        poll_until_asked(&mut controller, &wakeups, &mut state);
+
+        submit(&mut controller, &mut state, "654321");
+        assert!(!poll_until_done(&mut controller, &wakeups, &mut state));
+        assert_eq!(
+            state.error_message.as_deref(),
+            Some("Authentication failed.")
+        );
+        // back to the password for the next attempt
+        assert_eq!(state.password_label(), "Password: ");
+        assert!(!controller.is_pending());
+    }
+
+    #[test]
+    fn escape_abandons_a_prompt() {
+        let (mut controller, wakeups) = spawned(second_factor());
+        let mut state = on_password("alice");
+        submit(&mut controller, &mut state, "hunter2");
+        poll_until_asked(&mut controller, &wakeups, &mut state);
+
+        controller.handle_input(&mut state, KeyInput::Esc);
+        assert!(!controller.is_pending());
+        assert_eq!(state.prompt, None);
+        // the worker gives up on the conversation, and its outcome is dropped
+        assert!(!poll_woken(&mut controller, &wakeups, &mut state));
+        assert_eq!(state.error_message, None);
+
+        submit(&mut controller, &mut state, "hunter2");
+        assert!(controller.is_pending());
+    }
+
+    #[test]
+    fn the_waker_is_called_once_the_outcome_can_be_polled() {
+        let (mut controller, wakeups) = spawned(MockAuthenticator::Accept);
+        let mut state = AppState::default();
+        controller.apply(
+            &mut state,
+            AppAction::Submit {
+                username: "alice".to_string(),
+                password: Secret::from("hunter2"),
+            },
+        );
+
+        wakeups.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(controller.poll(&mut state));
+        assert!(!controller.is_pending());
+    }
+
+    // Counts the attempts that got past the policy.
+    struct Counting(MockAuthenticator, u32);
+
+    impl Authenticator for Counting {
+        fn authenticate(
+            &mut self,
+            username: &str,
+            password: &str,
+            conversation: &mut dyn Conversation,
+        ) -> Result<(), AuthError> {
+            self.1 += 1;
+            self.0.authenticate(username, password, conversation)
+        }
+    }
+
+    fn allowing_alice() -> Policy<Counting> {
+        let authenticator = Counting(MockAuthenticator::Password("hunter2".to_string()), 0);
+        Policy::new(authenticator)
+            .with_clock(FakeClock::new())
+            .with_allowed_users(parse_user_list("alice, ,bob"))
+    }
+
+    #[test]
+    fn allowed_users_go_on_to_the_authenticator() {
+        let mut policy = allowing_alice();
+        assert_eq!(
+            policy.authenticate("alice", "hunter3", &mut PasswordOnly),
+            Err(AuthError::Failed)
+        );
+        assert_eq!(policy.authenticator.1, 1);
+        assert_eq!(
+            policy.authenticate("alice", "hunter2", &mut PasswordOnly),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn other_users_fail_like_a_wrong_password() {
+        let mut policy = allowing_alice();
+        let wrong_password = policy.authenticate("alice", "hunter3", &mut PasswordOnly);
+        let attempts = policy.authenticator.1;
+
+        let not_allowed = policy.authenticate("mallory", "hunter2", &mut PasswordOnly);
+        assert_eq!(policy.authenticator.1, attempts);
+        assert_eq!(not_allowed, wrong_password);
+    }
+
+    // Rejects every attempt after taking `delay`, like PAM's own failure delay.
+    struct Slow(FakeClock, Duration);
+
+    impl Authenticator for Slow {
+        fn authenticate(
+            &mut self,
+            _: &str,
+            _: &str,
+            _: &mut dyn Conversation,
+        ) -> Result<(), AuthError> {
+            self.0.advance(self.1);
+            Err(AuthError::Failed)
+        }
+    }
+
+    // How long an attempt as `username` took on `clock`.
+    fn timed<A: Authenticator>(
+        policy: &mut Policy<A>,
+        clock: &FakeClock,
+        username: &str,
+    ) -> Duration {
+        let started = clock.elapsed();
+        let _ = policy.authenticate(username, "hunter2", &mut PasswordOnly);
+        clock.elapsed() - started
+    }
+
+    #[test]
+    fn failures_take_at_least_the_minimum_duration() {
+        const FLOOR: Duration = Duration::from_millis(1500);
+        let clock = FakeClock::new();
+        let mut policy = Policy::new(MockAuthenticator::Password("hunter2".to_string()))
+            .with_clock(clock.clone())
+            .with_min_failure_duration(FLOOR)
+            .with_allowed_users(vec!["alice".to_string()]);
+
+        let started = clock.elapsed();
+        let _ = policy.authenticate("alice", "wrong", &mut PasswordOnly);
+        assert_eq!(clock.elapsed() - started, FLOOR);
+        // a disallowed user never reaches the authenticator, but waits just as long
+        assert_eq!(timed(&mut policy, &clock, "mallory"), FLOOR);
+        // success isn't padded
+        assert_eq!(timed(&mut policy, &clock, "alice"), Duration::ZERO);
+    }
+
+    #[test]
+    fn slow_failures_are_not_padded_further() {
+        let clock = FakeClock::new();
+        let delay = DEFAULT_MIN_FAILURE_DURATION * 2;
+        let mut policy = Policy::new(Slow(clock.clone(), delay)).with_clock(clock.clone());
+        assert_eq!(timed(&mut policy, &clock, "alice"), delay);
+    }
+}
@@ -0,0 +1,183 @@
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use anyhow::anyhow;
+
+/// Where the `LILAC_*` variables are read from: the process environment, or a fixed set of
+/// values so tests don't have to change the environment every other test shares.
+pub struct Vars {
+    lookup: Box<dyn Fn(&str) -> Option<OsString>>,
+}
+
+impl Vars {
+    pub fn process() -> Self {
+        Self {
+            lookup: Box::new(|name: &str| std::env::var_os(name)),
+        }
+    }
+
+    /// Only `values` are set, given as `(name, value)` pairs.
+    pub fn fixed(values: &[(&str, &str)]) -> Self {
+        let values: Vec<(String, OsString)> = values
+            .iter()
+            .map(|&(name, value)| (name.to_string(), value.into()))
+            .collect();
+        Self {
+            lookup: Box::new(move |name: &str| {
+                values
+                    .iter()
+                    .find(|(set, _)| set == name)
+                    .map(|(_, value)| value.clone())
+            }),
+        }
+    }
+
+    /// The variable as text, or `None` when it isn't set or isn't valid unicode.
+    pub fn var(&self, name: &str) -> Option<String> {
+        (self.lookup)(name)?.into_string().ok()
+    }
+
+    /// The variable as it was set, for paths that needn't be unicode.
+    pub fn var_os(&self, name: &str) -> Option<OsString> {
+        (self.lookup)(name)
+    }
+
+    /// See `parse`.
+    pub fn parse<T: FromStr>(&self, name: &str, expected: &str) -> anyhow::Result<Option<T>> {
+        let Some(value) = self.var(name) else {
+            return Ok(None);
+        };
+        parse_value(name, &value, expected).map(Some)
+    }
+
+    /// See `millis`.
+    pub fn millis(&self, name: &str) -> anyhow::Result<Option<Duration>> {
+        Ok(self
+            .parse(name, "a number of milliseconds")?
+            .map(Duration::from_millis))
+    }
+
+    /// See `secs`.
+    pub fn secs(&self, name: &str) -> anyhow::Result<Option<Duration>> {
+        Ok(self
+            .parse(name, "a number of seconds")?
+            .map(Duration::from_secs))
+    }
+
+    /// See `flag`.
+    pub fn flag(&self, name: &str) -> anyhow::Result<Option<bool>> {
+        let Some(value) = self.var(name) else {
+            return Ok(None);
+        };
+        parse_flag(&value)
+            .map(Some)
+            .ok_or_else(|| anyhow!("invalid {name} {value:?}, expected 1 or 0"))
+    }
+}
+
+/// Parses the `LILAC_*` variable `name`, or `None` when it isn't set so the default decides. A
+/// value that doesn't parse is an error rather than quietly ignored. `expected` describes a
+/// valid value for that error, e.g. "a count".
+pub fn parse<T: FromStr>(name: &str, expected: &str) -> anyhow::Result<Option<T>> {
+    Vars::process().parse(name, expected)
+}
+
+/// A duration given in whole milliseconds, e.g. `LILAC_MIN_FAILURE_MS=1500`.
+pub fn millis(name: &str) -> anyhow::Result<Option<Duration>> {
+    Vars::process().millis(name)
+}
+
+/// A duration given in whole seconds, e.g. `LILAC_GRACE_PERIOD_SECS=10`.
+pub fn secs(name: &str) -> anyhow::Result<Option<Duration>> {
+    Vars::process().secs(name)
+}
+
+/// An on/off switch, or `None` when the variable isn't set so the default decides. `1`, `true`,
+/// `yes` and `on` turn it on, `0`, `false`, `no` and `off` turn it off, so
+/// `LILAC_REDUCED_MOTION=0` means off rather than merely being set. Anything else is an error
+/// rather than quietly ignored.
+pub fn flag(name: &str) -> anyhow::Result<Option<bool>> {
+    Vars::process().flag(name)
+}
+
+/// `$XDG_STATE_HOME/lilac`, falling back to `~/.local/state/lilac`, or `None` when neither
+/// `XDG_STATE_HOME` nor `HOME` is set. Holds the log and what lilac remembers between runs.
+pub fn state_dir() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_STATE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".local/state")))?;
+    Some(base.join("lilac"))
+}
+
+fn parse_value<T: FromStr>(name: &str, value: &str, expected: &str) -> anyhow::Result<T> {
+    value
+        .parse()
+        .map_err(|_| anyhow!("invalid {name} {value:?}, expected {expected}"))
+}
+
+fn parse_flag(value: &str) -> Option<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Some(true),
+        "0" | "false" | "no" | "off" => Some(false),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_are_off_when_set_to_zero() {
+        for value in ["0", "false", "No", "OFF"] {
+            assert_eq!(parse_flag(value), Some(false), "{value}");
+        }
+        for value in ["1", "true", "Yes", "ON"] {
+            assert_eq!(parse_flag(value), Some(true), "{value}");
+        }
+    }
+
+    #[test]
+    fn garbage_flags_are_rejected() {
+        assert_eq!(parse_flag(""), None);
+        assert_eq!(parse_flag("maybe"), None);
+    }
+
+    #[test]
+    fn unparsable_values_name_the_variable() {
+        let err = parse_value::<u32>("LILAC_COMMIT_RETRY_BUDGET", "lots", "a count").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid LILAC_COMMIT_RETRY_BUDGET \"lots\", expected a count"
+        );
+        assert_eq!(
+            parse_value::<u32>("LILAC_COMMIT_RETRY_BUDGET", "5", "a count").unwrap(),
+            5
+        );
+    }
+
+    #[test]
+    fn unset_variables_fall_through() {
+        let name = "LILAC_TEST_VARIABLE_THAT_IS_NEVER_SET";
+        assert!(parse::<u32>(name, "a count").unwrap().is_none());
+        assert!(flag(name).unwrap().is_none());
+        assert!(millis(name).unwrap().is_none());
+        assert!(secs(name).unwrap().is_none());
+    }
+
+    #[test]
+    fn fixed_vars_are_the_only_ones_set() {
+        let vars = Vars::fixed(&[("LILAC_IDLE_SECS", "30"), ("LILAC_PIN_PAD", "on")]);
+        assert_eq!(
+            vars.secs("LILAC_IDLE_SECS").unwrap(),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(vars.flag("LILAC_PIN_PAD").unwrap(), Some(true));
+        assert_eq!(vars.var("HOME"), None);
+    }
+}
@@ -0,0 +1,27 @@
+use std::{
+    os::unix::process::CommandExt,
+    process::{Command, Stdio},
+};
+
+/// Runs a user supplied hook command through `sh -c` without waiting for it to finish.
+///
+/// The child gets its own process group and no stdio, so it outlives lilac and can't hold the
+/// terminal. Every fd lilac opens is close-on-exec, so the Wayland socket and shm pools are not
+/// inherited either.
+pub fn spawn(command: &str, env: &[(&str, String)]) -> std::io::Result<()> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .envs(env.iter().map(|(key, value)| (*key, value.as_str())))
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .process_group(0)
+        .spawn()?;
+
+    // reap the child in the background so it doesn't linger as a zombie
+    std::thread::spawn(move || {
+        let _ = child.wait();
+    });
+    Ok(())
+}
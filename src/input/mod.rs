@@ -0,0 +1,468 @@
+use std::{
+    env,
+    ffi::{OsStr, OsString},
+    io,
+    os::fd::OwnedFd,
+    time::Duration,
+};
+
+use xkbcommon::xkb::{self, compose};
+
+use crate::tui::KeyInput;
+
+/// Decodes `wl_keyboard` key events into `KeyInput`s through the compositor's xkb keymap.
+///
+/// Every press goes through the xkb state, which tracks the modifiers and layout group from
+/// `wl_keyboard.modifiers`, so Shift gives uppercase and symbols and AltGr gives the
+/// layout-specific characters. Mapping keycodes to characters directly would make most
+/// passwords untypable.
+///
+/// Dead keys and multi-key sequences go through the system compose file for the current locale,
+/// so `´` followed by `e` yields a single `é` instead of nothing.
+pub struct Keyboard {
+    state: xkb::State,
+    // `None` when the locale has no compose table; keys are then decoded one at a time
+    compose: Option<compose::State>,
+}
+
+impl Keyboard {
+    /// Compiles a keymap in the text format sent by `wl_keyboard.keymap`.
+    pub fn from_keymap_string(keymap: String) -> Option<Self> {
+        let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+        let keymap = xkb::Keymap::new_from_string(
+            &context,
+            keymap,
+            xkb::KEYMAP_FORMAT_TEXT_V1,
+            xkb::KEYMAP_COMPILE_NO_FLAGS,
+        )?;
+        Some(Self::with_keymap(&context, &keymap))
+    }
+
+    /// Compiles the keymap straight from the fd and size of a `wl_keyboard.keymap` event.
+    /// `Ok(None)` means the keymap was read but didn't compile.
+    pub fn from_keymap_fd(fd: OwnedFd, size: u32) -> io::Result<Option<Self>> {
+        let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+        // Safety: the fd comes from the compositor's keymap event, which promises `size` bytes
+        // of keymap behind it; xkbcommon maps it privately and only reads from it.
+        let keymap = unsafe {
+            xkb::Keymap::new_from_fd(
+                &context,
+                fd,
+                size as usize,
+                xkb::KEYMAP_FORMAT_TEXT_V1,
+                xkb::KEYMAP_COMPILE_NO_FLAGS,
+            )?
+        };
+        Ok(keymap.map(|keymap| Self::with_keymap(&context, &keymap)))
+    }
+
+    fn with_keymap(context: &xkb::Context, keymap: &xkb::Keymap) -> Self {
+        Self {
+            state: xkb::State::new(keymap),
+            compose: compose_state(context, &locale()),
+        }
+    }
+
+    /// Applies a `wl_keyboard.modifiers` event.
+    pub fn update_modifiers(&mut self, depressed: u32, latched: u32, locked: u32, group: u32) {
+        self.state
+            .update_mask(depressed, latched, locked, 0, 0, group);
+    }
+
+    /// Whether Caps Lock is in effect, read from the modifier state rather than key presses.
+    pub fn caps_lock(&self) -> bool {
+        self.state
+            .mod_name_is_active(xkb::MOD_NAME_CAPS, xkb::STATE_MODS_EFFECTIVE)
+    }
+
+    fn ctrl(&self) -> bool {
+        self.state
+            .mod_name_is_active(xkb::MOD_NAME_CTRL, xkb::STATE_MODS_EFFECTIVE)
+    }
+
+    fn shift(&self) -> bool {
+        self.state
+            .mod_name_is_active(xkb::MOD_NAME_SHIFT, xkb::STATE_MODS_EFFECTIVE)
+    }
+
+    /// Translates a pressed `wl_keyboard.key` (an evdev keycode) into input for the UI.
+    pub fn key_press(&mut self, keycode: u32) -> Option<KeyInput> {
+        let (keysym, text) = self.key_symbol(keycode);
+        if self.ctrl() && matches!(keysym, xkb::Keysym::r | xkb::Keysym::R) {
+            return Some(KeyInput::ToggleReveal);
+        }
+        if (self.ctrl() && matches!(keysym, xkb::Keysym::v | xkb::Keysym::V))
+            || (self.shift() && keysym == xkb::Keysym::Insert)
+        {
+            return Some(KeyInput::Paste);
+        }
+
+        if let Some(compose) = &mut self.compose
+            && compose.feed(keysym) == compose::FeedResult::Accepted
+        {
+            match compose.status() {
+                // a dead key or a partial sequence: nothing to type yet
+                compose::Status::Composing => return None,
+                compose::Status::Composed => {
+                    let text = compose.utf8().unwrap_or_default();
+                    compose.reset();
+                    return text_input(&text);
+                }
+                // the sequence didn't match anything; drop it like other compose implementations
+                compose::Status::Cancelled => {
+                    compose.reset();
+                    return None;
+                }
+                compose::Status::Nothing => {}
+            }
+        }
+
+        if let Some(input) = special_key(keysym) {
+            return Some(input);
+        }
+
+        text_input(&text)
+    }
+
+    /// Whether the keymap marks the key as repeating when held. Modifiers and the like don't.
+    pub fn key_repeats(&self, keycode: u32) -> bool {
+        self.state
+            .get_keymap()
+            .key_repeats(xkb::Keycode::new(keycode + 8))
+    }
+
+    /// The logical keysym and the UTF-8 text a key produces under the current modifiers and
+    /// layout, before compose sequences are applied.
+    pub fn key_symbol(&self, keycode: u32) -> (xkb::Keysym, String) {
+        // xkb keycodes are evdev keycodes offset by 8
+        let keycode = xkb::Keycode::new(keycode + 8);
+        (
+            self.state.key_get_one_sym(keycode),
+            self.state.key_get_utf8(keycode),
+        )
+    }
+}
+
+/// Client-side key repeat. Wayland sends a single press for a held key and leaves repeating it
+/// to the client, at the rate and delay from `wl_keyboard.repeat_info`. This tracks the held
+/// key; the caller runs the timer.
+#[derive(Debug)]
+pub struct KeyRepeat {
+    // repeats per second, 0 to disable repeat
+    rate: i32,
+    delay: Duration,
+    // the evdev keycode being held and the input it produced when pressed
+    held: Option<(u32, KeyInput)>,
+}
+
+impl Default for KeyRepeat {
+    // what most compositors send, for those older than wl_keyboard v4 that never do
+    fn default() -> Self {
+        Self {
+            rate: 25,
+            delay: Duration::from_millis(600),
+            held: None,
+        }
+    }
+}
+
+impl KeyRepeat {
+    /// Applies a `wl_keyboard.repeat_info` event.
+    pub fn set_info(&mut self, rate: i32, delay_ms: i32) {
+        self.rate = rate.max(0);
+        self.delay = Duration::from_millis(delay_ms.max(0) as u64);
+        if self.rate == 0 {
+            self.held = None;
+        }
+    }
+
+    /// Records a press of `keycode`, which replaces any key held before it. Returns how long to
+    /// wait before the first repeat, or None when the input shouldn't repeat.
+    pub fn press(&mut self, keycode: u32, input: &KeyInput, key_repeats: bool) -> Option<Duration> {
+        self.held = None;
+        if self.rate == 0 || !key_repeats || !repeatable(input) {
+            return None;
+        }
+        self.held = Some((keycode, input.clone()));
+        Some(self.delay)
+    }
+
+    /// Records a release of `keycode`. Returns whether that stopped the repeat.
+    pub fn release(&mut self, keycode: u32) -> bool {
+        if self.held.as_ref().is_some_and(|(held, _)| *held == keycode) {
+            self.held = None;
+            return true;
+        }
+        false
+    }
+
+    pub fn stop(&mut self) {
+        self.held = None;
+    }
+
+    /// The input to emit again, while a key is held.
+    pub fn held(&self) -> Option<&KeyInput> {
+        self.held.as_ref().map(|(_, input)| input)
+    }
+
+    /// The time between repeats after the first.
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(1) / self.rate.max(1) as u32
+    }
+}
+
+// Editing and moving repeat; keys that act on the form as a whole would fire several times
+// from one long press.
+fn repeatable(input: &KeyInput) -> bool {
+    matches!(
+        input,
+        KeyInput::Char(_)
+            | KeyInput::Backspace
+            | KeyInput::Up
+            | KeyInput::Down
+            | KeyInput::Left
+            | KeyInput::Right
+    )
+}
+
+// Compose tables are per locale, looked up the same way libc does it.
+fn locale() -> OsString {
+    ["LC_ALL", "LC_CTYPE", "LANG"]
+        .iter()
+        .filter_map(env::var_os)
+        .find(|value| !value.is_empty())
+        .unwrap_or_else(|| OsString::from("C"))
+}
+
+fn compose_state(context: &xkb::Context, locale: &OsStr) -> Option<compose::State> {
+    let table = compose::Table::new_from_locale(context, locale, compose::COMPILE_NO_FLAGS).ok()?;
+    Some(compose::State::new(&table, compose::STATE_NO_FLAGS))
+}
+
+fn special_key(keysym: xkb::Keysym) -> Option<KeyInput> {
+    match keysym {
+        xkb::Keysym::Return | xkb::Keysym::KP_Enter => Some(KeyInput::Enter),
+        xkb::Keysym::BackSpace => Some(KeyInput::Backspace),
+        xkb::Keysym::Tab => Some(KeyInput::Tab),
+        xkb::Keysym::ISO_Left_Tab => Some(KeyInput::BackTab),
+        xkb::Keysym::Up => Some(KeyInput::Up),
+        xkb::Keysym::Down => Some(KeyInput::Down),
+        xkb::Keysym::Left => Some(KeyInput::Left),
+        xkb::Keysym::Right => Some(KeyInput::Right),
+        xkb::Keysym::Escape => Some(KeyInput::Esc),
+        _ => None,
+    }
+}
+
+// Keys that produce exactly one printable character become `KeyInput::Char`; dead keys and
+// modifiers produce no text and are dropped.
+fn text_input(text: &str) -> Option<KeyInput> {
+    let mut chars = text.chars();
+    match (chars.next(), chars.next()) {
+        (Some(ch), None) if !ch.is_control() => Some(KeyInput::Char(ch)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    // evdev keycodes, as sent in `wl_keyboard.key`
+    const KEY_1: u32 = 2;
+    const KEY_EQUAL: u32 = 13;
+    const KEY_Q: u32 = 16;
+    const KEY_E: u32 = 18;
+    const KEY_R: u32 = 19;
+    const KEY_LEFTSHIFT: u32 = 42;
+    const KEY_ENTER: u32 = 28;
+    const KEY_V: u32 = 47;
+    const KEY_LEFT: u32 = 105;
+    const KEY_RIGHT: u32 = 106;
+    const KEY_INSERT: u32 = 110;
+
+    // Shares the keymap's text form through a memfd, as a compositor would send it.
+    fn keyboard(layout: &str) -> (Keyboard, xkb::Keymap) {
+        let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+        let keymap = xkb::Keymap::new_from_names(
+            &context,
+            "evdev",
+            "pc105",
+            layout,
+            "",
+            None,
+            xkb::KEYMAP_COMPILE_NO_FLAGS,
+        )
+        .expect("xkeyboard-config is installed");
+        let text = keymap.get_as_string(xkb::KEYMAP_FORMAT_TEXT_V1);
+        let mut file = memfd::MemfdOptions::default()
+            .create("keymap")
+            .unwrap()
+            .into_file();
+        file.write_all(text.as_bytes()).unwrap();
+        let keyboard = Keyboard::from_keymap_fd(file.into(), text.len() as u32)
+            .unwrap()
+            .unwrap();
+        (keyboard, keymap)
+    }
+
+    fn char_of(input: Option<KeyInput>) -> Option<char> {
+        match input {
+            Some(KeyInput::Char(ch)) => Some(ch),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn shift_gives_symbols_on_a_us_layout() {
+        let (mut keyboard, keymap) = keyboard("us");
+        assert_eq!(char_of(keyboard.key_press(KEY_1)), Some('1'));
+
+        let shift = 1 << keymap.mod_get_index(xkb::MOD_NAME_SHIFT);
+        keyboard.update_modifiers(shift, 0, 0, 0);
+        assert_eq!(char_of(keyboard.key_press(KEY_1)), Some('!'));
+        assert_eq!(char_of(keyboard.key_press(KEY_Q)), Some('Q'));
+
+        keyboard.update_modifiers(0, 0, 0, 0);
+        assert_eq!(char_of(keyboard.key_press(KEY_Q)), Some('q'));
+    }
+
+    #[test]
+    fn caps_lock_follows_the_locked_modifiers() {
+        let (mut keyboard, keymap) = keyboard("us");
+        assert!(!keyboard.caps_lock());
+
+        let caps = 1 << keymap.mod_get_index(xkb::MOD_NAME_CAPS);
+        keyboard.update_modifiers(0, 0, caps, 0);
+        assert!(keyboard.caps_lock());
+        keyboard.update_modifiers(0, 0, 0, 0);
+        assert!(!keyboard.caps_lock());
+    }
+
+    #[test]
+    fn ctrl_r_toggles_the_password_reveal() {
+        let (mut keyboard, keymap) = keyboard("us");
+        assert_eq!(char_of(keyboard.key_press(KEY_R)), Some('r'));
+
+        let ctrl = 1 << keymap.mod_get_index(xkb::MOD_NAME_CTRL);
+        keyboard.update_modifiers(ctrl, 0, 0, 0);
+        assert!(matches!(
+            keyboard.key_press(KEY_R),
+            Some(KeyInput::ToggleReveal)
+        ));
+    }
+
+    #[test]
+    fn ctrl_v_and_shift_insert_paste() {
+        let (mut keyboard, keymap) = keyboard("us");
+        assert_eq!(char_of(keyboard.key_press(KEY_V)), Some('v'));
+
+        let ctrl = 1 << keymap.mod_get_index(xkb::MOD_NAME_CTRL);
+        keyboard.update_modifiers(ctrl, 0, 0, 0);
+        assert!(matches!(keyboard.key_press(KEY_V), Some(KeyInput::Paste)));
+
+        let shift = 1 << keymap.mod_get_index(xkb::MOD_NAME_SHIFT);
+        keyboard.update_modifiers(shift, 0, 0, 0);
+        assert!(matches!(
+            keyboard.key_press(KEY_INSERT),
+            Some(KeyInput::Paste)
+        ));
+    }
+
+    #[test]
+    fn keys_give_their_keysym_and_text() {
+        let (mut keyboard, keymap) = keyboard("us");
+        let (keysym, text) = keyboard.key_symbol(KEY_Q);
+        assert_eq!((keysym, text.as_str()), (xkb::Keysym::q, "q"));
+
+        let shift = 1 << keymap.mod_get_index(xkb::MOD_NAME_SHIFT);
+        keyboard.update_modifiers(shift, 0, 0, 0);
+        let (keysym, text) = keyboard.key_symbol(KEY_1);
+        assert_eq!((keysym, text.as_str()), (xkb::Keysym::exclam, "!"));
+        let (keysym, text) = keyboard.key_symbol(KEY_ENTER);
+        assert_eq!((keysym, text.as_str()), (xkb::Keysym::Return, "\r"));
+    }
+
+    #[test]
+    fn altgr_gives_layout_specific_characters() {
+        let (mut keyboard, keymap) = keyboard("de");
+        let altgr = 1 << keymap.mod_get_index(xkb::MOD_NAME_ISO_LEVEL3_SHIFT);
+        keyboard.update_modifiers(altgr, 0, 0, 0);
+        assert_eq!(char_of(keyboard.key_press(KEY_Q)), Some('@'));
+    }
+
+    #[test]
+    fn dead_keys_and_special_keys_type_no_text() {
+        let (mut keyboard, _) = keyboard("de");
+        assert!(matches!(
+            keyboard.key_press(KEY_ENTER),
+            Some(KeyInput::Enter)
+        ));
+        // the acute accent is a dead key on a German layout
+        assert!(keyboard.key_press(KEY_EQUAL).is_none());
+    }
+
+    #[test]
+    fn left_and_right_arrows_are_keys_of_their_own() {
+        let (mut keyboard, _) = keyboard("us");
+        assert!(matches!(keyboard.key_press(KEY_LEFT), Some(KeyInput::Left)));
+        assert!(matches!(
+            keyboard.key_press(KEY_RIGHT),
+            Some(KeyInput::Right)
+        ));
+    }
+
+    #[test]
+    fn dead_keys_compose_with_the_next_key() {
+        let (mut keyboard, _) = keyboard("de");
+        let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+        keyboard.compose = compose_state(&context, OsStr::new("en_US.UTF-8"));
+        assert!(
+            keyboard.compose.is_some(),
+            "the en_US.UTF-8 compose table is installed"
+        );
+
+        assert!(keyboard.key_press(KEY_EQUAL).is_none());
+        assert_eq!(char_of(keyboard.key_press(KEY_E)), Some('é'));
+        // the sequence is over, so the next key types on its own
+        assert_eq!(char_of(keyboard.key_press(KEY_E)), Some('e'));
+    }
+
+    #[test]
+    fn held_keys_repeat_until_released_or_replaced() {
+        let mut repeat = KeyRepeat::default();
+        let delay = repeat.press(KEY_Q, &KeyInput::Char('q'), true);
+        assert_eq!(delay, Some(Duration::from_millis(600)));
+        assert!(matches!(repeat.held(), Some(KeyInput::Char('q'))));
+        assert_eq!(repeat.interval(), Duration::from_millis(40));
+
+        // releasing another key changes nothing, pressing one takes over
+        assert!(!repeat.release(KEY_E));
+        repeat.press(KEY_E, &KeyInput::Char('e'), true);
+        assert!(!repeat.release(KEY_Q));
+        assert!(repeat.release(KEY_E));
+        assert!(repeat.held().is_none());
+
+        // keys acting on the whole form fire once, as do keys the keymap doesn't repeat
+        assert_eq!(repeat.press(KEY_ENTER, &KeyInput::Enter, true), None);
+        assert_eq!(repeat.press(KEY_Q, &KeyInput::Char('q'), false), None);
+
+        repeat.set_info(50, 200);
+        let delay = repeat.press(KEY_Q, &KeyInput::Backspace, true);
+        assert_eq!(delay, Some(Duration::from_millis(200)));
+        assert_eq!(repeat.interval(), Duration::from_millis(20));
+        // a rate of zero turns repeat off
+        repeat.set_info(0, 200);
+        assert!(repeat.held().is_none());
+        assert_eq!(repeat.press(KEY_Q, &KeyInput::Char('q'), true), None);
+    }
+
+    #[test]
+    fn modifiers_do_not_repeat() {
+        let (keyboard, _) = keyboard("us");
+        assert!(keyboard.key_repeats(KEY_Q));
+        assert!(!keyboard.key_repeats(KEY_LEFTSHIFT));
+    }
+}
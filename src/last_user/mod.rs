@@ -0,0 +1,75 @@
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+};
+
+// far longer than any login name; anything bigger isn't a file we wrote
+const MAX_USERNAME_BYTES: usize = 256;
+
+/// `last_user` in the state directory, see `env::state_dir`.
+pub fn default_path() -> Option<PathBuf> {
+    crate::env::state_dir().map(|dir| dir.join("last_user"))
+}
+
+/// The username remembered at `path`. A missing, unreadable or corrupt file remembers nobody,
+/// so the field starts out blank.
+pub fn load(path: &Path) -> Option<String> {
+    let contents = std::fs::read(path).ok()?;
+    if contents.len() > MAX_USERNAME_BYTES {
+        return None;
+    }
+    let username = String::from_utf8(contents).ok()?;
+    let username = username.trim();
+    let valid = !username.is_empty() && !username.chars().any(char::is_control);
+    valid.then(|| username.to_string())
+}
+
+/// Remembers `username` at `path`, readable by the user alone. Written to a temporary file
+/// renamed into place, so a crash halfway leaves the old name rather than a torn one.
+pub fn save(path: &Path, username: &str) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let partial = path.with_extension("partial");
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&partial)?;
+    writeln!(file, "{username}")?;
+    file.sync_all()?;
+    std::fs::rename(&partial, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_saved_username_loads_back() {
+        let dir = std::env::temp_dir().join(format!("lilac-last-user-{}", std::process::id()));
+        let path = dir.join("state").join("last_user");
+        assert_eq!(load(&path), None);
+
+        save(&path, "alice").unwrap();
+        assert_eq!(load(&path).as_deref(), Some("alice"));
+        save(&path, "bob").unwrap();
+        assert_eq!(load(&path).as_deref(), Some("bob"));
+
+        // anything that can't be a name we wrote is ignored
+        for corrupt in [
+            &b""[..],
+            b"\n",
+            b"\xFF\xFEalice",
+            b"al\x1Bice",
+            &[b'a'; 1024],
+        ] {
+            std::fs::write(&path, corrupt).unwrap();
+            assert_eq!(load(&path), None, "{corrupt:?}");
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,2 +1,18 @@
+pub mod auth;
+pub mod config;
+pub mod controller;
+pub mod env;
+pub mod hooks;
+pub mod input;
+pub mod last_user;
+pub mod lock;
+pub mod locker;
+pub mod log;
+#[cfg(feature = "dbus")]
+pub mod logind;
+pub mod media;
+#[cfg(feature = "systemd")]
+pub mod notify;
+pub mod power;
 pub mod render;
 pub mod tui;
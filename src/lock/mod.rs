@@ -0,0 +1,193 @@
+/// Where the session lock stands. Only ever moves forward, Idle to Finished.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum LockState {
+    // haven’t requested a lock yet
+    #[default]
+    Idle,
+    // lock request sent, waiting for locked or finished
+    Waiting,
+    // received locked, surfaces should be active
+    Locked,
+    // received finished, lock denied or unlock succeeded
+    Finished,
+}
+
+/// The request a transition needs sent to the ext_session_lock_v1 object.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Action {
+    // end the lock; the only way out once locked was received
+    UnlockAndDestroy,
+    // drop a lock that was never confirmed; a protocol error once locked was received
+    Destroy,
+    // nothing to send
+    None,
+}
+
+/// The ext-session-lock-v1 lifecycle without any Wayland in it. Events from the compositor and
+/// our own decisions go in, and what has to happen to the lock object comes out as an
+/// `Action` for the caller to send.
+#[derive(Debug, Default)]
+pub struct LockMachine {
+    state: LockState,
+}
+
+impl LockMachine {
+    pub fn state(&self) -> LockState {
+        self.state
+    }
+
+    /// The lock request went out. Returns false unless nothing was requested before.
+    pub fn request_lock(&mut self) -> bool {
+        if self.state != LockState::Idle {
+            return false;
+        }
+        self.state = LockState::Waiting;
+        true
+    }
+
+    /// The compositor sent locked. Returns false, leaving the state alone, unless a lock was
+    /// requested and hasn't ended yet.
+    pub fn on_locked(&mut self) -> bool {
+        if self.state != LockState::Waiting {
+            return false;
+        }
+        self.state = LockState::Locked;
+        true
+    }
+
+    /// The compositor sent finished, or we're giving up on a lock we still own. Which request
+    /// ends it depends on whether locked was received.
+    pub fn on_finished(&mut self) -> Action {
+        let action = match self.state {
+            LockState::Waiting => Action::Destroy,
+            LockState::Locked => Action::UnlockAndDestroy,
+            LockState::Idle | LockState::Finished => Action::None,
+        };
+        self.state = LockState::Finished;
+        action
+    }
+
+    /// Whether an authenticated user may end the lock now.
+    pub fn can_unlock(&self) -> bool {
+        self.state == LockState::Locked
+    }
+
+    /// Ends a confirmed lock. Anything else is refused with `Action::None` and no change, as
+    /// unlocking before the compositor confirmed the lock is a protocol error.
+    pub fn request_unlock(&mut self) -> Action {
+        if !self.can_unlock() {
+            return Action::None;
+        }
+        self.state = LockState::Finished;
+        Action::UnlockAndDestroy
+    }
+
+    /// Stops without authentication. An unconfirmed lock is destroyed, but a confirmed one is
+    /// abandoned without a request so the compositor keeps the session locked.
+    pub fn abandon(&mut self) -> Action {
+        let action = match self.state {
+            LockState::Waiting => Action::Destroy,
+            LockState::Idle | LockState::Locked | LockState::Finished => Action::None,
+        };
+        self.state = LockState::Finished;
+        action
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn machine_in(state: LockState) -> LockMachine {
+        let mut machine = LockMachine::default();
+        if state == LockState::Idle {
+            return machine;
+        }
+        assert!(machine.request_lock());
+        match state {
+            LockState::Locked => assert!(machine.on_locked()),
+            LockState::Finished => {
+                machine.on_finished();
+            }
+            LockState::Idle | LockState::Waiting => {}
+        }
+        assert_eq!(machine.state(), state);
+        machine
+    }
+
+    #[test]
+    fn a_lock_is_requested_once() {
+        let mut machine = LockMachine::default();
+        assert!(machine.request_lock());
+        assert!(!machine.request_lock());
+        assert_eq!(machine.state(), LockState::Waiting);
+
+        assert!(!machine_in(LockState::Locked).request_lock());
+        assert!(!machine_in(LockState::Finished).request_lock());
+    }
+
+    #[test]
+    fn locked_only_confirms_a_pending_request() {
+        let mut idle = machine_in(LockState::Idle);
+        assert!(!idle.on_locked());
+        assert_eq!(idle.state(), LockState::Idle);
+
+        let mut finished = machine_in(LockState::Finished);
+        assert!(!finished.on_locked());
+        assert_eq!(finished.state(), LockState::Finished);
+
+        let mut locked = machine_in(LockState::Locked);
+        assert!(!locked.on_locked());
+        assert_eq!(locked.state(), LockState::Locked);
+    }
+
+    #[test]
+    fn finished_ends_the_lock_with_the_request_its_state_allows() {
+        for (state, action) in [
+            (LockState::Idle, Action::None),
+            (LockState::Waiting, Action::Destroy),
+            (LockState::Locked, Action::UnlockAndDestroy),
+            (LockState::Finished, Action::None),
+        ] {
+            let mut machine = machine_in(state);
+            assert_eq!(machine.on_finished(), action, "finished while {state:?}");
+            assert_eq!(machine.state(), LockState::Finished);
+        }
+    }
+
+    #[test]
+    fn only_a_confirmed_lock_can_be_unlocked() {
+        for state in [LockState::Idle, LockState::Waiting, LockState::Finished] {
+            let mut machine = machine_in(state);
+            assert!(!machine.can_unlock());
+            assert_eq!(
+                machine.request_unlock(),
+                Action::None,
+                "unlock while {state:?}"
+            );
+            assert_eq!(machine.state(), state);
+        }
+
+        let mut machine = machine_in(LockState::Locked);
+        assert!(machine.can_unlock());
+        assert_eq!(machine.request_unlock(), Action::UnlockAndDestroy);
+        assert_eq!(machine.state(), LockState::Finished);
+        // unlocking twice would reuse a destroyed object
+        assert_eq!(machine.request_unlock(), Action::None);
+    }
+
+    #[test]
+    fn abandoning_never_unlocks() {
+        for (state, action) in [
+            (LockState::Idle, Action::None),
+            (LockState::Waiting, Action::Destroy),
+            (LockState::Locked, Action::None),
+            (LockState::Finished, Action::None),
+        ] {
+            let mut machine = machine_in(state);
+            assert_eq!(machine.abandon(), action, "abandoned while {state:?}");
+            assert_eq!(machine.state(), LockState::Finished);
+            assert!(!machine.can_unlock());
+        }
+    }
+}
@@ -0,0 +1,938 @@
+use std::time::Instant;
+
+use wayland_client::{
+    Connection, Dispatch, Proxy, QueueHandle, WEnum, event_created_child,
+    protocol::{
+        wl_buffer::{self, WlBuffer},
+        wl_callback::{self, WlCallback},
+        wl_compositor::{self, WlCompositor},
+        wl_data_device::{self, WlDataDevice},
+        wl_data_device_manager::{self, WlDataDeviceManager},
+        wl_data_offer::{self, WlDataOffer},
+        wl_keyboard::{self, WlKeyboard},
+        wl_output::{self, WlOutput},
+        wl_pointer::{self, WlPointer},
+        wl_registry,
+        wl_seat::{self, WlSeat},
+        wl_shm::{self, WlShm},
+        wl_shm_pool::{self, WlShmPool},
+        wl_surface::{self, WlSurface},
+        wl_touch::{self, WlTouch},
+    },
+};
+
+#[cfg(feature = "ime")]
+use wayland_protocols::wp::text_input::zv3::client::{
+    zwp_text_input_manager_v3::{self, ZwpTextInputManagerV3},
+    zwp_text_input_v3::{self, ZwpTextInputV3},
+};
+use wayland_protocols::{
+    ext::session_lock::v1::client::{
+        ext_session_lock_manager_v1::{self, ExtSessionLockManagerV1},
+        ext_session_lock_surface_v1::{self, ExtSessionLockSurfaceV1},
+        ext_session_lock_v1::{self, ExtSessionLockV1},
+    },
+    wp::{
+        fractional_scale::v1::client::{
+            wp_fractional_scale_manager_v1::{self, WpFractionalScaleManagerV1},
+            wp_fractional_scale_v1::{self, WpFractionalScaleV1},
+        },
+        single_pixel_buffer::v1::client::wp_single_pixel_buffer_manager_v1::{
+            self, WpSinglePixelBufferManagerV1,
+        },
+        viewporter::client::{
+            wp_viewport::{self, WpViewport},
+            wp_viewporter::{self, WpViewporter},
+        },
+    },
+    xdg::system_bell::v1::client::xdg_system_bell_v1::{self, XdgSystemBellV1},
+};
+
+use tracing::{debug, info, warn};
+
+use crate::{input, tui::KeyInput};
+
+use super::{
+    BTN_LEFT, BufferTag, FrameTag, Locker, Monitor, OfferMimeTypes, OutputTag, SurfaceTag,
+    WL_OUTPUT_VERSION, WL_SEAT_VERSION,
+};
+
+impl Dispatch<wl_registry::WlRegistry, ()> for Locker {
+    fn event(
+        state: &mut Self,
+        registry: &wl_registry::WlRegistry,
+        event: wl_registry::Event,
+        _: &(),
+        _: &Connection,
+        qh: &QueueHandle<Locker>,
+    ) {
+        // an output was unplugged; nothing else we bind is expected to go away
+        if let wl_registry::Event::GlobalRemove { name } = event {
+            state.globals.retain(|(global, _, _)| *global != name);
+            let Some(monitor) = state.monitors.remove(&name) else {
+                return;
+            };
+            info!("{} was removed", monitor.label());
+            let was_primary = monitor.primary;
+            monitor.destroy();
+            if state.focused_output == Some(name) {
+                state.focused_output = None;
+            }
+            if let Some((focus, _, _)) = state.pointer_focus
+                && focus == name
+            {
+                state.pointer_focus = None;
+            }
+            if was_primary {
+                state.select_primary();
+            }
+            return;
+        }
+
+        // When receiving events from the wl_registry, we are only interested in the
+        // `global` event, which signals a new available global.
+        // When receiving this event, we just print its characteristics in this example.
+        if let wl_registry::Event::Global {
+            name,
+            interface,
+            version,
+        } = event
+        {
+            state.globals.push((name, interface.clone(), version));
+
+            match interface.as_str() {
+                "ext_session_lock_manager_v1" => {
+                    let version = version.min(ExtSessionLockManagerV1::interface().version);
+                    let lock_manager =
+                        registry.bind::<ExtSessionLockManagerV1, (), Locker>(name, version, qh, ());
+                    state.lock_manager = Some(lock_manager);
+                }
+                "wl_compositor" => {
+                    let version = version.min(WlCompositor::interface().version);
+                    let compositor =
+                        registry.bind::<WlCompositor, (), Locker>(name, version, qh, ());
+                    state.compositor = Some(compositor);
+                }
+                "wl_shm" => {
+                    let version = version.min(WlShm::interface().version);
+                    let shared_memory = registry.bind::<WlShm, (), Locker>(name, version, qh, ());
+                    state.shared_memory = Some(shared_memory);
+                }
+                "wp_single_pixel_buffer_manager_v1" => {
+                    let version = version.min(WpSinglePixelBufferManagerV1::interface().version);
+                    let manager = registry.bind::<WpSinglePixelBufferManagerV1, (), Locker>(
+                        name,
+                        version,
+                        qh,
+                        (),
+                    );
+                    state.single_pixel_buffer_manager = Some(manager);
+                }
+                "wp_viewporter" => {
+                    let version = version.min(WpViewporter::interface().version);
+                    let viewporter =
+                        registry.bind::<WpViewporter, (), Locker>(name, version, qh, ());
+                    state.viewporter = Some(viewporter);
+                }
+                "wp_fractional_scale_manager_v1" => {
+                    let version = version.min(WpFractionalScaleManagerV1::interface().version);
+                    let manager = registry.bind::<WpFractionalScaleManagerV1, (), Locker>(
+                        name,
+                        version,
+                        qh,
+                        (),
+                    );
+                    state.fractional_scale_manager = Some(manager);
+                }
+                "xdg_system_bell_v1" => {
+                    let version = version.min(XdgSystemBellV1::interface().version);
+                    let bell = registry.bind::<XdgSystemBellV1, (), Locker>(name, version, qh, ());
+                    state.system_bell = Some(bell);
+                }
+                "wl_seat" => {
+                    // one keyboard is enough to type a password
+                    if state.seat.is_some() {
+                        return;
+                    }
+                    let version = version.min(WL_SEAT_VERSION);
+                    state.seat = Some(registry.bind::<WlSeat, (), Locker>(name, version, qh, ()));
+                    state.bind_data_device(qh);
+                    #[cfg(feature = "ime")]
+                    state.bind_text_input(qh);
+                }
+                "wl_data_device_manager" => {
+                    let version = version.min(WlDataDeviceManager::interface().version);
+                    let manager =
+                        registry.bind::<WlDataDeviceManager, (), Locker>(name, version, qh, ());
+                    state.data_device_manager = Some(manager);
+                    state.bind_data_device(qh);
+                }
+                #[cfg(feature = "ime")]
+                "zwp_text_input_manager_v3" => {
+                    let version = version.min(ZwpTextInputManagerV3::interface().version);
+                    let manager =
+                        registry.bind::<ZwpTextInputManagerV3, (), Locker>(name, version, qh, ());
+                    state.text_input_manager = Some(manager);
+                    state.bind_text_input(qh);
+                }
+                "wl_output" => {
+                    // Some compositors re-advertise an output we already track after a mode
+                    // change. Rebinding would replace the monitor and drop its lock surface and
+                    // buffers mid-lock, so keep the existing one.
+                    if state.monitors.contains_key(&name) {
+                        info!("ignoring duplicate wl_output global {name}");
+                        return;
+                    }
+
+                    // v4 is the first version that advertises the output name and description
+                    let version = version.min(WL_OUTPUT_VERSION);
+                    if version < WL_OUTPUT_VERSION {
+                        info!("wl_output v{version} does not advertise names or descriptions");
+                    }
+                    let tag = OutputTag { monitor_name: name };
+                    let output =
+                        registry.bind::<WlOutput, OutputTag, Locker>(name, version, qh, tag);
+                    let mut disp = Monitor::default().with_name(name).with_output(output);
+
+                    // plugged in while locked: it needs a lock surface of its own right away,
+                    // or it would show the unlocked session
+                    let scaling = state
+                        .fractional_scale_manager
+                        .as_ref()
+                        .zip(state.viewporter.as_ref());
+                    let locked = match (state.compositor.as_ref(), state.lock.as_ref()) {
+                        (Some(compositor), Some(lock)) => {
+                            disp.create_surface_and_lock(compositor, lock, scaling, qh)
+                        }
+                        _ => Ok(()),
+                    };
+                    if let Err(err) = locked {
+                        warn!("failed to lock new output {name}: {err}");
+                    }
+                    state.monitors.insert(name, disp);
+                }
+                _ => return,
+            }
+
+            debug!("Locker found [{}] {} (v{})", name, interface, version);
+        }
+    }
+}
+
+impl Dispatch<ExtSessionLockManagerV1, ()> for Locker {
+    fn event(
+        _state: &mut Self,
+        _: &ExtSessionLockManagerV1,
+        _: ext_session_lock_manager_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Locker>,
+    ) {
+        debug!("received an event from ExtSessionLockManager, but don't know what to do with it...")
+    }
+}
+
+impl Dispatch<ExtSessionLockV1, ()> for Locker {
+    fn event(
+        state: &mut Self,
+        _: &ExtSessionLockV1,
+        event: ext_session_lock_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Locker>,
+    ) {
+        match event {
+            // session successfully locked This client is now responsible for displaying
+            // graphics while the session is locked and deciding when to unlock the session.
+            //
+            // The locked event must not be sent until a new “locked” frame has been presented
+            // on all outputs and no security sensitive normal/unlocked content is possibly
+            // visible.
+            //
+            // If this event is sent, making the destroy request is a protocol error, the lock
+            // object must be destroyed using the unlock_and_destroy request.
+            ext_session_lock_v1::Event::Locked => {
+                debug!("received ext_session_lock_v1::Locked");
+                state.locked(Instant::now());
+            }
+            // the session lock object should be destroyed
+            //
+            // The compositor has decided that the session lock should be destroyed as it will
+            // no longer be used by the compositor. Exactly when this event is sent is
+            // compositor policy, but it must never be sent more than once for a given session
+            // lock object.
+            //
+            // This might be sent because there is already another ext_session_lock_v1 object
+            // held by a client, or the compositor has decided to deny the request to lock the
+            // session for some other reason. This might also be sent because the compositor
+            // implements some alternative, secure way to authenticate and unlock the session.
+            //
+            // The finished event should be sent immediately on creation of this object if the
+            // compositor decides that the locked event will not be sent.
+            //
+            // If the locked event is sent on creation of this object the finished event may
+            // still be sent at some later time in this object’s lifetime. This is compositor
+            // policy.
+            //
+            // Upon receiving this event, the client should make either the destroy request or
+            // the unlock_and_destroy request, depending on whether or not the locked event was
+            // received on this object.
+            ext_session_lock_v1::Event::Finished => {
+                debug!("received ext_session_lock_v1::Finished");
+                state.cleanup();
+            }
+            _ => debug!("unknown event received from ExtSessionLock"),
+        }
+    }
+}
+
+impl Dispatch<WlCompositor, ()> for Locker {
+    fn event(
+        _state: &mut Self,
+        _: &WlCompositor,
+        _: wl_compositor::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Locker>,
+    ) {
+        debug!("received an event from WlCompositor, but don't know what to do with it...")
+    }
+}
+
+impl Dispatch<WlSeat, ()> for Locker {
+    fn event(
+        state: &mut Self,
+        seat: &WlSeat,
+        event: wl_seat::Event,
+        _: &(),
+        _: &Connection,
+        qh: &QueueHandle<Locker>,
+    ) {
+        match event {
+            wl_seat::Event::Capabilities {
+                capabilities: WEnum::Value(capabilities),
+            } => {
+                let has_keyboard = capabilities.contains(wl_seat::Capability::Keyboard);
+                if has_keyboard && state.keyboard.is_none() {
+                    state.keyboard = Some(seat.get_keyboard(qh, ()));
+                } else if !has_keyboard && let Some(keyboard) = state.keyboard.take() {
+                    info!("the seat lost its keyboard");
+                    keyboard.release();
+                    state.xkb = None;
+                }
+                let has_pointer = capabilities.contains(wl_seat::Capability::Pointer);
+                if has_pointer && state.pointer.is_none() {
+                    state.pointer = Some(seat.get_pointer(qh, ()));
+                } else if !has_pointer && let Some(pointer) = state.pointer.take() {
+                    pointer.release();
+                }
+                let has_touch = capabilities.contains(wl_seat::Capability::Touch);
+                if has_touch && state.touch.is_none() {
+                    state.touch = Some(seat.get_touch(qh, ()));
+                } else if !has_touch && let Some(touch) = state.touch.take() {
+                    touch.release();
+                }
+            }
+            wl_seat::Event::Name { name } => info!("using seat {name}"),
+            _ => debug!("received an event from WlSeat, but don't know what to do with it..."),
+        }
+    }
+}
+
+impl Dispatch<WlKeyboard, ()> for Locker {
+    fn event(
+        state: &mut Self,
+        _: &WlKeyboard,
+        event: wl_keyboard::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Locker>,
+    ) {
+        match event {
+            wl_keyboard::Event::Keymap {
+                format: WEnum::Value(wl_keyboard::KeymapFormat::XkbV1),
+                fd,
+                size,
+            } => {
+                state.stop_key_repeat();
+                state.xkb = match input::Keyboard::from_keymap_fd(fd, size) {
+                    Ok(keyboard) => keyboard,
+                    Err(err) => {
+                        warn!("failed to read the keymap: {err}");
+                        None
+                    }
+                };
+                if state.xkb.is_none() {
+                    warn!("could not compile the compositor's keymap, typing is disabled");
+                }
+            }
+            wl_keyboard::Event::Keymap { .. } => {
+                warn!("the compositor sent a keymap that isn't xkb v1, typing is disabled");
+                state.xkb = None;
+            }
+            // The lock surface of whichever output has keyboard focus; BoxPlacement::Focused
+            // follows it.
+            wl_keyboard::Event::Enter { surface, .. } => {
+                let focused = state
+                    .monitors
+                    .values()
+                    .find(|monitor| monitor.surface.as_ref() == Some(&surface))
+                    .map(|monitor| monitor.name);
+                if focused.is_some() && focused != state.focused_output {
+                    state.focused_output = focused;
+                    state.render_all();
+                }
+            }
+            // keep showing the box where it was; focus returns on the next Enter. The release
+            // of a held key goes to whoever has focus now, so its repeat ends here.
+            wl_keyboard::Event::Leave { .. } => state.stop_key_repeat(),
+            wl_keyboard::Event::Modifiers {
+                mods_depressed,
+                mods_latched,
+                mods_locked,
+                group,
+                ..
+            } => {
+                let Some(xkb) = state.xkb.as_mut() else {
+                    return;
+                };
+                xkb.update_modifiers(mods_depressed, mods_latched, mods_locked, group);
+                // also arrives when Caps Lock is toggled without typing anything
+                let caps_lock = xkb.caps_lock();
+                if caps_lock != state.app.caps_lock {
+                    state.app.caps_lock = caps_lock;
+                    state.render_all();
+                }
+            }
+            wl_keyboard::Event::Key {
+                key,
+                state: WEnum::Value(wl_keyboard::KeyState::Pressed),
+                ..
+            } => {
+                state.wake();
+                let Some(input) = state.xkb.as_mut().and_then(|xkb| xkb.key_press(key)) else {
+                    state.stop_key_repeat();
+                    return;
+                };
+                state.start_key_repeat(key, &input);
+                if matches!(input, KeyInput::Paste) {
+                    state.request_paste();
+                    return;
+                }
+                state.controller.handle_input(&mut state.app, input);
+                state.render_all();
+            }
+            wl_keyboard::Event::Key {
+                key,
+                state: WEnum::Value(wl_keyboard::KeyState::Released),
+                ..
+            } => {
+                if state.key_repeat.release(key) {
+                    state.stop_key_repeat();
+                }
+            }
+            wl_keyboard::Event::Key { .. } => {}
+            wl_keyboard::Event::RepeatInfo { rate, delay } => {
+                state.key_repeat.set_info(rate, delay);
+            }
+            _ => debug!("received an event from WlKeyboard, but don't know what to do with it..."),
+        }
+    }
+}
+
+impl Dispatch<WlDataDeviceManager, ()> for Locker {
+    fn event(
+        _: &mut Self,
+        _: &WlDataDeviceManager,
+        _: wl_data_device_manager::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Locker>,
+    ) {
+        debug!("received an event from WlDataDeviceManager, but don't know what to do with it...")
+    }
+}
+
+impl Dispatch<WlDataDevice, ()> for Locker {
+    fn event(
+        state: &mut Self,
+        _: &WlDataDevice,
+        event: wl_data_device::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Locker>,
+    ) {
+        match event {
+            // the new offer's mime types follow on the offer itself
+            wl_data_device::Event::DataOffer { .. } => {}
+            wl_data_device::Event::Selection { id } => {
+                if let Some(previous) = std::mem::replace(&mut state.selection, id) {
+                    previous.destroy();
+                }
+            }
+            // nothing can be dropped onto a lock screen
+            wl_data_device::Event::Enter {
+                id: Some(offer), ..
+            } => offer.destroy(),
+            _ => {}
+        }
+    }
+
+    event_created_child!(Locker, WlDataDevice, [
+        wl_data_device::EVT_DATA_OFFER_OPCODE => (WlDataOffer, OfferMimeTypes::default()),
+    ]);
+}
+
+impl Dispatch<WlDataOffer, OfferMimeTypes> for Locker {
+    fn event(
+        _: &mut Self,
+        _: &WlDataOffer,
+        event: wl_data_offer::Event,
+        mime_types: &OfferMimeTypes,
+        _: &Connection,
+        _: &QueueHandle<Locker>,
+    ) {
+        if let wl_data_offer::Event::Offer { mime_type } = event {
+            mime_types
+                .0
+                .lock()
+                .unwrap_or_else(|err| err.into_inner())
+                .push(mime_type);
+        }
+    }
+}
+
+#[cfg(feature = "ime")]
+impl Dispatch<ZwpTextInputManagerV3, ()> for Locker {
+    fn event(
+        _state: &mut Self,
+        _: &ZwpTextInputManagerV3,
+        _: zwp_text_input_manager_v3::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Locker>,
+    ) {
+        debug!("received an event from ZwpTextInputManagerV3, but don't know what to do with it...")
+    }
+}
+
+// Collects what the input method sends until its done event, which applies it all at once.
+// Whether it's enabled follows the focused field, see `sync_text_input`.
+#[cfg(feature = "ime")]
+impl Dispatch<ZwpTextInputV3, ()> for Locker {
+    fn event(
+        state: &mut Self,
+        _: &ZwpTextInputV3,
+        event: zwp_text_input_v3::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Locker>,
+    ) {
+        let Some(text_input) = state.text_input.as_mut() else {
+            return;
+        };
+        match event {
+            zwp_text_input_v3::Event::Enter { .. } => text_input.entered = true,
+            zwp_text_input_v3::Event::Leave { .. } => text_input.entered = false,
+            zwp_text_input_v3::Event::PreeditString { text, .. } => text_input.preedit = text,
+            zwp_text_input_v3::Event::CommitString { text } => text_input.commit = text,
+            zwp_text_input_v3::Event::Done { .. } => state.text_input_done(),
+            // no surrounding text is ever sent, so there's nothing around the caret to delete
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<WlPointer, ()> for Locker {
+    fn event(
+        state: &mut Self,
+        _: &WlPointer,
+        event: wl_pointer::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Locker>,
+    ) {
+        match event {
+            // Like the keyboard's Enter, BoxPlacement::Focused follows the output the pointer
+            // is on.
+            wl_pointer::Event::Enter {
+                surface,
+                surface_x,
+                surface_y,
+                ..
+            } => {
+                let Some(name) = state
+                    .monitors
+                    .values()
+                    .find(|monitor| monitor.surface.as_ref() == Some(&surface))
+                    .map(|monitor| monitor.name)
+                else {
+                    return;
+                };
+                state.pointer_focus = Some((name, surface_x, surface_y));
+                if state.focused_output != Some(name) {
+                    state.focused_output = Some(name);
+                    state.render_all();
+                }
+            }
+            wl_pointer::Event::Leave { .. } => state.pointer_focus = None,
+            wl_pointer::Event::Motion {
+                surface_x,
+                surface_y,
+                ..
+            } => {
+                state.wake();
+                if let Some((_, x, y)) = state.pointer_focus.as_mut() {
+                    (*x, *y) = (surface_x, surface_y);
+                }
+            }
+            wl_pointer::Event::Button {
+                button,
+                state: button_state,
+                ..
+            } => {
+                state.wake();
+                if button == BTN_LEFT
+                    && button_state == WEnum::Value(wl_pointer::ButtonState::Pressed)
+                {
+                    state.click();
+                }
+            }
+            wl_pointer::Event::Axis { .. } => state.wake(),
+            // the rest of a pointer frame carries nothing the lock screen uses
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<WlTouch, ()> for Locker {
+    fn event(
+        state: &mut Self,
+        _: &WlTouch,
+        event: wl_touch::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Locker>,
+    ) {
+        match event {
+            // only where a finger lands counts; a tap moves the box like the pointer does
+            wl_touch::Event::Down { surface, x, y, .. } => {
+                state.wake();
+                let Some(name) = state
+                    .monitors
+                    .values()
+                    .find(|monitor| monitor.surface.as_ref() == Some(&surface))
+                    .map(|monitor| monitor.name)
+                else {
+                    return;
+                };
+                if state.focused_output != Some(name) {
+                    state.focused_output = Some(name);
+                    state.render_all();
+                }
+                state.press(name, x, y);
+            }
+            wl_touch::Event::Motion { .. } => state.wake(),
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<WlOutput, OutputTag> for Locker {
+    fn event(
+        state: &mut Self,
+        _: &WlOutput,
+        event: wl_output::Event,
+        tag: &OutputTag,
+        _: &Connection,
+        qh: &QueueHandle<Locker>,
+    ) {
+        let Some(monitor) = state.monitors.get_mut(&tag.monitor_name) else {
+            return;
+        };
+
+        match event {
+            wl_output::Event::Name { name } => {
+                info!("output {} is named {name}", monitor.name);
+                monitor.output_name = Some(name);
+            }
+            wl_output::Event::Description { description } => {
+                info!("output {} is described as {description}", monitor.name);
+                monitor.description = Some(description);
+            }
+            wl_output::Event::Mode {
+                flags: WEnum::Value(flags),
+                width,
+                height,
+                refresh,
+            } if flags.contains(wl_output::Mode::Current) => {
+                monitor.mode = Some((width, height, refresh));
+            }
+            wl_output::Event::Scale { factor } => {
+                // a surface already on screen is presented again at the new scale once the
+                // output is done
+                if factor != monitor.scale
+                    && monitor.dimensions != (0, 0)
+                    && monitor.pending_configure.is_none()
+                {
+                    monitor.pending_configure = Some(monitor.dimensions);
+                }
+                monitor.scale = factor;
+            }
+            wl_output::Event::Geometry {
+                physical_width,
+                physical_height,
+                transform,
+                ..
+            } => {
+                monitor.physical_size = (physical_width, physical_height);
+                monitor.transform = transform.into_result().ok();
+            }
+            wl_output::Event::Done => {
+                let first_done = !monitor.done;
+                monitor.done = true;
+                let configured = monitor.pending_configure.is_some();
+                // an output added after locking may be the preferred one, now its name is known
+                if first_done && state.lock.is_some() {
+                    state.select_primary();
+                }
+                // a configure that arrived before the output was done
+                if configured {
+                    state.present(tag.monitor_name, qh);
+                }
+            }
+            wl_output::Event::Mode { .. } => {}
+            _ => debug!("received an event from WlOutput, but don't know what to do with it..."),
+        }
+    }
+}
+
+impl Dispatch<WlSurface, ()> for Locker {
+    fn event(
+        _state: &mut Self,
+        _: &WlSurface,
+        _: wl_surface::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Locker>,
+    ) {
+        debug!("received an event from WlSurface, but don't know what to do with it...")
+    }
+}
+
+impl Dispatch<WlShm, ()> for Locker {
+    fn event(
+        state: &mut Self,
+        _: &WlShm,
+        event: wl_shm::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Locker>,
+    ) {
+        match event {
+            wl_shm::Event::Format {
+                format: WEnum::Value(format),
+            } => {
+                state.shm_formats.insert(format);
+            }
+            // a fourcc this version of the protocol bindings doesn't know
+            wl_shm::Event::Format { .. } => {}
+            _ => debug!("received an event from WlShm, but don't know what to do with it..."),
+        }
+    }
+}
+
+impl Dispatch<WlShmPool, ()> for Locker {
+    fn event(
+        _state: &mut Self,
+        _: &WlShmPool,
+        _: wl_shm_pool::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Locker>,
+    ) {
+        debug!("received an event from WlShmPool, but don't know what to do with it...")
+    }
+}
+
+impl Dispatch<WpSinglePixelBufferManagerV1, ()> for Locker {
+    fn event(
+        _state: &mut Self,
+        _: &WpSinglePixelBufferManagerV1,
+        _: wp_single_pixel_buffer_manager_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Locker>,
+    ) {
+        debug!(
+            "received an event from WpSinglePixelBufferManager, but don't know what to do with it..."
+        )
+    }
+}
+
+impl Dispatch<WpViewporter, ()> for Locker {
+    fn event(
+        _state: &mut Self,
+        _: &WpViewporter,
+        _: wp_viewporter::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Locker>,
+    ) {
+        debug!("received an event from WpViewporter, but don't know what to do with it...")
+    }
+}
+
+impl Dispatch<XdgSystemBellV1, ()> for Locker {
+    fn event(
+        _state: &mut Self,
+        _: &XdgSystemBellV1,
+        _: xdg_system_bell_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Locker>,
+    ) {
+        debug!("received an event from XdgSystemBellV1, but don't know what to do with it...")
+    }
+}
+
+impl Dispatch<WpFractionalScaleManagerV1, ()> for Locker {
+    fn event(
+        _state: &mut Self,
+        _: &WpFractionalScaleManagerV1,
+        _: wp_fractional_scale_manager_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Locker>,
+    ) {
+        debug!(
+            "received an event from WpFractionalScaleManagerV1, but don't know what to do with it..."
+        )
+    }
+}
+
+impl Dispatch<WpFractionalScaleV1, OutputTag> for Locker {
+    fn event(
+        state: &mut Self,
+        _: &WpFractionalScaleV1,
+        event: wp_fractional_scale_v1::Event,
+        tag: &OutputTag,
+        _: &Connection,
+        qh: &QueueHandle<Locker>,
+    ) {
+        let wp_fractional_scale_v1::Event::PreferredScale { scale } = event else {
+            return;
+        };
+        let Some(monitor) = state.monitors.get_mut(&tag.monitor_name) else {
+            return;
+        };
+        if monitor.preferred_scale == Some(scale) {
+            return;
+        }
+        info!(
+            "{} prefers a scale of {}",
+            monitor.label(),
+            scale as f64 / 120.0
+        );
+        monitor.preferred_scale = Some(scale);
+        // before the first configure there's nothing to resize yet
+        if monitor.dimensions != (0, 0) {
+            monitor.pending_configure = Some(monitor.dimensions);
+            state.present(tag.monitor_name, qh);
+        }
+    }
+}
+
+impl Dispatch<WpViewport, ()> for Locker {
+    fn event(
+        _state: &mut Self,
+        _: &WpViewport,
+        _: wp_viewport::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Locker>,
+    ) {
+        debug!("received an event from WpViewport, but don't know what to do with it...")
+    }
+}
+
+// Single pixel buffers are never written to, so there is nothing to track on release.
+impl Dispatch<WlBuffer, ()> for Locker {
+    fn event(
+        _state: &mut Self,
+        _: &WlBuffer,
+        _: wl_buffer::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Locker>,
+    ) {
+    }
+}
+
+impl Dispatch<WlCallback, FrameTag> for Locker {
+    fn event(
+        state: &mut Self,
+        _: &WlCallback,
+        event: wl_callback::Event,
+        tag: &FrameTag,
+        _: &Connection,
+        _: &QueueHandle<Locker>,
+    ) {
+        let wl_callback::Event::Done { .. } = event else {
+            return;
+        };
+        if let Some(monitor) = state.monitors.get_mut(&tag.monitor_name) {
+            monitor.frame_done();
+        }
+    }
+}
+
+impl Dispatch<WlBuffer, BufferTag> for Locker {
+    fn event(
+        state: &mut Self,
+        _: &WlBuffer,
+        event: wl_buffer::Event,
+        tag: &BufferTag,
+        _: &Connection,
+        _: &QueueHandle<Locker>,
+    ) {
+        match event {
+            wl_buffer::Event::Release => {
+                debug!("received a Release event for WlBuffer");
+                let Some(monitor) = state.monitors.get_mut(&tag.monitor_name) else {
+                    return;
+                };
+                if let Some(buffer_state) = monitor.buffer_state.as_mut() {
+                    buffer_state.release(tag.index);
+                }
+            }
+            _ => debug!("received an event from WlBuffer, but don't know what to do with it..."),
+        };
+    }
+}
+
+impl Dispatch<ExtSessionLockSurfaceV1, SurfaceTag> for Locker {
+    fn event(
+        state: &mut Self,
+        proxy: &ExtSessionLockSurfaceV1,
+        event: ext_session_lock_surface_v1::Event,
+        tag: &SurfaceTag,
+        _: &Connection,
+        qh: &QueueHandle<Locker>,
+    ) {
+        match event {
+            ext_session_lock_surface_v1::Event::Configure {
+                width,
+                height,
+                serial,
+            } => {
+                if !state.configure_surface(tag.monitor_name, width, height) {
+                    return;
+                }
+                proxy.ack_configure(serial);
+                state.present(tag.monitor_name, qh);
+            }
+            _ => debug!("unknown event rx'd in extsessionlocksurfacev1 dispatch handler"),
+        }
+    }
+}
@@ -0,0 +1,4290 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::{ErrorKind, Read},
+    os::fd::{AsFd, AsRawFd, FromRawFd, OwnedFd},
+    path::PathBuf,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use calloop::{
+    Interest, LoopHandle, Mode, PostAction, RegistrationToken,
+    generic::Generic,
+    ping::{PingSource, make_ping},
+    timer::{TimeoutAction, Timer},
+};
+use memfd::{Memfd, MemfdOptions};
+use mmap::{MapOption, MemoryMap};
+use wayland_client::{
+    Connection, Proxy, QueueHandle,
+    protocol::{
+        wl_buffer::WlBuffer,
+        wl_compositor::WlCompositor,
+        wl_data_device::WlDataDevice,
+        wl_data_device_manager::WlDataDeviceManager,
+        wl_data_offer::WlDataOffer,
+        wl_keyboard::WlKeyboard,
+        wl_output::{self, WlOutput},
+        wl_pointer::WlPointer,
+        wl_seat::WlSeat,
+        wl_shm::{self, WlShm},
+        wl_shm_pool::WlShmPool,
+        wl_surface::WlSurface,
+        wl_touch::WlTouch,
+    },
+};
+
+#[cfg(feature = "ime")]
+use wayland_protocols::wp::text_input::zv3::client::{
+    zwp_text_input_manager_v3::ZwpTextInputManagerV3,
+    zwp_text_input_v3::{self, ZwpTextInputV3},
+};
+use wayland_protocols::{
+    ext::session_lock::v1::client::{
+        ext_session_lock_manager_v1::ExtSessionLockManagerV1,
+        ext_session_lock_surface_v1::ExtSessionLockSurfaceV1,
+        ext_session_lock_v1::ExtSessionLockV1,
+    },
+    wp::{
+        fractional_scale::v1::client::{
+            wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1,
+            wp_fractional_scale_v1::WpFractionalScaleV1,
+        },
+        single_pixel_buffer::v1::client::wp_single_pixel_buffer_manager_v1::WpSinglePixelBufferManagerV1,
+        viewporter::client::{wp_viewport::WpViewport, wp_viewporter::WpViewporter},
+    },
+    xdg::system_bell::v1::client::xdg_system_bell_v1::XdgSystemBellV1,
+};
+
+use anyhow::anyhow;
+use ratatui::{buffer::Buffer, layout::Rect, style::Color};
+use tracing::{debug, error, info, warn};
+use zeroize::Zeroizing;
+
+use crate::{
+    auth::{self, Pam},
+    config::Config,
+    controller::{self, Controller, Policy},
+    env::Vars,
+    hooks, input, last_user,
+    lock::{Action, LockMachine, LockState},
+    media::NowPlaying,
+    render::{
+        LayerSlot, LayerStack, MAX_DAMAGE_RECTS, PixelBuffer, Rasterizer, SolidColor,
+        cells_to_pixels, changed_cells, content_hash, parse_rgb,
+    },
+    tui::{
+        self, AppState, BackgroundKind, Fire, FocusTarget, KeyInput, LockReminder, Lockout,
+        PalettePreset, PasswordDisplay, Theme,
+    },
+};
+
+mod dispatch;
+
+/// This struct represents the state of our app.
+/// This type supports the `dispatch` implementations needed for the below state diagram
+///
+/// Session lock protocol message flow (client perspective).
+///
+/// CLIENT                                 COMPOSITOR
+///   |                                         |
+///   | wl_registry.bind ext_session_lock_manager_v1
+///   |---------------------------------------->|
+///   |                                         |
+///   | ext_session_lock_manager_v1.lock
+///   |---------------------------------------->|
+///   |                                         |
+///   |           (either)                      |
+///   |<------------------ ext_session_lock_v1.locked
+///   |                                         |
+///   |  create wl_surface + per-output:
+///   |  ext_session_lock_v1.get_lock_surface
+///   |---------------------------------------->|
+///   |                                         |
+///   |<---------------- ext_session_lock_surface_v1.configure
+///   |  (width,height,serial)                  |
+///   |                                         |
+///   |  attach buffer + wl_surface.commit      |
+///   |---------------------------------------->|
+///   |                                         |
+///   | (repeat configure/commit as needed)     |
+///   |                                         |
+///   |  ... user authenticates ...             |
+///   |  ext_session_lock_v1.unlock_and_destroy |
+///   |---------------------------------------->|
+///   |                                         |
+///   | (client destroys lock surfaces)         |
+///   |                                         |
+///   |<------------------ ext_session_lock_v1.finished (optional)
+///   |                                         |
+///   | (or, if lock was denied)                |
+///   |<------------------ ext_session_lock_v1.finished
+///   |  (no locked was sent)                   |
+///   |                                         |
+pub struct Locker {
+    lock_manager: Option<ExtSessionLockManagerV1>,
+    lock: Option<ExtSessionLockV1>,
+    compositor: Option<WlCompositor>,
+    shared_memory: Option<WlShm>,
+    // every pixel format wl_shm advertised, and the one buffers are allocated in
+    shm_formats: HashSet<wl_shm::Format>,
+    shm_format: wl_shm::Format,
+    // optional: together these let solid-color surfaces use a 1x1 buffer scaled to fit
+    single_pixel_buffer_manager: Option<WpSinglePixelBufferManagerV1>,
+    viewporter: Option<WpViewporter>,
+    // optional: lets outputs with a fractional scale get buffers at their exact pixel size
+    fractional_scale_manager: Option<WpFractionalScaleManagerV1>,
+    // optional: rung after a failed attempt, when configured to
+    system_bell: Option<XdgSystemBellV1>,
+    seat: Option<WlSeat>,
+    keyboard: Option<WlKeyboard>,
+    // wakes the animation, and clicks move focus between the fields
+    pointer: Option<WlPointer>,
+    // the monitor under the pointer and the pointer's surface-local position on it
+    pointer_focus: Option<(u32, f64, f64)>,
+    // taps press pin pad keys and focus fields, like clicks
+    touch: Option<WlTouch>,
+    // the clipboard, for pasting into the focused field
+    data_device_manager: Option<WlDataDeviceManager>,
+    data_device: Option<WlDataDevice>,
+    selection: Option<WlDataOffer>,
+    // the compositor's input method, composing text into the focused field
+    #[cfg(feature = "ime")]
+    text_input_manager: Option<ZwpTextInputManagerV3>,
+    #[cfg(feature = "ime")]
+    text_input: Option<TextInput>,
+    // built from the keymap the compositor sends for the keyboard
+    xkb: Option<input::Keyboard>,
+    // the held key, and the timer repeating it while one is
+    key_repeat: input::KeyRepeat,
+    repeat_timer: Option<RegistrationToken>,
+    monitors: HashMap<u32, Monitor>,
+    // every global the registry advertised, as (name, interface, version)
+    globals: Vec<(u32, String, u32)>,
+    machine: LockMachine,
+    // how long the compositor gets to answer the lock request, and when that runs out
+    locked_timeout: Duration,
+    waiting_deadline: Option<Instant>,
+    // schedules the frame timer and, in development builds, the unlock timer
+    loop_handle: Option<LoopHandle<'static, Locker>>,
+    // whether the frame timer is running; it stops while the UI is idle
+    animating: bool,
+    // READY=1 has been sent to the service manager
+    #[cfg(feature = "systemd")]
+    notified_ready: bool,
+    #[cfg(feature = "dev-autounlock")]
+    auto_unlock_after: Duration,
+    // composited in order into every monitor's shm buffers: the background color on its own
+    // where there's nothing else to show, otherwise the rasterized UI and anything over it
+    layers: LayerStack,
+    rasterizer: Rasterizer,
+    // copies of `rasterizer` for scaled outputs, by scale in 120ths, see `rasterizer_at`
+    scaled_rasterizers: HashMap<u32, Rasterizer>,
+    // the UI state every monitor renders from
+    app: AppState,
+    // authenticates what the user submits
+    controller: Controller,
+    // pinged by the authentication thread, until it's inserted into the event loop
+    auth_wakeup: Option<PingSource>,
+    // output name or description the login box should be pinned to
+    preferred_output: Option<String>,
+    box_placement: BoxPlacement,
+    // the output that last received keyboard or pointer focus
+    focused_output: Option<u32>,
+    // consecutive busy commits tolerated before a monitor is considered stuck, 0 to never give up
+    commit_retry_budget: u32,
+    // shm buffers allocated for every monitor
+    buffer_count: usize,
+    // shell commands spawned once the session is locked, and after a successful unlock
+    on_lock: Option<String>,
+    on_unlock: Option<String>,
+    // where whoever unlocks is remembered for next time
+    last_user_file: Option<PathBuf>,
+    first_frame: FirstFrame,
+    placeholder_color: u32,
+    // kept so that dropping the locker can still flush its last requests
+    connection: Connection,
+}
+
+/// Checks a configured number of shm buffers per output: 2 to double buffer, or 3 so a frame
+/// can still be drawn while the compositor holds one buffer for scanout and another queued.
+pub fn check_buffer_count(count: usize) -> anyhow::Result<usize> {
+    match count {
+        2 | 3 => Ok(count),
+        _ => Err(anyhow!("unsupported buffer count {count}, expected 2 or 3")),
+    }
+}
+
+/// Everything the locker is configured with, gathered before any Wayland objects exist.
+pub struct LockConfig {
+    pub preferred_output: Option<String>,
+    pub box_placement: BoxPlacement,
+    pub commit_retry_budget: u32,
+    // shm buffers per output, see `check_buffer_count`
+    pub buffer_count: usize,
+    pub locked_timeout: Duration,
+    pub theme: Theme,
+    pub password_display: PasswordDisplay,
+    pub logo: Option<String>,
+    pub background: BackgroundKind,
+    pub fire: Fire,
+    // draws a static gradient instead of animating at all
+    pub reduced_motion: bool,
+    pub lock_reminder: LockReminder,
+    // taps and clicks on its keys type into the focused field
+    pub pin_pad: bool,
+    // an unlock button on the box's bottom border
+    pub submit_button: bool,
+    // a power off button opposite it, pressed through logind
+    pub power_button: bool,
+    // pick the username from `allowed_users` instead of typing it
+    pub user_list: bool,
+    // after a failed attempt, flash the box border red and ring the compositor's bell
+    pub failure_flash: bool,
+    pub failure_bell: bool,
+    // where the last user to unlock is remembered, None to remember nobody
+    pub last_user_file: Option<PathBuf>,
+    // a bold face of the bundled font, for bold labels; without one, bold is synthesized
+    pub bold_font: Option<Vec<u8>>,
+    pub palette: Option<Vec<Color>>,
+    // None uses the bundled font
+    pub font: Option<Vec<u8>>,
+    pub font_size: f32,
+    // shown on every surface until the rasterized UI is ready
+    pub placeholder_color: u32,
+    pub first_frame: FirstFrame,
+    pub on_lock: Option<String>,
+    pub on_unlock: Option<String>,
+    pub pam_service: String,
+    // None lets every user PAM accepts unlock
+    pub allowed_users: Option<Vec<String>>,
+    pub min_failure_duration: Duration,
+    pub lockout: Lockout,
+    // zero keeps the password required from the moment the session locks
+    pub grace_period: Duration,
+    // None never stops animating
+    pub idle_after: Option<Duration>,
+    // tries at reaching the compositor's socket before giving up, at least 1
+    pub connect_attempts: u32,
+    pub connect_retry_delay: Duration,
+    #[cfg(feature = "dev-autounlock")]
+    pub auto_unlock_after: Duration,
+}
+
+impl Default for LockConfig {
+    fn default() -> Self {
+        Self {
+            preferred_output: None,
+            box_placement: BoxPlacement::default(),
+            commit_retry_budget: DEFAULT_COMMIT_RETRY_BUDGET,
+            buffer_count: DEFAULT_BUFFER_COUNT,
+            locked_timeout: DEFAULT_LOCKED_TIMEOUT,
+            theme: Theme::default(),
+            password_display: PasswordDisplay::default(),
+            logo: None,
+            background: BackgroundKind::default(),
+            fire: Fire::default(),
+            reduced_motion: false,
+            lock_reminder: LockReminder::default(),
+            pin_pad: false,
+            submit_button: false,
+            power_button: false,
+            user_list: false,
+            failure_flash: true,
+            failure_bell: false,
+            last_user_file: None,
+            bold_font: None,
+            palette: None,
+            font: None,
+            font_size: FONT_SIZE_PX,
+            placeholder_color: DEFAULT_PLACEHOLDER_COLOR,
+            first_frame: FirstFrame::default(),
+            on_lock: None,
+            on_unlock: None,
+            pam_service: auth::DEFAULT_SERVICE.to_string(),
+            allowed_users: None,
+            min_failure_duration: controller::DEFAULT_MIN_FAILURE_DURATION,
+            lockout: Lockout::default(),
+            grace_period: Duration::ZERO,
+            idle_after: Some(DEFAULT_IDLE_AFTER),
+            connect_attempts: DEFAULT_CONNECT_ATTEMPTS,
+            connect_retry_delay: DEFAULT_CONNECT_RETRY_DELAY,
+            #[cfg(feature = "dev-autounlock")]
+            auto_unlock_after: DEV_AUTOUNLOCK_AFTER,
+        }
+    }
+}
+
+impl LockConfig {
+    /// The defaults with whatever the config file overrides.
+    pub fn from_file(file: &Config) -> anyhow::Result<Self> {
+        let mut config = Self::default();
+        file.apply_theme(&mut config.theme)?;
+        if let Some(color) = file.background_color()? {
+            config.placeholder_color = color;
+        }
+        if let Some(background) = file.background_kind()? {
+            config.background = background;
+        }
+        config.palette = file.palette()?;
+        config.font = file.font_bytes()?;
+        if let Some(size) = file.font_size()? {
+            config.font_size = size;
+        }
+        if let Some(timeout) = file.locked_timeout() {
+            config.locked_timeout = timeout;
+        }
+        if let Some(grace_period) = file.grace_period() {
+            config.grace_period = grace_period;
+        }
+        file.apply_fire(&mut config.fire)?;
+        file.apply_lockout(&mut config.lockout);
+        if let Some(lock_reminder) = file.lock_reminder() {
+            config.lock_reminder = lock_reminder;
+        }
+        config.reduced_motion = file
+            .reduced_motion
+            .unwrap_or_else(tui::desktop_prefers_reduced_motion);
+        if let Some(pin_pad) = file.pin_pad {
+            config.pin_pad = pin_pad;
+        }
+        if let Some(submit_button) = file.submit_button {
+            config.submit_button = submit_button;
+        }
+        if let Some(power_button) = file.power_button {
+            config.power_button = power_button;
+        }
+        if let Some(user_list) = file.user_list {
+            config.user_list = user_list;
+        }
+        if let Some(flash) = file.failure_flash {
+            config.failure_flash = flash;
+        }
+        if let Some(bell) = file.failure_bell {
+            config.failure_bell = bell;
+        }
+        if file.remember_user == Some(true) {
+            config.last_user_file = last_user::default_path();
+        }
+        if let Some(mode) = file.password_display()? {
+            config.password_display = mode;
+        }
+        if let Some(logo) = file.logo()? {
+            config.logo = Some(logo);
+        }
+        if let Some(bold_font) = file.bold_font_bytes()? {
+            config.bold_font = Some(bold_font);
+        }
+        if let Some(placement) = &file.box_on {
+            config.box_placement = placement.parse()?;
+        }
+        if let Some(output) = &file.output {
+            config.preferred_output = Some(output.clone());
+        }
+        if let Some(policy) = &file.first_frame {
+            config.first_frame = policy.parse()?;
+        }
+        if let Some(budget) = file.commit_retry_budget {
+            config.commit_retry_budget = budget;
+        }
+        if let Some(count) = file.buffer_count {
+            config.buffer_count = check_buffer_count(count)?;
+        }
+        if let Some(users) = &file.allowed_users {
+            config.allowed_users = Some(users.clone());
+        }
+        if let Some(duration) = file.min_failure_duration() {
+            config.min_failure_duration = duration;
+        }
+        if let Some(idle_after) = file.idle_after() {
+            config.idle_after = (!idle_after.is_zero()).then_some(idle_after);
+        }
+        if let Some(attempts) = file.connect_attempts {
+            config.connect_attempts = attempts.max(1);
+        }
+        if let Some(delay) = file.connect_retry_delay() {
+            config.connect_retry_delay = delay;
+        }
+        #[cfg(feature = "dev-autounlock")]
+        if let Some(after) = file.auto_unlock_after() {
+            config.auto_unlock_after = after;
+        }
+        Ok(config)
+    }
+
+    /// Overrides whatever the `LILAC_*` variables in `vars` set. Both the locker and the
+    /// terminal demo layer the environment over the config file this way.
+    pub fn apply_env(&mut self, vars: &Vars) -> anyhow::Result<()> {
+        if let Some(color) = vars.var("LILAC_PLACEHOLDER_COLOR") {
+            self.placeholder_color = parse_rgb(&color)
+                .ok_or_else(|| anyhow!("invalid placeholder color {color:?}, expected RRGGBB"))?;
+        }
+        if let Some(policy) = vars.var("LILAC_FIRST_FRAME") {
+            self.first_frame = policy.parse()?;
+        }
+        if let Some(grace_period) = vars.secs("LILAC_GRACE_PERIOD_SECS")? {
+            self.grace_period = grace_period;
+        }
+        if let Some(border) = vars.var("LILAC_BORDER") {
+            self.theme.border = border.parse().map_err(anyhow::Error::msg)?;
+        }
+        if let Some(mode) = vars.var("LILAC_PASSWORD_DISPLAY") {
+            self.password_display = mode.parse().map_err(anyhow::Error::msg)?;
+        }
+        // inline art wins over a file
+        if let Some(logo) = vars.var("LILAC_LOGO") {
+            self.logo = Some(logo);
+        } else if let Some(path) = vars.var_os("LILAC_LOGO_FILE") {
+            self.logo = Some(
+                std::fs::read_to_string(&path)
+                    .map_err(|err| anyhow!("failed to read LILAC_LOGO_FILE: {err}"))?,
+            );
+        }
+        if let Some(path) = vars.var_os("LILAC_BOLD_FONT") {
+            self.bold_font = Some(
+                std::fs::read(&path)
+                    .map_err(|err| anyhow!("failed to read LILAC_BOLD_FONT: {err}"))?,
+            );
+        }
+        if let Some(name) = vars.var("LILAC_BACKGROUND") {
+            self.background = name.parse().map_err(anyhow::Error::msg)?;
+        }
+        if let Some(wind) = vars.parse("LILAC_FIRE_WIND", "an integer")? {
+            self.fire.wind = wind;
+        }
+        if let Some(spread) = vars.parse("LILAC_FIRE_SPREAD", "a number of cells")? {
+            self.fire.spread = spread;
+        }
+        if let Some(direction) = vars.var("LILAC_FIRE_DIRECTION") {
+            self.fire.direction = direction.parse().map_err(anyhow::Error::msg)?;
+        }
+        if let Some(intensity) = vars.var("LILAC_FIRE_INTENSITY") {
+            self.fire.intensity = tui::parse_intensity(&intensity).map_err(anyhow::Error::msg)?;
+        }
+        if let Some(name) = vars.var("LILAC_FIRE_PALETTE") {
+            let preset: PalettePreset = name.parse().map_err(anyhow::Error::msg)?;
+            self.palette = Some(preset.colors());
+        }
+        // zero never goes idle
+        if let Some(secs) = vars.secs("LILAC_IDLE_SECS")? {
+            self.idle_after = (!secs.is_zero()).then_some(secs);
+        }
+        if let Some(threshold) = vars.parse("LILAC_LOCKOUT_THRESHOLD", "a count")? {
+            self.lockout.threshold = threshold;
+        }
+        if let Some(max_delay) = vars.secs("LILAC_LOCKOUT_MAX_SECS")? {
+            self.lockout.max_delay = max_delay;
+        }
+        if let Some(output) = vars.var("LILAC_OUTPUT") {
+            self.preferred_output = Some(output);
+        }
+        if let Some(budget) = vars.parse("LILAC_COMMIT_RETRY_BUDGET", "a count")? {
+            self.commit_retry_budget = budget;
+        }
+        if let Some(count) = vars.parse("LILAC_BUFFER_COUNT", "2 or 3")? {
+            self.buffer_count = check_buffer_count(count)?;
+        }
+        if let Some(timeout) = vars.millis("LILAC_LOCKED_TIMEOUT_MS")? {
+            self.locked_timeout = timeout;
+        }
+        if let Some(command) = vars.var("LILAC_ON_LOCK") {
+            self.on_lock = Some(command);
+        }
+        if let Some(command) = vars.var("LILAC_ON_UNLOCK") {
+            self.on_unlock = Some(command);
+        }
+        if let Some(reduce) = vars.flag("LILAC_REDUCED_MOTION")? {
+            self.reduced_motion = reduce;
+        }
+        if let Some(pin_pad) = vars.flag("LILAC_PIN_PAD")? {
+            self.pin_pad = pin_pad;
+        }
+        if let Some(submit_button) = vars.flag("LILAC_SUBMIT_BUTTON")? {
+            self.submit_button = submit_button;
+        }
+        if let Some(power_button) = vars.flag("LILAC_POWER_BUTTON")? {
+            self.power_button = power_button;
+        }
+        if let Some(user_list) = vars.flag("LILAC_USER_LIST")? {
+            self.user_list = user_list;
+        }
+        if let Some(flash) = vars.flag("LILAC_FAILURE_FLASH")? {
+            self.failure_flash = flash;
+        }
+        if let Some(bell) = vars.flag("LILAC_FAILURE_BELL")? {
+            self.failure_bell = bell;
+        }
+        if let Some(remember) = vars.flag("LILAC_REMEMBER_USER")? {
+            self.last_user_file = remember.then(last_user::default_path).flatten();
+        }
+        if let Some(service) = vars.var("LILAC_PAM_SERVICE") {
+            self.pam_service = service;
+        }
+        if let Some(users) = vars.var("LILAC_ALLOWED_USERS") {
+            self.allowed_users = Some(controller::parse_user_list(&users));
+        }
+        if let Some(duration) = vars.millis("LILAC_MIN_FAILURE_MS")? {
+            self.min_failure_duration = duration;
+        }
+        // connecting is always tried at least once
+        if let Some(attempts) = vars.parse::<u32>("LILAC_CONNECT_ATTEMPTS", "a count")? {
+            self.connect_attempts = attempts.max(1);
+        }
+        if let Some(delay) = vars.millis("LILAC_CONNECT_RETRY_MS")? {
+            self.connect_retry_delay = delay;
+        }
+        Ok(())
+    }
+
+    /// The rasterizer for the configured font, or one drawing text as blocks if it won't load.
+    pub fn rasterizer(&self) -> Rasterizer {
+        let font = self.font.clone().unwrap_or_else(|| FONT.to_vec());
+        let mut rasterizer = match Rasterizer::with_font(font, self.font_size) {
+            Ok(rasterizer) => rasterizer,
+            Err(err) => {
+                warn!("failed to load the font, drawing text as blocks: {err}");
+                Rasterizer::default()
+            }
+        };
+        if let Some(bytes) = self.bold_font.clone()
+            && let Err(err) = rasterizer.set_bold_font(bytes, self.font_size)
+        {
+            warn!("failed to load the bold font, emboldening the regular one: {err}");
+        }
+        rasterizer
+    }
+
+    /// The UI state a lock starts from, with the last user to unlock already filled in when
+    /// they're remembered. With a user list, the first user is picked otherwise.
+    pub fn app_state(&self) -> AppState {
+        let mut app = AppState {
+            theme: self.theme.clone(),
+            password_mode: self.password_display,
+            logo: self.logo.clone(),
+            background: self.background.create(self.fire.clone()),
+            palette: self.palette.clone(),
+            lockout: self.lockout.clone(),
+            reduced_motion: self.reduced_motion,
+            lock_reminder: self.lock_reminder.clone(),
+            pin_pad: self.pin_pad,
+            submit_button: self.submit_button,
+            power_button: self.power_button,
+            user_list: match &self.allowed_users {
+                Some(users) if self.user_list => users.clone(),
+                _ => Vec::new(),
+            },
+            flash_on_failure: self.failure_flash,
+            bell_on_failure: self.failure_bell,
+            grace_period: self.grace_period,
+            idle_after: self.idle_after,
+            // until the compositor confirms the lock, keys may be typed into whatever was
+            // focused before, so none of them go into the fields
+            ignore_input: true,
+            ..AppState::default()
+        };
+        let remembered = self.last_user_file.as_deref().and_then(last_user::load);
+        if let Some(username) =
+            remembered.filter(|user| app.user_list.is_empty() || app.user_list.contains(user))
+        {
+            app.username = username;
+            app.focused = FocusTarget::Password;
+        } else if let Some(first) = app.user_list.first() {
+            app.username = first.clone();
+            app.focused = FocusTarget::UserList;
+        }
+        app
+    }
+}
+
+impl Locker {
+    /// The Wayland proxies stay None until the registry advertises them.
+    pub fn new(config: LockConfig, connection: Connection) -> Self {
+        let rasterizer = config.rasterizer();
+        let app = config.app_state();
+        let mut layers = LayerStack::default();
+        layers.push(
+            LayerSlot::Background,
+            Box::new(SolidColor::new(config.placeholder_color)),
+        );
+        let mut policy = Policy::new(Pam::new(config.pam_service))
+            .with_min_failure_duration(config.min_failure_duration);
+        if let Some(users) = config.allowed_users {
+            policy = policy.with_allowed_users(users);
+        }
+        let (controller, auth_wakeup) = match make_ping() {
+            Ok((ping, source)) => (
+                Controller::spawn_with_waker(policy, move || ping.ping()),
+                Some(source),
+            ),
+            Err(err) => {
+                warn!(
+                    "failed to create the authentication wakeup, outcomes wait for a frame: {err}"
+                );
+                (Controller::spawn(policy), None)
+            }
+        };
+
+        Self {
+            lock_manager: None,
+            lock: None,
+            compositor: None,
+            shared_memory: None,
+            shm_formats: HashSet::new(),
+            shm_format: wl_shm::Format::Argb8888,
+            single_pixel_buffer_manager: None,
+            system_bell: None,
+            viewporter: None,
+            fractional_scale_manager: None,
+            seat: None,
+            keyboard: None,
+            pointer: None,
+            pointer_focus: None,
+            touch: None,
+            data_device_manager: None,
+            data_device: None,
+            selection: None,
+            #[cfg(feature = "ime")]
+            text_input_manager: None,
+            #[cfg(feature = "ime")]
+            text_input: None,
+            xkb: None,
+            key_repeat: input::KeyRepeat::default(),
+            repeat_timer: None,
+            monitors: HashMap::new(),
+            globals: Vec::new(),
+            machine: LockMachine::default(),
+            locked_timeout: config.locked_timeout,
+            waiting_deadline: None,
+            loop_handle: None,
+            animating: false,
+            #[cfg(feature = "systemd")]
+            notified_ready: false,
+            #[cfg(feature = "dev-autounlock")]
+            auto_unlock_after: config.auto_unlock_after,
+            layers,
+            rasterizer,
+            scaled_rasterizers: HashMap::new(),
+            app,
+            controller,
+            auth_wakeup,
+            preferred_output: config.preferred_output,
+            box_placement: config.box_placement,
+            focused_output: None,
+            commit_retry_budget: config.commit_retry_budget,
+            buffer_count: config.buffer_count,
+            on_lock: config.on_lock,
+            on_unlock: config.on_unlock,
+            last_user_file: config.last_user_file,
+            first_frame: config.first_frame,
+            placeholder_color: config.placeholder_color,
+            connection,
+        }
+    }
+
+    /// Shows what a media player reports as playing under the box, or nothing once it stops.
+    /// Drawn straight away, so the line is right even while the animation is paused.
+    pub fn now_playing(&mut self, now_playing: Option<NowPlaying>) {
+        if self.app.now_playing == now_playing {
+            return;
+        }
+        match &now_playing {
+            Some(playing) => debug!("now playing {}", playing.line()),
+            None => debug!("nothing is playing"),
+        }
+        self.app.now_playing = now_playing;
+        self.render_all();
+    }
+
+    // Tells systemd the lock is up, once the compositor confirmed it and every output has
+    // shown a locked frame. Until then the unlocked session may still be visible somewhere.
+    #[cfg(feature = "systemd")]
+    fn notify_ready(&mut self) {
+        if self.notified_ready
+            || self.machine.state() != LockState::Locked
+            || !self.outputs_without_frame().is_empty()
+        {
+            return;
+        }
+        self.notified_ready = true;
+        if let Err(err) = crate::notify::ready() {
+            warn!("failed to notify systemd of readiness: {err}");
+        }
+    }
+
+    /// Follows logind: Unlock ends a confirmed lock the way a successful authentication would.
+    /// logind only sends it when root or the session's owner asks, so it grants nothing that
+    /// killing lilac wouldn't. A Lock while already locking has nothing left to do.
+    #[cfg(feature = "dbus")]
+    pub fn session_signal(&mut self, signal: crate::logind::SessionSignal) {
+        use crate::logind::SessionSignal;
+
+        match signal {
+            SessionSignal::Lock => info!("logind asked to lock, already locking"),
+            SessionSignal::Unlock if self.machine.can_unlock() => {
+                info!("logind asked to unlock the session");
+                let conn = self.connection.clone();
+                if let Err(err) = self.unlock(&conn) {
+                    warn!("failed to unlock: {err}");
+                }
+            }
+            SessionSignal::Unlock => {
+                info!("logind asked to unlock before the lock was confirmed, ignoring it");
+            }
+        }
+    }
+
+    // Runs the on_lock hook, describing the locked monitors in LILAC_MONITORS.
+    fn run_on_lock_hook(&self) {
+        let Some(command) = self.on_lock.as_deref() else {
+            return;
+        };
+
+        let mut names: Vec<_> = self.monitors.keys().copied().collect();
+        names.sort_unstable();
+        let monitors = names
+            .iter()
+            .map(|name| self.monitors[name].label())
+            .collect::<Vec<_>>()
+            .join(",");
+        if let Err(err) = hooks::spawn(command, &[("LILAC_MONITORS", monitors)]) {
+            warn!("failed to spawn the on_lock hook: {err}");
+        }
+    }
+
+    // Ends the lock and, once the request is on the wire, runs the on_unlock hook.
+    fn unlock(&mut self, conn: &Connection) -> anyhow::Result<()> {
+        let Action::UnlockAndDestroy = self.machine.request_unlock() else {
+            return Ok(());
+        };
+        self.end_lock(Action::UnlockAndDestroy);
+        conn.flush()?;
+
+        if let Some(command) = self.on_unlock.as_deref() {
+            let env = [("LILAC_USER", self.app.username.clone())];
+            if let Err(err) = hooks::spawn(command, &env) {
+                warn!("failed to spawn the on_unlock hook: {err}");
+            }
+        }
+        Ok(())
+    }
+
+    // Tears everything down after the compositor ended the lock with `finished`: every lock
+    // surface and its buffers, then the lock itself. The protocol only allows `destroy` while
+    // locked was never received; once it was, the lock has to go through
+    // `unlock_and_destroy` even though nobody authenticated, as the compositor has already
+    // given up on it.
+    fn cleanup(&mut self) {
+        let action = self.machine.on_finished();
+        self.app.ignore_input = true;
+        for monitor in self.monitors.values_mut() {
+            monitor.destroy_surface();
+        }
+        self.end_lock(action);
+        if let Err(err) = self.connection.flush() {
+            warn!("failed to flush the teardown: {err}");
+        }
+    }
+
+    // Sends the request the lock machine chose to end the lock object with, if we still hold
+    // it. `Action::None` drops the proxy without a request.
+    fn end_lock(&mut self, action: Action) {
+        let Some(lock) = self.lock.take() else {
+            return;
+        };
+        match action {
+            Action::UnlockAndDestroy => lock.unlock_and_destroy(),
+            Action::Destroy => lock.destroy(),
+            Action::None => {}
+        }
+    }
+
+    // Fails once the compositor has had `locked_timeout` to answer the lock request and hasn't.
+    fn check_waiting_deadline(&self, now: Instant) -> anyhow::Result<()> {
+        if self.machine.state() != LockState::Waiting
+            || self.waiting_deadline.is_none_or(|deadline| now < deadline)
+        {
+            return Ok(());
+        }
+        Err(anyhow!(
+            "the compositor did not answer the lock request within {:?}",
+            self.locked_timeout
+        ))
+    }
+
+    /// Shuts down on SIGTERM or SIGINT. A confirmed lock is never given up without
+    /// authentication: the lock object is abandoned without a request, and the compositor keeps
+    /// the session locked once we're gone. Development builds unlock instead.
+    pub fn terminate(&mut self) {
+        if !self.machine.can_unlock() {
+            info!("terminated before the session was locked");
+            self.cleanup();
+            return;
+        }
+
+        #[cfg(feature = "dev-autounlock")]
+        {
+            info!("dev-autounlock: terminated, unlocking");
+            let conn = self.connection.clone();
+            if let Err(err) = self.unlock(&conn) {
+                warn!("failed to unlock: {err}");
+            }
+        }
+
+        if self.machine.abandon() == Action::None && self.lock.take().is_some() {
+            info!("terminated while locked, leaving the session locked");
+        }
+        if let Some(keyboard) = self.keyboard.take() {
+            keyboard.release();
+        }
+        if let Some(pointer) = self.pointer.take() {
+            pointer.release();
+        }
+        if let Some(touch) = self.touch.take() {
+            touch.release();
+        }
+        if let Some(offer) = self.selection.take() {
+            offer.destroy();
+        }
+        #[cfg(feature = "ime")]
+        if let Some(text_input) = self.text_input.take() {
+            text_input.proxy.destroy();
+        }
+        // release only exists from version 2 on
+        if let Some(data_device) = self.data_device.take()
+            && data_device.version() >= 2
+        {
+            data_device.release();
+        }
+        if let Err(err) = self.connection.flush() {
+            warn!("failed to flush before exiting: {err}");
+        }
+    }
+
+    // Outputs that haven't had a single frame committed yet, and so may still be showing the
+    // unlocked session.
+    fn outputs_without_frame(&self) -> Vec<String> {
+        let mut names: Vec<_> = self
+            .monitors
+            .values()
+            .filter(|monitor| !monitor.first_frame_committed)
+            .map(|monitor| monitor.name)
+            .collect();
+        names.sort_unstable();
+        names
+            .iter()
+            .map(|name| self.monitors[name].label())
+            .collect()
+    }
+
+    // The compositor confirmed the lock at `now`: input goes to the fields from here on.
+    fn locked(&mut self, now: Instant) {
+        if !self.machine.on_locked() {
+            warn!("ignoring locked in the {:?} state", self.machine.state());
+            return;
+        }
+        self.waiting_deadline = None;
+        self.app.ignore_input = false;
+        self.app.locked_at = Some(now);
+
+        // the compositor is only supposed to send this once every output shows a
+        // locked frame, so anything missing here is a gap where the session was visible
+        let missing = self.outputs_without_frame();
+        if !missing.is_empty() {
+            warn!(
+                "locked before a frame was committed on {}, unlocked content may have been visible",
+                missing.join(", ")
+            );
+        }
+        #[cfg(feature = "dev-autounlock")]
+        self.schedule_auto_unlock();
+        self.run_on_lock_hook();
+    }
+
+    // Records a lock surface configure for the monitor `name`, returning whether it still has a
+    // lock surface. A monitor whose output was already removed has none to configure.
+    fn configure_surface(&mut self, name: u32, width: u32, height: u32) -> bool {
+        let Some(monitor) = self
+            .monitors
+            .get_mut(&name)
+            .filter(|monitor| monitor.lock_surface.is_some())
+        else {
+            return false;
+        };
+        monitor.pending_configure = Some((width, height));
+        true
+    }
+
+    /// Asks the compositor to lock the session and gives every output a lock surface. The
+    /// registry has to have been read by then, so that every global the lock needs is bound.
+    pub fn request_lock(&mut self, qh: &QueueHandle<Locker>) -> anyhow::Result<()> {
+        self.is_initialized()?;
+        self.select_shm_format()?;
+        self.select_primary();
+        self.log_outputs();
+
+        // at this point, we're in a happy initial state, as we've registered all of our globals
+        let lock = self
+            .lock_manager
+            .as_ref()
+            .ok_or_else(|| anyhow!("lock manager cannot be empty when trying to call lock"))?
+            .lock(qh, ());
+        let compositor = self
+            .compositor
+            .as_ref()
+            .ok_or_else(|| anyhow!("compositor must not be None when creating surfaces"))?;
+
+        let scaling = self
+            .fractional_scale_manager
+            .as_ref()
+            .zip(self.viewporter.as_ref());
+        for monitor in self.monitors.values_mut() {
+            monitor.create_surface_and_lock(compositor, &lock, scaling, qh)?;
+        }
+
+        self.lock = Some(lock);
+        self.machine.request_lock();
+        self.waiting_deadline = Some(Instant::now() + self.locked_timeout);
+        Ok(())
+    }
+
+    /// Hands over the event loop the locker runs in, which its timers and the authentication
+    /// thread's wakeups are scheduled on.
+    pub fn attach(&mut self, loop_handle: LoopHandle<'static, Locker>) -> anyhow::Result<()> {
+        // only wakes the loop: the poll after every dispatch picks up what the worker sent
+        if let Some(wakeup) = self.auth_wakeup.take() {
+            loop_handle
+                .insert_source(wakeup, |_, _, _| {})
+                .map_err(|err| anyhow!("failed to watch for authentication: {}", err.error))?;
+        }
+        self.loop_handle = Some(loop_handle);
+        #[cfg(feature = "power")]
+        self.start_battery_timer()?;
+        self.start_frame_timer()
+    }
+
+    /// Acts on what the last dispatch left behind: a finished authentication, outputs to render
+    /// and commit, and a lock request the compositor is taking too long to answer.
+    pub fn after_dispatch(&mut self, qh: &QueueHandle<Locker>) -> anyhow::Result<()> {
+        if self.controller.poll(&mut self.app) {
+            info!("unlocking for {}", self.app.username);
+            if let Some(path) = &self.last_user_file
+                && let Err(err) = last_user::save(path, &self.app.username)
+            {
+                warn!("failed to remember {}: {err}", self.app.username);
+            }
+            let conn = self.connection.clone();
+            if let Err(err) = self.unlock(&conn) {
+                warn!("failed to unlock: {err}");
+            }
+        } else if self.app.take_bell() {
+            self.ring_bell();
+        }
+        if self.controller.take_power_off() {
+            power_off();
+        }
+        #[cfg(feature = "ime")]
+        self.sync_text_input();
+
+        for name in self.monitors_to_represent() {
+            if let Some(monitor) = self.monitors.get_mut(&name) {
+                monitor.pending_configure = Some(monitor.dimensions);
+            }
+            self.present(name, qh);
+        }
+
+        for monitor in self.monitors.values_mut() {
+            let is_dirty = monitor
+                .buffer_state
+                .as_ref()
+                .map(|bs| bs.dirty)
+                .unwrap_or(false);
+
+            if is_dirty && !monitor.frame_pending {
+                let committed = monitor.commit(qh)?;
+                if monitor.record_commit(committed, self.commit_retry_budget) {
+                    error!(
+                        "{} has had no free buffer for {} commits, force releasing its buffers",
+                        monitor.label(),
+                        monitor.busy_commits
+                    );
+                    monitor.force_release();
+                } else if !committed {
+                    debug!(
+                        "all buffers were in use on {}, will try to commit on a later event",
+                        monitor.label()
+                    )
+                }
+            }
+        }
+
+        #[cfg(feature = "systemd")]
+        self.notify_ready();
+
+        match self.machine.state() {
+            LockState::Finished => Ok(()),
+            LockState::Idle => Err(anyhow!(
+                "illegal state: Lock should not have been idle when entering the loop"
+            )),
+            // checked after dispatching, so a locked event that arrived together with the
+            // deadline has already moved us to Locked and wins
+            LockState::Waiting => self.check_waiting_deadline(Instant::now()),
+            // only ever left through authentication, see `Controller::poll` above
+            LockState::Locked => Ok(()),
+        }
+    }
+
+    /// Whether the lock is over, unlocked or refused, and the loop should stop.
+    pub fn is_finished(&self) -> bool {
+        self.machine.state() == LockState::Finished
+    }
+
+    fn is_initialized(&self) -> anyhow::Result<()> {
+        if self.lock_manager.is_none() {
+            return Err(anyhow!(
+                "could not find a lock manager in the registry advertisement"
+            ));
+        }
+
+        if self.compositor.is_none() {
+            return Err(anyhow!(
+                "could not find a compositor in the registry advertisement"
+            ));
+        }
+
+        if self.shared_memory.is_none() {
+            return Err(anyhow!(
+                "could not find shared memory in the registry advertisement"
+            ));
+        }
+
+        if self.monitors.is_empty() {
+            return Err(anyhow!(
+                "could not find any outputs in the registry advertisement"
+            ));
+        }
+        Ok(())
+    }
+
+    // Picks the pixel format for every buffer from what wl_shm advertised. The rasterizer
+    // always writes opaque ARGB8888, whose byte layout XRGB8888 shares with the alpha byte
+    // ignored, so either one works without converting.
+    fn select_shm_format(&mut self) -> anyhow::Result<()> {
+        self.shm_format = [wl_shm::Format::Argb8888, wl_shm::Format::Xrgb8888]
+            .into_iter()
+            .find(|format| self.shm_formats.contains(format))
+            .ok_or_else(|| {
+                anyhow!("wl_shm offers neither argb8888 nor xrgb8888, no supported pixel format")
+            })?;
+        info!("allocating buffers as {:?}", self.shm_format);
+        Ok(())
+    }
+
+    // Marks the monitor that should show the login box. An output matching `preferred_output`
+    // wins; otherwise we fall back to the focused output, or the first advertised one before
+    // anything has focus.
+    fn select_primary(&mut self) {
+        let matched = self.preferred_output.as_deref().and_then(|wanted| {
+            self.monitors
+                .values()
+                .find(|monitor| monitor.matches_output(wanted))
+                .map(|monitor| monitor.name)
+        });
+        let focused = self
+            .focused_output
+            .filter(|name| self.monitors.contains_key(name));
+
+        if let (Some(wanted), None) = (self.preferred_output.as_deref(), matched) {
+            let fallback = if focused.is_some() {
+                "focused"
+            } else {
+                "first"
+            };
+            info!("no output matched {wanted:?}, falling back to the {fallback} output");
+        }
+
+        let primary = matched
+            .or(focused)
+            .or_else(|| self.monitors.keys().min().copied());
+        for monitor in self.monitors.values_mut() {
+            monitor.primary = Some(monitor.name) == primary;
+            if monitor.primary {
+                info!("showing the login box on {}", monitor.label());
+            }
+        }
+    }
+
+    // The color `monitor` shows through a single pixel buffer, when it has nothing to show but
+    // a solid background and the compositor can stretch one over the surface.
+    fn solid_color(&self, monitor: &Monitor) -> Option<u32> {
+        if self.single_pixel_buffer_manager.is_none() || self.viewporter.is_none() {
+            return None;
+        }
+        if self.shows_box(monitor) || !self.app.reduced_motion {
+            return None;
+        }
+        self.layers.background_color()
+    }
+
+    // Whether `monitor` renders the login box or only the background.
+    fn shows_box(&self, monitor: &Monitor) -> bool {
+        self.box_placement.shows_box(monitor, self.focused_output)
+    }
+
+    // Monitors whose box came or went since they were presented, and so have to switch between
+    // a single pixel buffer and shm buffers.
+    fn monitors_to_represent(&self) -> Vec<u32> {
+        self.monitors
+            .values()
+            .filter(|monitor| monitor.first_frame_committed && monitor.pending_configure.is_none())
+            .filter(|monitor| monitor.shows_solid_color() != self.solid_color(monitor).is_some())
+            .map(|monitor| monitor.name)
+            .collect()
+    }
+
+    // Advances the animation by a frame. Returns false once the UI has gone idle, which leaves
+    // the last frame on screen and stops the frame timer until `wake`.
+    fn render_frame(&mut self) -> bool {
+        if self.app.is_idle(Instant::now()) {
+            info!("idle, pausing the animation");
+            self.animating = false;
+            return false;
+        }
+        self.app.tick();
+        self.render_all();
+        true
+    }
+
+    fn start_frame_timer(&mut self) -> anyhow::Result<()> {
+        let handle = self
+            .loop_handle
+            .as_ref()
+            .ok_or_else(|| anyhow!("loop handle cannot be None"))?;
+        let timer = Timer::from_duration(FRAME_INTERVAL);
+        handle
+            .insert_source(timer, |_, _, locker| {
+                if locker.render_frame() {
+                    TimeoutAction::ToDuration(FRAME_INTERVAL)
+                } else {
+                    TimeoutAction::Drop
+                }
+            })
+            .map_err(|err| anyhow!("failed to start the frame timer: {}", err.error))?;
+        self.animating = true;
+        Ok(())
+    }
+
+    // Reads the batteries right away and then every BATTERY_INTERVAL, redrawing when the charge
+    // changed, even while the animation is paused. Without a battery the indicator stays hidden.
+    #[cfg(feature = "power")]
+    fn start_battery_timer(&mut self) -> anyhow::Result<()> {
+        let handle = self
+            .loop_handle
+            .as_ref()
+            .ok_or_else(|| anyhow!("loop handle cannot be None"))?;
+        handle
+            .insert_source(Timer::immediate(), |_, _, locker| {
+                let dir = std::path::Path::new(crate::power::POWER_SUPPLY_DIR);
+                let battery = crate::power::read_batteries(dir);
+                if locker.app.battery != battery {
+                    locker.app.battery = battery;
+                    locker.render_all();
+                }
+                TimeoutAction::ToDuration(BATTERY_INTERVAL)
+            })
+            .map_err(|err| anyhow!("failed to start the battery timer: {}", err.error))?;
+        Ok(())
+    }
+
+    // Lets the user know an attempt failed without looking at the screen. Only compositors with
+    // xdg_system_bell_v1 can ring one.
+    fn ring_bell(&self) {
+        match &self.system_bell {
+            Some(bell) => bell.ring(None),
+            None => debug!("the compositor has no bell to ring"),
+        }
+    }
+
+    // Input arrived: counts as activity and restarts the animation if it was paused.
+    fn wake(&mut self) {
+        self.app.record_activity(Instant::now());
+        if self.animating {
+            return;
+        }
+        if let Err(err) = self.start_frame_timer() {
+            warn!("{err}");
+        }
+    }
+
+    // Starts repeating the key just pressed after the repeat delay, replacing whatever key
+    // was repeating before. Keys that don't repeat just stop the old one.
+    fn start_key_repeat(&mut self, key: u32, input: &KeyInput) {
+        self.stop_key_repeat();
+        let key_repeats = self.xkb.as_ref().is_some_and(|xkb| xkb.key_repeats(key));
+        let Some(delay) = self.key_repeat.press(key, input, key_repeats) else {
+            return;
+        };
+        let Some(handle) = self.loop_handle.as_ref() else {
+            return;
+        };
+        let inserted = handle.insert_source(Timer::from_duration(delay), |_, _, locker| {
+            let Some(input) = locker.key_repeat.held().cloned() else {
+                locker.repeat_timer = None;
+                return TimeoutAction::Drop;
+            };
+            locker.wake();
+            locker.controller.handle_input(&mut locker.app, input);
+            locker.render_all();
+            TimeoutAction::ToDuration(locker.key_repeat.interval())
+        });
+        match inserted {
+            Ok(token) => self.repeat_timer = Some(token),
+            Err(err) => warn!("failed to start key repeat: {}", err.error),
+        }
+    }
+
+    fn stop_key_repeat(&mut self) {
+        self.key_repeat.stop();
+        if let (Some(token), Some(handle)) = (self.repeat_timer.take(), self.loop_handle.as_ref()) {
+            handle.remove(token);
+        }
+    }
+
+    // A left click at the pointer's position.
+    fn click(&mut self) {
+        if let Some((name, x, y)) = self.pointer_focus {
+            self.press(name, x, y);
+        }
+    }
+
+    // Presses the pin pad key or focuses the field at surface position `(x, y)` of monitor
+    // `name`, if that monitor shows the login box.
+    fn press(&mut self, name: u32, x: f64, y: f64) {
+        let Some(monitor) = self.monitors.get(&name) else {
+            return;
+        };
+        if !self.shows_box(monitor) {
+            return;
+        }
+        let area = Rect::new(0, 0, monitor.grid.0, monitor.grid.1);
+        // surface coordinates to buffer pixels, which the cells are measured in
+        let scale = monitor.scale_120() as f64 / 120.0;
+        let rasterizer = rasterizer_at(
+            &mut self.rasterizer,
+            &mut self.scaled_rasterizers,
+            monitor.scale_120(),
+        );
+        let column = (x.max(0.0) * scale / rasterizer.cell_width.max(1) as f64) as u16;
+        let row = (y.max(0.0) * scale / rasterizer.cell_height.max(1) as f64) as u16;
+        if let Some(key) = tui::pin_pad_hit(area, &self.app, column, row) {
+            self.controller.handle_input(&mut self.app, key);
+            self.render_all();
+        } else if let Some(target) = tui::field_hit(area, &self.app, column, row) {
+            self.app.focus(target);
+            self.render_all();
+        }
+    }
+
+    // The seat and the data device manager can be advertised in either order; the data device
+    // needs both.
+    fn bind_data_device(&mut self, qh: &QueueHandle<Locker>) {
+        if let (Some(manager), Some(seat), None) = (
+            self.data_device_manager.as_ref(),
+            self.seat.as_ref(),
+            self.data_device.as_ref(),
+        ) {
+            self.data_device = Some(manager.get_data_device(seat, qh, ()));
+        }
+    }
+
+    #[cfg(feature = "ime")]
+    fn bind_text_input(&mut self, qh: &QueueHandle<Locker>) {
+        if let (Some(manager), Some(seat), None) = (
+            self.text_input_manager.as_ref(),
+            self.seat.as_ref(),
+            self.text_input.as_ref(),
+        ) {
+            self.text_input = Some(TextInput::new(manager.get_text_input(seat, qh, ())));
+        }
+    }
+
+    // Enables the input method while a lock surface has text input focus and the fields take
+    // input, telling it what the focused field is for so it can stay out of a password. Only
+    // sent when that changes, since every commit starts the input method's state over.
+    #[cfg(feature = "ime")]
+    fn sync_text_input(&mut self) {
+        use zwp_text_input_v3::{ContentHint, ContentPurpose};
+
+        let Some(text_input) = self.text_input.as_mut() else {
+            return;
+        };
+        let takes_input = !self.app.ignore_input && !self.app.authenticating;
+        let wanted = (text_input.entered && takes_input).then(|| {
+            if self.app.focused == FocusTarget::Password && self.app.password_masked() {
+                ContentPurpose::Password
+            } else {
+                ContentPurpose::Normal
+            }
+        });
+        if wanted == text_input.enabled_for {
+            return;
+        }
+        let proxy = &text_input.proxy;
+        match wanted {
+            Some(purpose) => {
+                if text_input.enabled_for.is_none() {
+                    proxy.enable();
+                }
+                let hint = if purpose == ContentPurpose::Password {
+                    ContentHint::SensitiveData | ContentHint::HiddenText
+                } else {
+                    ContentHint::empty()
+                };
+                proxy.set_content_type(hint, purpose);
+            }
+            None => proxy.disable(),
+        }
+        proxy.commit();
+        text_input.enabled_for = wanted;
+        // whatever was being composed is abandoned along with the input method
+        if wanted.is_none() && self.app.preedit.take().is_some() {
+            self.render_all();
+        }
+    }
+
+    // Applies what the input method sent since its last done event: the committed text is typed
+    // into the focused field and the preedit replaces the last one.
+    #[cfg(feature = "ime")]
+    fn text_input_done(&mut self) {
+        let Some(text_input) = self.text_input.as_mut() else {
+            return;
+        };
+        let commit = text_input.commit.take();
+        let preedit = text_input.preedit.take();
+        self.wake();
+        if let Some(text) = commit {
+            self.app.paste(&text);
+        }
+        self.app.preedit = preedit.filter(|preedit| !preedit.is_empty());
+        self.render_all();
+    }
+
+    // Asks the clipboard owner for its text, which is typed into the focused field once all of
+    // it has arrived. The pipe is read from the event loop, so a slow owner never blocks it.
+    // The text is never logged and is zeroed once typed.
+    fn request_paste(&mut self) {
+        let Some(offer) = self.selection.as_ref() else {
+            return;
+        };
+        let Some(mime_types) = offer.data::<OfferMimeTypes>() else {
+            return;
+        };
+        let mime_type = {
+            let offered = mime_types.0.lock().unwrap_or_else(|err| err.into_inner());
+            PASTE_MIME_TYPES
+                .iter()
+                .copied()
+                .find(|mime_type| offered.iter().any(|offered| offered == mime_type))
+        };
+        let Some(mime_type) = mime_type else {
+            info!("the clipboard holds no text to paste");
+            return;
+        };
+        let Some(handle) = self.loop_handle.as_ref() else {
+            return;
+        };
+
+        let (read, write) = match nonblocking_pipe() {
+            Ok(pipe) => pipe,
+            Err(err) => {
+                warn!("failed to create a pipe for pasting: {err}");
+                return;
+            }
+        };
+        offer.receive(mime_type.to_string(), write.as_fd());
+        // the request holds its own copy of the write end; ours has to close so the read end
+        // sees EOF once the owner is done
+        drop(write);
+        if let Err(err) = self.connection.flush() {
+            warn!("failed to request the clipboard: {err}");
+            return;
+        }
+
+        // never grows past the cap, so no copy is left behind by a reallocation
+        let mut pasted = Zeroizing::new(Vec::with_capacity(MAX_PASTE_BYTES));
+        let source = Generic::new(File::from(read), Interest::READ, Mode::Level);
+        let inserted = handle.insert_source(source, move |_, pipe, locker| {
+            let mut reader: &File = pipe.as_ref();
+            let mut chunk = Zeroizing::new([0u8; 512]);
+            loop {
+                match reader.read(&mut chunk[..]) {
+                    Ok(0) => break,
+                    Ok(read) if pasted.len() + read > MAX_PASTE_BYTES => {
+                        info!("the clipboard text is over {MAX_PASTE_BYTES} bytes, not pasting");
+                        return Ok(PostAction::Remove);
+                    }
+                    Ok(read) => pasted.extend_from_slice(&chunk[..read]),
+                    Err(err) if err.kind() == ErrorKind::WouldBlock => {
+                        return Ok(PostAction::Continue);
+                    }
+                    Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                    Err(err) => {
+                        warn!("failed to read the clipboard: {err}");
+                        return Ok(PostAction::Remove);
+                    }
+                }
+            }
+            match std::str::from_utf8(&pasted) {
+                Ok(text) => {
+                    locker.app.paste(text);
+                    locker.render_all();
+                }
+                Err(_) => info!("the clipboard text isn't valid UTF-8, not pasting"),
+            }
+            Ok(PostAction::Remove)
+        });
+        if let Err(err) = inserted {
+            warn!("failed to watch the clipboard pipe: {}", err.error);
+        }
+    }
+
+    // Re-renders every configured monitor that isn't waiting on a frame callback. Only monitors
+    // whose pixels actually changed end up dirty.
+    fn render_all(&mut self) {
+        let names: Vec<_> = self.monitors.keys().copied().collect();
+        for name in names {
+            let monitor = &self.monitors[&name];
+            // a single pixel already shows all there is to show
+            if monitor.shows_solid_color() || monitor.frame_pending {
+                continue;
+            }
+            let show_box = self.shows_box(monitor);
+            let monitor = self.monitors.get_mut(&name).unwrap();
+            let rasterizer = rasterizer_at(
+                &mut self.rasterizer,
+                &mut self.scaled_rasterizers,
+                monitor.scale_120(),
+            );
+            monitor.render(&mut self.layers, rasterizer, &mut self.app, show_box);
+        }
+    }
+
+    // Sizes a monitor for its latest configure, allocates its buffers and renders the first frame.
+    // Waits for the output's `done`, so nothing is sized from half-applied output state.
+    fn present(&mut self, name: u32, qh: &QueueHandle<Locker>) {
+        let solid = self
+            .monitors
+            .get(&name)
+            .and_then(|monitor| self.solid_color(monitor));
+        let Some(monitor) = self.monitors.get_mut(&name) else {
+            return;
+        };
+        if !monitor.done {
+            info!(
+                "{} is configured, waiting for its output to be done",
+                monitor.label()
+            );
+            return;
+        }
+        let Some((width, height)) = monitor.pending_configure.take() else {
+            return;
+        };
+        let Some((width, height)) = monitor.surface_size(width, height) else {
+            error!(
+                "{} was configured without a size and has no mode to fall back on",
+                monitor.label()
+            );
+            return;
+        };
+
+        monitor.dimensions = (width, height);
+        let (pixel_width, pixel_height) = monitor.buffer_size();
+        let rasterizer = rasterizer_at(
+            &mut self.rasterizer,
+            &mut self.scaled_rasterizers,
+            monitor.scale_120(),
+        );
+        monitor.grid = rasterizer.grid_dims(pixel_width, pixel_height);
+        info!(
+            "configured {} at {width}x{height} ({pixel_width}x{pixel_height} pixels, {}x{} cells, scale {}, {}x{}mm)",
+            monitor.label(),
+            monitor.grid.0,
+            monitor.grid.1,
+            monitor.scale_120() as f64 / 120.0,
+            monitor.physical_size.0,
+            monitor.physical_size.1
+        );
+
+        // nothing but the background: a single pixel is all the surface needs, without
+        // allocating or rendering any shm buffers
+        if let (Some(argb), Some(manager), Some(viewporter)) = (
+            solid,
+            self.single_pixel_buffer_manager.as_ref(),
+            self.viewporter.as_ref(),
+        ) {
+            match monitor.commit_single_pixel(manager, viewporter, argb, qh) {
+                Ok(()) => {
+                    if let Some(buffer_state) = monitor.buffer_state.take() {
+                        buffer_state.destroy();
+                    }
+                    return;
+                }
+                Err(err) => warn!("single pixel commit failed on {}: {err}", monitor.label()),
+            }
+        }
+
+        let prefill = self.first_frame == FirstFrame::Prefill && !monitor.first_frame_committed;
+        if let (true, Some(manager), Some(viewporter)) = (
+            prefill,
+            self.single_pixel_buffer_manager.as_ref(),
+            self.viewporter.as_ref(),
+        ) {
+            if let Err(err) =
+                monitor.commit_single_pixel(manager, viewporter, self.placeholder_color, qh)
+            {
+                warn!("single pixel commit failed on {}: {err}", monitor.label());
+            }
+        }
+
+        // wl_shm sizes are i32, so anything past that can't be allocated
+        let (Ok(buffer_width), Ok(buffer_height)) =
+            (i32::try_from(pixel_width), i32::try_from(pixel_height))
+        else {
+            error!(
+                "{} needs {pixel_width}x{pixel_height} pixels, which is too large for a buffer",
+                monitor.label()
+            );
+            return;
+        };
+        let size = (buffer_width, buffer_height);
+        match monitor.buffer_state.as_mut() {
+            // same size: draw into the buffers we have. Forgetting the committed frame makes
+            // the render below commit even if it looks the same, which completes the configure.
+            Some(buffer_state) if buffer_state.size() == size => {
+                buffer_state.committed_hash = None;
+                buffer_state.committed_cells = None;
+            }
+            _ => {
+                let Some(shm) = self.shared_memory.as_ref() else {
+                    error!("no wl_shm to allocate buffers for {}", monitor.label());
+                    return;
+                };
+                if let Some(old) = monitor.buffer_state.take() {
+                    old.destroy();
+                }
+                let allocated =
+                    BufferState::new(shm, qh, name, size, self.shm_format, self.buffer_count);
+                let buffer_state = match allocated {
+                    Ok(buffer_state) => buffer_state,
+                    Err(err) => {
+                        warn!("failed to allocate buffers for {}: {err}", monitor.label());
+                        return;
+                    }
+                };
+                monitor.buffer_state = Some(buffer_state);
+            }
+        }
+
+        let show_box = self.box_placement.shows_box(monitor, self.focused_output);
+        let rasterizer = rasterizer_at(
+            &mut self.rasterizer,
+            &mut self.scaled_rasterizers,
+            monitor.scale_120(),
+        );
+        // only renders: the main loop is the one place frames are committed, so the dirty
+        // frame goes out there once, subject to the same frame pacing and retry budget
+        monitor.render(&mut self.layers, rasterizer, &mut self.app, show_box);
+    }
+
+    #[cfg(feature = "dev-autounlock")]
+    fn schedule_auto_unlock(&mut self) {
+        let Some(handle) = self.loop_handle.as_ref() else {
+            return;
+        };
+        info!("dev-autounlock: unlocking in {:?}", self.auto_unlock_after);
+        let timer = Timer::from_duration(self.auto_unlock_after);
+        let inserted = handle.insert_source(timer, |_, _, locker| {
+            if locker.machine.can_unlock() {
+                info!("dev-autounlock: unlocking without authentication");
+                let conn = locker.connection.clone();
+                if let Err(err) = locker.unlock(&conn) {
+                    warn!("failed to unlock: {err}");
+                }
+            }
+            TimeoutAction::Drop
+        });
+        if let Err(err) = inserted {
+            warn!("dev-autounlock: failed to start the timer: {}", err.error);
+        }
+    }
+
+    fn log_outputs(&self) {
+        let mut names: Vec<_> = self.monitors.keys().copied().collect();
+        names.sort_unstable();
+        for name in names {
+            let monitor = &self.monitors[&name];
+            info!(
+                "output {}: name={:?} description={:?} primary={} box={}",
+                monitor.name,
+                monitor.output_name,
+                monitor.description,
+                monitor.primary,
+                self.shows_box(monitor)
+            );
+        }
+    }
+}
+
+// If the locker goes away while it still holds the lock (an early return or a panic unwinding
+// out of main), let go of it without unlocking. A confirmed lock is abandoned without a
+// request, so the compositor keeps the session locked; only a lock that was never confirmed
+// is destroyed. Nothing but authentication ever sends unlock_and_destroy.
+impl Drop for Locker {
+    fn drop(&mut self) {
+        if self.lock.is_none() {
+            return;
+        }
+        let action = self.machine.abandon();
+        if action == Action::None {
+            info!("locker dropped while locked, leaving the session locked");
+        }
+        self.end_lock(action);
+        let _ = self.connection.flush();
+    }
+}
+
+#[derive(Default)]
+struct Monitor {
+    name: u32,
+    output: Option<WlOutput>,
+    surface: Option<WlSurface>,
+    lock_surface: Option<ExtSessionLockSurfaceV1>,
+    dimensions: (u32, u32),
+    // the (cols, rows) cell grid the TUI is laid out in for these dimensions
+    grid: (u16, u16),
+    buffer_state: Option<BufferState>,
+    // used instead of buffer_state when the surface only shows a solid color
+    single_pixel: Option<SinglePixel>,
+    // sizes the surface independently of its buffer: stretches the single pixel placeholder,
+    // and shrinks buffers rendered at a fractional scale back to the surface size
+    viewport: Option<WpViewport>,
+    // consecutive commits that found every buffer still held by the compositor
+    busy_commits: u32,
+    // advertised by wl_output.name / wl_output.description (v4+)
+    output_name: Option<String>,
+    description: Option<String>,
+    // the current mode as (width, height, refresh in mHz), and the integer scale factor
+    mode: Option<(i32, i32, i32)>,
+    scale: i32,
+    // the compositor's preferred scale for the surface in 120ths, which takes precedence over
+    // `scale` once it has been sent
+    fractional_scale: Option<WpFractionalScaleV1>,
+    preferred_scale: Option<u32>,
+    // from wl_output.geometry: the size in millimeters and how the output is rotated
+    physical_size: (i32, i32),
+    transform: Option<wl_output::Transform>,
+    // set by wl_output.done once the properties above are consistent
+    done: bool,
+    // the size of a configure that hasn't been presented yet, waiting for `done`
+    pending_configure: Option<(u32, u32)>,
+    // whether this monitor shows the login box
+    primary: bool,
+    // set by the first successful commit of any buffer to the lock surface
+    first_frame_committed: bool,
+    // a frame callback was requested with the last commit and hasn't fired yet
+    frame_pending: bool,
+}
+
+impl Monitor {
+    fn with_name(mut self, n: u32) -> Self {
+        self.name = n;
+        self
+    }
+
+    fn with_output(mut self, o: WlOutput) -> Self {
+        self.output = Some(o);
+        self
+    }
+
+    // The compositor is ready for a new frame on this surface; the next render picks it up.
+    fn frame_done(&mut self) {
+        self.frame_pending = false;
+    }
+
+    // A human readable identifier for logs, e.g. `DP-1 (Dell Inc. U2720Q)`.
+    fn label(&self) -> String {
+        match (&self.output_name, &self.description) {
+            (Some(name), Some(description)) => format!("{name} ({description})"),
+            (Some(name), None) => name.clone(),
+            _ => format!("output {}", self.name),
+        }
+    }
+
+    // Whether the surface shows a single pixel buffer in place of any rendered frame.
+    fn shows_solid_color(&self) -> bool {
+        self.buffer_state.is_none() && self.single_pixel.is_some()
+    }
+
+    // The surface size for a configure of `width` x `height`. Zero leaves the size to us, so it
+    // falls back to the output's current mode, turned for rotated outputs and divided by the
+    // scale into surface coordinates.
+    fn surface_size(&self, width: u32, height: u32) -> Option<(u32, u32)> {
+        if width != 0 && height != 0 {
+            return Some((width, height));
+        }
+        let (mode_width, mode_height, _) = self.mode?;
+        let (mode_width, mode_height) = match self.transform {
+            Some(
+                wl_output::Transform::_90
+                | wl_output::Transform::_270
+                | wl_output::Transform::Flipped90
+                | wl_output::Transform::Flipped270,
+            ) => (mode_height, mode_width),
+            _ => (mode_width, mode_height),
+        };
+        let scale = self.scale.max(1);
+        let size = |configured: u32, mode: i32| match configured {
+            0 => (mode / scale).max(1) as u32,
+            configured => configured,
+        };
+        Some((size(width, mode_width), size(height, mode_height)))
+    }
+
+    // Buffer pixels per surface coordinate in 120ths, the unit wp_fractional_scale_v1 uses.
+    fn scale_120(&self) -> u32 {
+        self.preferred_scale
+            .unwrap_or(self.scale.max(1) as u32 * 120)
+    }
+
+    // The buffer size that covers the surface at its scale, rounded to whole pixels.
+    fn buffer_size(&self) -> (u32, u32) {
+        let scale_120 = self.scale_120() as u64;
+        let pixels = |size: u32| ((size as u64 * scale_120 + 60) / 120).clamp(1, u32::MAX as u64);
+        (
+            pixels(self.dimensions.0) as u32,
+            pixels(self.dimensions.1) as u32,
+        )
+    }
+
+    fn matches_output(&self, wanted: &str) -> bool {
+        self.output_name.as_deref() == Some(wanted) || self.description.as_deref() == Some(wanted)
+    }
+
+    fn create_surface_and_lock(
+        &mut self,
+        compositor: &WlCompositor,
+        lock: &ExtSessionLockV1,
+        scaling: Option<(&WpFractionalScaleManagerV1, &WpViewporter)>,
+        qh: &QueueHandle<Locker>,
+    ) -> anyhow::Result<()> {
+        let wl_surface = compositor.create_surface(qh, ());
+
+        let wl_output = self.output.as_ref().ok_or_else(|| {
+            anyhow!(format!(
+                "monitor with name {} must have Some(output)",
+                self.name
+            ))
+        })?;
+
+        let tag = SurfaceTag {
+            monitor_name: self.name,
+        };
+        let lock_surface = lock.get_lock_surface(&wl_surface, wl_output, qh, tag);
+
+        // a fractional scale can only be shown through a viewport, buffer_scale is an integer
+        if let Some((manager, viewporter)) = scaling {
+            let tag = OutputTag {
+                monitor_name: self.name,
+            };
+            self.fractional_scale = Some(manager.get_fractional_scale(&wl_surface, qh, tag));
+            self.viewport = Some(viewporter.get_viewport(&wl_surface, qh, ()));
+        }
+
+        self.surface = Some(wl_surface);
+        self.lock_surface = Some(lock_surface);
+
+        Ok(())
+    }
+
+    // Shows `argb` through a 1x1 wp_single_pixel_buffer_v1 stretched over the whole surface by
+    // a viewport, instead of allocating a full resolution shm buffer.
+    fn commit_single_pixel(
+        &mut self,
+        manager: &WpSinglePixelBufferManagerV1,
+        viewporter: &WpViewporter,
+        argb: u32,
+        qh: &QueueHandle<Locker>,
+    ) -> anyhow::Result<()> {
+        let surface = self
+            .surface
+            .as_ref()
+            .ok_or_else(|| anyhow!("surface cannot be None"))?;
+
+        let single_pixel = match self.single_pixel.take() {
+            Some(single_pixel) => single_pixel,
+            None => {
+                // opaque whatever the color says, like every other frame of the lock
+                let [b, g, r, _] = argb.to_le_bytes().map(|c| c as u32 * 0x0101_0101);
+                SinglePixel {
+                    buffer: manager.create_u32_rgba_buffer(r, g, b, u32::MAX, qh, ()),
+                }
+            }
+        };
+
+        let viewport = self
+            .viewport
+            .get_or_insert_with(|| viewporter.get_viewport(surface, qh, ()));
+        viewport.set_destination(self.dimensions.0.try_into()?, self.dimensions.1.try_into()?);
+        surface.attach(Some(&single_pixel.buffer), 0, 0);
+        surface.damage_buffer(0, 0, 1, 1);
+        surface.commit();
+        self.single_pixel = Some(single_pixel);
+        self.first_frame_committed = true;
+        Ok(())
+    }
+
+    // Records the outcome of a commit. Returns true once `budget` consecutive commits have found
+    // every buffer busy, which usually means the compositor dropped a Release.
+    fn record_commit(&mut self, committed: bool, budget: u32) -> bool {
+        if committed {
+            self.busy_commits = 0;
+            return false;
+        }
+        self.busy_commits = self.busy_commits.saturating_add(1);
+        budget != 0 && self.busy_commits >= budget
+    }
+
+    // Destroys every protocol object the monitor owns, for an output that went away.
+    fn destroy(mut self) {
+        self.destroy_surface();
+        // wl_output.release only exists from v3
+        if let Some(output) = self.output.take().filter(|output| output.version() >= 3) {
+            output.release();
+        }
+    }
+
+    // Destroys the lock surface and everything shown on it, keeping the output. The lock
+    // surface has to go before the wl_surface it was created for.
+    fn destroy_surface(&mut self) {
+        if let Some(lock_surface) = self.lock_surface.take() {
+            lock_surface.destroy();
+        }
+        if let Some(single_pixel) = self.single_pixel.take() {
+            single_pixel.buffer.destroy();
+        }
+        if let Some(viewport) = self.viewport.take() {
+            viewport.destroy();
+        }
+        if let Some(fractional_scale) = self.fractional_scale.take() {
+            fractional_scale.destroy();
+        }
+        self.preferred_scale = None;
+        if let Some(surface) = self.surface.take() {
+            surface.destroy();
+        }
+        if let Some(buffer_state) = self.buffer_state.take() {
+            buffer_state.destroy();
+        }
+        self.first_frame_committed = false;
+        self.frame_pending = false;
+    }
+
+    // Last resort for a stuck monitor: assume the compositor is done with every buffer.
+    fn force_release(&mut self) {
+        if let Some(buffer_state) = self.buffer_state.as_mut() {
+            for buffer in &mut buffer_state.buffers {
+                buffer.in_use = false;
+            }
+        }
+        self.busy_commits = 0;
+    }
+
+    // Renders the shared UI, laid out on this monitor's cell grid, into its next free buffer.
+    fn render(
+        &mut self,
+        layers: &mut LayerStack,
+        rasterizer: &mut Rasterizer,
+        app: &mut AppState,
+        show_box: bool,
+    ) {
+        let Some(buffer_state) = self.buffer_state.as_mut() else {
+            return;
+        };
+
+        // without the box or any motion, the background is all there is to show
+        if !show_box && app.reduced_motion {
+            buffer_state.render(|target| layers.compose_background(target, app.tick));
+            buffer_state.pending_cells = None;
+            return;
+        }
+
+        let (cols, rows) = self.grid;
+        let cells = if show_box {
+            tui::render_to_buffer(app, cols, rows)
+        } else {
+            tui::render_background(app, cols, rows)
+        };
+        let caret = show_box
+            .then(|| tui::caret_position(cells.area, app))
+            .flatten();
+        let tint = show_box.then(|| tui::box_tint(cells.area, app)).flatten();
+        buffer_state.render(|target| {
+            layers.compose_with(target, app.tick, |target| {
+                rasterizer.rasterize_tinted(&cells, tint, target);
+                if let Some(caret) = caret {
+                    rasterizer.draw_caret(&cells, caret, target);
+                }
+            })
+        });
+        // whatever is drawn over the cells can change anywhere, so only a frame that is
+        // nothing but cells can be damaged by what changed in them
+        let only_cells = buffer_state.pending_index.is_some() && !layers.draws_over_content();
+        buffer_state.pending_cells = only_cells.then(|| FrameCells {
+            cells,
+            caret,
+            cell_size: (rasterizer.cell_width, rasterizer.cell_height),
+        });
+    }
+
+    // Commits the pending render and asks for a frame callback, so nothing more is committed
+    // to this surface until the compositor has used this frame.
+    fn commit(&mut self, qh: &QueueHandle<Locker>) -> anyhow::Result<bool> {
+        let buffer_state = self
+            .buffer_state
+            .as_mut()
+            .ok_or_else(|| anyhow!("buffer state cannot be None"))?;
+
+        // nothing was rendered because every buffer was still held by the compositor
+        let Some(buffer_index) = buffer_state.pending_index.take() else {
+            return Ok(false);
+        };
+        // the surface's first frame covers the unlocked session, so nothing of it may show
+        // through, whatever was drawn
+        if !self.first_frame_committed {
+            buffer_state.pixel_buffer(buffer_index).make_opaque();
+        }
+        let buffer = &buffer_state.buffers[buffer_index].buffer;
+
+        let surface = self
+            .surface
+            .as_ref()
+            .ok_or_else(|| anyhow!("surface cannot be None"))?;
+
+        // A prefilled placeholder is replaced by the full size buffer. Unless the viewport
+        // shrinks a fractionally scaled buffer, it's dropped in the same commit, which keeps the
+        // surface from being stretched for a frame.
+        let placeholder = self.single_pixel.take();
+        let buffer_scale = match (self.preferred_scale, self.viewport.as_ref()) {
+            (Some(_), Some(viewport)) => {
+                viewport
+                    .set_destination(self.dimensions.0.try_into()?, self.dimensions.1.try_into()?);
+                1
+            }
+            (None, Some(viewport)) if self.fractional_scale.is_some() => {
+                // kept for when a preferred scale arrives
+                viewport.set_destination(-1, -1);
+                self.scale.max(1)
+            }
+            _ => {
+                if let Some(viewport) = self.viewport.take() {
+                    viewport.destroy();
+                }
+                self.scale.max(1)
+            }
+        };
+        // wl_surface.set_buffer_scale only exists from v3
+        if surface.version() >= 3 {
+            surface.set_buffer_scale(buffer_scale);
+        }
+
+        let (buffer_width, buffer_height) = buffer_state.size();
+        // the placeholder was a single pixel, so there's nothing to compare the frame with
+        let damage = match placeholder {
+            Some(_) => vec![(0, 0, buffer_width, buffer_height)],
+            None => buffer_state.damage(),
+        };
+        surface.attach(Some(buffer), 0, 0);
+        for (x, y, width, height) in damage {
+            surface.damage_buffer(x, y, width, height);
+        }
+        surface.frame(
+            qh,
+            FrameTag {
+                monitor_name: self.name,
+            },
+        );
+        surface.commit();
+        self.frame_pending = true;
+        if let Some(placeholder) = placeholder {
+            placeholder.buffer.destroy();
+        }
+        buffer_state.buffers[buffer_index].in_use = true;
+        buffer_state.dirty = false;
+        buffer_state.committed_hash = buffer_state.pending_hash;
+        buffer_state.committed_cells = buffer_state.pending_cells.take();
+        self.first_frame_committed = true;
+        Ok(true)
+    }
+}
+
+/// Which monitors show the login box; the rest only show the background.
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone)]
+pub enum BoxPlacement {
+    // every monitor, so the user can type from any display
+    All,
+    // only the primary monitor, see `Locker::select_primary`
+    #[default]
+    Primary,
+    // whichever monitor has focus, falling back to the primary one
+    Focused,
+}
+
+impl BoxPlacement {
+    // Whether `monitor` renders the login box or only the background.
+    fn shows_box(self, monitor: &Monitor, focused_output: Option<u32>) -> bool {
+        match self {
+            Self::All => true,
+            Self::Primary => monitor.primary,
+            Self::Focused => match focused_output {
+                Some(focused) => focused == monitor.name,
+                None => monitor.primary,
+            },
+        }
+    }
+}
+
+impl std::str::FromStr for BoxPlacement {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all" => Ok(Self::All),
+            "primary" => Ok(Self::Primary),
+            "focused" => Ok(Self::Focused),
+            _ => Err(anyhow!(
+                "unknown box placement {s:?}, expected all, primary or focused"
+            )),
+        }
+    }
+}
+
+/// What the first commit to each lock surface shows.
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone)]
+pub enum FirstFrame {
+    // cover the output with the placeholder color the moment it is configured, and follow up
+    // with the composed frame; keeps the window where unlocked content can show to a minimum
+    #[default]
+    Prefill,
+    // wait until the composed frame is rendered and commit that first
+    Composed,
+}
+
+impl std::str::FromStr for FirstFrame {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "prefill" => Ok(Self::Prefill),
+            "composed" => Ok(Self::Composed),
+            _ => Err(anyhow!(
+                "unknown first frame policy {s:?}, expected prefill or composed"
+            )),
+        }
+    }
+}
+
+struct SinglePixel {
+    buffer: WlBuffer,
+}
+
+const WL_OUTPUT_VERSION: u32 = 4;
+
+// v7 is the first version that requires the keymap fd to be mapped privately
+const WL_SEAT_VERSION: u32 = 7;
+
+// DejaVu Sans Mono, see assets/fonts/LICENSE
+static FONT: &[u8] = include_bytes!("../../assets/fonts/DejaVuSansMono.ttf");
+const FONT_SIZE_PX: f32 = 16.0;
+
+// how often the UI advances and is re-rendered, ~30fps to match the terminal demo
+const FRAME_INTERVAL: Duration = Duration::from_millis(33);
+
+// how often the battery indicator is brought up to date; a percent takes minutes to change
+#[cfg(feature = "power")]
+const BATTERY_INTERVAL: Duration = Duration::from_secs(30);
+
+// how long a `dev-autounlock` build stays locked without a password, unless the config file
+// says otherwise
+#[cfg(feature = "dev-autounlock")]
+const DEV_AUTOUNLOCK_AFTER: Duration = Duration::from_secs(5);
+
+const DEFAULT_PLACEHOLDER_COLOR: u32 = 0xFF0000FF;
+
+// from linux/input-event-codes.h, what wl_pointer.button reports for the left button
+const BTN_LEFT: u32 = 0x110;
+
+// what a paste asks the clipboard owner for, most preferred first
+const PASTE_MIME_TYPES: [&str; 4] = [
+    "text/plain;charset=utf-8",
+    "text/plain",
+    "UTF8_STRING",
+    "TEXT",
+];
+
+// far longer than any password; anything bigger was copied by mistake
+const MAX_PASTE_BYTES: usize = 4096;
+
+// how long without input before the animation freezes, to save power on a locked laptop
+const DEFAULT_IDLE_AFTER: Duration = Duration::from_secs(60);
+
+// how long the compositor gets to answer a lock request with locked or finished
+const DEFAULT_LOCKED_TIMEOUT: Duration = Duration::from_secs(5);
+
+// started from early boot, the compositor gets about five seconds to create its socket
+const DEFAULT_CONNECT_ATTEMPTS: u32 = 10;
+const DEFAULT_CONNECT_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+// roughly two seconds of the main loop finding every buffer busy
+const DEFAULT_COMMIT_RETRY_BUDGET: u32 = 120;
+
+// double buffering: one buffer on screen while the next frame is drawn into the other
+const DEFAULT_BUFFER_COUNT: usize = 2;
+
+// The seat's text input, with what it was last sent and what the input method sent since its
+// last done event; like a surface's state, text input state is double buffered.
+#[cfg(feature = "ime")]
+struct TextInput {
+    proxy: ZwpTextInputV3,
+    // a lock surface has text input focus
+    entered: bool,
+    // the purpose the input method was last enabled for, None while it's disabled
+    enabled_for: Option<zwp_text_input_v3::ContentPurpose>,
+    preedit: Option<String>,
+    commit: Option<String>,
+}
+
+#[cfg(feature = "ime")]
+impl TextInput {
+    fn new(proxy: ZwpTextInputV3) -> Self {
+        Self {
+            proxy,
+            entered: false,
+            enabled_for: None,
+            preedit: None,
+            commit: None,
+        }
+    }
+}
+
+// The mime types an offer announced, collected from its `offer` events.
+#[derive(Default)]
+struct OfferMimeTypes(Mutex<Vec<String>>);
+
+#[derive(Copy, Clone)]
+struct OutputTag {
+    monitor_name: u32,
+}
+
+#[derive(Copy, Clone)]
+struct SurfaceTag {
+    monitor_name: u32,
+}
+
+#[derive(Copy, Clone)]
+struct FrameTag {
+    monitor_name: u32,
+}
+
+#[derive(Copy, Clone)]
+struct BufferTag {
+    monitor_name: u32,
+    index: usize,
+}
+
+// One of the buffers of a monitor, at its offset in the shared pool.
+struct BufferSlot {
+    // where this buffer's pixels start in the pool
+    offset: usize,
+    buffer: WlBuffer,
+    // whether or not the compositor is currently reading the shared memory
+    in_use: bool,
+}
+
+struct BufferState {
+    // the byte size of a single buffer, and the length of a row
+    size: i32,
+    stride: i32,
+    // every buffer shares one memfd and one pool, each right after the one before
+    mem_fd: Memfd,
+    // access to the actual underlying bytes
+    bytes: MemoryMap,
+    pool: WlShmPool,
+    buffers: Vec<BufferSlot>,
+    // whether or not the contents of the buffer in the memory map have been sent to the compositor
+    //   - dirty = true whenever UI state changes (input, configure, timer, etc.), regardless of
+    //   buffer usage.
+    //
+    //   - successful render+commit sets dirty = false.
+    //
+    //   - if a render was desired but all buffers were in use, leave dirty = true and try again
+    //   on the next Release.
+    //
+    //   - a render that produces the same pixels as the last commit clears dirty, so visually
+    //   identical frames are never sent to the compositor.
+    dirty: bool,
+    next_index: usize,
+    // the buffer holding the latest render, until it is committed
+    pending_index: Option<usize>,
+    // content hash of the most recent render, and of the render that was last committed
+    pending_hash: Option<u64>,
+    committed_hash: Option<u64>,
+    // the cells the pending and the committed render were drawn from, when that's all they
+    // were drawn from; what changed between the two is all a commit damages
+    pending_cells: Option<FrameCells>,
+    committed_cells: Option<FrameCells>,
+}
+
+// A frame as the cells it was rasterized from, to find what changed from one to the next.
+struct FrameCells {
+    cells: Buffer,
+    caret: Option<(u16, u16)>,
+    // the rasterizer's cell size in pixels
+    cell_size: (u32, u32),
+}
+
+impl BufferState {
+    // the flow is:
+    //  1. Create an fd (memfd or a temp file) and set_len(size) to fit all `count` buffers.
+    //  2. mmap the fd to get a writable byte slice.
+    //  3. Pass that fd to wl_shm.create_pool(fd, size) to get a wl_shm_pool.
+    //  4. Create a wl_buffer for each slice of the pool with width/height/stride/format.
+    fn new(
+        shared_memory: &WlShm,
+        qh: &QueueHandle<Locker>,
+        monitor_name: u32,
+        (width, height): (i32, i32),
+        format: wl_shm::Format,
+        count: usize,
+    ) -> anyhow::Result<Self> {
+        if width <= 0 || height <= 0 {
+            return Err(anyhow!("cannot allocate {width}x{height} buffers"));
+        }
+        let too_large = || anyhow!("{width}x{height} buffers don't fit in a wl_shm pool");
+        let stride = width.checked_mul(4).ok_or_else(too_large)?;
+        let size = stride.checked_mul(height).ok_or_else(too_large)?;
+        let pool_size = i32::try_from(count)
+            .ok()
+            .and_then(|count| size.checked_mul(count))
+            .ok_or_else(too_large)?;
+
+        let mem_fd_opts = MemfdOptions::default().allow_sealing(true);
+        let mem_fd = mem_fd_opts.create(monitor_name.to_string())?;
+        mem_fd.as_file().set_len(pool_size as u64)?;
+        let c_fd = mem_fd.as_file().as_raw_fd();
+
+        let mmap_opts = vec![
+            MapOption::MapReadable,
+            MapOption::MapWritable,
+            MapOption::MapFd(c_fd),
+            MapOption::MapNonStandardFlags(libc::MAP_SHARED),
+        ];
+
+        let bytes = MemoryMap::new(pool_size as usize, mmap_opts.as_slice())?;
+        // A fresh memfd reads as zeroes, which is transparent in Argb8888. Whatever buffer ends
+        // up committed first, it can never let the unlocked session show through.
+        let opaque_black = 0xFF00_0000u32.to_ne_bytes();
+        let pool_bytes =
+            unsafe { std::slice::from_raw_parts_mut(bytes.data() as *mut u8, pool_size as usize) };
+        for px in pool_bytes.chunks_exact_mut(4) {
+            px.copy_from_slice(&opaque_black);
+        }
+
+        let pool = shared_memory.create_pool(mem_fd.as_file().as_fd(), pool_size, qh, ());
+
+        let slot = |index: usize| {
+            let tag = BufferTag {
+                monitor_name,
+                index,
+            };
+            let offset = size * index as i32;
+            let buffer = pool.create_buffer(offset, width, height, stride, format, qh, tag);
+            BufferSlot {
+                offset: offset as usize,
+                buffer,
+                in_use: false,
+            }
+        };
+        let buffers = (0..count).map(slot).collect();
+
+        Ok(Self {
+            size,
+            stride,
+            mem_fd,
+            bytes,
+            pool,
+            buffers,
+            dirty: false,
+            next_index: 0,
+            pending_index: None,
+            pending_hash: None,
+            committed_hash: None,
+            pending_cells: None,
+            committed_cells: None,
+        })
+    }
+
+    // Draws into a buffer the compositor isn't reading: the one already holding an uncommitted
+    // render, or else the next free one. With none free the frame stays dirty and the commit
+    // reports the buffers as busy.
+    fn render(&mut self, draw: impl FnOnce(&mut PixelBuffer)) {
+        let index = match self.pending_index.take() {
+            Some(index) => index,
+            None => match self.acquire_free_buffer_index() {
+                Some(index) => index,
+                None => {
+                    self.dirty = true;
+                    return;
+                }
+            },
+        };
+
+        draw(&mut self.pixel_buffer(index));
+        let hash = content_hash(self.pixels(index));
+        self.pending_hash = Some(hash);
+        if self.committed_hash == Some(hash) {
+            self.dirty = false;
+        } else {
+            self.pending_index = Some(index);
+            self.dirty = true;
+        }
+    }
+
+    // The (x, y, width, height) pixels the pending render changed since the last commit, see
+    // `changed_cells`. It's all of them unless both renders are known as cells of one size: for
+    // the first frame, after a configure, and for frames with more than cells in them.
+    fn damage(&self) -> Vec<(i32, i32, i32, i32)> {
+        let (width, height) = self.size();
+        let (Some(previous), Some(next)) = (&self.committed_cells, &self.pending_cells) else {
+            return vec![(0, 0, width, height)];
+        };
+        if previous.cell_size != next.cell_size {
+            return vec![(0, 0, width, height)];
+        }
+
+        let mut changed = changed_cells(&previous.cells, &next.cells);
+        if previous.caret != next.caret {
+            let carets = [previous.caret, next.caret].into_iter().flatten();
+            changed.extend(carets.map(|(x, y)| Rect::new(x, y, 1, 1)));
+        }
+        if changed.len() > MAX_DAMAGE_RECTS {
+            let bounds = changed.iter().copied().reduce(Rect::union);
+            changed = bounds.into_iter().collect();
+        }
+        let size = (width as u32, height as u32);
+        changed
+            .into_iter()
+            .map(|cells| {
+                let (x, y, width, height) =
+                    cells_to_pixels(cells, next.cells.area, next.cell_size, size);
+                (x as i32, y as i32, width as i32, height as i32)
+            })
+            .collect()
+    }
+
+    // the (width, height) in pixels every buffer was allocated with
+    fn size(&self) -> (i32, i32) {
+        (self.stride / 4, self.size / self.stride)
+    }
+
+    // Destroys the buffers and the pool; the memfd and mapping go with `self`.
+    fn destroy(self) {
+        for slot in self.buffers {
+            slot.buffer.destroy();
+        }
+        self.pool.destroy();
+    }
+
+    fn pixels(&self, index: usize) -> &[u8] {
+        let len = self.size as usize;
+        let ptr = self.bytes.data() as *const u8;
+        let pool = unsafe { std::slice::from_raw_parts(ptr, len * self.buffers.len()) };
+        let offset = self.buffers[index].offset;
+        &pool[offset..offset + len]
+    }
+
+    // one buffer's part of the mmap'd bytes viewed as pixels, so all drawing goes through the
+    // wayland-free type
+    fn pixel_buffer(&mut self, index: usize) -> PixelBuffer<'_> {
+        let len = self.size as usize;
+        let ptr = self.bytes.data() as *mut u8;
+        let pool = unsafe { std::slice::from_raw_parts_mut(ptr, len * self.buffers.len()) };
+        let offset = self.buffers[index].offset;
+
+        let width = (self.stride / 4) as u32;
+        let height = (self.size / self.stride) as u32;
+        PixelBuffer::new(
+            &mut pool[offset..offset + len],
+            width,
+            height,
+            self.stride as u32,
+        )
+    }
+
+    fn acquire_free_buffer_index(&mut self) -> Option<usize> {
+        let total = self.buffers.len();
+        for offset in 0..total {
+            let index = (self.next_index + offset) % total;
+            if !self.buffers[index].in_use {
+                self.next_index = (index + 1) % total;
+                return Some(index);
+            }
+        }
+        None
+    }
+
+    // The compositor is done reading buffer `index`, so it can be drawn into again.
+    fn release(&mut self, index: usize) {
+        if let Some(slot) = self.buffers.get_mut(index) {
+            slot.in_use = false;
+        }
+    }
+}
+
+// Asks logind to power off, off the event loop since the call can wait on polkit.
+#[cfg(feature = "dbus")]
+fn power_off() {
+    info!("powering off");
+    std::thread::spawn(|| {
+        if let Err(err) = crate::logind::power_off() {
+            warn!("failed to power off: {err}");
+        }
+    });
+}
+
+#[cfg(not(feature = "dbus"))]
+fn power_off() {
+    warn!("powering off goes through logind, which needs the dbus feature");
+}
+
+// A close-on-exec pipe whose read end doesn't block. The write end is handed to another client,
+// which may not expect non-blocking writes, so it's left alone.
+fn nonblocking_pipe() -> std::io::Result<(OwnedFd, OwnedFd)> {
+    let mut fds = [0; 2];
+    // Safety: `fds` has room for the two descriptors pipe2 writes.
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    // Safety: pipe2 succeeded, so both descriptors are open and owned by nobody else.
+    let (read, write) = unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+    // Safety: `read` is an open descriptor for the duration of both calls.
+    let flags = unsafe { libc::fcntl(read.as_raw_fd(), libc::F_GETFL) };
+    if flags < 0
+        || unsafe { libc::fcntl(read.as_raw_fd(), libc::F_SETFL, flags | libc::O_NONBLOCK) } < 0
+    {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok((read, write))
+}
+
+// The rasterizer for buffers at `scale_120` (see `Monitor::scale_120`), made from the unscaled
+// one on first use and shared by every monitor at that scale.
+fn rasterizer_at<'a>(
+    base: &'a mut Rasterizer,
+    scaled: &'a mut HashMap<u32, Rasterizer>,
+    scale_120: u32,
+) -> &'a mut Rasterizer {
+    if scale_120 == 120 {
+        return base;
+    }
+    scaled
+        .entry(scale_120)
+        .or_insert_with(|| base.scaled(scale_120 as f32 / 120.0))
+}
+
+/// Describes the globals and outputs the compositor advertised, for `--probe`.
+pub fn probe_report(locker: &Locker, json: bool) -> String {
+    let mut globals = locker.globals.clone();
+    globals.sort_unstable();
+    let mut monitors: Vec<_> = locker.monitors.values().collect();
+    monitors.sort_unstable_by_key(|monitor| monitor.name);
+    let has_lock_manager = globals
+        .iter()
+        .any(|(_, interface, _)| interface == "ext_session_lock_manager_v1");
+
+    let mut out = String::new();
+    if json {
+        let globals = globals
+            .iter()
+            .map(|(name, interface, version)| {
+                format!(
+                    "{{\"name\":{name},\"interface\":{},\"version\":{version}}}",
+                    json_string(interface)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+        let outputs = monitors
+            .iter()
+            .map(|monitor| {
+                let mode = monitor
+                    .mode
+                    .map(|(width, height, refresh)| {
+                        format!(
+                            "{{\"width\":{width},\"height\":{height},\"refresh_mhz\":{refresh}}}"
+                        )
+                    })
+                    .unwrap_or_else(|| "null".to_string());
+                let optional = |value: &Option<String>| {
+                    value
+                        .as_deref()
+                        .map(json_string)
+                        .unwrap_or_else(|| "null".to_string())
+                };
+                format!(
+                    "{{\"global\":{},\"name\":{},\"description\":{},\"mode\":{mode},\"scale\":{}}}",
+                    monitor.name,
+                    optional(&monitor.output_name),
+                    optional(&monitor.description),
+                    monitor.scale.max(1)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+        out.push_str(&format!(
+            "{{\"session_lock\":{has_lock_manager},\"globals\":[{globals}],\"outputs\":[{outputs}]}}\n"
+        ));
+        return out;
+    }
+
+    out.push_str(&format!(
+        "ext_session_lock_manager_v1: {}\n\nglobals:\n",
+        if has_lock_manager {
+            "present"
+        } else {
+            "MISSING"
+        }
+    ));
+    for (name, interface, version) in &globals {
+        out.push_str(&format!("  [{name:>3}] {interface} v{version}\n"));
+    }
+    out.push_str("\noutputs:\n");
+    for monitor in monitors {
+        out.push_str(&format!("  [{:>3}] {}\n", monitor.name, monitor.label()));
+        if let Some((width, height, refresh)) = monitor.mode {
+            out.push_str(&format!(
+                "        mode {width}x{height} @ {}.{:03} Hz\n",
+                refresh / 1000,
+                refresh % 1000
+            ));
+        }
+        // outputs before v2, or that never sent a scale, are at 1
+        out.push_str(&format!("        scale {}\n", monitor.scale.max(1)));
+    }
+    out
+}
+
+fn json_string(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for ch in value.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            ch if (ch as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => out.push(ch),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Write, os::unix::net::UnixStream};
+
+    use wayland_client::{
+        Dispatch, EventQueue, WEnum,
+        protocol::{wl_callback, wl_keyboard, wl_registry, wl_touch},
+    };
+    use wayland_protocols::ext::session_lock::v1::client::{
+        ext_session_lock_surface_v1, ext_session_lock_v1,
+    };
+    use xkbcommon::xkb;
+
+    use super::*;
+
+    // A connection nobody answers on, to create proxies that are never sent anywhere. The queue
+    // and the server end have to outlive every one of them.
+    struct Fixture {
+        conn: Connection,
+        registry: wl_registry::WlRegistry,
+        qh: QueueHandle<Locker>,
+        _queue: EventQueue<Locker>,
+        server: UnixStream,
+    }
+
+    impl Fixture {
+        fn new() -> Self {
+            let (client, server) = UnixStream::pair().unwrap();
+            let conn = Connection::from_socket(client).unwrap();
+            let queue = conn.new_event_queue::<Locker>();
+            let qh = queue.handle();
+            Self {
+                registry: conn.display().get_registry(&qh, ()),
+                conn,
+                qh,
+                _queue: queue,
+                server,
+            }
+        }
+
+        fn locker(&self, config: LockConfig) -> Locker {
+            Locker::new(config, self.conn.clone())
+        }
+
+        // A monitor with a surface and buffers, bound as if the compositor advertised them.
+        fn monitor(&self, width: i32, height: i32) -> Monitor {
+            let compositor = self
+                .registry
+                .bind::<WlCompositor, (), Locker>(1, 1, &self.qh, ());
+            let shm = self.registry.bind::<WlShm, (), Locker>(2, 1, &self.qh, ());
+            Monitor {
+                surface: Some(compositor.create_surface(&self.qh, ())),
+                dimensions: (width as u32, height as u32),
+                buffer_state: Some(
+                    BufferState::new(
+                        &shm,
+                        &self.qh,
+                        1,
+                        (width, height),
+                        wl_shm::Format::Argb8888,
+                        DEFAULT_BUFFER_COUNT,
+                    )
+                    .unwrap(),
+                ),
+                ..Monitor::default()
+            }
+        }
+    }
+
+    #[test]
+    fn rendering_the_same_frame_twice_commits_once() {
+        let fixture = Fixture::new();
+        let mut monitor = fixture.monitor(4, 4);
+        let mut layers = LayerStack::default();
+        layers.push(LayerSlot::Background, Box::new(SolidColor::new(0xFF0000FF)));
+
+        let mut commits = 0;
+        for _ in 0..2 {
+            let buffer_state = monitor.buffer_state.as_mut().unwrap();
+            buffer_state.render(|target| layers.compose(target, 0));
+            // what the main loop does with a dirty monitor
+            if buffer_state.dirty && monitor.commit(&fixture.qh).unwrap() {
+                commits += 1;
+            }
+        }
+        assert_eq!(commits, 1);
+
+        // a different frame is still committed
+        layers.push(LayerSlot::Overlay, Box::new(SolidColor::new(0xFF00FF00)));
+        let buffer_state = monitor.buffer_state.as_mut().unwrap();
+        buffer_state.render(|target| layers.compose(target, 0));
+        assert!(buffer_state.dirty);
+    }
+
+    #[test]
+    fn a_scale_2_monitor_gets_buffers_with_twice_the_pixels() {
+        let fixture = Fixture::new();
+        let mut locker = fixture.locker(LockConfig::default());
+        locker.shared_memory = Some(fixture.registry.bind::<WlShm, (), Locker>(
+            2,
+            1,
+            &fixture.qh,
+            (),
+        ));
+        let compositor = fixture
+            .registry
+            .bind::<WlCompositor, (), Locker>(1, 1, &fixture.qh, ());
+        let mut monitor = Monitor {
+            surface: Some(compositor.create_surface(&fixture.qh, ())),
+            scale: 2,
+            done: true,
+            pending_configure: Some((320, 200)),
+            ..Monitor::default().with_name(7)
+        };
+        locker.monitors.insert(7, monitor);
+        locker.present(7, &fixture.qh);
+
+        monitor = locker.monitors.remove(&7).unwrap();
+        assert_eq!(monitor.dimensions, (320, 200));
+        let buffer_state = monitor.buffer_state.as_ref().unwrap();
+        assert_eq!(buffer_state.size(), (640, 400));
+        assert_eq!(buffer_state.stride, 640 * 4);
+        // the cells grow with the pixels, so text keeps its size on screen
+        let scaled = &locker.scaled_rasterizers[&240];
+        assert_eq!(monitor.grid, scaled.grid_dims(640, 400));
+        let base = &locker.rasterizer;
+        assert!(scaled.cell_height.abs_diff(base.cell_height * 2) <= 1);
+    }
+
+    #[test]
+    fn solid_monitors_use_a_single_pixel_buffer_when_advertised() {
+        let fixture = Fixture::new();
+        let mut locker = fixture.locker(LockConfig {
+            reduced_motion: true,
+            ..LockConfig::default()
+        });
+        let mut monitor = fixture.monitor(4, 4);
+        assert_eq!(locker.solid_color(&monitor), None);
+
+        let registry = &fixture.registry;
+        locker.single_pixel_buffer_manager =
+            Some(registry.bind::<WpSinglePixelBufferManagerV1, (), Locker>(3, 1, &fixture.qh, ()));
+        locker.viewporter = Some(registry.bind::<WpViewporter, (), Locker>(4, 1, &fixture.qh, ()));
+        let argb = locker.solid_color(&monitor).unwrap();
+        assert_eq!(argb, DEFAULT_PLACEHOLDER_COLOR);
+
+        monitor
+            .commit_single_pixel(
+                locker.single_pixel_buffer_manager.as_ref().unwrap(),
+                locker.viewporter.as_ref().unwrap(),
+                argb,
+                &fixture.qh,
+            )
+            .unwrap();
+        assert!(monitor.single_pixel.is_some());
+
+        // the login box, or anything moving behind it, needs full size buffers again
+        monitor.primary = true;
+        assert_eq!(locker.solid_color(&monitor), None);
+        monitor.primary = false;
+        locker.app.reduced_motion = false;
+        assert_eq!(locker.solid_color(&monitor), None);
+    }
+
+    #[test]
+    fn a_re_advertised_output_keeps_its_monitor() {
+        let fixture = Fixture::new();
+        let mut locker = fixture.locker(LockConfig::default());
+        let advertise = |locker: &mut Locker| {
+            let global = wl_registry::Event::Global {
+                name: 7,
+                interface: "wl_output".to_string(),
+                version: WL_OUTPUT_VERSION,
+            };
+            Locker::event(
+                locker,
+                &fixture.registry,
+                global,
+                &(),
+                &fixture.conn,
+                &fixture.qh,
+            );
+        };
+
+        advertise(&mut locker);
+        let monitor = locker.monitors.get_mut(&7).unwrap();
+        monitor.surface = fixture.monitor(4, 4).surface;
+        let surface = monitor.surface.as_ref().unwrap().id();
+
+        let log = crate::log::capture();
+        advertise(&mut locker);
+        assert!(log.contains("ignoring duplicate wl_output global 7"));
+        assert_eq!(locker.monitors.len(), 1);
+        assert_eq!(locker.monitors[&7].surface.as_ref().unwrap().id(), surface);
+    }
+
+    #[test]
+    fn busy_commits_count_until_one_goes_through() {
+        let fixture = Fixture::new();
+        let mut monitor = fixture.monitor(4, 4);
+        assert!(!monitor.record_commit(false, 3));
+        assert!(!monitor.record_commit(false, 3));
+        assert_eq!(monitor.busy_commits, 2);
+
+        assert!(!monitor.record_commit(true, 3));
+        assert_eq!(monitor.busy_commits, 0);
+
+        // stuck once the budget is spent
+        for _ in 0..2 {
+            monitor.record_commit(false, 3);
+        }
+        assert!(monitor.record_commit(false, 3));
+
+        // giving up frees every buffer for the next frame
+        for buffer in &mut monitor.buffer_state.as_mut().unwrap().buffers {
+            buffer.in_use = true;
+        }
+        monitor.force_release();
+        assert_eq!(monitor.busy_commits, 0);
+        let buffers = &monitor.buffer_state.as_ref().unwrap().buffers;
+        assert!(buffers.iter().all(|buffer| !buffer.in_use));
+    }
+
+    #[test]
+    fn a_zero_budget_never_gives_up() {
+        let mut monitor = Monitor::default();
+        for _ in 0..1000 {
+            assert!(!monitor.record_commit(false, 0));
+        }
+    }
+
+    // Which of a primary monitor 1 and a secondary monitor 2 show the box.
+    fn showing_box(placement: BoxPlacement, focused: Option<u32>) -> Vec<u32> {
+        let fixture = Fixture::new();
+        let mut locker = fixture.locker(LockConfig {
+            box_placement: placement,
+            ..LockConfig::default()
+        });
+        locker.focused_output = focused;
+        for name in [1, 2] {
+            let monitor = Monitor {
+                primary: name == 1,
+                ..Monitor::default().with_name(name)
+            };
+            locker.monitors.insert(name, monitor);
+        }
+        let mut names: Vec<_> = locker
+            .monitors
+            .values()
+            .filter(|monitor| locker.shows_box(monitor))
+            .map(|monitor| monitor.name)
+            .collect();
+        names.sort_unstable();
+        names
+    }
+
+    #[test]
+    fn every_monitor_shows_the_box_when_placed_on_all() {
+        assert_eq!(showing_box(BoxPlacement::All, Some(2)), [1, 2]);
+    }
+
+    #[test]
+    fn only_the_primary_monitor_shows_the_box_by_default() {
+        assert_eq!(showing_box(BoxPlacement::default(), Some(2)), [1]);
+    }
+
+    #[test]
+    fn the_focused_monitor_shows_the_box_once_one_has_focus() {
+        assert_eq!(showing_box(BoxPlacement::Focused, Some(2)), [2]);
+        assert_eq!(showing_box(BoxPlacement::Focused, None), [1]);
+    }
+
+    // The surface position of the middle of the first cell `hit` accepts on `monitor`'s grid.
+    fn cell_center(
+        locker: &Locker,
+        monitor: u32,
+        hit: impl Fn(Rect, u16, u16) -> bool,
+    ) -> (f64, f64) {
+        let (cols, rows) = locker.monitors[&monitor].grid;
+        let area = Rect::new(0, 0, cols, rows);
+        let (column, row) = (0..rows)
+            .flat_map(|row| (0..cols).map(move |column| (column, row)))
+            .find(|&(column, row)| hit(area, column, row))
+            .unwrap();
+        let (width, height) = (locker.rasterizer.cell_width, locker.rasterizer.cell_height);
+        (
+            ((column as u32 * width) + width / 2) as f64,
+            ((row as u32 * height) + height / 2) as f64,
+        )
+    }
+
+    #[test]
+    fn clicks_and_taps_focus_fields_and_press_pin_pad_keys() {
+        let fixture = Fixture::new();
+        let mut locker = fixture.locker(LockConfig {
+            pin_pad: true,
+            reduced_motion: true,
+            ..LockConfig::default()
+        });
+        locker.app.ignore_input = false;
+        let (width, height) = (
+            40 * locker.rasterizer.cell_width as i32,
+            24 * locker.rasterizer.cell_height as i32,
+        );
+        let mut monitor = fixture.monitor(width, height);
+        monitor.name = 7;
+        monitor.primary = true;
+        monitor.grid = (40, 24);
+        let surface = monitor.surface.clone().unwrap();
+        locker.monitors.insert(7, monitor);
+
+        // a click on the password line focuses it
+        let (x, y) = cell_center(&locker, 7, |area, column, row| {
+            tui::field_hit(area, &locker.app, column, row) == Some(FocusTarget::Password)
+        });
+        locker.pointer_focus = Some((7, x, y));
+        locker.click();
+        assert_eq!(locker.app.focused, FocusTarget::Password);
+
+        // a tap on a pin pad key types it
+        let (x, y) = cell_center(&locker, 7, |area, column, row| {
+            let key = tui::pin_pad_hit(area, &locker.app, column, row);
+            matches!(key, Some(KeyInput::Char('5')))
+        });
+        let seat = fixture
+            .registry
+            .bind::<WlSeat, (), Locker>(5, 1, &fixture.qh, ());
+        let touch = seat.get_touch(&fixture.qh, ());
+        let event = wl_touch::Event::Down {
+            serial: 1,
+            time: 0,
+            surface,
+            id: 0,
+            x,
+            y,
+        };
+        Locker::event(&mut locker, &touch, event, &(), &fixture.conn, &fixture.qh);
+        assert_eq!(locker.app.password.as_str(), "5");
+        assert_eq!(locker.focused_output, Some(7));
+    }
+
+    #[test]
+    fn a_locker_takes_its_settings_from_the_config() {
+        let fixture = Fixture::new();
+        let locker = fixture.locker(LockConfig {
+            preferred_output: Some("DP-1".to_string()),
+            box_placement: BoxPlacement::All,
+            commit_retry_budget: 7,
+            theme: Theme {
+                box_alpha: 128,
+                ..Theme::default()
+            },
+            ..LockConfig::default()
+        });
+        assert_eq!(locker.preferred_output.as_deref(), Some("DP-1"));
+        assert_eq!(locker.box_placement, BoxPlacement::All);
+        assert_eq!(locker.commit_retry_budget, 7);
+        assert_eq!(locker.app.theme.box_alpha, 128);
+        assert_eq!(locker.layers.background_color(), Some(0xFF0000FF));
+
+        // nothing is bound until the registry advertises it
+        assert!(locker.lock_manager.is_none() && locker.compositor.is_none());
+        assert!(locker.monitors.is_empty());
+    }
+
+    #[test]
+    fn the_config_file_feeds_the_lock_config() {
+        let file = Config::parse(
+            r##"
+background = "#1E1E2E"
+box_on = "focused"
+output = "DP-1"
+first_frame = "composed"
+commit_retry_budget = 7
+allowed_users = ["alice"]
+lock_reminder_secs = 60
+pin_pad = true
+failure_bell = true
+idle_timeout_secs = 0
+connect_attempts = 0
+connect_retry_ms = 250
+
+[font]
+size = 20.0
+"##,
+        )
+        .unwrap();
+        let config = LockConfig::from_file(&file).unwrap();
+        assert_eq!(config.placeholder_color, 0xFF1E1E2E);
+        assert_eq!(config.box_placement, BoxPlacement::Focused);
+        assert_eq!(config.preferred_output.as_deref(), Some("DP-1"));
+        assert_eq!(config.first_frame, FirstFrame::Composed);
+        assert_eq!(config.commit_retry_budget, 7);
+        assert_eq!(config.allowed_users, Some(vec!["alice".to_string()]));
+        assert_eq!(config.lock_reminder.after, Some(Duration::from_secs(60)));
+        assert!(config.pin_pad);
+        assert!(config.failure_flash && config.failure_bell);
+        assert_eq!(config.idle_after, None);
+        // connecting is always tried at least once
+        assert_eq!(config.connect_attempts, 1);
+        assert_eq!(config.connect_retry_delay, Duration::from_millis(250));
+        assert_eq!(config.font_size, 20.0);
+
+        let file = Config::parse("box_on = \"everywhere\"").unwrap();
+        assert!(LockConfig::from_file(&file).is_err());
+        // a zero size would make one pixel cells and a huge grid
+        let file = Config::parse("[font]\nsize = 0.0").unwrap();
+        assert!(LockConfig::from_file(&file).is_err());
+    }
+
+    #[test]
+    fn the_environment_overrides_the_config_file() {
+        let file = Config::parse(
+            r#"
+pin_pad = true
+idle_timeout_secs = 30
+remember_user = true
+allowed_users = ["alice"]
+
+[box]
+border = "double"
+"#,
+        )
+        .unwrap();
+        let mut config = LockConfig::from_file(&file).unwrap();
+        config
+            .apply_env(&Vars::fixed(&[
+                ("LILAC_PIN_PAD", "0"),
+                ("LILAC_IDLE_SECS", "0"),
+                ("LILAC_REMEMBER_USER", "off"),
+                ("LILAC_ALLOWED_USERS", "bob,carol"),
+                ("LILAC_CONNECT_ATTEMPTS", "0"),
+                ("LILAC_BORDER", "thick"),
+            ]))
+            .unwrap();
+        assert!(!config.pin_pad);
+        assert_eq!(config.theme.border, tui::BoxBorder::Thick);
+        assert_eq!(config.idle_after, None);
+        assert_eq!(config.last_user_file, None);
+        assert_eq!(
+            config.allowed_users,
+            Some(vec!["bob".to_string(), "carol".to_string()])
+        );
+        assert_eq!(config.connect_attempts, 1);
+        let app = config.app_state();
+        assert!(!app.pin_pad);
+        assert_eq!(app.idle_after, None);
+
+        // unset variables leave the file's settings alone
+        let mut config = LockConfig::from_file(&file).unwrap();
+        config.apply_env(&Vars::fixed(&[])).unwrap();
+        assert!(config.pin_pad);
+        assert_eq!(config.idle_after, Some(Duration::from_secs(30)));
+        assert_eq!(config.theme.border, tui::BoxBorder::Double);
+
+        let mut config = LockConfig::from_file(&file).unwrap();
+        assert!(
+            config
+                .apply_env(&Vars::fixed(&[("LILAC_PIN_PAD", "maybe")]))
+                .is_err()
+        );
+        assert!(
+            config
+                .apply_env(&Vars::fixed(&[("LILAC_BORDER", "wavy")]))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn the_last_user_to_unlock_is_prefilled() {
+        let path = std::env::temp_dir()
+            .join(format!("lilac-prefill-{}", std::process::id()))
+            .join("last_user");
+        let config = LockConfig {
+            last_user_file: Some(path.clone()),
+            ..LockConfig::default()
+        };
+        let app = config.app_state();
+        assert!(app.username.is_empty());
+        assert_eq!(app.focused, FocusTarget::Username);
+
+        last_user::save(&path, "alice").unwrap();
+        let app = config.app_state();
+        assert_eq!(app.username, "alice");
+        assert_eq!(app.focused, FocusTarget::Password);
+
+        // left off, nothing is remembered even with a file around
+        let app = LockConfig::default().app_state();
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        assert!(app.username.is_empty());
+    }
+
+    #[test]
+    fn the_user_list_picks_from_the_allowed_users() {
+        let users = vec!["alice".to_string(), "bob".to_string()];
+        let config = LockConfig {
+            allowed_users: Some(users.clone()),
+            user_list: true,
+            ..LockConfig::default()
+        };
+        let app = config.app_state();
+        assert_eq!(app.user_list, users);
+        assert_eq!(app.username, "alice");
+        assert_eq!(app.focused, FocusTarget::UserList);
+
+        // without an allowlist there's nothing to pick from
+        let config = LockConfig {
+            user_list: true,
+            ..LockConfig::default()
+        };
+        let app = config.app_state();
+        assert!(app.user_list.is_empty());
+        assert_eq!(app.focused, FocusTarget::Username);
+    }
+
+    #[test]
+    fn the_paste_pipe_reads_without_blocking() {
+        let (read, write) = nonblocking_pipe().unwrap();
+        let mut reader = File::from(read);
+        let mut buf = [0u8; 8];
+        let err = reader.read(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::WouldBlock);
+
+        File::from(write).write_all(b"hunter2").unwrap();
+        assert_eq!(reader.read(&mut buf).unwrap(), 7);
+        assert_eq!(&buf[..7], b"hunter2");
+        // the write end is closed, so the owner is done
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn a_finished_lock_is_logged() {
+        let fixture = Fixture::new();
+        let manager = fixture
+            .registry
+            .bind::<ExtSessionLockManagerV1, (), Locker>(3, 1, &fixture.qh, ());
+        let lock = manager.lock(&fixture.qh, ());
+        let mut locker = fixture.locker(LockConfig::default());
+
+        let log = crate::log::capture();
+        Locker::event(
+            &mut locker,
+            &lock,
+            ext_session_lock_v1::Event::Finished,
+            &(),
+            &fixture.conn,
+            &fixture.qh,
+        );
+        assert!(locker.machine.state() == LockState::Finished);
+        assert_eq!(
+            log.lines(),
+            ["DEBUG received ext_session_lock_v1::Finished"]
+        );
+    }
+
+    // What a detached hook wrote to `path`, waiting a while for it to become `expected`. The
+    // file is removed afterwards.
+    fn written_by_hook(path: &std::path::Path, expected: &str) -> String {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut written = String::new();
+        while written != expected && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+            written = std::fs::read_to_string(path).unwrap_or_default();
+        }
+        let _ = std::fs::remove_file(path);
+        written
+    }
+
+    #[test]
+    fn unlocking_runs_the_on_unlock_hook_as_the_user() {
+        let fixture = Fixture::new();
+        let manager = fixture
+            .registry
+            .bind::<ExtSessionLockManagerV1, (), Locker>(3, 1, &fixture.qh, ());
+        let sentinel = std::env::temp_dir().join(format!("lilac-on-unlock-{}", std::process::id()));
+        let mut locker = fixture.locker(LockConfig {
+            on_unlock: Some(format!(
+                "printf %s \"$LILAC_USER\" > '{}'",
+                sentinel.display()
+            )),
+            ..LockConfig::default()
+        });
+        locker.app.username = "alice".to_string();
+        locker.lock = Some(manager.lock(&fixture.qh, ()));
+        locker.machine.request_lock();
+        locker.machine.on_locked();
+
+        locker.unlock(&fixture.conn).unwrap();
+        assert!(locker.lock.is_none());
+
+        assert_eq!(written_by_hook(&sentinel, "alice"), "alice");
+    }
+
+    #[test]
+    fn locking_runs_the_on_lock_hook_with_the_monitors() {
+        let fixture = Fixture::new();
+        let manager = fixture
+            .registry
+            .bind::<ExtSessionLockManagerV1, (), Locker>(3, 1, &fixture.qh, ());
+        let lock = manager.lock(&fixture.qh, ());
+        let sentinel = std::env::temp_dir().join(format!("lilac-on-lock-{}", std::process::id()));
+        let mut locker = fixture.locker(LockConfig {
+            on_lock: Some(format!(
+                "printf %s \"$LILAC_MONITORS\" > '{}'",
+                sentinel.display()
+            )),
+            ..LockConfig::default()
+        });
+        for name in [1, 2] {
+            let monitor = Monitor::default().with_name(name);
+            locker.monitors.insert(name, monitor);
+        }
+        locker.monitors.get_mut(&2).unwrap().output_name = Some("DP-1".to_string());
+        locker.machine.request_lock();
+
+        Locker::event(
+            &mut locker,
+            &lock,
+            ext_session_lock_v1::Event::Locked,
+            &(),
+            &fixture.conn,
+            &fixture.qh,
+        );
+        assert!(locker.machine.state() == LockState::Locked);
+        let expected = "output 1,DP-1";
+        assert_eq!(written_by_hook(&sentinel, expected), expected);
+    }
+
+    // Binds every global a lock needs and gives each of `names` a monitor with a lock surface.
+    fn lock_outputs(fixture: &Fixture, locker: &mut Locker, names: &[u32]) -> ExtSessionLockV1 {
+        let (registry, qh) = (&fixture.registry, &fixture.qh);
+        locker.compositor = Some(registry.bind::<WlCompositor, (), Locker>(1, 1, qh, ()));
+        locker.shared_memory = Some(registry.bind::<WlShm, (), Locker>(2, 1, qh, ()));
+        locker.single_pixel_buffer_manager =
+            Some(registry.bind::<WpSinglePixelBufferManagerV1, (), Locker>(3, 1, qh, ()));
+        locker.viewporter = Some(registry.bind::<WpViewporter, (), Locker>(4, 1, qh, ()));
+        let manager = registry.bind::<ExtSessionLockManagerV1, (), Locker>(5, 1, qh, ());
+        let lock = manager.lock(qh, ());
+        locker.machine.request_lock();
+
+        for &name in names {
+            let tag = OutputTag { monitor_name: name };
+            let output = registry.bind::<WlOutput, OutputTag, Locker>(name, 4, qh, tag);
+            let mut monitor = Monitor::default().with_name(name).with_output(output);
+            // its output is done, so a configure is presented straight away
+            monitor.done = true;
+            let compositor = locker.compositor.as_ref().unwrap();
+            monitor
+                .create_surface_and_lock(compositor, &lock, None, qh)
+                .unwrap();
+            locker.monitors.insert(name, monitor);
+        }
+        lock
+    }
+
+    fn configure(fixture: &Fixture, locker: &mut Locker, name: u32) {
+        let lock_surface = locker.monitors[&name].lock_surface.clone().unwrap();
+        let event = ext_session_lock_surface_v1::Event::Configure {
+            serial: name,
+            width: 320,
+            height: 160,
+        };
+        let (conn, qh) = (&fixture.conn, &fixture.qh);
+        let tag = SurfaceTag { monitor_name: name };
+        Locker::event(locker, &lock_surface, event, &tag, conn, qh);
+    }
+
+    #[test]
+    fn every_output_is_prefilled_before_locked() {
+        let fixture = Fixture::new();
+        let mut locker = fixture.locker(LockConfig::default());
+        let lock = lock_outputs(&fixture, &mut locker, &[10, 11]);
+        let locked = |locker: &mut Locker| {
+            let event = ext_session_lock_v1::Event::Locked;
+            Locker::event(locker, &lock, event, &(), &fixture.conn, &fixture.qh);
+        };
+
+        // a compositor that sends locked too early is caught
+        configure(&fixture, &mut locker, 10);
+        let log = crate::log::capture();
+        locked(&mut locker);
+        assert!(log.contains("locked before a frame was committed on output 11"));
+        drop(log);
+
+        configure(&fixture, &mut locker, 11);
+        for monitor in locker.monitors.values() {
+            assert!(monitor.first_frame_committed);
+            // the placeholder until the main loop commits the composed frame
+            assert!(monitor.single_pixel.is_some());
+            assert!(monitor.buffer_state.as_ref().unwrap().dirty);
+        }
+        let log = crate::log::capture();
+        locked(&mut locker);
+        assert!(!log.contains("locked before a frame was committed"));
+    }
+
+    #[test]
+    fn only_the_monitors_showing_the_box_render_it() {
+        let fixture = Fixture::new();
+        let mut locker = fixture.locker(LockConfig::default());
+        let _lock = lock_outputs(&fixture, &mut locker, &[10, 11]);
+        locker.monitors.get_mut(&10).unwrap().primary = true;
+        configure(&fixture, &mut locker, 10);
+        configure(&fixture, &mut locker, 11);
+
+        let hash = |locker: &Locker, name| {
+            let buffer_state = locker.monitors[&name].buffer_state.as_ref().unwrap();
+            buffer_state.pending_hash.unwrap()
+        };
+        locker.render_frame();
+        assert_ne!(hash(&locker, 10), hash(&locker, 11));
+        // both at the same tick, once the box is on each
+        locker.monitors.get_mut(&11).unwrap().primary = true;
+        locker.render_frame();
+        assert_eq!(hash(&locker, 10), hash(&locker, 11));
+    }
+
+    #[test]
+    fn monitors_without_the_box_or_motion_rest_on_a_single_pixel() {
+        let fixture = Fixture::new();
+        let mut locker = fixture.locker(LockConfig {
+            reduced_motion: true,
+            ..LockConfig::default()
+        });
+        let _lock = lock_outputs(&fixture, &mut locker, &[10, 11]);
+        locker.monitors.get_mut(&10).unwrap().primary = true;
+        configure(&fixture, &mut locker, 10);
+        configure(&fixture, &mut locker, 11);
+
+        let dirty = |locker: &Locker, name| {
+            let buffer_state = locker.monitors[&name].buffer_state.as_ref().unwrap();
+            buffer_state.dirty
+        };
+        for _ in 0..2 {
+            // no shm buffers are allocated for it at all
+            assert!(locker.monitors[&11].shows_solid_color());
+            assert!(dirty(&locker, 10));
+            locker.render_frame();
+        }
+        assert!(locker.monitors_to_represent().is_empty());
+
+        // the box moving over needs full size buffers again
+        locker.monitors.get_mut(&11).unwrap().primary = true;
+        assert_eq!(locker.monitors_to_represent(), [11]);
+        locker.monitors.get_mut(&11).unwrap().pending_configure = Some((320, 160));
+        locker.present(11, &fixture.qh);
+        assert!(!locker.monitors[&11].shows_solid_color());
+        assert!(dirty(&locker, 11));
+        assert!(locker.monitors_to_represent().is_empty());
+    }
+
+    #[test]
+    fn a_configure_waits_for_the_output_and_falls_back_on_its_mode() {
+        let fixture = Fixture::new();
+        let mut locker = fixture.locker(LockConfig::default());
+        let _lock = lock_outputs(&fixture, &mut locker, &[10]);
+        locker.monitors.get_mut(&10).unwrap().done = false;
+        let (conn, qh) = (&fixture.conn, &fixture.qh);
+
+        // a zero size leaves it to us, but the output hasn't said what it is yet
+        let lock_surface = locker.monitors[&10].lock_surface.clone().unwrap();
+        let configure = ext_session_lock_surface_v1::Event::Configure {
+            serial: 1,
+            width: 0,
+            height: 0,
+        };
+        let tag = SurfaceTag { monitor_name: 10 };
+        Locker::event(&mut locker, &lock_surface, configure, &tag, conn, qh);
+        assert!(locker.monitors[&10].buffer_state.is_none());
+
+        let output = locker.monitors[&10].output.clone().unwrap();
+        let tag = OutputTag { monitor_name: 10 };
+        let events = [
+            wl_output::Event::Geometry {
+                x: 0,
+                y: 0,
+                physical_width: 340,
+                physical_height: 600,
+                subpixel: WEnum::Value(wl_output::Subpixel::Unknown),
+                make: "Dell Inc.".to_string(),
+                model: "U2720Q".to_string(),
+                transform: WEnum::Value(wl_output::Transform::_90),
+            },
+            wl_output::Event::Mode {
+                flags: WEnum::Value(wl_output::Mode::Current),
+                width: 2560,
+                height: 1440,
+                refresh: 60000,
+            },
+            wl_output::Event::Scale { factor: 2 },
+            wl_output::Event::Done,
+        ];
+        for event in events {
+            Locker::event(&mut locker, &output, event, &tag, conn, qh);
+        }
+
+        // the mode turned upright and in surface coordinates
+        let monitor = &locker.monitors[&10];
+        assert_eq!(monitor.dimensions, (720, 1280));
+        assert_eq!(monitor.physical_size, (340, 600));
+        assert!(monitor.buffer_state.is_some());
+        assert_eq!(monitor.pending_configure, None);
+    }
+
+    // The monitor `select_primary` picks out of DP-1 (1) and DP-2 (2), with `focused` focused.
+    fn primary_for(preferred: Option<&str>, focused: Option<u32>) -> u32 {
+        let fixture = Fixture::new();
+        let mut locker = fixture.locker(LockConfig {
+            preferred_output: preferred.map(str::to_string),
+            ..LockConfig::default()
+        });
+        locker.focused_output = focused;
+        for (name, output_name, description) in [
+            (1, "DP-1", "Dell Inc. DELL U2720Q"),
+            (2, "DP-2", "LG Electronics LG ULTRAFINE"),
+        ] {
+            let monitor = Monitor {
+                output_name: Some(output_name.to_string()),
+                description: Some(description.to_string()),
+                ..Monitor::default().with_name(name)
+            };
+            locker.monitors.insert(name, monitor);
+        }
+        locker.select_primary();
+        let primary: Vec<_> = locker
+            .monitors
+            .values()
+            .filter(|monitor| monitor.primary)
+            .map(|monitor| monitor.name)
+            .collect();
+        assert_eq!(primary.len(), 1, "{primary:?}");
+        primary[0]
+    }
+
+    #[test]
+    fn the_preferred_output_is_primary() {
+        // by name or by description, whatever has focus
+        assert_eq!(primary_for(Some("DP-2"), None), 2);
+        assert_eq!(primary_for(Some("DP-2"), Some(1)), 2);
+        assert_eq!(primary_for(Some("LG Electronics LG ULTRAFINE"), Some(1)), 2);
+        assert_eq!(primary_for(Some("Dell Inc. DELL U2720Q"), Some(2)), 1);
+    }
+
+    #[test]
+    fn without_a_match_the_focused_output_is_primary() {
+        assert_eq!(primary_for(Some("HDMI-A-1"), Some(2)), 2);
+        assert_eq!(primary_for(None, Some(2)), 2);
+        // before anything has focus, or focus is on an output that's gone, the first one
+        assert_eq!(primary_for(Some("HDMI-A-1"), None), 1);
+        assert_eq!(primary_for(None, Some(9)), 1);
+    }
+
+    #[test]
+    fn the_json_probe_report_parses_back() {
+        let fixture = Fixture::new();
+        let mut locker = fixture.locker(LockConfig::default());
+        locker.globals = vec![
+            (3, "wl_output".to_string(), 1),
+            (1, "ext_session_lock_manager_v1".to_string(), 1),
+        ];
+        let description = "Dell \"U2720Q\"\\27\tinch\u{1}";
+        let monitor = Monitor {
+            description: Some(description.to_string()),
+            mode: Some((3840, 2160, 60000)),
+            ..Monitor::default().with_name(3)
+        };
+        locker.monitors.insert(3, monitor);
+
+        let report: serde_json::Value = serde_json::from_str(&probe_report(&locker, true)).unwrap();
+        assert_eq!(report["session_lock"], true);
+        assert_eq!(
+            report["globals"][0]["interface"],
+            "ext_session_lock_manager_v1"
+        );
+        let output = &report["outputs"][0];
+        assert_eq!(output["description"], description);
+        assert_eq!(output["name"], serde_json::Value::Null);
+        assert_eq!(output["mode"]["width"], 3840);
+        // a v1 output never sends its scale
+        assert_eq!(output["scale"], 1);
+        assert!(probe_report(&locker, false).contains("scale 1\n"));
+    }
+
+    #[test]
+    fn an_unplugged_output_is_dropped_and_the_box_moves() {
+        let fixture = Fixture::new();
+        let mut locker = fixture.locker(LockConfig::default());
+        let lock = lock_outputs(&fixture, &mut locker, &[10, 11]);
+        locker.lock = Some(lock);
+        let output = |name| (name, "wl_output".to_string(), 4);
+        locker.globals = vec![output(10), output(11)];
+        configure(&fixture, &mut locker, 10);
+        locker.select_primary();
+        assert!(locker.monitors[&10].primary);
+
+        let event = wl_registry::Event::GlobalRemove { name: 10 };
+        let (registry, conn, qh) = (&fixture.registry, &fixture.conn, &fixture.qh);
+        Locker::event(&mut locker, registry, event, &(), conn, qh);
+        assert!(!locker.monitors.contains_key(&10));
+        assert!(locker.monitors[&11].primary);
+        assert_eq!(locker.globals, [output(11)]);
+    }
+
+    #[test]
+    fn an_output_plugged_in_while_locked_is_locked_at_once() {
+        let fixture = Fixture::new();
+        let mut locker = fixture.locker(LockConfig::default());
+        let lock = lock_outputs(&fixture, &mut locker, &[10]);
+        locker.lock = Some(lock);
+
+        let event = wl_registry::Event::Global {
+            name: 12,
+            interface: "wl_output".to_string(),
+            version: 4,
+        };
+        let (registry, conn, qh) = (&fixture.registry, &fixture.conn, &fixture.qh);
+        Locker::event(&mut locker, registry, event, &(), conn, qh);
+        let monitor = &locker.monitors[&12];
+        assert!(monitor.surface.is_some());
+        assert!(monitor.lock_surface.is_some());
+    }
+
+    #[test]
+    fn an_identical_configure_reuses_the_buffers() {
+        let fixture = Fixture::new();
+        let mut locker = fixture.locker(LockConfig::default());
+        let _lock = lock_outputs(&fixture, &mut locker, &[10]);
+        let buffer_id = |locker: &Locker| {
+            let buffer_state = locker.monitors[&10].buffer_state.as_ref().unwrap();
+            buffer_state.buffers[0].buffer.id()
+        };
+
+        let in_use = |locker: &Locker| {
+            let buffer_state = locker.monitors[&10].buffer_state.as_ref().unwrap();
+            let buffers = &buffer_state.buffers;
+            buffers.iter().filter(|slot| slot.in_use).count()
+        };
+
+        configure(&fixture, &mut locker, 10);
+        let first = buffer_id(&locker);
+        let monitor = locker.monitors.get_mut(&10).unwrap();
+        assert!(monitor.commit(&fixture.qh).unwrap());
+        assert_eq!(in_use(&locker), 1);
+        configure(&fixture, &mut locker, 10);
+        assert_eq!(buffer_id(&locker), first);
+        // the same frame is rendered again into the other buffer, and committed by the main
+        // loop once the compositor is done with the last one, completing the configure
+        assert!(locker.monitors[&10].buffer_state.as_ref().unwrap().dirty);
+        assert_eq!(in_use(&locker), 1);
+        locker.monitors.get_mut(&10).unwrap().frame_done();
+        locker.after_dispatch(&fixture.qh).unwrap();
+        assert_eq!(in_use(&locker), 2);
+
+        // a new size needs new buffers
+        let lock_surface = locker.monitors[&10].lock_surface.clone().unwrap();
+        let event = ext_session_lock_surface_v1::Event::Configure {
+            serial: 2,
+            width: 640,
+            height: 160,
+        };
+        let (conn, qh) = (&fixture.conn, &fixture.qh);
+        let tag = SurfaceTag { monitor_name: 10 };
+        Locker::event(&mut locker, &lock_surface, event, &tag, conn, qh);
+        assert_ne!(buffer_id(&locker), first);
+        let buffer_state = locker.monitors[&10].buffer_state.as_ref().unwrap();
+        assert_eq!(buffer_state.size(), (640, 160));
+    }
+
+    // wl_surface request opcodes
+    const SURFACE_ATTACH: u16 = 1;
+    const SURFACE_COMMIT: u16 = 6;
+
+    #[test]
+    fn a_configure_is_committed_once_by_the_main_loop() {
+        let mut fixture = Fixture::new();
+        let mut locker = fixture.locker(LockConfig {
+            first_frame: FirstFrame::Composed,
+            ..LockConfig::default()
+        });
+        let _lock = lock_outputs(&fixture, &mut locker, &[10]);
+        let surface = locker.monitors[&10].surface.clone().unwrap();
+        let surface = surface.id().protocol_id();
+        requests(&mut fixture);
+
+        // the handler only renders, leaving both buffers free for the commit below
+        configure(&fixture, &mut locker, 10);
+        assert!(!locker.monitors[&10].frame_pending);
+        locker.after_dispatch(&fixture.qh).unwrap();
+        locker.after_dispatch(&fixture.qh).unwrap();
+
+        let sent = requests(&mut fixture);
+        let count = |opcode| sent.iter().filter(|&&req| req == (surface, opcode)).count();
+        assert_eq!(count(SURFACE_ATTACH), 1);
+        assert_eq!(count(SURFACE_COMMIT), 1);
+        let buffers = &locker.monitors[&10].buffer_state.as_ref().unwrap().buffers;
+        assert_eq!(buffers.iter().filter(|slot| slot.in_use).count(), 1);
+    }
+
+    #[test]
+    fn a_commit_damages_only_the_cells_that_changed() {
+        let fixture = Fixture::new();
+        let mut locker = fixture.locker(LockConfig {
+            first_frame: FirstFrame::Composed,
+            ..LockConfig::default()
+        });
+        let _lock = lock_outputs(&fixture, &mut locker, &[10]);
+        locker.monitors.get_mut(&10).unwrap().primary = true;
+        let damage = |locker: &Locker| {
+            let buffer_state = locker.monitors[&10].buffer_state.as_ref().unwrap();
+            buffer_state.damage()
+        };
+
+        // nothing to compare the first frame with
+        configure(&fixture, &mut locker, 10);
+        assert_eq!(damage(&locker), [(0, 0, 320, 160)]);
+        locker.after_dispatch(&fixture.qh).unwrap();
+
+        locker.monitors.get_mut(&10).unwrap().frame_done();
+        locker.app.username = "alice".to_string();
+        locker.render_all();
+        let damage = damage(&locker);
+        let damaged: i32 = damage.iter().map(|&(_, _, w, h)| w * h).sum();
+        assert!(0 < damaged && damaged < 320 * 160 / 4, "{damage:?}");
+
+        // a configure starts over from the whole buffer
+        configure(&fixture, &mut locker, 10);
+        let buffer_state = locker.monitors[&10].buffer_state.as_ref().unwrap();
+        assert_eq!(buffer_state.damage(), [(0, 0, 320, 160)]);
+    }
+
+    #[test]
+    fn both_buffers_share_one_pool_without_overlapping() {
+        let fixture = Fixture::new();
+        let mut monitor = fixture.monitor(4, 2);
+        let buffer_state = monitor.buffer_state.as_mut().unwrap();
+        assert_eq!(buffer_state.buffers[0].offset, 0);
+        assert_eq!(buffer_state.buffers[1].offset, 4 * 4 * 2);
+
+        buffer_state.pixel_buffer(0).fill_solid_color(0xFFFFFFFF);
+        assert!(buffer_state.pixels(0).iter().all(|&byte| byte == 0xFF));
+        // the other one is still as allocated
+        let opaque_black = 0xFF00_0000u32.to_ne_bytes();
+        let mut untouched = buffer_state.pixels(1).chunks(4);
+        assert!(untouched.all(|px| *px == opaque_black));
+    }
+
+    #[test]
+    fn every_buffer_starts_out_opaque() {
+        let fixture = Fixture::new();
+        let monitor = fixture.monitor(5, 3);
+        let buffer_state = monitor.buffer_state.as_ref().unwrap();
+        for index in 0..buffer_state.buffers.len() {
+            let pixels = buffer_state.pixels(index);
+            assert_eq!(pixels.len(), 5 * 3 * 4);
+            for px in pixels.chunks(4) {
+                let argb = u32::from_ne_bytes(px.try_into().unwrap());
+                assert_eq!(argb >> 24, 0xFF, "{argb:#010X}");
+            }
+        }
+    }
+
+    #[test]
+    fn a_translucent_first_frame_is_committed_opaque() {
+        let fixture = Fixture::new();
+        let mut monitor = fixture.monitor(4, 2);
+        let buffer_state = monitor.buffer_state.as_mut().unwrap();
+        buffer_state.render(|pixels| pixels.fill_solid_color(0x0011_2233));
+        let index = buffer_state.pending_index.unwrap();
+        monitor.commit(&fixture.qh).unwrap();
+
+        let buffer_state = monitor.buffer_state.as_mut().unwrap();
+        let pixels = buffer_state.pixel_buffer(index);
+        assert_eq!(pixels.pixel(3, 1), Some(0xFF11_2233));
+    }
+
+    #[test]
+    fn a_committed_monitor_waits_for_its_frame_callback() {
+        let fixture = Fixture::new();
+        let mut locker = fixture.locker(LockConfig::default());
+        let _lock = lock_outputs(&fixture, &mut locker, &[10]);
+        locker.monitors.get_mut(&10).unwrap().primary = true;
+        configure(&fixture, &mut locker, 10);
+        let dirty = |locker: &Locker| locker.monitors[&10].buffer_state.as_ref().unwrap().dirty;
+
+        let monitor = locker.monitors.get_mut(&10).unwrap();
+        assert!(monitor.commit(&fixture.qh).unwrap());
+        assert!(monitor.frame_pending);
+        // nothing is drawn while the compositor hasn't asked for a frame
+        locker.app.username = "alice".to_string();
+        locker.render_all();
+        assert!(!dirty(&locker));
+
+        let surface = locker.monitors[&10].surface.clone().unwrap();
+        let (conn, qh) = (&fixture.conn, &fixture.qh);
+        let callback = surface.frame(qh, FrameTag { monitor_name: 10 });
+        let done = wl_callback::Event::Done { callback_data: 0 };
+        let tag = FrameTag { monitor_name: 10 };
+        Locker::event(&mut locker, &callback, done, &tag, conn, qh);
+        assert!(!locker.monitors[&10].frame_pending);
+        locker.render_all();
+        assert!(dirty(&locker));
+    }
+
+    #[test]
+    fn oversized_configures_are_skipped_without_panicking() {
+        let fixture = Fixture::new();
+        let (registry, qh) = (&fixture.registry, &fixture.qh);
+        let shm = registry.bind::<WlShm, (), Locker>(2, 1, qh, ());
+        for (width, height) in [(0, 10), (-1, 10), (i32::MAX, 1), (40_000, 40_000)] {
+            let format = wl_shm::Format::Argb8888;
+            let size = (width, height);
+            assert!(BufferState::new(&shm, qh, 1, size, format, DEFAULT_BUFFER_COUNT).is_err());
+        }
+
+        let mut locker = fixture.locker(LockConfig::default());
+        let _lock = lock_outputs(&fixture, &mut locker, &[10]);
+        let lock_surface = locker.monitors[&10].lock_surface.clone().unwrap();
+        let event = ext_session_lock_surface_v1::Event::Configure {
+            serial: 1,
+            width: u32::MAX,
+            height: 160,
+        };
+        let log = crate::log::capture();
+        let tag = SurfaceTag { monitor_name: 10 };
+        Locker::event(&mut locker, &lock_surface, event, &tag, &fixture.conn, qh);
+        assert!(log.contains("which is too large for a buffer"));
+        assert!(locker.monitors[&10].buffer_state.is_none());
+    }
+
+    #[test]
+    fn buffers_fall_back_to_xrgb_when_argb_is_not_offered() {
+        let fixture = Fixture::new();
+        let mut locker = fixture.locker(LockConfig::default());
+        let shm = fixture
+            .registry
+            .bind::<WlShm, (), Locker>(2, 1, &fixture.qh, ());
+        let offer = |locker: &mut Locker, format| {
+            let event = wl_shm::Event::Format {
+                format: WEnum::Value(format),
+            };
+            Locker::event(locker, &shm, event, &(), &fixture.conn, &fixture.qh);
+        };
+
+        offer(&mut locker, wl_shm::Format::Rgb565);
+        assert!(locker.select_shm_format().is_err());
+        offer(&mut locker, wl_shm::Format::Xrgb8888);
+        locker.select_shm_format().unwrap();
+        assert_eq!(locker.shm_format, wl_shm::Format::Xrgb8888);
+        offer(&mut locker, wl_shm::Format::Argb8888);
+        locker.select_shm_format().unwrap();
+        assert_eq!(locker.shm_format, wl_shm::Format::Argb8888);
+    }
+
+    // A keyboard on a seat, with a US keymap shared the way a compositor shares it.
+    fn keyboard(fixture: &Fixture, locker: &mut Locker) -> WlKeyboard {
+        let seat = fixture
+            .registry
+            .bind::<WlSeat, (), Locker>(6, WL_SEAT_VERSION, &fixture.qh, ());
+        let keyboard = seat.get_keyboard(&fixture.qh, ());
+
+        let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+        let keymap = xkb::Keymap::new_from_names(&context, "", "", "us", "", None, 0)
+            .unwrap()
+            .get_as_string(xkb::KEYMAP_FORMAT_TEXT_V1);
+        let mut file = MemfdOptions::default()
+            .create("keymap")
+            .unwrap()
+            .into_file();
+        file.write_all(keymap.as_bytes()).unwrap();
+        let event = wl_keyboard::Event::Keymap {
+            format: WEnum::Value(wl_keyboard::KeymapFormat::XkbV1),
+            fd: file.into(),
+            size: keymap.len() as u32,
+        };
+        Locker::event(locker, &keyboard, event, &(), &fixture.conn, &fixture.qh);
+        assert!(locker.xkb.is_some());
+        keyboard
+    }
+
+    #[test]
+    fn keys_are_dropped_until_the_lock_is_confirmed() {
+        let fixture = Fixture::new();
+        let manager = fixture
+            .registry
+            .bind::<ExtSessionLockManagerV1, (), Locker>(3, 1, &fixture.qh, ());
+        let lock = manager.lock(&fixture.qh, ());
+        let mut locker = fixture.locker(LockConfig::default());
+        let keyboard = keyboard(&fixture, &mut locker);
+        let type_keys = |locker: &mut Locker| {
+            // a, l, i, c, e
+            for key in [30, 38, 23, 46, 18] {
+                let event = wl_keyboard::Event::Key {
+                    serial: 0,
+                    time: 0,
+                    key,
+                    state: WEnum::Value(wl_keyboard::KeyState::Pressed),
+                };
+                Locker::event(locker, &keyboard, event, &(), &fixture.conn, &fixture.qh);
+            }
+        };
+
+        // the lock has been requested but not confirmed
+        locker.machine.request_lock();
+        type_keys(&mut locker);
+        assert!(locker.app.username.is_empty());
+
+        Locker::event(
+            &mut locker,
+            &lock,
+            ext_session_lock_v1::Event::Locked,
+            &(),
+            &fixture.conn,
+            &fixture.qh,
+        );
+        type_keys(&mut locker);
+        assert_eq!(locker.app.username, "alice");
+    }
+
+    // ext_session_lock_v1 request opcodes
+    const LOCK_DESTROY: u16 = 0;
+    const LOCK_UNLOCK_AND_DESTROY: u16 = 2;
+
+    // Every request sent so far as (object id, opcode), read back off the fixture's socket.
+    fn requests(fixture: &mut Fixture) -> Vec<(u32, u16)> {
+        fixture.conn.flush().unwrap();
+        fixture.server.set_nonblocking(true).unwrap();
+        let mut bytes = Vec::new();
+        // ends with WouldBlock once everything sent so far has been read
+        let _ = fixture.server.read_to_end(&mut bytes);
+        let mut requests = Vec::new();
+        let mut rest = bytes.as_slice();
+        while rest.len() >= 8 {
+            let id = u32::from_ne_bytes(rest[0..4].try_into().unwrap());
+            let header = u32::from_ne_bytes(rest[4..8].try_into().unwrap());
+            requests.push((id, (header & 0xFFFF) as u16));
+            let size = (header >> 16) as usize;
+            rest = &rest[size.clamp(8, rest.len())..];
+        }
+        requests
+    }
+
+    // Drops a locker holding a lock in `state`, returning the lock's id and what was sent.
+    fn drop_holding_lock(state: LockState) -> (u32, Vec<(u32, u16)>) {
+        let mut fixture = Fixture::new();
+        let manager = fixture
+            .registry
+            .bind::<ExtSessionLockManagerV1, (), Locker>(3, 1, &fixture.qh, ());
+        let lock = manager.lock(&fixture.qh, ());
+        let id = lock.id().protocol_id();
+        let mut locker = fixture.locker(LockConfig::default());
+        locker.lock = Some(lock);
+        locker.machine.request_lock();
+        if state == LockState::Locked {
+            locker.machine.on_locked();
+        }
+
+        drop(locker);
+        (id, requests(&mut fixture))
+    }
+
+    #[test]
+    fn dropping_a_confirmed_lock_leaves_the_session_locked() {
+        let (lock, sent) = drop_holding_lock(LockState::Locked);
+        assert!(!sent.contains(&(lock, LOCK_UNLOCK_AND_DESTROY)));
+        assert!(!sent.contains(&(lock, LOCK_DESTROY)));
+    }
+
+    #[test]
+    fn dropping_an_unconfirmed_lock_destroys_it() {
+        let (lock, sent) = drop_holding_lock(LockState::Waiting);
+        assert!(sent.contains(&(lock, LOCK_DESTROY)));
+        assert!(!sent.contains(&(lock, LOCK_UNLOCK_AND_DESTROY)));
+    }
+
+    // Sends finished to a lock over one configured output, after locked when `locked` is set,
+    // returning the lock's id and what was sent in response.
+    fn finish(locked: bool) -> (Locker, u32, Vec<(u32, u16)>) {
+        let mut fixture = Fixture::new();
+        let mut locker = fixture.locker(LockConfig::default());
+        let lock = lock_outputs(&fixture, &mut locker, &[10]);
+        let id = lock.id().protocol_id();
+        locker.lock = Some(lock.clone());
+        configure(&fixture, &mut locker, 10);
+        let (conn, qh) = (&fixture.conn, &fixture.qh);
+        if locked {
+            let event = ext_session_lock_v1::Event::Locked;
+            Locker::event(&mut locker, &lock, event, &(), conn, qh);
+        }
+        requests(&mut fixture);
+
+        let (conn, qh) = (&fixture.conn, &fixture.qh);
+        let event = ext_session_lock_v1::Event::Finished;
+        Locker::event(&mut locker, &lock, event, &(), conn, qh);
+        let sent = requests(&mut fixture);
+        (locker, id, sent)
+    }
+
+    #[cfg(feature = "ime")]
+    const TEXT_INPUT_ENABLE: u16 = 1;
+    #[cfg(feature = "ime")]
+    const TEXT_INPUT_DISABLE: u16 = 2;
+    #[cfg(feature = "ime")]
+    const TEXT_INPUT_SET_CONTENT_TYPE: u16 = 5;
+    #[cfg(feature = "ime")]
+    const TEXT_INPUT_COMMIT: u16 = 7;
+
+    #[cfg(feature = "ime")]
+    #[test]
+    fn the_input_method_composes_into_the_focused_field() {
+        let mut fixture = Fixture::new();
+        let mut locker = fixture.locker(LockConfig::default());
+        locker.app.ignore_input = false;
+        let (conn, qh) = (fixture.conn.clone(), fixture.qh.clone());
+        let globals = [(1, "zwp_text_input_manager_v3", 1), (2, "wl_seat", 1)];
+        for (name, interface, version) in globals {
+            let global = wl_registry::Event::Global {
+                name,
+                interface: interface.to_string(),
+                version,
+            };
+            Locker::event(&mut locker, &fixture.registry, global, &(), &conn, &qh);
+        }
+        let text_input = locker.text_input.as_ref().unwrap().proxy.clone();
+        let id = text_input.id().protocol_id();
+        let surface = fixture.monitor(8, 8).surface.clone().unwrap();
+        requests(&mut fixture);
+        let sent_to_text_input = |fixture: &mut Fixture| -> Vec<u16> {
+            let sent = requests(fixture).into_iter();
+            sent.filter(|&(target, _)| target == id)
+                .map(|(_, opcode)| opcode)
+                .collect()
+        };
+
+        let enter = zwp_text_input_v3::Event::Enter { surface };
+        Locker::event(&mut locker, &text_input, enter, &(), &conn, &qh);
+        locker.after_dispatch(&qh).unwrap();
+        assert_eq!(
+            sent_to_text_input(&mut fixture),
+            [
+                TEXT_INPUT_ENABLE,
+                TEXT_INPUT_SET_CONTENT_TYPE,
+                TEXT_INPUT_COMMIT
+            ]
+        );
+        // nothing changed, nothing is sent
+        locker.after_dispatch(&qh).unwrap();
+        assert!(sent_to_text_input(&mut fixture).is_empty());
+
+        let events = [
+            zwp_text_input_v3::Event::PreeditString {
+                text: Some("にほ".to_string()),
+                cursor_begin: 6,
+                cursor_end: 6,
+            },
+            zwp_text_input_v3::Event::Done { serial: 1 },
+        ];
+        for event in events {
+            Locker::event(&mut locker, &text_input, event, &(), &conn, &qh);
+        }
+        assert_eq!(locker.app.preedit.as_deref(), Some("にほ"));
+        assert!(locker.app.username.is_empty());
+
+        let events = [
+            zwp_text_input_v3::Event::PreeditString {
+                text: None,
+                cursor_begin: 0,
+                cursor_end: 0,
+            },
+            zwp_text_input_v3::Event::CommitString {
+                text: Some("日本".to_string()),
+            },
+            zwp_text_input_v3::Event::Done { serial: 1 },
+        ];
+        for event in events {
+            Locker::event(&mut locker, &text_input, event, &(), &conn, &qh);
+        }
+        assert_eq!(locker.app.preedit, None);
+        assert_eq!(locker.app.username, "日本");
+
+        // the password field asks the input method to stay out of it
+        locker.app.focused = FocusTarget::Password;
+        locker.after_dispatch(&qh).unwrap();
+        assert_eq!(
+            sent_to_text_input(&mut fixture),
+            [TEXT_INPUT_SET_CONTENT_TYPE, TEXT_INPUT_COMMIT]
+        );
+        let surface = fixture.monitor(8, 8).surface.clone().unwrap();
+        let leave = zwp_text_input_v3::Event::Leave { surface };
+        Locker::event(&mut locker, &text_input, leave, &(), &conn, &qh);
+        locker.after_dispatch(&qh).unwrap();
+        assert_eq!(
+            sent_to_text_input(&mut fixture),
+            [TEXT_INPUT_DISABLE, TEXT_INPUT_COMMIT]
+        );
+    }
+
+    #[cfg(feature = "dbus")]
+    #[test]
+    fn logind_unlocks_only_a_confirmed_lock() {
+        use crate::logind::SessionSignal;
+
+        let mut fixture = Fixture::new();
+        let mut locker = fixture.locker(LockConfig::default());
+        let lock = lock_outputs(&fixture, &mut locker, &[10]);
+        let id = lock.id().protocol_id();
+        locker.lock = Some(lock.clone());
+        requests(&mut fixture);
+
+        locker.session_signal(SessionSignal::Unlock);
+        assert!(locker.machine.state() != LockState::Finished);
+        assert!(!requests(&mut fixture).contains(&(id, LOCK_UNLOCK_AND_DESTROY)));
+
+        let event = ext_session_lock_v1::Event::Locked;
+        Locker::event(&mut locker, &lock, event, &(), &fixture.conn, &fixture.qh);
+        locker.session_signal(SessionSignal::Lock);
+        assert!(locker.machine.state() == LockState::Locked);
+        locker.session_signal(SessionSignal::Unlock);
+        assert!(locker.machine.state() == LockState::Finished);
+        assert!(requests(&mut fixture).contains(&(id, LOCK_UNLOCK_AND_DESTROY)));
+    }
+
+    #[test]
+    fn a_lock_goes_from_advertisement_to_finished() {
+        let mut fixture = Fixture::new();
+        let mut locker = fixture.locker(LockConfig::default());
+        let (conn, qh) = (fixture.conn.clone(), fixture.qh.clone());
+        let globals = [
+            (1, "ext_session_lock_manager_v1", 1),
+            (2, "wl_compositor", 4),
+            (3, "wl_shm", 1),
+            (4, "wl_output", WL_OUTPUT_VERSION),
+        ];
+        for (name, interface, version) in globals {
+            let global = wl_registry::Event::Global {
+                name,
+                interface: interface.to_string(),
+                version,
+            };
+            Locker::event(&mut locker, &fixture.registry, global, &(), &conn, &qh);
+        }
+        let shm = locker.shared_memory.clone().unwrap();
+        let format = wl_shm::Event::Format {
+            format: WEnum::Value(wl_shm::Format::Argb8888),
+        };
+        Locker::event(&mut locker, &shm, format, &(), &conn, &qh);
+        let output = locker.monitors[&4].output.clone().unwrap();
+        let tag = OutputTag { monitor_name: 4 };
+        Locker::event(
+            &mut locker,
+            &output,
+            wl_output::Event::Done,
+            &tag,
+            &conn,
+            &qh,
+        );
+
+        locker.request_lock(&qh).unwrap();
+        assert!(locker.machine.state() == LockState::Waiting);
+        assert!(locker.monitors[&4].lock_surface.is_some());
+        let lock = locker.lock.clone().unwrap();
+        let id = lock.id().protocol_id();
+
+        // the configure allocates the buffers, and the main loop commits the UI into them
+        configure(&fixture, &mut locker, 4);
+        let buffers = locker.monitors[&4].buffer_state.as_ref().unwrap();
+        assert_eq!((buffers.stride, buffers.size), (320 * 4, 320 * 4 * 160));
+        locker.after_dispatch(&qh).unwrap();
+        let monitor = &locker.monitors[&4];
+        assert!(monitor.frame_pending);
+        let buffers = monitor.buffer_state.as_ref().unwrap();
+        assert!(!buffers.dirty);
+        let in_use: Vec<_> = (0..buffers.buffers.len())
+            .filter(|&index| buffers.buffers[index].in_use)
+            .collect();
+        assert_eq!(in_use.len(), 1);
+
+        let monitor = locker.monitors.get_mut(&4).unwrap();
+        monitor.frame_done();
+        monitor.buffer_state.as_mut().unwrap().release(in_use[0]);
+        assert!(!monitor.frame_pending);
+        assert!(
+            monitor
+                .buffer_state
+                .as_ref()
+                .unwrap()
+                .buffers
+                .iter()
+                .all(|slot| !slot.in_use)
+        );
+
+        let event = ext_session_lock_v1::Event::Locked;
+        Locker::event(&mut locker, &lock, event, &(), &conn, &qh);
+        assert!(locker.machine.state() == LockState::Locked);
+        assert!(!locker.app.ignore_input);
+        locker.after_dispatch(&qh).unwrap();
+        assert!(!locker.is_finished());
+
+        let event = ext_session_lock_v1::Event::Finished;
+        Locker::event(&mut locker, &lock, event, &(), &conn, &qh);
+        assert!(locker.is_finished());
+        assert!(requests(&mut fixture).contains(&(id, LOCK_UNLOCK_AND_DESTROY)));
+    }
+
+    #[test]
+    fn a_configure_for_an_unknown_monitor_is_ignored() {
+        let fixture = Fixture::new();
+        let mut locker = fixture.locker(LockConfig::default());
+        let _lock = lock_outputs(&fixture, &mut locker, &[10]);
+
+        assert!(locker.configure_surface(10, 640, 480));
+        assert_eq!(locker.monitors[&10].pending_configure, Some((640, 480)));
+        assert!(!locker.configure_surface(11, 640, 480));
+        // its surface was torn down, so a late configure has nowhere to go
+        locker.monitors.get_mut(&10).unwrap().destroy_surface();
+        assert!(!locker.configure_surface(10, 320, 160));
+        assert_eq!(locker.monitors[&10].pending_configure, Some((640, 480)));
+    }
+
+    #[test]
+    fn a_third_buffer_is_drawn_into_while_two_are_held() {
+        let fixture = Fixture::new();
+        let shm = fixture
+            .registry
+            .bind::<WlShm, (), Locker>(2, 1, &fixture.qh, ());
+        let format = wl_shm::Format::Argb8888;
+        let mut buffers = BufferState::new(&shm, &fixture.qh, 1, (4, 2), format, 3).unwrap();
+        assert_eq!(buffers.buffers[2].offset, 2 * 4 * 4 * 2);
+
+        for _ in 0..3 {
+            let index = buffers.acquire_free_buffer_index().unwrap();
+            buffers.buffers[index].in_use = true;
+        }
+        assert_eq!(buffers.acquire_free_buffer_index(), None);
+        // a tag past the last buffer is still ignored, and the last one comes back
+        buffers.release(3);
+        assert_eq!(buffers.acquire_free_buffer_index(), None);
+        buffers.release(2);
+        assert_eq!(buffers.acquire_free_buffer_index(), Some(2));
+    }
+
+    #[test]
+    fn only_two_or_three_buffers_can_be_configured() {
+        assert_eq!(LockConfig::default().buffer_count, 2);
+        assert!(check_buffer_count(3).is_ok());
+        for count in [0, 1, 4] {
+            assert!(check_buffer_count(count).is_err());
+        }
+    }
+
+    #[test]
+    fn a_new_track_is_drawn_even_while_idle() {
+        let fixture = Fixture::new();
+        let mut locker = fixture.locker(LockConfig::default());
+        let _lock = lock_outputs(&fixture, &mut locker, &[10]);
+        let monitor = locker.monitors.get_mut(&10).unwrap();
+        monitor.primary = true;
+        // with room under the box for the line
+        monitor.pending_configure = Some((1280, 720));
+        locker.present(10, &fixture.qh);
+        locker.after_dispatch(&fixture.qh).unwrap();
+        locker.monitors.get_mut(&10).unwrap().frame_done();
+        let dirty = |locker: &Locker| locker.monitors[&10].buffer_state.as_ref().unwrap().dirty;
+        assert!(!dirty(&locker));
+
+        let playing = NowPlaying {
+            title: "Blue in Green".to_string(),
+            artist: Some("Miles Davis".to_string()),
+        };
+        locker.now_playing(Some(playing.clone()));
+        assert_eq!(locker.app.now_playing, Some(playing.clone()));
+        assert!(dirty(&locker));
+        locker.after_dispatch(&fixture.qh).unwrap();
+        locker.monitors.get_mut(&10).unwrap().frame_done();
+
+        // the same track again changes nothing, and a stopped player takes the line away
+        locker.now_playing(Some(playing));
+        assert!(!dirty(&locker));
+        locker.now_playing(None);
+        assert_eq!(locker.app.now_playing, None);
+        assert!(dirty(&locker));
+    }
+
+    #[test]
+    fn releasing_an_unknown_buffer_is_ignored() {
+        let mut monitor = Fixture::new().monitor(4, 4);
+        let buffers = monitor.buffer_state.as_mut().unwrap();
+        buffers.buffers[1].in_use = true;
+        buffers.release(5);
+        assert!(buffers.buffers[1].in_use);
+        buffers.release(1);
+        assert!(!buffers.buffers[1].in_use);
+    }
+
+    #[test]
+    fn finished_after_locked_unlocks_and_tears_down() {
+        let (locker, lock, sent) = finish(true);
+        assert!(sent.contains(&(lock, LOCK_UNLOCK_AND_DESTROY)));
+        assert!(!sent.contains(&(lock, LOCK_DESTROY)));
+        assert!(locker.lock.is_none());
+        assert!(matches!(locker.machine.state(), LockState::Finished));
+        assert!(locker.app.ignore_input);
+        let monitor = &locker.monitors[&10];
+        assert!(monitor.lock_surface.is_none());
+        assert!(monitor.surface.is_none());
+        assert!(monitor.buffer_state.is_none());
+        // only what was shown on the output goes, the output itself is still bound
+        assert!(monitor.output.is_some());
+    }
+
+    #[test]
+    fn finished_while_waiting_destroys_the_lock() {
+        let (locker, lock, sent) = finish(false);
+        assert!(sent.contains(&(lock, LOCK_DESTROY)));
+        assert!(!sent.contains(&(lock, LOCK_UNLOCK_AND_DESTROY)));
+        assert!(locker.lock.is_none());
+        assert!(matches!(locker.machine.state(), LockState::Finished));
+        assert!(locker.monitors[&10].lock_surface.is_none());
+    }
+
+    #[test]
+    fn an_unanswered_lock_request_times_out() {
+        let fixture = Fixture::new();
+        let mut locker = fixture.locker(LockConfig::default());
+        let now = Instant::now();
+        locker.machine.request_lock();
+        locker.waiting_deadline = Some(now + locker.locked_timeout);
+
+        assert!(locker.check_waiting_deadline(now).is_ok());
+        let err = locker
+            .check_waiting_deadline(now + locker.locked_timeout)
+            .unwrap_err();
+        assert!(err.to_string().contains("did not answer the lock request"));
+    }
+
+    #[test]
+    fn locked_arriving_with_the_deadline_wins() {
+        let fixture = Fixture::new();
+        let mut locker = fixture.locker(LockConfig::default());
+        let lock = lock_outputs(&fixture, &mut locker, &[10]);
+        let now = Instant::now();
+        locker.waiting_deadline = Some(now);
+
+        let event = ext_session_lock_v1::Event::Locked;
+        Locker::event(&mut locker, &lock, event, &(), &fixture.conn, &fixture.qh);
+        assert!(locker.check_waiting_deadline(now).is_ok());
+        assert!(matches!(locker.machine.state(), LockState::Locked));
+    }
+
+    #[test]
+    #[cfg(not(feature = "dev-autounlock"))]
+    fn terminating_a_confirmed_lock_leaves_the_session_locked() {
+        let mut fixture = Fixture::new();
+        let mut locker = fixture.locker(LockConfig::default());
+        let lock = lock_outputs(&fixture, &mut locker, &[10]);
+        let id = lock.id().protocol_id();
+        locker.lock = Some(lock.clone());
+        let event = ext_session_lock_v1::Event::Locked;
+        Locker::event(&mut locker, &lock, event, &(), &fixture.conn, &fixture.qh);
+
+        let log = crate::log::capture();
+        locker.terminate();
+        assert_eq!(
+            log.lines(),
+            ["INFO terminated while locked, leaving the session locked"]
+        );
+        let sent = requests(&mut fixture);
+        assert!(!sent.contains(&(id, LOCK_UNLOCK_AND_DESTROY)));
+        assert!(!sent.contains(&(id, LOCK_DESTROY)));
+        assert!(matches!(locker.machine.state(), LockState::Finished));
+    }
+
+    #[test]
+    fn terminating_before_locked_gives_up_the_lock() {
+        let mut fixture = Fixture::new();
+        let mut locker = fixture.locker(LockConfig::default());
+        let lock = lock_outputs(&fixture, &mut locker, &[10]);
+        let id = lock.id().protocol_id();
+        locker.lock = Some(lock);
+
+        let log = crate::log::capture();
+        locker.terminate();
+        assert_eq!(
+            log.lines(),
+            ["INFO terminated before the session was locked"]
+        );
+        assert!(requests(&mut fixture).contains(&(id, LOCK_DESTROY)));
+        assert!(locker.monitors[&10].lock_surface.is_none());
+    }
+}
@@ -0,0 +1,288 @@
+use std::{
+    fmt::{self, Write as _},
+    fs::{File, OpenOptions},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use anyhow::Context;
+use tracing::{
+    Event, Subscriber,
+    field::{Field, Visit},
+    subscriber::DefaultGuard,
+};
+use tracing_subscriber::{
+    EnvFilter, Layer, Registry,
+    fmt::{format::Writer, time::FormatTime},
+    layer::{self, SubscriberExt},
+};
+
+// what's logged when LILAC_LOG isn't set
+const DEFAULT_FILTER: &str = "info";
+
+/// Where log lines are written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Destination {
+    Stderr,
+    // appended to, and created along with its directories if missing
+    File(PathBuf),
+}
+
+impl Destination {
+    /// `$XDG_STATE_HOME/lilac/lilac.log`, falling back to `~/.local/state`, or stderr when
+    /// neither `XDG_STATE_HOME` nor `HOME` is set.
+    pub fn default_file() -> Self {
+        match crate::env::state_dir() {
+            Some(dir) => Self::File(dir.join("lilac.log")),
+            None => Self::Stderr,
+        }
+    }
+
+    /// A configured log path, where `-` means stderr.
+    pub fn from_path(path: PathBuf) -> Self {
+        if path.as_os_str() == "-" {
+            Self::Stderr
+        } else {
+            Self::File(path)
+        }
+    }
+}
+
+/// How the time at the start of each line is written.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum TimeFormat {
+    // seconds since the Unix epoch
+    #[default]
+    Epoch,
+    // e.g. 2025-01-31T18:04:05.123456Z
+    Rfc3339,
+}
+
+impl std::str::FromStr for TimeFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "epoch" => Ok(Self::Epoch),
+            "rfc3339" => Ok(Self::Rfc3339),
+            _ => Err(format!(
+                "unknown log time format {s:?}, expected epoch or rfc3339"
+            )),
+        }
+    }
+}
+
+// Starts every line with the time and lilac's pid, which tells apart the lines of runs that
+// overlap in one file.
+struct LinePrefix {
+    time: TimeFormat,
+    pid: u32,
+}
+
+impl FormatTime for LinePrefix {
+    fn format_time(&self, w: &mut Writer<'_>) -> fmt::Result {
+        match self.time {
+            TimeFormat::Epoch => {
+                let secs = SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or(0);
+                write!(w, "{secs}")?;
+            }
+            TimeFormat::Rfc3339 => tracing_subscriber::fmt::time::SystemTime.format_time(w)?,
+        }
+        write!(w, " [{}]", self.pid)
+    }
+}
+
+/// Installs the global `tracing` subscriber writing to `destination`. What gets through is
+/// filtered by `LILAC_LOG` in `tracing_subscriber`'s directive syntax, e.g. `debug` or
+/// `lilac=debug,zbus=warn`, and defaults to `info`.
+///
+/// A log file that can't be opened falls back to stderr rather than losing every line.
+pub fn init(destination: &Destination, time: TimeFormat) -> anyhow::Result<()> {
+    let filter = match std::env::var("LILAC_LOG") {
+        Ok(directives) => EnvFilter::try_new(&directives)
+            .with_context(|| format!("invalid LILAC_LOG {directives:?}"))?,
+        Err(_) => EnvFilter::new(DEFAULT_FILTER),
+    };
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_timer(LinePrefix {
+            time,
+            pid: std::process::id(),
+        });
+
+    let (file, failed) = match destination {
+        Destination::Stderr => (None, None),
+        Destination::File(path) => match open_log_file(path) {
+            Ok(file) => (Some(file), None),
+            Err(err) => (None, Some((path, err))),
+        },
+    };
+    let installed = match file {
+        Some(file) => builder
+            .with_ansi(false)
+            .with_writer(Mutex::new(file))
+            .try_init(),
+        None => builder.with_writer(std::io::stderr).try_init(),
+    };
+    installed.map_err(|err| anyhow::anyhow!("failed to set up logging: {err}"))?;
+
+    if let Some((path, err)) = failed {
+        tracing::warn!(
+            "failed to open the log file {}, logging to stderr: {err:#}",
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+fn open_log_file(path: &Path) -> anyhow::Result<File> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create {}", dir.display()))?;
+    }
+    Ok(OpenOptions::new().create(true).append(true).open(path)?)
+}
+
+pub fn log_line(args: std::fmt::Arguments) {
+    tracing::info!("{args}");
+}
+
+/// Logs a line at the info level. Kept for callers that predate the leveled `tracing` macros.
+#[macro_export]
+macro_rules! logln {
+    ($($arg:tt)*) => {
+        $crate::log::log_line(format_args!($($arg)*))
+    };
+}
+
+/// Records every event logged on the current thread, at any level, in memory until dropped,
+/// so tests can assert on code paths that are otherwise only observable in the log. Lines read
+/// `LEVEL message`, e.g. `WARN failed to unlock: ...`.
+pub struct Capture {
+    lines: Arc<Mutex<Vec<String>>>,
+    // the capturing subscriber stays the thread's default until this is dropped
+    _guard: DefaultGuard,
+}
+
+pub fn capture() -> Capture {
+    let lines = Arc::new(Mutex::new(Vec::new()));
+    let recorder = Recorder {
+        lines: lines.clone(),
+    };
+    let guard = tracing::subscriber::set_default(Registry::default().with(recorder));
+    Capture {
+        lines,
+        _guard: guard,
+    }
+}
+
+impl Capture {
+    pub fn lines(&self) -> Vec<String> {
+        self.lines.lock().unwrap().clone()
+    }
+
+    pub fn contains(&self, needle: &str) -> bool {
+        self.lines
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|line| line.contains(needle))
+    }
+}
+
+struct Recorder {
+    lines: Arc<Mutex<Vec<String>>>,
+}
+
+impl<S: Subscriber> Layer<S> for Recorder {
+    fn on_event(&self, event: &Event<'_>, _: layer::Context<'_, S>) {
+        let mut message = Message(String::new());
+        event.record(&mut message);
+        let line = format!("{} {}", event.metadata().level(), message.0);
+        self.lines.lock().unwrap().push(line);
+    }
+}
+
+// The formatted message of an event; other fields are left out.
+struct Message(String);
+
+impl Visit for Message {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.0, "{value:?}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn time_formats_parse_by_name() {
+        assert_eq!("epoch".parse(), Ok(TimeFormat::Epoch));
+        assert_eq!("rfc3339".parse(), Ok(TimeFormat::Rfc3339));
+        assert!("iso".parse::<TimeFormat>().is_err());
+    }
+
+    #[test]
+    fn a_dash_logs_to_stderr() {
+        assert_eq!(Destination::from_path("-".into()), Destination::Stderr);
+        assert_eq!(
+            Destination::from_path("lilac.log".into()),
+            Destination::File("lilac.log".into())
+        );
+    }
+
+    #[test]
+    fn the_log_files_directories_are_created() {
+        let dir = std::env::temp_dir().join(format!("lilac-log-{}", std::process::id()));
+        let path = dir.join("state").join("lilac.log");
+        open_log_file(&path).unwrap();
+        let created = path.exists();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(created);
+    }
+
+    #[test]
+    fn capture_records_events_with_their_level() {
+        let log = capture();
+        tracing::warn!("all {} buffers were in use", 3);
+        tracing::debug!(output = "DP-1", "configured");
+        assert_eq!(
+            log.lines(),
+            ["WARN all 3 buffers were in use", "DEBUG configured"]
+        );
+        assert!(log.contains("buffers were in use"));
+        assert!(!log.contains("DP-1"));
+    }
+
+    #[test]
+    fn logln_is_captured_at_info() {
+        let log = capture();
+        crate::logln!("commit failed after {}", "configure");
+        assert_eq!(log.lines(), ["INFO commit failed after configure"]);
+    }
+
+    #[test]
+    fn capture_stops_when_dropped() {
+        let log = capture();
+        let lines = log.lines.clone();
+        drop(log);
+        tracing::info!("not captured");
+        assert!(lines.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn captures_are_per_thread() {
+        let log = capture();
+        std::thread::spawn(|| tracing::info!("from another thread"))
+            .join()
+            .unwrap();
+        assert!(log.lines().is_empty());
+    }
+}
@@ -0,0 +1,118 @@
+use calloop::channel::{self, Channel};
+use zbus::{
+    blocking::{Connection, Proxy},
+    zvariant::OwnedObjectPath,
+};
+
+const LOGIND: &str = "org.freedesktop.login1";
+
+/// What logind asks of the session's screen locker, from `loginctl lock-session` and
+/// `loginctl unlock-session` or the desktop equivalents.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SessionSignal {
+    Lock,
+    Unlock,
+}
+
+impl std::str::FromStr for SessionSignal {
+    type Err = String;
+
+    // the member names of the session's signals
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Lock" => Ok(Self::Lock),
+            "Unlock" => Ok(Self::Unlock),
+            _ => Err(format!("{s:?} is not a lock signal")),
+        }
+    }
+}
+
+/// The logind session lilac runs in, on the system bus.
+pub struct Session {
+    connection: Connection,
+    path: OwnedObjectPath,
+}
+
+impl Session {
+    /// Looks up the session from `XDG_SESSION_ID`, or the one logind places the process in
+    /// when that isn't set.
+    pub fn current() -> zbus::Result<Self> {
+        let connection = Connection::system()?;
+        let manager = Proxy::new(
+            &connection,
+            LOGIND,
+            "/org/freedesktop/login1",
+            "org.freedesktop.login1.Manager",
+        )?;
+        let id = std::env::var("XDG_SESSION_ID").unwrap_or_else(|_| "auto".to_string());
+        let path: OwnedObjectPath = manager.call("GetSession", &(id,))?;
+        Ok(Self { connection, path })
+    }
+
+    /// Blocks until logind asks the session to lock.
+    pub fn wait_for_lock(&self) -> zbus::Result<()> {
+        let mut signals = self.proxy()?.receive_signal("Lock")?;
+        match signals.next() {
+            Some(_) => Ok(()),
+            None => Err(zbus::Error::Failure(
+                "the bus closed before the session was locked".to_string(),
+            )),
+        }
+    }
+
+    /// Forwards the session's Lock and Unlock signals into an event loop. The signals are read
+    /// on a thread of their own, which ends when the channel is dropped.
+    pub fn watch(self) -> zbus::Result<Channel<SessionSignal>> {
+        let proxy = self.proxy()?;
+        // subscribed before returning, so nothing sent after this call is missed
+        let signals = proxy.receive_all_signals()?;
+        let (sender, channel) = channel::channel();
+        std::thread::spawn(move || {
+            let _proxy = proxy;
+            for message in signals {
+                let header = message.header();
+                let Some(signal) = header.member().and_then(|member| member.parse().ok()) else {
+                    continue;
+                };
+                if sender.send(signal).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(channel)
+    }
+
+    fn proxy(&self) -> zbus::Result<Proxy<'static>> {
+        Proxy::new(
+            &self.connection,
+            LOGIND,
+            self.path.clone().into_inner(),
+            "org.freedesktop.login1.Session",
+        )
+    }
+}
+
+/// Asks logind to power the machine off. Whether the session may is up to polkit, and no
+/// password is asked for here.
+pub fn power_off() -> zbus::Result<()> {
+    let connection = Connection::system()?;
+    let manager = Proxy::new(
+        &connection,
+        LOGIND,
+        "/org/freedesktop/login1",
+        "org.freedesktop.login1.Manager",
+    )?;
+    manager.call("PowerOff", &(false,))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_lock_and_unlock_are_followed() {
+        assert_eq!("Lock".parse(), Ok(SessionSignal::Lock));
+        assert_eq!("Unlock".parse(), Ok(SessionSignal::Unlock));
+        assert!("PauseDevice".parse::<SessionSignal>().is_err());
+    }
+}
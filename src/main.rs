@@ -1,657 +1,278 @@
 use std::{
-    collections::HashMap,
-    fs::OpenOptions,
-    io::{ErrorKind, Write},
-    os::fd::{AsFd, AsRawFd},
-    time::{Duration, Instant, SystemTime},
+    cell::Cell,
+    fs::File,
+    io::BufWriter,
+    os::unix::net::UnixStream,
+    path::{Path, PathBuf},
+    rc::Rc,
+    time::Duration,
 };
 
-use memfd::{Memfd, MemfdOptions};
-use mmap::{MapOption, MemoryMap};
-use wayland_client::{
-    backend::WaylandError,
-    Connection, Dispatch, Proxy, QueueHandle,
-    protocol::{
-        wl_buffer::{self, WlBuffer},
-        wl_compositor::{self, WlCompositor},
-        wl_output::{self, WlOutput},
-        wl_registry,
-        wl_shm::{self, WlShm},
-        wl_shm_pool::{self, WlShmPool},
-        wl_surface::{self, WlSurface},
-    },
-};
-
-use wayland_protocols::ext::session_lock::v1::client::{
-    ext_session_lock_manager_v1::{self, ExtSessionLockManagerV1},
-    ext_session_lock_surface_v1::{self, ExtSessionLockSurfaceV1},
-    ext_session_lock_v1::{self, ExtSessionLockV1},
-};
+use calloop::{EventLoop, Interest, Mode, PostAction, generic::Generic};
+use calloop_wayland_source::WaylandSource;
+use wayland_client::{ConnectError, Connection};
 
 use anyhow::anyhow;
+use clap::Parser;
+use tracing::info;
+
+use lilac::{
+    config::Config,
+    env::Vars,
+    locker::{BoxPlacement, FirstFrame, LockConfig, Locker, probe_report},
+    log::Destination,
+    render::{PixelBuffer, parse_rgb},
+    tui::{self, FocusTarget},
+};
 
-fn log_line(args: std::fmt::Arguments) {
-    let timestamp = SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .map(|d| d.as_secs())
-        .unwrap_or(0);
-    if let Ok(mut file) = OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open("lilac.log")
-    {
-        let _ = writeln!(file, "[{}] {}", timestamp, args);
-    }
-}
-
-macro_rules! logln {
-    ($($arg:tt)*) => {
-        log_line(format_args!($($arg)*))
-    };
-}
-
-/// This struct represents the state of our app.
-/// This type supports the `dispatch` implementations needed for the below state diagram
-///
-/// Session lock protocol message flow (client perspective).
+/// A screen locker for Wayland compositors with ext-session-lock-v1.
 ///
-/// CLIENT                                 COMPOSITOR
-///   |                                         |
-///   | wl_registry.bind ext_session_lock_manager_v1
-///   |---------------------------------------->|
-///   |                                         |
-///   | ext_session_lock_manager_v1.lock
-///   |---------------------------------------->|
-///   |                                         |
-///   |           (either)                      |
-///   |<------------------ ext_session_lock_v1.locked
-///   |                                         |
-///   |  create wl_surface + per-output:
-///   |  ext_session_lock_v1.get_lock_surface
-///   |---------------------------------------->|
-///   |                                         |
-///   |<---------------- ext_session_lock_surface_v1.configure
-///   |  (width,height,serial)                  |
-///   |                                         |
-///   |  attach buffer + wl_surface.commit      |
-///   |---------------------------------------->|
-///   |                                         |
-///   | (repeat configure/commit as needed)     |
-///   |                                         |
-///   |  ... user authenticates ...             |
-///   |  ext_session_lock_v1.unlock_and_destroy |
-///   |---------------------------------------->|
-///   |                                         |
-///   | (client destroys lock surfaces)         |
-///   |                                         |
-///   |<------------------ ext_session_lock_v1.finished (optional)
-///   |                                         |
-///   | (or, if lock was denied)                |
-///   |<------------------ ext_session_lock_v1.finished
-///   |  (no locked was sent)                   |
-///   |                                         |
-#[derive(Default)]
-struct Locker {
-    lock_manager: Option<ExtSessionLockManagerV1>,
-    lock: Option<ExtSessionLockV1>,
-    compositor: Option<WlCompositor>,
-    shared_memory: Option<WlShm>,
-    monitors: HashMap<u32, Monitor>,
-    state: LockState,
-    auto_unlock_deadline: Option<Instant>,
-    auto_unlock_sent: bool,
+/// Flags override the `LILAC_*` environment, which overrides the config file.
+#[derive(Parser)]
+#[command(version, about)]
+struct Args {
+    /// Print what the compositor advertises and exit without locking
+    #[arg(long)]
+    probe: bool,
+    /// Print the --probe report as JSON
+    #[arg(long, requires = "probe")]
+    json: bool,
+    /// Which monitors show the login box: all, primary or focused [default: primary]
+    #[arg(long, value_name = "MONITORS")]
+    box_on: Option<BoxPlacement>,
+    /// What the first commit to each output shows: prefill or composed
+    #[arg(long, value_name = "POLICY")]
+    first_frame: Option<FirstFrame>,
+    /// Shell command run once the session is locked
+    #[arg(long, value_name = "COMMAND")]
+    on_lock: Option<String>,
+    /// Shell command run after a successful unlock
+    #[arg(long, value_name = "COMMAND")]
+    on_unlock: Option<String>,
+    /// RRGGBB shown on every output until the login screen is drawn
+    #[arg(long, value_name = "COLOR")]
+    placeholder_color: Option<String>,
+    /// Read the config from this file instead of $XDG_CONFIG_HOME/lilac/config.toml
+    #[arg(long, value_name = "PATH")]
+    config: Option<PathBuf>,
+    /// The PAM service passwords are checked against
+    #[arg(long, value_name = "NAME")]
+    service: Option<String>,
+    /// Write the log here instead of $XDG_STATE_HOME/lilac/lilac.log, `-` for stderr
+    #[arg(long, value_name = "PATH")]
+    log: Option<PathBuf>,
+    /// Seconds after locking during which Enter unlocks without a password
+    #[arg(long, value_name = "SECS")]
+    grace: Option<u64>,
+    /// Render the login screen into a PNG and exit, without locking
+    #[arg(long, visible_alias = "preview", value_name = "PATH")]
+    test_frame: Option<PathBuf>,
+    /// The output width --test-frame renders at, in pixels
+    #[arg(
+        long,
+        value_name = "PX",
+        default_value_t = 1920,
+        value_parser = clap::value_parser!(u32).range(1..),
+        requires = "test_frame"
+    )]
+    width: u32,
+    /// The output height --test-frame renders at, in pixels
+    #[arg(
+        long,
+        value_name = "PX",
+        default_value_t = 1080,
+        value_parser = clap::value_parser!(u32).range(1..),
+        requires = "test_frame"
+    )]
+    height: u32,
+    /// The animation frame --test-frame captures
+    #[arg(long, value_name = "N", default_value_t = 0, requires = "test_frame")]
+    tick: u64,
+    /// A username --test-frame shows as typed in
+    #[arg(long, value_name = "NAME", requires = "test_frame")]
+    username: Option<String>,
+    /// An error --test-frame shows as if an attempt had failed
+    #[arg(long, value_name = "MESSAGE", requires = "test_frame")]
+    error: Option<String>,
+    /// Wait for logind to ask the session to lock before locking
+    #[cfg(feature = "dbus")]
+    #[arg(long)]
+    wait_for_lock: bool,
 }
 
-impl Locker {
-    fn is_initialized(&self) -> anyhow::Result<()> {
-        if self.lock_manager.is_none() {
-            return Err(anyhow!(
-                "could not find a lock manager in the registry advertisement"
-            ));
-        }
-
-        if self.compositor.is_none() {
-            return Err(anyhow!(
-                "could not find a compositor in the registry advertisement"
-            ));
-        }
-
-        if self.shared_memory.is_none() {
-            return Err(anyhow!(
-                "could not find shared memory in the registry advertisement"
-            ));
-        }
-
-        if self.monitors.is_empty() {
-            return Err(anyhow!(
-                "could not find any outputs in the registry advertisement"
-            ));
-        }
-        Ok(())
+// command line flags first, then the LILAC_* environment, then the config file, then
+// defaults
+fn lock_config(args: &Args, file: &Config) -> anyhow::Result<LockConfig> {
+    let mut config = LockConfig::from_file(file)?;
+    config.apply_env(&Vars::process())?;
+    if let Some(color) = &args.placeholder_color {
+        config.placeholder_color = parse_rgb(color)
+            .ok_or_else(|| anyhow!("invalid placeholder color {color:?}, expected RRGGBB"))?;
     }
-}
-
-#[derive(Default)]
-struct Monitor {
-    name: u32,
-    output: Option<WlOutput>,
-    surface: Option<WlSurface>,
-    lock_surface: Option<ExtSessionLockSurfaceV1>,
-    dimensions: (u32, u32),
-    buffer_state: Option<BufferState>,
-}
-
-impl Monitor {
-    fn with_name(mut self, n: u32) -> Self {
-        self.name = n;
-        self
+    if let Some(policy) = args.first_frame {
+        config.first_frame = policy;
     }
-
-    fn with_output(mut self, o: WlOutput) -> Self {
-        self.output = Some(o);
-        self
+    if let Some(secs) = args.grace {
+        config.grace_period = Duration::from_secs(secs);
     }
-
-    fn create_surface_and_lock(
-        &mut self,
-        compositor: &WlCompositor,
-        lock: &ExtSessionLockV1,
-        qh: &QueueHandle<Locker>,
-    ) -> anyhow::Result<()> {
-        let wl_surface = compositor.create_surface(qh, ());
-
-        let wl_output = self.output.as_ref().ok_or_else(|| {
-            anyhow!(format!(
-                "monitor with name {} must have Some(output)",
-                self.name
-            ))
-        })?;
-
-        let lock_surface = lock.get_lock_surface(&wl_surface, wl_output, qh, ());
-
-        self.surface = Some(wl_surface);
-        self.lock_surface = Some(lock_surface);
-
-        Ok(())
+    if let Some(placement) = args.box_on {
+        config.box_placement = placement;
     }
-
-    fn commit(&mut self) -> anyhow::Result<bool> {
-        let buffer_state = self
-            .buffer_state
-            .as_mut()
-            .ok_or_else(|| anyhow!("buffer state cannot be None"))?;
-
-        let Some(buffer_index) = buffer_state.acquire_free_buffer_index() else {
-            return Ok(false);
-        };
-        let buffer = &buffer_state.buffers[buffer_index].buffer;
-
-        let surface = self
-            .surface
-            .as_ref()
-            .ok_or_else(|| anyhow!("surface cannot be None"))?;
-
-        surface.attach(Some(buffer), 0, 0);
-        surface.damage_buffer(
-            0,
-            0,
-            self.dimensions.0.try_into()?,
-            self.dimensions.1.try_into()?,
-        );
-        surface.commit();
-        buffer_state.buffers[buffer_index].in_use = true;
-        buffer_state.dirty = false;
-        Ok(true)
+    if let Some(command) = &args.on_lock {
+        config.on_lock = Some(command.clone());
     }
-}
-
-#[derive(PartialEq, Eq, Copy, Clone)]
-enum LockState {
-    // haven’t requested a lock yet
-    Idle,
-    // lock request sent, waiting for locked or finished
-    Waiting,
-    // received locked, surfaces should be active
-    Locked,
-    // received finished, lock denied or unlock succeeded
-    Finished,
-}
-
-impl Default for LockState {
-    fn default() -> Self {
-        Self::Idle
-    }
-}
-
-#[derive(Copy, Clone)]
-struct BufferTag {
-    monitor_name: u32,
-    index: usize,
-}
-
-struct BufferSlot {
-    // the total number of bytes this buffer contains
-    size: i32,
-    // the length of a row
-    stride: i32,
-    // the fd for the data
-    mem_fd: Memfd,
-    // access to the actual underlying bytes
-    bytes: MemoryMap,
-    pool: WlShmPool,
-    buffer: WlBuffer,
-    // whether or not the compositor is currently reading the shared memory
-    in_use: bool,
-}
-
-struct BufferState {
-    buffers: [BufferSlot; 2],
-    // whether or not the contents of the buffer in the memory map have been sent to the compositor
-    //   - dirty = true whenever UI state changes (input, configure, timer, etc.), regardless of
-    //   buffer usage.
-    //
-    //   - successful render+commit sets dirty = false.
-    //
-    //   - if a render was desired but all buffers were in use, leave dirty = true and try again
-    //   on the next Release.
-    dirty: bool,
-    next_index: usize,
-}
-
-impl BufferState {
-    // the flow is:
-    //  1. Create an fd (memfd or a temp file) and set_len(size).
-    //  2. mmap the fd to get a writable byte slice.
-    //  3. Pass that fd to wl_shm.create_pool(fd, size) to get a wl_shm_pool.
-    //  4. Create a wl_buffer from the pool with width/height/stride/format.
-    fn new(
-        shared_memory: &WlShm,
-        qh: &QueueHandle<Locker>,
-        name: u32,
-        width: i32,
-        height: i32,
-    ) -> anyhow::Result<Self> {
-        let buffer_0 = BufferSlot::new(shared_memory, qh, name, 0, width, height)?;
-        let buffer_1 = BufferSlot::new(shared_memory, qh, name, 1, width, height)?;
-
-        Ok(Self {
-            buffers: [buffer_0, buffer_1],
-            dirty: true,
-            next_index: 0,
-        })
+    if let Some(command) = &args.on_unlock {
+        config.on_unlock = Some(command.clone());
     }
-
-    fn fill_solid_color(&mut self, color: [u8; 4]) {
-        for buffer in &mut self.buffers {
-            buffer.fill_solid_color(color);
-        }
-        self.dirty = true;
-    }
-
-    fn acquire_free_buffer_index(&mut self) -> Option<usize> {
-        let total = self.buffers.len();
-        for offset in 0..total {
-            let index = (self.next_index + offset) % total;
-            if !self.buffers[index].in_use {
-                self.next_index = (index + 1) % total;
-                return Some(index);
-            }
-        }
-        None
+    if let Some(service) = &args.service {
+        config.pam_service = service.clone();
     }
+    Ok(config)
 }
 
-impl BufferSlot {
-    fn new(
-        shared_memory: &WlShm,
-        qh: &QueueHandle<Locker>,
-        monitor_name: u32,
-        index: usize,
-        width: i32,
-        height: i32,
-    ) -> anyhow::Result<Self> {
-        let stride = width * 4;
-        let size = stride * height;
-        let name = monitor_name.wrapping_mul(2).wrapping_add(index as u32);
-
-        let mem_fd_opts = MemfdOptions::default().allow_sealing(true);
-        let mem_fd = mem_fd_opts.create(name.to_string())?;
-        mem_fd.as_file().set_len(size as u64)?;
-        let c_fd = mem_fd.as_file().as_raw_fd();
-
-        let mmap_opts = vec![
-            MapOption::MapReadable,
-            MapOption::MapWritable,
-            MapOption::MapFd(c_fd),
-            MapOption::MapNonStandardFlags(libc::MAP_SHARED),
-        ];
-
-        let bytes = MemoryMap::new(size as usize, mmap_opts.as_slice())?;
-
-        let pool = shared_memory.create_pool(mem_fd.as_file().as_fd(), size, qh, ());
-
-        let tag = BufferTag {
-            monitor_name,
-            index,
-        };
-        let buffer = pool.create_buffer(
-            0,
-            width,
-            height,
-            stride,
-            wl_shm::Format::Argb8888,
-            qh,
-            tag,
-        );
-
-        Ok(Self {
-            size,
-            stride,
-            mem_fd,
-            bytes,
-            pool,
-            buffer,
-            in_use: false,
-        })
+// Renders the login screen into a PNG the way the lock draws it on an output of the size in
+// `args`, for trying out a theme without locking the session. Backgrounds keep state between
+// frames, so every frame up to the requested tick is drawn, not just the last one.
+fn write_test_frame(config: &LockConfig, args: &Args, path: &Path) -> anyhow::Result<()> {
+    let (width, height) = (args.width, args.height);
+    let mut rasterizer = config.rasterizer();
+    let mut app = config.app_state();
+    if let Some(username) = &args.username {
+        app.username = username.clone();
+        app.focused = FocusTarget::Password;
     }
+    app.error_message = args.error.clone();
 
-    fn fill_solid_color(&mut self, color: [u8; 4]) {
-        let len = self.size as usize;
-        let ptr = self.bytes.data() as *mut u8;
-        let data = unsafe { std::slice::from_raw_parts_mut(ptr, len) };
-
-        for px in data.chunks_exact_mut(4) {
-            px.copy_from_slice(&color);
-        }
+    let (cols, rows) = rasterizer.grid_dims(width, height);
+    let mut cells = tui::render_to_buffer(&mut app, cols, rows);
+    for _ in 0..args.tick {
+        app.tick();
+        cells = tui::render_to_buffer(&mut app, cols, rows);
     }
-}
-
-impl Dispatch<wl_registry::WlRegistry, ()> for Locker {
-    fn event(
-        state: &mut Self,
-        registry: &wl_registry::WlRegistry,
-        event: wl_registry::Event,
-        _: &(),
-        _: &Connection,
-        qh: &QueueHandle<Locker>,
-    ) {
-        // When receiving events from the wl_registry, we are only interested in the
-        // `global` event, which signals a new available global.
-        // When receiving this event, we just print its characteristics in this example.
-        if let wl_registry::Event::Global {
-            name,
-            interface,
-            version,
-        } = event
-        {
-            match interface.as_str() {
-                "ext_session_lock_manager_v1" => {
-                    let version = version.min(ExtSessionLockManagerV1::interface().version);
-                    let lock_manager =
-                        registry.bind::<ExtSessionLockManagerV1, (), Locker>(name, version, qh, ());
-                    state.lock_manager = Some(lock_manager);
-                }
-                "wl_compositor" => {
-                    let version = version.min(WlCompositor::interface().version);
-                    let compositor =
-                        registry.bind::<WlCompositor, (), Locker>(name, version, qh, ());
-                    state.compositor = Some(compositor);
-                }
-                "wl_shm" => {
-                    let version = version.min(WlShm::interface().version);
-                    let shared_memory = registry.bind::<WlShm, (), Locker>(name, version, qh, ());
-                    state.shared_memory = Some(shared_memory);
-                }
-                "wl_output" => {
-                    let version = version.min(WlOutput::interface().version);
-                    let output = registry.bind::<WlOutput, (), Locker>(name, version, qh, ());
-                    let disp = Monitor::default().with_name(name).with_output(output);
-                    state.monitors.insert(name, disp);
-                }
-                _ => return,
-            }
-
-            logln!("Locker found [{}] {} (v{})", name, interface, version);
-        }
+    let mut argb = vec![0; width as usize * height as usize * 4];
+    let mut target = PixelBuffer::new(&mut argb, width, height, width * 4);
+    rasterizer.rasterize_tinted(&cells, tui::box_tint(cells.area, &app), &mut target);
+    if let Some(caret) = tui::caret_position(cells.area, &app) {
+        rasterizer.draw_caret(&cells, caret, &mut target);
     }
-}
 
-impl Dispatch<ExtSessionLockManagerV1, ()> for Locker {
-    fn event(
-        _state: &mut Self,
-        _: &ExtSessionLockManagerV1,
-        _: ext_session_lock_manager_v1::Event,
-        _: &(),
-        _: &Connection,
-        _: &QueueHandle<Locker>,
-    ) {
-        logln!(
-            "received an event from ExtSessionLockManager, but don't know what to do with it..."
-        )
+    let mut rgba = Vec::with_capacity(width as usize * height as usize * 4);
+    for y in 0..height {
+        for x in 0..width {
+            let [_, r, g, b] = target.pixel(x, y).unwrap_or(0).to_be_bytes();
+            rgba.extend_from_slice(&[r, g, b, 0xFF]);
+        }
     }
+    let file =
+        File::create(path).map_err(|err| anyhow!("failed to create {}: {err}", path.display()))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(&rgba)?;
+    info!(
+        "wrote frame {} at {width}x{height} to {}",
+        args.tick,
+        path.display()
+    );
+    Ok(())
 }
 
-impl Dispatch<ExtSessionLockV1, ()> for Locker {
-    fn event(
-        state: &mut Self,
-        _: &ExtSessionLockV1,
-        event: ext_session_lock_v1::Event,
-        _: &(),
-        _: &Connection,
-        _: &QueueHandle<Locker>,
-    ) {
-        match event {
-            // session successfully locked This client is now responsible for displaying
-            // graphics while the session is locked and deciding when to unlock the session.
-            //
-            // The locked event must not be sent until a new “locked” frame has been presented
-            // on all outputs and no security sensitive normal/unlocked content is possibly
-            // visible.
-            //
-            // If this event is sent, making the destroy request is a protocol error, the lock
-            // object must be destroyed using the unlock_and_destroy request.
-            ext_session_lock_v1::Event::Locked => {
-                logln!("received ext_session_lock_v1::Locked");
-                state.state = LockState::Locked;
-                state.auto_unlock_deadline = Some(Instant::now() + Duration::from_secs(5));
+// Connects to the compositor named by WAYLAND_DISPLAY. Started as an early-boot service, lilac
+// can race the compositor creating its socket, so a missing compositor is retried up to
+// `attempts` times, `delay` apart. Any other failure won't fix itself and is returned at once.
+fn connect(
+    attempts: u32,
+    delay: Duration,
+    mut try_connect: impl FnMut() -> Result<Connection, ConnectError>,
+) -> anyhow::Result<Connection> {
+    for attempt in 1.. {
+        match try_connect() {
+            Ok(conn) => return Ok(conn),
+            Err(ConnectError::NoCompositor) if attempt < attempts => {
+                info!("no Wayland compositor yet (attempt {attempt} of {attempts}), retrying");
+                std::thread::sleep(delay);
             }
-            // the session lock object should be destroyed
-            //
-            // The compositor has decided that the session lock should be destroyed as it will
-            // no longer be used by the compositor. Exactly when this event is sent is
-            // compositor policy, but it must never be sent more than once for a given session
-            // lock object.
-            //
-            // This might be sent because there is already another ext_session_lock_v1 object
-            // held by a client, or the compositor has decided to deny the request to lock the
-            // session for some other reason. This might also be sent because the compositor
-            // implements some alternative, secure way to authenticate and unlock the session.
-            //
-            // The finished event should be sent immediately on creation of this object if the
-            // compositor decides that the locked event will not be sent.
-            //
-            // If the locked event is sent on creation of this object the finished event may
-            // still be sent at some later time in this object’s lifetime. This is compositor
-            // policy.
-            //
-            // Upon receiving this event, the client should make either the destroy request or
-            // the unlock_and_destroy request, depending on whether or not the locked event was
-            // received on this object.
-            ext_session_lock_v1::Event::Finished => {
-                logln!("received ext_session_lock_v1::Finished");
-                state.state = LockState::Finished;
-            }
-            _ => logln!("unknown event received from ExtSessionLock"),
+            Err(ConnectError::NoCompositor) => break,
+            Err(err) => return Err(anyhow!("failed to connect to the compositor: {err}")),
         }
     }
+    let display = std::env::var("WAYLAND_DISPLAY").unwrap_or_else(|_| "wayland-0".to_string());
+    Err(anyhow!(
+        "couldn't find the Wayland socket {display:?} after {attempts} attempts; is the compositor running and XDG_RUNTIME_DIR set?"
+    ))
 }
 
-impl Dispatch<WlCompositor, ()> for Locker {
-    fn event(
-        _state: &mut Self,
-        _: &WlCompositor,
-        _: wl_compositor::Event,
-        _: &(),
-        _: &Connection,
-        _: &QueueHandle<Locker>,
-    ) {
-        logln!("received an event from WlCompositor, but don't know what to do with it...")
-    }
-}
-
-impl Dispatch<WlOutput, ()> for Locker {
-    fn event(
-        _state: &mut Self,
-        _: &WlOutput,
-        _: wl_output::Event,
-        _: &(),
-        _: &Connection,
-        _: &QueueHandle<Locker>,
-    ) {
-        logln!("received an event from WlOutput, but don't know what to do with it...")
+// A loop source that becomes readable once SIGTERM or SIGINT arrives. The handlers only write
+// a byte to a socket, which is all a signal handler may safely do, and that wakes the loop even
+// while it's blocked waiting for the compositor. A signal before the loop runs waits in the
+// socket until it does.
+fn termination_signals() -> anyhow::Result<Generic<UnixStream>> {
+    let (read, write) = UnixStream::pair()?;
+    write.set_nonblocking(true)?;
+    for signal in [signal_hook::consts::SIGTERM, signal_hook::consts::SIGINT] {
+        signal_hook::low_level::pipe::register(signal, write.try_clone()?)?;
     }
+    Ok(wake_source(read)?)
 }
 
-impl Dispatch<WlSurface, ()> for Locker {
-    fn event(
-        _state: &mut Self,
-        _: &WlSurface,
-        _: wl_surface::Event,
-        _: &(),
-        _: &Connection,
-        _: &QueueHandle<Locker>,
-    ) {
-        logln!("received an event from WlSurface, but don't know what to do with it...")
-    }
+// Watches the read end of the socket the signal handlers write to.
+fn wake_source(read: UnixStream) -> std::io::Result<Generic<UnixStream>> {
+    read.set_nonblocking(true)?;
+    Ok(Generic::new(read, Interest::READ, Mode::Level))
 }
 
-impl Dispatch<WlShm, ()> for Locker {
-    fn event(
-        _state: &mut Self,
-        _: &WlShm,
-        _: wl_shm::Event,
-        _: &(),
-        _: &Connection,
-        _: &QueueHandle<Locker>,
-    ) {
-        logln!("received an event from WlShm, but don't know what to do with it...")
-    }
-}
+// The main function of our program
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
 
-impl Dispatch<WlShmPool, ()> for Locker {
-    fn event(
-        _state: &mut Self,
-        _: &WlShmPool,
-        _: wl_shm_pool::Event,
-        _: &(),
-        _: &Connection,
-        _: &QueueHandle<Locker>,
-    ) {
-        logln!("received an event from WlShmPool, but don't know what to do with it...")
-    }
-}
+    let signals = termination_signals()?;
 
-impl Dispatch<WlBuffer, BufferTag> for Locker {
-    fn event(
-        state: &mut Self,
-        _: &WlBuffer,
-        event: wl_buffer::Event,
-        tag: &BufferTag,
-        _: &Connection,
-        _: &QueueHandle<Locker>,
-    ) {
-        match event {
-            wl_buffer::Event::Release => {
-                logln!("received a Release event for WlBuffer");
-                let Some(monitor) = state.monitors.get_mut(&tag.monitor_name) else {
-                    return;
-                };
-                let Some(buffer_state) = monitor.buffer_state.as_mut() else {
-                    return;
-                };
-
-                if tag.index < buffer_state.buffers.len() {
-                    buffer_state.buffers[tag.index].in_use = false;
-                }
-            }
-            _ => logln!("received an event from WlBuffer, but don't know what to do with it..."),
-        };
+    let file = match &args.config {
+        Some(path) => Config::load_from(path)?,
+        None => Config::load()?,
+    };
+    // set up first, so nothing logged while starting is lost
+    let log_file = args
+        .log
+        .clone()
+        .or_else(|| std::env::var_os("LILAC_LOG_FILE").map(PathBuf::from))
+        .or_else(|| file.log_file.clone());
+    let log_time = match std::env::var("LILAC_LOG_TIME") {
+        Ok(format) => format.parse().map_err(anyhow::Error::msg)?,
+        Err(_) => file.log_time()?.unwrap_or_default(),
+    };
+    let destination = log_file.map_or_else(Destination::default_file, Destination::from_path);
+    lilac::log::init(&destination, log_time)?;
+    let config = lock_config(&args, &file)?;
+    if let Some(path) = &args.test_frame {
+        return write_test_frame(&config, &args, path);
     }
-}
 
-impl Dispatch<ExtSessionLockSurfaceV1, ()> for Locker {
-    fn event(
-        state: &mut Self,
-        proxy: &ExtSessionLockSurfaceV1,
-        event: ext_session_lock_surface_v1::Event,
-        _: &(),
-        _: &Connection,
-        qh: &QueueHandle<Locker>,
-    ) {
-        match event {
-            ext_session_lock_surface_v1::Event::Configure {
-                width,
-                height,
-                serial,
-            } => {
-                let event_proxy_id = proxy.id();
-                for (name, monitor) in state.monitors.iter_mut() {
-                    if let Some(lock_surface) = monitor.lock_surface.as_ref() {
-                        if lock_surface.id() != event_proxy_id {
-                            continue;
-                        }
-
-                        let mut final_width = width;
-                        let mut final_height = height;
-
-                        if final_width == 0 {
-                            final_width = 1920;
-                        }
-
-                        if final_height == 0 {
-                            final_height = 1080;
-                        }
-                        monitor.dimensions = (final_width, final_height);
-
-                        lock_surface.ack_configure(serial);
-
-                        let shm = &state.shared_memory.as_ref().unwrap();
-
-                        let buffer_state = BufferState::new(
-                            shm,
-                            qh,
-                            *name,
-                            final_width.try_into().unwrap(),
-                            final_height.try_into().unwrap(),
-                        )
-                        .unwrap();
-
-                        let mut buffer_state = buffer_state;
-                        let blue = 0xFF0000FFu32.to_ne_bytes();
-                        buffer_state.fill_solid_color(blue);
-                        monitor.buffer_state = Some(buffer_state);
-                        match monitor.commit() {
-                            Ok(true) => {}
-                            Ok(false) => {
-                                logln!("all buffers were in use after configure");
-                            }
-                            Err(err) => {
-                                logln!("commit failed after configure: {err}");
-                            }
-                        }
-                    }
-                }
-            }
-            _ => logln!("unknown event rx'd in extsessionlocksurfacev1 dispatch handler"),
+    // without logind the lock still works, only loginctl can't drive it
+    #[cfg(feature = "dbus")]
+    let session = match lilac::logind::Session::current() {
+        Ok(session) => Some(session),
+        Err(err) if !args.wait_for_lock => {
+            tracing::warn!("failed to find the logind session, lock-session is ignored: {err}");
+            None
         }
+        Err(err) => return Err(anyhow!("failed to find the logind session: {err}")),
+    };
+    #[cfg(feature = "dbus")]
+    if args.wait_for_lock
+        && let Some(session) = session.as_ref()
+    {
+        info!("waiting for logind to lock the session");
+        session.wait_for_lock()?;
     }
-}
 
-// The main function of our program
-fn main() -> anyhow::Result<()> {
     // Create a Wayland connection by connecting to the server through the
     // environment-provided configuration.
-    let conn = Connection::connect_to_env()?;
+    let conn = connect(
+        config.connect_attempts,
+        config.connect_retry_delay,
+        Connection::connect_to_env,
+    )?;
 
     // Retrieve the WlDisplay Wayland object from the connection. This object is
     // the starting point of any Wayland program, from which all other objects will
@@ -669,7 +290,7 @@ fn main() -> anyhow::Result<()> {
     // with this registry (here it is () as we don't need user-data).
     let _registry = display.get_registry(&qh, ());
 
-    let mut locker = Locker::default();
+    let mut locker = Locker::new(config, conn.clone());
 
     // To actually receive the events, we invoke the `roundtrip` method. This method
     // is special and you will generally only invoke it during the setup of your program:
@@ -685,92 +306,204 @@ fn main() -> anyhow::Result<()> {
     // `ext_session_lock_manager_v1` interface advertisement, and bind to it.
     event_queue.roundtrip(&mut locker)?;
 
-    locker.is_initialized()?;
-
-    // at this point, we're in a happy initial state, as we've registered all of our globals
-    let lock = locker
-        .lock_manager
-        .as_ref()
-        .ok_or_else(|| anyhow!("lock manager cannot be empty when trying to call lock"))?
-        .lock(&qh, ());
-    let compositor = locker
-        .compositor
-        .as_ref()
-        .ok_or_else(|| anyhow!("compositor must not be None when creating surfaces"))?;
-
-    for monitor in locker.monitors.values_mut() {
-        monitor.create_surface_and_lock(compositor, &lock, &qh)?;
+    // A second roundtrip delivers the events sent in response to binding the outputs above,
+    // such as their names and descriptions.
+    event_queue.roundtrip(&mut locker)?;
+
+    if args.probe {
+        print!("{}", probe_report(&locker, args.json));
+        return Ok(());
     }
 
-    locker.lock = Some(lock);
-    locker.state = LockState::Waiting;
+    locker.request_lock(&qh)?;
+
+    // the Wayland socket and the animation tick share one poll; the loop data is the locker
+    let mut event_loop: EventLoop<'static, Locker> = EventLoop::try_new()?;
+    let loop_handle = event_loop.handle();
+    WaylandSource::new(conn.clone(), event_queue)
+        .insert(loop_handle.clone())
+        .map_err(|err| anyhow!("failed to watch the wayland socket: {}", err.error))?;
+    locker.attach(loop_handle.clone())?;
+    let terminate = Rc::new(Cell::new(false));
+    let terminated = Rc::clone(&terminate);
+    loop_handle
+        .insert_source(signals, move |_, _, _| {
+            terminated.set(true);
+            Ok(PostAction::Remove)
+        })
+        .map_err(|err| anyhow!("failed to watch for signals: {}", err.error))?;
+    #[cfg(feature = "dbus")]
+    if let Some(session) = session {
+        loop_handle
+            .insert_source(session.watch()?, |event, _, locker| {
+                if let calloop::channel::Event::Msg(signal) = event {
+                    locker.session_signal(signal);
+                }
+            })
+            .map_err(|err| anyhow!("failed to watch the logind session: {}", err.error))?;
+    }
+    // without a session bus there's just no line under the box
+    #[cfg(feature = "mpris")]
+    match lilac::media::Mpris::session().and_then(|mpris| mpris.watch()) {
+        Ok(players) => {
+            loop_handle
+                .insert_source(players, |event, _, locker| {
+                    if let calloop::channel::Event::Msg(now_playing) = event {
+                        locker.now_playing(now_playing);
+                    }
+                })
+                .map_err(|err| anyhow!("failed to watch the media players: {}", err.error))?;
+        }
+        Err(err) => tracing::warn!("failed to watch the media players, not showing any: {err}"),
+    }
 
     loop {
-        conn.flush()?;
-        if let Some(guard) = event_queue.prepare_read() {
-            match guard.read() {
-                Ok(_) => {}
-                Err(WaylandError::Io(err)) if err.kind() == ErrorKind::WouldBlock => {}
-                Err(err) => return Err(err.into()),
-            }
+        // flushes our requests, then blocks until the compositor, a timer or the authentication
+        // thread has work for us
+        event_loop.dispatch(None, &mut locker)?;
+
+        if terminate.get() {
+            locker.terminate();
+            break;
         }
 
-        let dispatched = event_queue.dispatch_pending(&mut locker)?;
+        locker.after_dispatch(&qh)?;
+        if locker.is_finished() {
+            break;
+        }
+    }
 
-        for monitor in locker.monitors.values_mut() {
-            let is_dirty = monitor
-                .buffer_state
-                .as_ref()
-                .map(|bs| bs.dirty)
-                .unwrap_or(false);
+    #[cfg(feature = "systemd")]
+    if let Err(err) = lilac::notify::stopping() {
+        tracing::warn!("failed to notify systemd of shutdown: {err}");
+    }
+    Ok(())
+}
 
-            if is_dirty {
-                let committed = monitor.commit()?;
-                if !committed {
-                    logln!("all buffers were in use, will try to commit on a later event")
-                }
-            }
-        }
+#[cfg(test)]
+mod tests {
+    use std::io::{BufReader, Write};
+
+    use clap::CommandFactory;
+
+    use super::*;
+
+    #[test]
+    fn flags_parse_into_args() {
+        Args::command().debug_assert();
+        let args = Args::try_parse_from([
+            "lilac",
+            "--box-on",
+            "all",
+            "--grace",
+            "5",
+            "--test-frame",
+            "frame.png",
+            "--width",
+            "640",
+            "--height",
+            "480",
+            "--tick",
+            "3",
+        ])
+        .unwrap();
+        assert_eq!(args.box_on, Some(BoxPlacement::All));
+        assert_eq!(args.grace, Some(5));
+        assert_eq!(args.test_frame, Some(PathBuf::from("frame.png")));
+        assert_eq!((args.width, args.height, args.tick), (640, 480, 3));
+
+        let args = Args::try_parse_from(["lilac"]).unwrap();
+        assert_eq!(args.box_on, None);
+        assert_eq!((args.width, args.height, args.tick), (1920, 1080, 0));
+        assert!(Args::try_parse_from(["lilac", "--json"]).is_err());
+        // the preview flags only make sense with a frame to write
+        assert!(Args::try_parse_from(["lilac", "--tick", "3"]).is_err());
+        assert!(Args::try_parse_from(["lilac", "--preview", "a.png", "--width", "0"]).is_err());
+        assert!(Args::try_parse_from(["lilac", "--box-on", "left"]).is_err());
+    }
 
-        match locker.state {
-            // break out of our loop
-            LockState::Finished => break,
-            LockState::Idle => {
-                return Err(anyhow!(
-                    "illegal state: Lock should not have been idle when entering the loop"
-                ));
-            }
-            LockState::Waiting => {}
-            LockState::Locked => {
-                if locker.auto_unlock_sent {
-                    continue;
-                }
-                if let Some(deadline) = locker.auto_unlock_deadline {
-                    if Instant::now() >= deadline {
-                        if let Some(lock) = locker.lock.as_ref() {
-                            lock.unlock_and_destroy();
-                            locker.auto_unlock_sent = true;
-                        }
-                    }
-                }
-            }
-        }
+    #[test]
+    fn a_test_frame_is_a_png_of_the_requested_size() {
+        let path = std::env::temp_dir().join(format!("lilac-frame-{}.png", std::process::id()));
+        let args = Args::try_parse_from([
+            "lilac",
+            "--preview",
+            "frame.png",
+            "--width",
+            "320",
+            "--height",
+            "200",
+            "--tick",
+            "2",
+            "--username",
+            "alice",
+            "--error",
+            "Authentication failed",
+        ])
+        .unwrap();
+        write_test_frame(&LockConfig::default(), &args, &path).unwrap();
+        let decoder = png::Decoder::new(BufReader::new(File::open(&path).unwrap()));
+        let info = decoder.read_info().unwrap().info().clone();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!((info.width, info.height), (320, 200));
+        assert_eq!(info.color_type, png::ColorType::Rgba);
+    }
 
-        if dispatched == 0 {
-            let mut sleep_for = Duration::from_millis(16);
-            if let Some(deadline) = locker.auto_unlock_deadline {
-                let now = Instant::now();
-                if deadline > now {
-                    sleep_for = sleep_for.min(deadline - now);
-                } else {
-                    sleep_for = Duration::from_millis(0);
-                }
+    #[test]
+    fn connecting_retries_only_while_there_is_no_compositor() {
+        // a connection nobody answers on stands in for the compositor's
+        let (client, _server) = UnixStream::pair().unwrap();
+        let compositor = Connection::from_socket(client).unwrap();
+        let mut tries = 0;
+        let conn = connect(3, Duration::ZERO, || {
+            tries += 1;
+            match tries {
+                3 => Ok(compositor.clone()),
+                _ => Err(ConnectError::NoCompositor),
             }
-            if sleep_for > Duration::from_millis(0) {
-                std::thread::sleep(sleep_for);
-            }
-        }
+        });
+        assert!(conn.is_ok());
+
+        tries = 0;
+        let err = connect(3, Duration::ZERO, || {
+            tries += 1;
+            Err(ConnectError::NoCompositor)
+        })
+        .unwrap_err();
+        assert_eq!(tries, 3);
+        assert!(err.to_string().contains("after 3 attempts"), "{err}");
+
+        // a broken WAYLAND_SOCKET won't fix itself
+        tries = 0;
+        let err = connect(3, Duration::ZERO, || {
+            tries += 1;
+            Err(ConnectError::InvalidFd)
+        })
+        .unwrap_err();
+        assert_eq!(tries, 1);
+        assert!(err.to_string().starts_with("failed to connect"), "{err}");
     }
 
-    Ok(())
+    #[test]
+    fn a_signal_ends_a_blocked_dispatch() {
+        let (read, write) = UnixStream::pair().unwrap();
+        let mut event_loop: EventLoop<'static, bool> = EventLoop::try_new().unwrap();
+        event_loop
+            .handle()
+            .insert_source(wake_source(read).unwrap(), |_, _, terminated| {
+                *terminated = true;
+                Ok(PostAction::Remove)
+            })
+            .unwrap();
+        // writes what a signal handler would, once the loop below is waiting with nothing else
+        // to wake it; raising a real SIGTERM would reach every test in the process
+        let handler = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            (&write).write_all(&[0]).unwrap();
+        });
+        let mut terminated = false;
+        event_loop.dispatch(None, &mut terminated).unwrap();
+        handler.join().unwrap();
+        assert!(terminated);
+    }
 }
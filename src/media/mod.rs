@@ -0,0 +1,202 @@
+/// The track a media player reports as currently playing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NowPlaying {
+    pub title: String,
+    pub artist: Option<String>,
+}
+
+impl NowPlaying {
+    pub fn line(&self) -> String {
+        match &self.artist {
+            Some(artist) => format!("♪ {} — {}", self.title, artist),
+            None => format!("♪ {}", self.title),
+        }
+    }
+}
+
+/// Something that can be asked what's playing. Returns `None` when nothing is, so the widget is
+/// simply omitted. Implemented over MPRIS for real sessions and by fakes in tests.
+pub trait MediaSource {
+    fn now_playing(&mut self) -> Option<NowPlaying>;
+}
+
+#[cfg(feature = "mpris")]
+pub use mpris::Mpris;
+
+#[cfg(feature = "mpris")]
+mod mpris {
+    use std::collections::HashMap;
+
+    use calloop::channel::{self, Channel};
+    use zbus::{
+        MatchRule,
+        blocking::{Connection, MessageIterator, Proxy, fdo::DBusProxy},
+        message::{self, Message},
+        zvariant::{Array, OwnedValue},
+    };
+
+    use super::{MediaSource, NowPlaying};
+
+    const PLAYER_PREFIX: &str = "org.mpris.MediaPlayer2.";
+    const PLAYER_PATH: &str = "/org/mpris/MediaPlayer2";
+
+    /// Read-only MPRIS2 client on the session bus. It only reads metadata and never exposes
+    /// playback control on the lock screen.
+    pub struct Mpris {
+        connection: Connection,
+    }
+
+    impl Mpris {
+        pub fn session() -> zbus::Result<Self> {
+            Ok(Self {
+                connection: Connection::session()?,
+            })
+        }
+
+        fn query(&self) -> zbus::Result<Option<NowPlaying>> {
+            let names = DBusProxy::new(&self.connection)?.list_names()?;
+            for name in names {
+                if !name.as_str().starts_with(PLAYER_PREFIX) {
+                    continue;
+                }
+
+                let player = Proxy::new(
+                    &self.connection,
+                    name.as_str(),
+                    PLAYER_PATH,
+                    "org.mpris.MediaPlayer2.Player",
+                )?;
+                let status: String = player.get_property("PlaybackStatus")?;
+                if status != "Playing" {
+                    continue;
+                }
+
+                let metadata: HashMap<String, OwnedValue> = player.get_property("Metadata")?;
+                let Some(title) = metadata
+                    .get("xesam:title")
+                    .and_then(|value| <&str>::try_from(&**value).ok())
+                    .filter(|title| !title.is_empty())
+                else {
+                    continue;
+                };
+                let artist = metadata
+                    .get("xesam:artist")
+                    .and_then(|value| <&Array>::try_from(&**value).ok())
+                    .map(|artists| {
+                        artists
+                            .iter()
+                            .filter_map(|artist| <&str>::try_from(artist).ok())
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    })
+                    .filter(|artist| !artist.is_empty());
+
+                return Ok(Some(NowPlaying {
+                    title: title.to_string(),
+                    artist,
+                }));
+            }
+            Ok(None)
+        }
+
+        /// Sends what's playing into an event loop, first as it is now and again whenever a
+        /// player's properties change or a player comes or goes. The signals are read on
+        /// threads of their own, which end when the channel is dropped.
+        pub fn watch(self) -> zbus::Result<Channel<Option<NowPlaying>>> {
+            let properties = MatchRule::builder()
+                .msg_type(message::Type::Signal)
+                .interface("org.freedesktop.DBus.Properties")?
+                .member("PropertiesChanged")?
+                .path(PLAYER_PATH)?
+                .build();
+            let owners = MatchRule::builder()
+                .msg_type(message::Type::Signal)
+                .sender("org.freedesktop.DBus")?
+                .interface("org.freedesktop.DBus")?
+                .member("NameOwnerChanged")?
+                .build();
+            // subscribed before the first query, so no change after it is missed
+            let properties = MessageIterator::for_match_rule(properties, &self.connection, None)?;
+            let owners = MessageIterator::for_match_rule(owners, &self.connection, None)?;
+
+            let (sender, channel) = channel::channel();
+            let forward = |signals: MessageIterator, relevant: fn(&Message) -> bool| {
+                let sender = sender.clone();
+                let mpris = Self {
+                    connection: self.connection.clone(),
+                };
+                std::thread::spawn(move || {
+                    // what's playing as the watch starts, then after every relevant signal
+                    let changes = signals.flatten().map(|message| relevant(&message));
+                    for changed in std::iter::once(true).chain(changes) {
+                        if changed && sender.send(mpris.query().ok().flatten()).is_err() {
+                            break;
+                        }
+                    }
+                });
+            };
+            forward(properties, |_| true);
+            forward(owners, names_a_player);
+            Ok(channel)
+        }
+    }
+
+    impl MediaSource for Mpris {
+        fn now_playing(&mut self) -> Option<NowPlaying> {
+            self.query().ok().flatten()
+        }
+    }
+
+    // Whether a NameOwnerChanged is about a player's bus name, which comes and goes with it.
+    fn names_a_player(message: &Message) -> bool {
+        message
+            .body()
+            .deserialize::<(String, String, String)>()
+            .is_ok_and(|(name, _, _)| name.starts_with(PLAYER_PREFIX))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tui::{self, AppState};
+
+    // Reports a fixed track, or nothing, the way a player on the bus would.
+    struct Fake(Option<NowPlaying>);
+
+    impl MediaSource for Fake {
+        fn now_playing(&mut self) -> Option<NowPlaying> {
+            self.0.clone()
+        }
+    }
+
+    // The lock screen's text with whatever `source` says is playing.
+    fn screen(source: &mut dyn MediaSource) -> String {
+        let mut state = AppState {
+            now_playing: source.now_playing(),
+            ..AppState::default()
+        };
+        let buffer = tui::render_to_buffer(&mut state, 80, 30);
+        buffer.content().iter().map(|cell| cell.symbol()).collect()
+    }
+
+    #[test]
+    fn the_playing_track_is_shown_under_the_box() {
+        let track = NowPlaying {
+            title: "Blue in Green".to_string(),
+            artist: Some("Miles Davis".to_string()),
+        };
+        assert!(screen(&mut Fake(Some(track))).contains("♪ Blue in Green — Miles Davis"));
+
+        let without_artist = NowPlaying {
+            title: "So What".to_string(),
+            artist: None,
+        };
+        assert!(screen(&mut Fake(Some(without_artist))).contains("♪ So What"));
+    }
+
+    #[test]
+    fn without_a_player_there_is_no_widget() {
+        assert!(!screen(&mut Fake(None)).contains('♪'));
+    }
+}
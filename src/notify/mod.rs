@@ -0,0 +1,68 @@
+use std::{
+    ffi::OsStr,
+    io,
+    os::{
+        linux::net::SocketAddrExt,
+        unix::net::{SocketAddr, UnixDatagram},
+    },
+};
+
+/// Tells the service manager that the session is locked and every output shows a locked
+/// frame, so a `Type=notify` unit only counts as started once the screen is actually secured.
+pub fn ready() -> io::Result<bool> {
+    send("READY=1")
+}
+
+/// Tells the service manager that lilac is shutting down.
+pub fn stopping() -> io::Result<bool> {
+    send("STOPPING=1")
+}
+
+/// Sends `state` to the socket in `$NOTIFY_SOCKET`, the protocol behind `sd_notify(3)`.
+/// Returns false without doing anything when lilac wasn't started by a service manager that
+/// listens for it.
+pub fn send(state: &str) -> io::Result<bool> {
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET").filter(|path| !path.is_empty()) else {
+        return Ok(false);
+    };
+    send_to(&path, state)?;
+    Ok(true)
+}
+
+fn send_to(path: &OsStr, state: &str) -> io::Result<()> {
+    // a leading @ names a socket in the abstract namespace
+    let addr = match path.as_encoded_bytes().strip_prefix(b"@") {
+        Some(name) => SocketAddr::from_abstract_name(name)?,
+        None => SocketAddr::from_pathname(path)?,
+    };
+    let socket = UnixDatagram::unbound()?;
+    socket.send_to_addr(state.as_bytes(), &addr)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn received(listener: &UnixDatagram) -> String {
+        let mut buf = [0; 64];
+        let len = listener.recv(&mut buf).unwrap();
+        String::from_utf8(buf[..len].to_vec()).unwrap()
+    }
+
+    #[test]
+    fn states_reach_path_and_abstract_sockets() {
+        let path = std::env::temp_dir().join(format!("lilac-notify-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixDatagram::bind(&path).unwrap();
+        send_to(path.as_os_str(), "READY=1").unwrap();
+        assert_eq!(received(&listener), "READY=1");
+        std::fs::remove_file(&path).unwrap();
+
+        let name = format!("lilac-notify-{}", std::process::id());
+        let addr = SocketAddr::from_abstract_name(name.as_bytes()).unwrap();
+        let listener = UnixDatagram::bind_addr(&addr).unwrap();
+        send_to(OsStr::new(&format!("@{name}")), "STOPPING=1").unwrap();
+        assert_eq!(received(&listener), "STOPPING=1");
+    }
+}
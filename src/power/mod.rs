@@ -0,0 +1,143 @@
+use std::path::Path;
+
+/// Where the kernel lists the power supplies it knows about.
+pub const POWER_SUPPLY_DIR: &str = "/sys/class/power_supply";
+
+// at or below this, a battery that isn't charging is drawn reversed to catch the eye
+const LOW_PERCENT: u8 = 15;
+
+/// The charge of the machine's batteries taken together.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Battery {
+    pub percent: u8,
+    pub charging: bool,
+}
+
+impl Battery {
+    pub fn line(&self) -> String {
+        if self.charging {
+            format!("Charging {}%", self.percent)
+        } else {
+            format!("Battery {}%", self.percent)
+        }
+    }
+
+    /// Running low and not plugged in, worth drawing attention to.
+    pub fn is_low(&self) -> bool {
+        !self.charging && self.percent <= LOW_PERCENT
+    }
+}
+
+/// Reads the system batteries listed under `dir`, see `POWER_SUPPLY_DIR`, averaging their
+/// charge. `None` when there are none, as on a desktop, so the indicator is left out. Batteries
+/// in peripherals such as a wireless mouse report a `Device` scope and don't count.
+pub fn read_batteries(dir: &Path) -> Option<Battery> {
+    let mut capacities = Vec::new();
+    let mut charging = false;
+    for entry in std::fs::read_dir(dir).ok()?.flatten() {
+        let supply = entry.path();
+        let read = |name: &str| {
+            let value = std::fs::read_to_string(supply.join(name)).ok()?;
+            Some(value.trim().to_string())
+        };
+        if read("type").as_deref() != Some("Battery") || read("scope").as_deref() == Some("Device")
+        {
+            continue;
+        }
+        let Some(capacity) = read("capacity").and_then(|capacity| capacity.parse::<u8>().ok())
+        else {
+            continue;
+        };
+        capacities.push(capacity.min(100) as u32);
+        charging |= read("status").as_deref() == Some("Charging");
+    }
+
+    if capacities.is_empty() {
+        return None;
+    }
+    let percent = capacities.iter().sum::<u32>() / capacities.len() as u32;
+    Some(Battery {
+        percent: percent as u8,
+        charging,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    // A power supply class directory with a supply for every `(name, [(file, contents)])`.
+    fn supplies(test: &str, supplies: &[(&str, &[(&str, &str)])]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("lilac-power-{test}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        for (name, files) in supplies {
+            let supply = dir.join(name);
+            std::fs::create_dir_all(&supply).unwrap();
+            for (file, contents) in *files {
+                std::fs::write(supply.join(file), format!("{contents}\n")).unwrap();
+            }
+        }
+        dir
+    }
+
+    #[test]
+    fn batteries_are_averaged_and_peripherals_left_out() {
+        let dir = supplies(
+            "laptop",
+            &[
+                ("AC", &[("type", "Mains"), ("online", "1")]),
+                (
+                    "BAT0",
+                    &[
+                        ("type", "Battery"),
+                        ("capacity", "80"),
+                        ("status", "Charging"),
+                    ],
+                ),
+                (
+                    "BAT1",
+                    &[("type", "Battery"), ("capacity", "41"), ("status", "Full")],
+                ),
+                (
+                    "hid-mouse",
+                    &[("type", "Battery"), ("scope", "Device"), ("capacity", "5")],
+                ),
+            ],
+        );
+        let battery = read_batteries(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            battery,
+            Battery {
+                percent: 60,
+                charging: true
+            }
+        );
+        assert!(!battery.is_low());
+    }
+
+    #[test]
+    fn a_desktop_has_no_battery() {
+        let dir = supplies("desktop", &[("AC", &[("type", "Mains"), ("online", "1")])]);
+        assert_eq!(read_batteries(&dir), None);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(read_batteries(&dir), None);
+    }
+
+    #[test]
+    fn a_draining_battery_runs_low() {
+        let battery = Battery {
+            percent: 12,
+            charging: false,
+        };
+        assert!(battery.is_low());
+        assert_eq!(battery.line(), "Battery 12%");
+        let charging = Battery {
+            charging: true,
+            ..battery
+        };
+        assert!(!charging.is_low());
+    }
+}
@@ -0,0 +1,145 @@
+use ratatui::style::Color;
+
+// xterm's defaults for the 16 ANSI colors, in ANSI index order
+const ANSI: [u32; 16] = [
+    0x000000, 0x800000, 0x008000, 0x808000, 0x000080, 0x800080, 0x008080, 0xC0C0C0, 0x808080,
+    0xFF0000, 0x00FF00, 0xFFFF00, 0x0000FF, 0xFF00FF, 0x00FFFF, 0xFFFFFF,
+];
+
+/// Converts a ratatui color to an opaque ARGB8888 value. `Color::Reset` has no color of its
+/// own, so the caller decides what it means (the default foreground or background).
+pub fn to_argb(color: Color) -> Option<u32> {
+    let rgb = match color {
+        Color::Reset => return None,
+        Color::Black => ANSI[0],
+        Color::Red => ANSI[1],
+        Color::Green => ANSI[2],
+        Color::Yellow => ANSI[3],
+        Color::Blue => ANSI[4],
+        Color::Magenta => ANSI[5],
+        Color::Cyan => ANSI[6],
+        Color::Gray => ANSI[7],
+        Color::DarkGray => ANSI[8],
+        Color::LightRed => ANSI[9],
+        Color::LightGreen => ANSI[10],
+        Color::LightYellow => ANSI[11],
+        Color::LightBlue => ANSI[12],
+        Color::LightMagenta => ANSI[13],
+        Color::LightCyan => ANSI[14],
+        Color::White => ANSI[15],
+        Color::Rgb(r, g, b) => u32::from_be_bytes([0, r, g, b]),
+        Color::Indexed(index) => indexed(index),
+    };
+    Some(0xFF00_0000 | rgb)
+}
+
+// the xterm 256 color palette: 16 ANSI colors, a 6x6x6 cube, then 24 grays
+fn indexed(index: u8) -> u32 {
+    match index {
+        0..=15 => ANSI[index as usize],
+        16..=231 => {
+            let level = |v: u8| if v == 0 { 0 } else { 55 + v as u32 * 40 };
+            let cube = index - 16;
+            (level(cube / 36) << 16) | (level((cube / 6) % 6) << 8) | level(cube % 6)
+        }
+        232..=255 => {
+            let gray = 8 + (index - 232) as u32 * 10;
+            (gray << 16) | (gray << 8) | gray
+        }
+    }
+}
+
+/// Converts a straight-alpha ARGB8888 color to the premultiplied form `wl_shm` expects, where
+/// each color channel is already scaled by the alpha. Opaque colors are unchanged.
+pub fn premultiply(argb: u32) -> u32 {
+    let [a, r, g, b] = argb.to_be_bytes();
+    let scale = |c: u8| ((c as u32 * a as u32 + 127) / 255) as u8;
+    u32::from_be_bytes([a, scale(r), scale(g), scale(b)])
+}
+
+/// Draws the straight-alpha color `over` onto the premultiplied pixel `under`, with `coverage`
+/// out of 255 (a glyph's antialiased edge). The result is premultiplied, and opaque whenever
+/// `under` is.
+pub fn blend_argb(over: u32, under: u32, coverage: u8) -> u32 {
+    let over = premultiply(over).to_be_bytes();
+    let under = under.to_be_bytes();
+    let coverage = coverage as u32;
+    // how much of `under` the covered part of `over` hides, out of 255 * 255
+    let hidden = over[0] as u32 * coverage;
+    let mix = |o: u8, u: u8| {
+        ((o as u32 * coverage * 255 + u as u32 * (255 * 255 - hidden) + 32512) / (255 * 255)) as u8
+    };
+    u32::from_be_bytes([
+        mix(over[0], under[0]),
+        mix(over[1], under[1]),
+        mix(over[2], under[2]),
+        mix(over[3], under[3]),
+    ])
+}
+
+/// Parses an `RRGGBB` or `#RRGGBB` hex color into opaque ARGB8888. There is deliberately no
+/// alpha: a lock surface must never let the session underneath show through.
+pub fn parse_rgb(value: &str) -> Option<u32> {
+    let hex = value.trim().trim_start_matches('#');
+    if hex.len() != 6 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    u32::from_str_radix(hex, 16)
+        .ok()
+        .map(|rgb| 0xFF00_0000 | rgb)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn named_and_indexed_colors_are_opaque_xterm_colors() {
+        assert_eq!(to_argb(Color::Reset), None);
+        assert_eq!(to_argb(Color::Red), Some(0xFF80_0000));
+        assert_eq!(to_argb(Color::Rgb(1, 2, 3)), Some(0xFF01_0203));
+        assert_eq!(to_argb(Color::Indexed(9)), to_argb(Color::LightRed));
+        assert_eq!(to_argb(Color::Indexed(16)), Some(0xFF00_0000));
+        assert_eq!(to_argb(Color::Indexed(196)), Some(0xFFFF_0000));
+        assert_eq!(to_argb(Color::Indexed(232)), Some(0xFF08_0808));
+        assert_eq!(to_argb(Color::Indexed(255)), Some(0xFFEE_EEEE));
+    }
+
+    #[test]
+    fn blend_covers_from_under_to_over() {
+        let (over, under) = (0xFFFF_FFFF, 0xFF00_0000);
+        assert_eq!(blend_argb(over, under, 0), under);
+        assert_eq!(blend_argb(over, under, 255), over);
+        assert_eq!(blend_argb(over, under, 128), 0xFF80_8080);
+    }
+
+    #[test]
+    fn premultiply_scales_the_channels_by_alpha() {
+        assert_eq!(premultiply(0xFF11_2233), 0xFF11_2233);
+        assert_eq!(premultiply(0x80FF_FFFF), 0x8080_8080);
+        assert_eq!(premultiply(0x80FF_8000), 0x8080_4000);
+        assert_eq!(premultiply(0x4000_FF80), 0x4000_4020);
+        assert_eq!(premultiply(0x00FF_FFFF), 0x0000_0000);
+    }
+
+    #[test]
+    fn translucent_colors_blend_premultiplied() {
+        // half transparent white over opaque black is mid gray, not white
+        assert_eq!(blend_argb(0x80FF_FFFF, 0xFF00_0000, 255), 0xFF80_8080);
+        // and at half coverage, a quarter of the way there
+        assert_eq!(blend_argb(0x80FF_FFFF, 0xFF00_0000, 128), 0xFF40_4040);
+        // over nothing, the result keeps the color's own alpha
+        assert_eq!(blend_argb(0x80FF_0000, 0x0000_0000, 255), 0x8080_0000);
+        // a fully transparent color leaves the pixel alone
+        assert_eq!(blend_argb(0x00FF_FFFF, 0xFF11_2233, 255), 0xFF11_2233);
+    }
+
+    #[test]
+    fn parse_rgb_takes_six_hex_digits() {
+        assert_eq!(parse_rgb("1E1E2E"), Some(0xFF1E_1E2E));
+        assert_eq!(parse_rgb(" #1e1e2e "), Some(0xFF1E_1E2E));
+        assert_eq!(parse_rgb("#1E1E2E80"), None);
+        assert_eq!(parse_rgb("#FFF"), None);
+        assert_eq!(parse_rgb("GGGGGG"), None);
+    }
+}
@@ -0,0 +1,96 @@
+use std::collections::HashMap;
+
+use ab_glyph::{Font, FontArc, InvalidFont, PxScale, ScaleFont, point};
+
+// horizontal subpixel positions a glyph can be rasterized at
+const SUBPIXEL_BUCKETS: u8 = 4;
+
+/// A monospace face laid out on a fixed cell, with its rasterized glyphs cached.
+pub(super) struct FontFace {
+    font: FontArc,
+    scale: PxScale,
+    // glyph origin (left edge of the advance, baseline) relative to the cell's top left corner
+    origin_x: f32,
+    baseline: f32,
+    glyphs: HashMap<(char, u8), Option<GlyphBitmap>>,
+}
+
+/// A glyph's coverage, one byte per pixel, placed relative to the cell's top left corner.
+pub(super) struct GlyphBitmap {
+    pub left: i32,
+    pub top: i32,
+    pub width: u32,
+    pub height: u32,
+    pub coverage: Vec<u8>,
+}
+
+impl FontFace {
+    /// Parses `bytes` and returns the face together with the `(width, height)` cell it fills:
+    /// one advance wide and one line (ascent, descent and line gap) tall, rounded up to whole
+    /// pixels. The glyph is centered in any rounding slack.
+    pub fn new(bytes: Vec<u8>, px_size: f32) -> Result<(Self, u32, u32), InvalidFont> {
+        Ok(Self::with_font(FontArc::try_from_vec(bytes)?, px_size))
+    }
+
+    /// The same font at `factor` times the size, with a fresh glyph cache.
+    pub fn scaled(&self, factor: f32) -> (Self, u32, u32) {
+        Self::with_font(self.font.clone(), self.scale.y * factor)
+    }
+
+    fn with_font(font: FontArc, px_size: f32) -> (Self, u32, u32) {
+        let scale = PxScale::from(px_size);
+        let scaled = font.as_scaled(scale);
+
+        let advance = scaled.h_advance(font.glyph_id('M'));
+        let line_height = scaled.ascent() - scaled.descent() + scaled.line_gap();
+        let cell_width = advance.ceil().max(1.0) as u32;
+        let cell_height = line_height.ceil().max(1.0) as u32;
+        let origin_x = (cell_width as f32 - advance) / 2.0;
+        let baseline = ((cell_height as f32 - line_height) / 2.0 + scaled.ascent()).round();
+
+        let face = Self {
+            font,
+            scale,
+            origin_x,
+            baseline,
+            glyphs: HashMap::new(),
+        };
+        (face, cell_width, cell_height)
+    }
+
+    /// The bitmap for `ch`, rasterizing it on first use. None for characters with no outline,
+    /// such as spaces.
+    pub fn glyph(&mut self, ch: char) -> Option<&GlyphBitmap> {
+        let bucket = (self.origin_x.fract() * SUBPIXEL_BUCKETS as f32).round() as u8;
+        let bucket = bucket % SUBPIXEL_BUCKETS;
+        let x = self.origin_x.floor() + bucket as f32 / SUBPIXEL_BUCKETS as f32;
+        let (font, scale, baseline) = (&self.font, self.scale, self.baseline);
+
+        self.glyphs
+            .entry((ch, bucket))
+            .or_insert_with(|| {
+                let glyph = font
+                    .glyph_id(ch)
+                    .with_scale_and_position(scale, point(x, baseline));
+                let outlined = font.outline_glyph(glyph)?;
+                let bounds = outlined.px_bounds();
+                let width = bounds.width() as u32;
+                let height = bounds.height() as u32;
+
+                let mut coverage = vec![0; (width * height) as usize];
+                outlined.draw(|gx, gy, c| {
+                    if gx < width && gy < height {
+                        coverage[(gy * width + gx) as usize] = (c.clamp(0.0, 1.0) * 255.0) as u8;
+                    }
+                });
+                Some(GlyphBitmap {
+                    left: bounds.min.x as i32,
+                    top: bounds.min.y as i32,
+                    width,
+                    height,
+                    coverage,
+                })
+            })
+            .as_ref()
+    }
+}
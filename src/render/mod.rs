@@ -1,8 +1,54 @@
-use ratatui::buffer::Buffer;
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+use ratatui::{
+    buffer::{Buffer, Cell},
+    layout::{Position, Rect},
+    style::Modifier,
+};
+
+mod color;
+mod glyph;
+mod pixel;
+
+use glyph::FontFace;
+
+pub use ab_glyph::InvalidFont;
+pub use color::{blend_argb, parse_rgb, premultiply, to_argb};
+pub use pixel::PixelBuffer;
+
+/// A translucent color laid over a rectangle of cells, such as a glassy login box over the
+/// fire. Cells are opaque, so this is blended by the rasterizer, over each cell's background.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Tint {
+    pub cells: Rect,
+    // straight alpha ARGB; its alpha is how much of the cells it hides
+    pub argb: u32,
+}
+
+impl Tint {
+    /// `bg` as seen through the tint.
+    pub fn over(&self, bg: u32) -> u32 {
+        blend_argb(self.argb, premultiply(bg), u8::MAX)
+    }
+}
 
 pub struct Rasterizer {
     pub cell_width: u32,
     pub cell_height: u32,
+    // ARGB used for cells whose colors are `Color::Reset`
+    pub default_fg: u32,
+    pub default_bg: u32,
+    // without a font, symbols are drawn as foreground blocks
+    font: Option<FontFace>,
+    // for BOLD cells; without one, bold is synthesized from `font`
+    bold_font: Option<FontFace>,
+}
+
+impl Default for Rasterizer {
+    // the classic 8x16 VGA text cell
+    fn default() -> Self {
+        Self::new(8, 16)
+    }
 }
 
 impl Rasterizer {
@@ -10,18 +56,806 @@ impl Rasterizer {
         Self {
             cell_width,
             cell_height,
+            default_fg: 0xFFFFFFFF,
+            default_bg: 0xFF000000,
+            font: None,
+            bold_font: None,
         }
     }
 
+    /// A rasterizer drawing text with a monospace font at `px_size`, with the cell sized to
+    /// the font's advance and line height.
+    pub fn with_font(bytes: Vec<u8>, px_size: f32) -> Result<Self, InvalidFont> {
+        let (face, cell_width, cell_height) = FontFace::new(bytes, px_size)?;
+        Ok(Self {
+            font: Some(face),
+            ..Self::new(cell_width, cell_height)
+        })
+    }
+
+    /// Draws BOLD cells with a bold face of the same font, laid out on the regular face's
+    /// cell, instead of emboldening the regular glyphs.
+    pub fn set_bold_font(&mut self, bytes: Vec<u8>, px_size: f32) -> Result<(), InvalidFont> {
+        let (face, _, _) = FontFace::new(bytes, px_size)?;
+        self.bold_font = Some(face);
+        Ok(())
+    }
+
+    /// A rasterizer for buffers `factor` times as dense as this one's, such as a HiDPI output's.
+    /// The font is rasterized at the larger size rather than stretched, so text stays sharp.
+    pub fn scaled(&self, factor: f32) -> Self {
+        let scale = |px: u32| (px as f32 * factor).round().max(1.0) as u32;
+        let mut scaled = match &self.font {
+            Some(font) => {
+                let (face, cell_width, cell_height) = font.scaled(factor);
+                Self {
+                    font: Some(face),
+                    // laid out on the regular face's cell, like at the original size
+                    bold_font: self.bold_font.as_ref().map(|bold| bold.scaled(factor).0),
+                    ..Self::new(cell_width, cell_height)
+                }
+            }
+            None => Self::new(scale(self.cell_width), scale(self.cell_height)),
+        };
+        scaled.default_fg = self.default_fg;
+        scaled.default_bg = self.default_bg;
+        scaled
+    }
+
+    /// The `(cols, rows)` cell grid that fits in a `width_px` x `height_px` surface.
+    ///
+    /// Partial cells are dropped (the division floors), and each dimension is at least 1 so
+    /// that even a surface smaller than one cell still gets a grid to render into.
+    pub fn grid_dims(&self, width_px: u32, height_px: u32) -> (u16, u16) {
+        let cols = width_px / self.cell_width.max(1);
+        let rows = height_px / self.cell_height.max(1);
+        (
+            cols.clamp(1, u16::MAX as u32) as u16,
+            rows.clamp(1, u16::MAX as u32) as u16,
+        )
+    }
+
+    /// Rasterizes `buffer` into a tightly packed `width_px` x `height_px` ARGB8888 frame, see
+    /// `rasterize_into`. The animation is already in the cells, so there's no tick to pass.
     pub fn rasterize(
-        &self,
+        &mut self,
         buffer: &Buffer,
         target_argb: &mut [u8],
         width_px: u32,
         height_px: u32,
+    ) {
+        let mut target = PixelBuffer::new(target_argb, width_px, height_px, width_px * 4);
+        self.rasterize_into(buffer, &mut target);
+    }
+
+    /// Paints every cell of `buffer` into its `cell_width` x `cell_height` pixel rectangle: the
+    /// background first, then the symbol in the foreground color.
+    ///
+    /// When the target isn't an exact multiple of the cell size, the leftover strip on the
+    /// right and bottom takes the background of the nearest cell rather than being left as
+    /// whatever the buffer held before (which shows up as a black gutter).
+    pub fn rasterize_into(&mut self, buffer: &Buffer, target: &mut PixelBuffer) {
+        self.rasterize_tinted(buffer, None, target);
+    }
+
+    /// Like `rasterize_into`, with the backgrounds of the cells under `tint` blended with it.
+    pub fn rasterize_tinted(
+        &mut self,
+        buffer: &Buffer,
+        tint: Option<Tint>,
+        target: &mut PixelBuffer,
+    ) {
+        let area = buffer.area;
+        let cell_width = self.cell_width.max(1);
+        let cell_height = self.cell_height.max(1);
+        let cols = (area.width as u32).min(target.width().div_ceil(cell_width));
+        let rows = (area.height as u32).min(target.height().div_ceil(cell_height));
+
+        for row in 0..rows {
+            let y = row * cell_height;
+            let height = if row + 1 == rows {
+                target.height() - y
+            } else {
+                cell_height
+            };
+
+            for col in 0..cols {
+                let x = col * cell_width;
+                let width = if col + 1 == cols {
+                    target.width() - x
+                } else {
+                    cell_width
+                };
+
+                let position = Position::new(area.x + col as u16, area.y + row as u16);
+                let cell = &buffer[position];
+                let tint = tint.filter(|tint| tint.cells.contains(position));
+                let (fg, bg) = self.tinted_colors(cell, tint);
+                target.fill_rect(x, y, width, height, bg);
+                self.draw_symbol(cell, target, x, y, fg, bg);
+
+                if cell.modifier.contains(Modifier::UNDERLINED) {
+                    let underline_y = y + cell_height.min(height) - 1;
+                    target.fill_rect(x, underline_y, cell_width.min(width), 1, fg);
+                }
+            }
+        }
+    }
+
+    /// Draws the text caret, a bar down the left edge of `cell`, in that cell's foreground
+    /// color. `buffer` is the frame already rasterized into `target`; the caret goes over it,
+    /// as it has no cell of its own.
+    pub fn draw_caret(&self, buffer: &Buffer, cell: (u16, u16), target: &mut PixelBuffer) {
+        let (column, row) = cell;
+        if !buffer.area.contains(Position::new(column, row)) {
+            return;
+        }
+        let cell_width = self.cell_width.max(1);
+        let cell_height = self.cell_height.max(1);
+        let x = (column - buffer.area.x) as u32 * cell_width;
+        let y = (row - buffer.area.y) as u32 * cell_height;
+        let (fg, _) = self.cell_colors(&buffer[(column, row)]);
+        target.fill_rect(x, y, (cell_width / 8).max(1), cell_height, fg);
+    }
+
+    // Draws a cell's symbol over its background, with the cell's top left corner at (x, y).
+    // Block elements such as the fire's `▒` always cover the whole cell, which is far cheaper
+    // than blending a glyph into every cell of the background.
+    fn draw_symbol(
+        &mut self,
+        cell: &Cell,
+        target: &mut PixelBuffer,
+        x: u32,
+        y: u32,
+        fg: u32,
+        bg: u32,
+    ) {
+        let symbol = cell.symbol();
+        let Some(ch) = symbol.chars().next().filter(|ch| !ch.is_whitespace()) else {
+            return;
+        };
+        if ('\u{2580}'..='\u{259F}').contains(&ch) {
+            target.fill_rect(x, y, self.cell_width, self.cell_height, fg);
+            return;
+        }
+
+        let bold = cell.modifier.contains(Modifier::BOLD);
+        // without a bold face, the glyph is emboldened by smearing it a pixel to the right
+        let embolden = bold && self.bold_font.is_none();
+        let face = if bold && self.bold_font.is_some() {
+            self.bold_font.as_mut()
+        } else {
+            self.font.as_mut()
+        };
+        let Some(font) = face else {
+            // a block inset by a pixel or two, so neighbouring characters stay apart
+            let inset_x = self.cell_width / 8;
+            let inset_y = self.cell_height / 8;
+            let width = self.cell_width.saturating_sub(inset_x * 2).max(1);
+            let height = self.cell_height.saturating_sub(inset_y * 2).max(1);
+            target.fill_rect(x + inset_x, y + inset_y, width, height, fg);
+            return;
+        };
+        let Some(glyph) = font.glyph(ch) else {
+            return;
+        };
+        // the background as `fill_rect` stored it
+        let under = premultiply(bg);
+
+        let at = |gx: u32, gy: u32| {
+            if gx < glyph.width {
+                glyph.coverage[(gy * glyph.width + gx) as usize]
+            } else {
+                0
+            }
+        };
+        for gy in 0..glyph.height {
+            let py = y as i64 + glyph.top as i64 + gy as i64;
+            for gx in 0..glyph.width + embolden as u32 {
+                let mut coverage = at(gx, gy);
+                if embolden && gx > 0 {
+                    coverage = coverage.max(at(gx - 1, gy));
+                }
+                let px = x as i64 + glyph.left as i64 + gx as i64;
+                if coverage == 0 || px < 0 || py < 0 {
+                    continue;
+                }
+                target.set_pixel(px as u32, py as u32, blend_argb(fg, under, coverage));
+            }
+        }
+    }
+
+    /// The `(fg, bg)` ARGB colors a cell is drawn with, after applying `Modifier::REVERSED`.
+    pub fn cell_colors(&self, cell: &Cell) -> (u32, u32) {
+        self.tinted_colors(cell, None)
+    }
+
+    // `cell_colors`, with the cell's background seen through `tint`. It's tinted before
+    // REVERSED swaps it, so a reversed cell's text is what takes the tint.
+    fn tinted_colors(&self, cell: &Cell, tint: Option<Tint>) -> (u32, u32) {
+        let fg = to_argb(cell.fg).unwrap_or(self.default_fg);
+        let mut bg = to_argb(cell.bg).unwrap_or(self.default_bg);
+        if let Some(tint) = tint {
+            bg = tint.over(bg);
+        }
+        if cell.modifier.contains(Modifier::REVERSED) {
+            (bg, fg)
+        } else {
+            (fg, bg)
+        }
+    }
+}
+
+/// Where a layer sits in the composition order. Layers in a lower slot are drawn first, so
+/// anything in a higher slot is written over (or blended onto) them.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LayerSlot {
+    // solid color or wallpaper
+    Background,
+    // animated effects such as the fire
+    Effect,
+    // the rasterized TUI
+    Content,
+    // menus, spinners, caret
+    Overlay,
+}
+
+/// A single stage of the lock surface composition. `target` already contains the output of
+/// every layer beneath this one.
+pub trait Layer {
+    fn draw(&mut self, target: &mut PixelBuffer, tick: u64);
+
+    /// The color this layer covers the whole target with, if it is nothing but a solid fill.
+    fn solid_color(&self) -> Option<u32> {
+        None
+    }
+}
+
+/// An ordered set of layers composited into a single shm buffer.
+#[derive(Default)]
+pub struct LayerStack {
+    layers: Vec<(LayerSlot, Box<dyn Layer>)>,
+}
+
+impl LayerStack {
+    /// Adds a layer to `slot`. Layers sharing a slot are drawn in insertion order.
+    pub fn push(&mut self, slot: LayerSlot, layer: Box<dyn Layer>) {
+        let index = self
+            .layers
+            .iter()
+            .position(|(existing, _)| *existing > slot)
+            .unwrap_or(self.layers.len());
+        self.layers.insert(index, (slot, layer));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layers.is_empty()
+    }
+
+    /// The color of the background when it is a single solid color, which lets surfaces that
+    /// show nothing else use a 1x1 buffer instead of a full resolution one.
+    pub fn background_color(&self) -> Option<u32> {
+        match &self.layers[..self.content_index()] {
+            [(_, layer)] => layer.solid_color(),
+            _ => None,
+        }
+    }
+
+    pub fn compose(&mut self, target: &mut PixelBuffer, tick: u64) {
+        for (_, layer) in &mut self.layers {
+            layer.draw(target, tick);
+        }
+    }
+
+    /// Composes only the layers beneath the `Content` slot, for surfaces with no content.
+    pub fn compose_background(&mut self, target: &mut PixelBuffer, tick: u64) {
+        let split = self.content_index();
+        for (_, layer) in &mut self.layers[..split] {
+            layer.draw(target, tick);
+        }
+    }
+
+    /// Composes the stack with `content` drawn at the start of the `Content` slot, for content
+    /// that depends on more than the tick, such as the rasterized TUI. `content` has to cover
+    /// every pixel, so the layers beneath it are skipped rather than drawn over.
+    pub fn compose_with(
+        &mut self,
+        target: &mut PixelBuffer,
         tick: u64,
+        content: impl FnOnce(&mut PixelBuffer),
     ) {
-        // TODO: map buffer cells into pixel rectangles and blend background animation.
-        let _ = (buffer, target_argb, width_px, height_px, tick);
+        let split = self.content_index();
+        content(target);
+        for (_, layer) in &mut self.layers[split..] {
+            layer.draw(target, tick);
+        }
+    }
+
+    /// Whether any layer is drawn after the content, so a frame can change beyond its cells.
+    pub fn draws_over_content(&self) -> bool {
+        self.content_index() < self.layers.len()
+    }
+
+    fn content_index(&self) -> usize {
+        self.layers
+            .iter()
+            .position(|(slot, _)| *slot >= LayerSlot::Content)
+            .unwrap_or(self.layers.len())
+    }
+}
+
+/// Fills the whole target with a single opaque color.
+pub struct SolidColor {
+    pub argb: u32,
+}
+
+impl SolidColor {
+    pub fn new(argb: u32) -> Self {
+        Self { argb }
+    }
+}
+
+impl Layer for SolidColor {
+    fn draw(&mut self, target: &mut PixelBuffer, _tick: u64) {
+        target.fill_solid_color(self.argb);
+    }
+
+    fn solid_color(&self) -> Option<u32> {
+        Some(self.argb)
+    }
+}
+
+/// Hashes a rendered frame so callers can skip committing pixels identical to the last commit.
+pub fn content_hash(target_argb: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    target_argb.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Past this many changed rectangles a frame is damaged as their bounding box instead, which
+/// keeps a busy frame such as the fire's down to a single request.
+pub const MAX_DAMAGE_RECTS: usize = 16;
+
+/// The cells that differ between `previous` and `next`: every run of changed cells on a row,
+/// grown downwards while the rows below change over exactly the same columns. Frames of
+/// different areas differ everywhere.
+pub fn changed_cells(previous: &Buffer, next: &Buffer) -> Vec<Rect> {
+    let area = next.area;
+    if previous.area != area {
+        return vec![area];
+    }
+    let changed = |x: u16, y: u16| previous[(x, y)] != next[(x, y)];
+
+    let mut closed = Vec::new();
+    // the rectangles reaching down to the row above
+    let mut open: Vec<Rect> = Vec::new();
+    for y in area.top()..area.bottom() {
+        let mut below = Vec::new();
+        let mut x = area.left();
+        while x < area.right() {
+            if !changed(x, y) {
+                x += 1;
+                continue;
+            }
+            let start = x;
+            while x < area.right() && changed(x, y) {
+                x += 1;
+            }
+            let width = x - start;
+            match open
+                .iter()
+                .position(|rect| rect.x == start && rect.width == width)
+            {
+                Some(index) => {
+                    let mut rect = open.swap_remove(index);
+                    rect.height += 1;
+                    below.push(rect);
+                }
+                None => below.push(Rect::new(start, y, width, 1)),
+            }
+        }
+        closed.append(&mut open);
+        open = below;
+    }
+    closed.append(&mut open);
+    closed
+}
+
+/// The `(x, y, width, height)` pixels covered by `cells` of a frame of `area`, rasterized with
+/// `cell_size` cells into a `size` target. Grown by a cell on every side for glyphs that spill
+/// over their cell, and out to the edge where the last row or column covers the leftover strip.
+pub fn cells_to_pixels(
+    cells: Rect,
+    area: Rect,
+    cell_size: (u32, u32),
+    size: (u32, u32),
+) -> (u32, u32, u32, u32) {
+    // the pixels from cell `start` up to `end` of `cells`, relative to the frame's origin
+    let span = |start: u16, end: u16, cells: u16, cell: u32, pixels: u32| {
+        let first = start.saturating_sub(1) as u32;
+        let last = end as u32 + 1;
+        let from = (first * cell.max(1)).min(pixels);
+        let to = if last >= cells as u32 {
+            pixels
+        } else {
+            (last * cell.max(1)).min(pixels)
+        };
+        (from, to - from)
+    };
+    let (left, right) = (
+        cells.left().saturating_sub(area.x),
+        cells.right().saturating_sub(area.x),
+    );
+    let (top, bottom) = (
+        cells.top().saturating_sub(area.y),
+        cells.bottom().saturating_sub(area.y),
+    );
+    let (x, width) = span(left, right, area.width, cell_size.0, size.0);
+    let (y, height) = span(top, bottom, area.height, cell_size.1, size.1);
+    (x, y, width, height)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use ratatui::{
+        layout::Rect,
+        style::{Color, Style},
+    };
+
+    use super::*;
+
+    // Records its name when drawn, to show the order layers are composed in.
+    struct Named {
+        name: &'static str,
+        drawn: Rc<RefCell<Vec<&'static str>>>,
+    }
+
+    impl Layer for Named {
+        fn draw(&mut self, _target: &mut PixelBuffer, _tick: u64) {
+            self.drawn.borrow_mut().push(self.name);
+        }
+    }
+
+    // Pushes a `Named` layer for every entry, in order, and composes the stack once.
+    fn composed(layers: &[(LayerSlot, &'static str)]) -> Vec<&'static str> {
+        let drawn = Rc::new(RefCell::new(Vec::new()));
+        let mut stack = LayerStack::default();
+        for &(slot, name) in layers {
+            let drawn = Rc::clone(&drawn);
+            stack.push(slot, Box::new(Named { name, drawn }));
+        }
+        let mut bytes = [0; 4];
+        stack.compose(&mut PixelBuffer::new(&mut bytes, 1, 1, 4), 0);
+        drawn.take()
+    }
+
+    #[test]
+    fn layers_compose_in_slot_order() {
+        let drawn = composed(&[
+            (LayerSlot::Overlay, "caret"),
+            (LayerSlot::Background, "wallpaper"),
+            (LayerSlot::Content, "tui"),
+            (LayerSlot::Effect, "fire"),
+        ]);
+        assert_eq!(drawn, ["wallpaper", "fire", "tui", "caret"]);
+    }
+
+    #[test]
+    fn layers_sharing_a_slot_compose_in_insertion_order() {
+        let drawn = composed(&[
+            (LayerSlot::Effect, "first"),
+            (LayerSlot::Background, "wallpaper"),
+            (LayerSlot::Effect, "second"),
+        ]);
+        assert_eq!(drawn, ["wallpaper", "first", "second"]);
+    }
+
+    #[test]
+    fn content_replaces_the_layers_beneath_it() {
+        let drawn = Rc::new(RefCell::new(Vec::new()));
+        let mut stack = LayerStack::default();
+        for (slot, name) in [
+            (LayerSlot::Background, "wallpaper"),
+            (LayerSlot::Overlay, "caret"),
+        ] {
+            let drawn = Rc::clone(&drawn);
+            stack.push(slot, Box::new(Named { name, drawn }));
+        }
+        let mut bytes = [0; 4];
+        let mut target = PixelBuffer::new(&mut bytes, 1, 1, 4);
+
+        stack.compose_background(&mut target, 0);
+        assert_eq!(drawn.take(), ["wallpaper"]);
+        stack.compose_with(&mut target, 0, |_| drawn.borrow_mut().push("tui"));
+        assert_eq!(drawn.take(), ["tui", "caret"]);
+    }
+
+    #[test]
+    fn only_a_lone_solid_background_is_a_background_color() {
+        let drawn = Rc::new(RefCell::new(Vec::new()));
+        let named = |name| {
+            let drawn = Rc::clone(&drawn);
+            Box::new(Named { name, drawn })
+        };
+        let mut stack = LayerStack::default();
+        assert_eq!(stack.background_color(), None);
+
+        stack.push(
+            LayerSlot::Background,
+            Box::new(SolidColor::new(0xFF11_2233)),
+        );
+        // whatever is drawn over the content doesn't change the background
+        stack.push(LayerSlot::Overlay, named("caret"));
+        assert_eq!(stack.background_color(), Some(0xFF11_2233));
+
+        stack.push(LayerSlot::Effect, named("fire"));
+        assert_eq!(stack.background_color(), None);
+    }
+
+    #[test]
+    fn changed_cells_merge_into_rectangles() {
+        let previous = Buffer::empty(Rect::new(0, 0, 8, 4));
+        let mut next = previous.clone();
+        assert!(changed_cells(&previous, &next).is_empty());
+
+        // a 2x2 block, and a run on the last row that doesn't line up with it
+        for (x, y) in [(1, 1), (2, 1), (1, 2), (2, 2), (4, 3), (5, 3), (6, 3)] {
+            next[(x, y)].set_symbol("x");
+        }
+        let mut changed = changed_cells(&previous, &next);
+        changed.sort_by_key(|rect| (rect.y, rect.x));
+        assert_eq!(changed, [Rect::new(1, 1, 2, 2), Rect::new(4, 3, 3, 1)]);
+
+        let resized = Buffer::empty(Rect::new(0, 0, 8, 5));
+        assert_eq!(changed_cells(&previous, &resized), [resized.area]);
+    }
+
+    #[test]
+    fn changed_cells_cover_their_neighbours_and_the_leftover_strip() {
+        let area = Rect::new(0, 0, 10, 5);
+        // 10x5 cells of 8x16 pixels, with a few pixels left over on either side
+        let size = (83, 85);
+        assert_eq!(
+            cells_to_pixels(Rect::new(4, 2, 2, 1), area, (8, 16), size),
+            (24, 16, 32, 48)
+        );
+        assert_eq!(
+            cells_to_pixels(Rect::new(0, 0, 1, 1), area, (8, 16), size),
+            (0, 0, 16, 32)
+        );
+        assert_eq!(
+            cells_to_pixels(Rect::new(9, 4, 1, 1), area, (8, 16), size),
+            (64, 48, 19, 37)
+        );
+    }
+
+    #[test]
+    fn solid_color_fills_every_pixel() {
+        let mut bytes = vec![0; 2 * 2 * 4];
+        let mut target = PixelBuffer::new(&mut bytes, 2, 2, 2 * 4);
+        let mut stack = LayerStack::default();
+        stack.push(
+            LayerSlot::Background,
+            Box::new(SolidColor::new(0xFF11_2233)),
+        );
+        stack.compose(&mut target, 0);
+        for (x, y) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+            assert_eq!(target.pixel(x, y), Some(0xFF11_2233));
+        }
+    }
+
+    #[test]
+    fn grid_dims_divide_exactly() {
+        let rasterizer = Rasterizer::new(8, 16);
+        assert_eq!(rasterizer.grid_dims(1920, 1088), (240, 68));
+    }
+
+    #[test]
+    fn grid_dims_drop_partial_cells() {
+        let rasterizer = Rasterizer::new(8, 16);
+        assert_eq!(rasterizer.grid_dims(1927, 1080), (240, 67));
+    }
+
+    #[test]
+    fn grid_dims_are_at_least_one_cell() {
+        let rasterizer = Rasterizer::new(8, 16);
+        assert_eq!(rasterizer.grid_dims(5, 10), (1, 1));
+        assert_eq!(rasterizer.grid_dims(0, 0), (1, 1));
+    }
+
+    #[test]
+    fn leftover_pixels_take_the_nearest_cells_background() {
+        let mut cells = Buffer::empty(Rect::new(0, 0, 2, 1));
+        cells[(0, 0)].set_bg(Color::Red);
+        cells[(1, 0)].set_bg(Color::Blue);
+        // one pixel wider and taller than the 2x1 grid of 2x2 cells
+        let (width, height) = (5, 3);
+        let mut bytes = vec![0; (width * height * 4) as usize];
+        let mut target = PixelBuffer::new(&mut bytes, width, height, width * 4);
+        Rasterizer::new(2, 2).rasterize_into(&cells, &mut target);
+
+        let (red, blue) = (to_argb(Color::Red), to_argb(Color::Blue));
+        assert_eq!(target.pixel(4, 0), blue);
+        assert_eq!(target.pixel(0, 2), red);
+        assert_eq!(target.pixel(4, 2), blue);
+    }
+
+    #[test]
+    fn a_two_by_two_buffer_rasterizes_to_known_bytes() {
+        let (red, green, blue) = (
+            Color::Rgb(0xFF, 0, 0),
+            Color::Rgb(0, 0xFF, 0),
+            Color::Rgb(0, 0, 0xFF),
+        );
+        let mut cells = Buffer::empty(Rect::new(0, 0, 2, 2));
+        cells[(0, 0)].set_bg(red);
+        cells[(1, 0)].set_symbol("x").set_fg(green).set_bg(red);
+        cells[(0, 1)].set_symbol("▒").set_fg(blue).set_bg(green);
+        cells[(1, 1)].set_bg(blue);
+
+        // 2x1 cells, so each cell is two pixels side by side; the symbol's block covers both
+        let mut argb = [0; 4 * 2 * 4];
+        Rasterizer::new(2, 1).rasterize(&cells, &mut argb, 4, 2);
+
+        let (red, green, blue) = ([0, 0, 0xFF, 0xFF], [0, 0xFF, 0, 0xFF], [0xFF, 0, 0, 0xFF]);
+        let expected = [red, red, green, green, blue, blue, blue, blue].concat();
+        assert_eq!(argb.as_slice(), expected.as_slice());
+    }
+
+    // Sums how much of a cell's white-on-black glyph is foreground.
+    fn glyph_coverage(rasterizer: &mut Rasterizer, symbol: &str, modifier: Modifier) -> u32 {
+        let (width, height) = (rasterizer.cell_width, rasterizer.cell_height);
+        let mut cells = Buffer::empty(Rect::new(0, 0, 1, 1));
+        cells[(0, 0)]
+            .set_symbol(symbol)
+            .set_fg(Color::White)
+            .set_bg(Color::Black)
+            .set_style(Style::default().add_modifier(modifier));
+        let mut bytes = vec![0; (width * height * 4) as usize];
+        let mut target = PixelBuffer::new(&mut bytes, width, height, width * 4);
+        rasterizer.rasterize_into(&cells, &mut target);
+        (0..width * height)
+            .map(|i| target.pixel(i % width, i / width).unwrap() & 0xFF)
+            .sum()
+    }
+
+    fn font_rasterizer() -> Rasterizer {
+        let font = include_bytes!("../../assets/fonts/DejaVuSansMono.ttf");
+        Rasterizer::with_font(font.to_vec(), 16.0).unwrap()
+    }
+
+    #[test]
+    fn glyphs_are_blended_from_the_font() {
+        let mut rasterizer = font_rasterizer();
+        let (width, height) = (rasterizer.cell_width, rasterizer.cell_height);
+        assert!((8..=12).contains(&width) && (16..=20).contains(&height));
+
+        let full = 0xFF * width * height;
+        let a = glyph_coverage(&mut rasterizer, "a", Modifier::empty());
+        let i = glyph_coverage(&mut rasterizer, "i", Modifier::empty());
+        assert!(0 < i && i < a && a < full / 2, "i {i}, a {a}");
+        // cached glyphs come out the same
+        assert_eq!(glyph_coverage(&mut rasterizer, "a", Modifier::empty()), a);
+        assert_eq!(glyph_coverage(&mut rasterizer, " ", Modifier::empty()), 0);
+    }
+
+    #[test]
+    fn bold_cells_have_more_foreground_coverage() {
+        let mut rasterizer = font_rasterizer();
+        for symbol in ["a", "L", "1"] {
+            let regular = glyph_coverage(&mut rasterizer, symbol, Modifier::empty());
+            let bold = glyph_coverage(&mut rasterizer, symbol, Modifier::BOLD);
+            assert!(bold > regular, "{symbol}: bold {bold}, regular {regular}");
+        }
+
+        // a bold face is used as is
+        let bold_font = include_bytes!("../../assets/fonts/DejaVuSansMono.ttf");
+        rasterizer.set_bold_font(bold_font.to_vec(), 16.0).unwrap();
+        let regular = glyph_coverage(&mut rasterizer, "a", Modifier::empty());
+        assert_eq!(
+            glyph_coverage(&mut rasterizer, "a", Modifier::BOLD),
+            regular
+        );
+    }
+
+    #[test]
+    fn a_scaled_rasterizer_draws_larger_glyphs() {
+        let mut rasterizer = font_rasterizer();
+        let bold_font = include_bytes!("../../assets/fonts/DejaVuSansMono.ttf");
+        rasterizer.set_bold_font(bold_font.to_vec(), 16.0).unwrap();
+        let mut scaled = rasterizer.scaled(2.0);
+        assert!(scaled.cell_height.abs_diff(rasterizer.cell_height * 2) <= 1);
+
+        let regular = glyph_coverage(&mut rasterizer, "a", Modifier::empty());
+        let larger = glyph_coverage(&mut scaled, "a", Modifier::empty());
+        assert!(larger > regular * 3, "scaled {larger}, regular {regular}");
+        // the bold face is scaled along with the regular one
+        assert_eq!(glyph_coverage(&mut scaled, "a", Modifier::BOLD), larger);
+
+        // without a font, the block cells grow instead
+        let blocks = Rasterizer::default().scaled(1.5);
+        assert_eq!((blocks.cell_width, blocks.cell_height), (12, 24));
+    }
+
+    // Rasterizes a single 4x4 pixel cell in `style` and returns its pixels row by row.
+    fn rasterized_cell(style: Style) -> Vec<u32> {
+        let mut cells = Buffer::empty(Rect::new(0, 0, 1, 1));
+        cells[(0, 0)].set_style(style);
+        let mut bytes = vec![0; 4 * 4 * 4];
+        let mut target = PixelBuffer::new(&mut bytes, 4, 4, 4 * 4);
+        Rasterizer::new(4, 4).rasterize_into(&cells, &mut target);
+        (0..16)
+            .map(|i| target.pixel(i % 4, i / 4).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn reversed_cells_swap_fg_and_bg() {
+        let style = Style::new()
+            .fg(Color::Red)
+            .bg(Color::Blue)
+            .add_modifier(Modifier::REVERSED);
+        let pixels = rasterized_cell(style);
+        let red = to_argb(Color::Red).unwrap();
+        assert!(pixels.iter().all(|&pixel| pixel == red));
+    }
+
+    #[test]
+    fn underlined_cells_draw_their_bottom_row_in_fg() {
+        let style = Style::new()
+            .fg(Color::Red)
+            .bg(Color::Blue)
+            .add_modifier(Modifier::UNDERLINED);
+        let pixels = rasterized_cell(style);
+        let (red, blue) = (to_argb(Color::Red).unwrap(), to_argb(Color::Blue).unwrap());
+        assert!(pixels[..12].iter().all(|&pixel| pixel == blue));
+        assert!(pixels[12..].iter().all(|&pixel| pixel == red));
+    }
+
+    #[test]
+    fn a_tint_blends_over_the_cells_it_covers() {
+        let mut cells = Buffer::empty(Rect::new(0, 0, 3, 1));
+        for x in 0..3 {
+            cells[(x, 0)].set_bg(Color::Rgb(0, 0, 0xFF));
+        }
+        cells[(2, 0)].set_fg(Color::Rgb(0x11, 0x22, 0x33));
+        cells[(2, 0)].modifier = Modifier::REVERSED;
+        let tint = Tint {
+            cells: Rect::new(1, 0, 2, 1),
+            // half transparent white
+            argb: 0x80FF_FFFF,
+        };
+
+        let mut bytes = vec![0; 3 * 4];
+        let mut target = PixelBuffer::new(&mut bytes, 3, 1, 3 * 4);
+        let mut rasterizer = Rasterizer::new(1, 1);
+        rasterizer.rasterize_tinted(&cells, Some(tint), &mut target);
+        // outside the tint, untouched
+        assert_eq!(target.pixel(0, 0), Some(0xFF00_00FF));
+        // blue seen through half white
+        assert_eq!(target.pixel(1, 0), Some(0xFF80_80FF));
+        // a reversed cell's background is its foreground, which isn't tinted
+        assert_eq!(target.pixel(2, 0), Some(0xFF11_2233));
+    }
+
+    #[test]
+    fn caret_is_a_bar_in_the_cell_foreground() {
+        let mut rasterizer = Rasterizer::new(8, 4);
+        let mut cells = Buffer::empty(Rect::new(0, 0, 2, 1));
+        cells[(1, 0)].set_fg(Color::Rgb(0xFF, 0, 0));
+        cells[(1, 0)].set_bg(Color::Rgb(0, 0, 0xFF));
+        let mut bytes = vec![0; 16 * 4 * 4];
+        let mut target = PixelBuffer::new(&mut bytes, 16, 4, 16 * 4);
+        rasterizer.rasterize_into(&cells, &mut target);
+        rasterizer.draw_caret(&cells, (1, 0), &mut target);
+
+        for y in 0..4 {
+            assert_eq!(target.pixel(8, y), Some(0xFFFF_0000));
+            assert_eq!(target.pixel(9, y), Some(0xFF00_00FF));
+        }
+        // nowhere near the caret's cell
+        assert_eq!(target.pixel(0, 0), Some(rasterizer.default_bg));
     }
 }
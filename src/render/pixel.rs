@@ -0,0 +1,167 @@
+use super::premultiply;
+
+/// ARGB8888 pixels in native byte order, independent of where the bytes live: the mmap'd shm
+/// pool on the lock surface, or a plain `Vec<u8>` when rendering headless.
+///
+/// Pixels are stored premultiplied, as `wl_shm` expects. `pixel` and `set_pixel` read and
+/// write them as stored; the fills take straight-alpha colors and premultiply them.
+pub struct PixelBuffer<'a> {
+    bytes: &'a mut [u8],
+    width: u32,
+    height: u32,
+    // bytes per row, at least `width * 4`
+    stride: u32,
+}
+
+impl<'a> PixelBuffer<'a> {
+    pub fn new(bytes: &'a mut [u8], width: u32, height: u32, stride: u32) -> Self {
+        assert!(stride >= width * 4, "stride must fit a full row of pixels");
+        assert!(
+            bytes.len() >= (stride * height) as usize,
+            "pixel buffer is smaller than stride * height"
+        );
+        Self {
+            bytes,
+            width,
+            height,
+            stride,
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn stride(&self) -> u32 {
+        self.stride
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        self.bytes
+    }
+
+    pub fn bytes_mut(&mut self) -> &mut [u8] {
+        self.bytes
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> Option<u32> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        let offset = self.offset(x, y);
+        let px = &self.bytes[offset..offset + 4];
+        Some(u32::from_ne_bytes([px[0], px[1], px[2], px[3]]))
+    }
+
+    pub fn set_pixel(&mut self, x: u32, y: u32, argb: u32) {
+        if x >= self.width || y >= self.height {
+            return;
+        }
+        let offset = self.offset(x, y);
+        self.bytes[offset..offset + 4].copy_from_slice(&argb.to_ne_bytes());
+    }
+
+    /// Fills the whole buffer with the straight-alpha color `argb`.
+    pub fn fill_solid_color(&mut self, argb: u32) {
+        self.fill_rect(0, 0, self.width, self.height, argb);
+    }
+
+    /// Fills a rectangle with the straight-alpha color `argb`, clamped to the buffer bounds.
+    pub fn fill_rect(&mut self, x: u32, y: u32, width: u32, height: u32, argb: u32) {
+        let color = premultiply(argb).to_ne_bytes();
+        let right = x.saturating_add(width).min(self.width);
+        let bottom = y.saturating_add(height).min(self.height);
+        if x >= right || y >= bottom {
+            return;
+        }
+
+        for row in y..bottom {
+            let start = self.offset(x, row);
+            let end = self.offset(right, row);
+            for px in self.bytes[start..end].chunks_exact_mut(4) {
+                px.copy_from_slice(&color);
+            }
+        }
+    }
+
+    /// Sets every pixel's alpha to 0xFF, keeping its color, so nothing below the buffer can
+    /// show through it. As the colors are premultiplied, that's each pixel drawn over black.
+    pub fn make_opaque(&mut self) {
+        // the byte of a native endian ARGB pixel that holds its alpha
+        let index = if cfg!(target_endian = "little") { 3 } else { 0 };
+        let row_bytes = (self.width * 4) as usize;
+        for row in 0..self.height {
+            let start = self.offset(0, row);
+            for px in self.bytes[start..start + row_bytes].chunks_exact_mut(4) {
+                px[index] = 0xFF;
+            }
+        }
+    }
+
+    fn offset(&self, x: u32, y: u32) -> usize {
+        (y * self.stride + x * 4) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fill_solid_color_writes_every_pixel_and_skips_the_row_padding() {
+        // 2x2 pixels in rows of 12 bytes
+        let mut bytes = vec![0xAA; 24];
+        let mut buffer = PixelBuffer::new(&mut bytes, 2, 2, 12);
+        buffer.fill_solid_color(0xFF11_2233);
+
+        let color = 0xFF11_2233u32.to_ne_bytes();
+        for row in bytes.chunks_exact(12) {
+            assert_eq!(row[0..4], color);
+            assert_eq!(row[4..8], color);
+            assert_eq!(row[8..12], [0xAA; 4]);
+        }
+    }
+
+    #[test]
+    fn making_a_buffer_opaque_keeps_the_colors() {
+        // 2x1 pixels in a row of 12 bytes
+        let mut bytes = vec![0xAA; 12];
+        let mut buffer = PixelBuffer::new(&mut bytes, 2, 1, 12);
+        buffer.set_pixel(0, 0, 0x0011_2233);
+        buffer.set_pixel(1, 0, 0x8080_8080);
+        buffer.make_opaque();
+        assert_eq!(buffer.pixel(0, 0), Some(0xFF11_2233));
+        // half transparent white, over black
+        assert_eq!(buffer.pixel(1, 0), Some(0xFF80_8080));
+        assert_eq!(bytes[8..], [0xAA; 4]);
+    }
+
+    #[test]
+    fn fills_write_premultiplied_pixels() {
+        let mut bytes = vec![0; 8];
+        let mut buffer = PixelBuffer::new(&mut bytes, 2, 1, 8);
+        buffer.fill_solid_color(0x80FF_8000);
+        assert_eq!(buffer.pixel(0, 0), Some(0x8080_4000));
+        buffer.fill_rect(1, 0, 1, 1, 0xFF11_2233);
+        assert_eq!(buffer.pixel(0, 0), Some(0x8080_4000));
+        assert_eq!(buffer.pixel(1, 0), Some(0xFF11_2233));
+    }
+
+    #[test]
+    fn fill_rect_is_clamped_to_the_buffer() {
+        // 3x2 pixels in rows of 16 bytes
+        let mut bytes = vec![0xAA; 32];
+        let mut buffer = PixelBuffer::new(&mut bytes, 3, 2, 16);
+        buffer.fill_rect(1, 1, 10, 10, 0xFF11_2233);
+        assert_eq!(buffer.pixel(0, 1), Some(0xAAAA_AAAA));
+        assert_eq!(buffer.pixel(1, 1), Some(0xFF11_2233));
+        assert_eq!(buffer.pixel(2, 1), Some(0xFF11_2233));
+        assert_eq!(buffer.pixel(1, 0), Some(0xAAAA_AAAA));
+        assert_eq!(buffer.pixel(3, 1), None);
+        assert!(bytes[28..].iter().all(|&byte| byte == 0xAA));
+    }
+}
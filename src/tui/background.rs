@@ -0,0 +1,534 @@
+use std::fmt;
+
+use ratatui::{
+    Frame,
+    layout::Rect,
+    style::{Color, Style},
+};
+
+use super::FIRE_PALETTE;
+
+/// An animation drawn behind the login box, from a palette ordered coldest first.
+/// Implementations own whatever they carry from one frame to the next. A single instance draws
+/// every monitor, so `draw` can be called several times per tick with different frame sizes.
+pub trait Background: fmt::Debug {
+    fn draw(&mut self, frame: &mut Frame, tick: u64, palette: &[Color]);
+}
+
+impl Default for Box<dyn Background> {
+    fn default() -> Self {
+        Box::new(Fire::default())
+    }
+}
+
+/// The built-in backgrounds, selectable by name.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum BackgroundKind {
+    #[default]
+    Fire,
+    Matrix,
+    Plasma,
+    Starfield,
+}
+
+impl BackgroundKind {
+    /// A fresh instance of this background. `fire` shapes the fire and is ignored otherwise.
+    pub fn create(self, fire: Fire) -> Box<dyn Background> {
+        match self {
+            Self::Fire => Box::new(fire),
+            Self::Matrix => Box::new(MatrixRain::default()),
+            Self::Plasma => Box::new(Generated(plasma_index)),
+            Self::Starfield => Box::new(Generated(starfield_index)),
+        }
+    }
+}
+
+impl std::str::FromStr for BackgroundKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fire" => Ok(Self::Fire),
+            "matrix" => Ok(Self::Matrix),
+            "plasma" => Ok(Self::Plasma),
+            "starfield" => Ok(Self::Starfield),
+            _ => Err(format!(
+                "unknown background {s:?}, expected fire, matrix, plasma or starfield"
+            )),
+        }
+    }
+}
+
+/// Shape of the fire background.
+#[derive(Debug, Clone)]
+pub struct Fire {
+    // cells the flames drift sideways per row; positive leans right
+    pub wind: i32,
+    // how far either side a cell may sample the row it burns from
+    pub spread: u16,
+    pub direction: FireDirection,
+    // how hot the source row seeds and how slowly the flames cool; 1.0 is the classic fire
+    pub intensity: f32,
+    // palette index of every cell, row by row counted from the seeded edge
+    heat: Vec<u8>,
+    // the tick and area `heat` was simulated for
+    simulated: Option<(u64, Rect)>,
+}
+
+impl Default for Fire {
+    fn default() -> Self {
+        Self {
+            wind: 0,
+            spread: 2,
+            direction: FireDirection::default(),
+            intensity: 1.0,
+            heat: Vec::new(),
+            simulated: None,
+        }
+    }
+}
+
+/// Which edge the fire burns from.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum FireDirection {
+    // from the bottom edge, rising
+    #[default]
+    Up,
+    // from the top edge, hanging down
+    Down,
+}
+
+impl FireDirection {
+    // The row `step` rows away from the seeded edge, so the same propagation works in either
+    // direction.
+    fn row(self, area: Rect, step: u16) -> u16 {
+        match self {
+            Self::Up => area.bottom() - 1 - step,
+            Self::Down => area.top() + step,
+        }
+    }
+}
+
+impl std::str::FromStr for FireDirection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "up" => Ok(Self::Up),
+            "down" => Ok(Self::Down),
+            _ => Err(format!("unknown fire direction {s:?}, expected up or down")),
+        }
+    }
+}
+
+/// Parses a fire intensity, a positive number where 1.0 is the classic fire.
+pub fn parse_intensity(value: &str) -> Result<f32, String> {
+    match value.trim().parse::<f32>() {
+        Ok(intensity) if intensity.is_finite() && intensity > 0.0 => Ok(intensity),
+        _ => Err(format!(
+            "invalid fire intensity {value:?}, expected a positive number such as 1.0"
+        )),
+    }
+}
+
+impl Fire {
+    // Fills `heat` for this tick. Cells hold palette indices and are never read back from the
+    // drawn colors, so any palette works, even one that repeats a color.
+    fn simulate(&mut self, tick: u64, area: Rect, hottest: usize) {
+        let row = |step: u16| self.direction.row(area, step);
+        let intensity = self.intensity.max(f32::EPSILON);
+        let width = area.width as usize;
+        self.heat.clear();
+        self.heat.resize(width * area.height as usize, 0);
+
+        // Seed the source row with a hot (but by default not max) color.
+        let source_index =
+            (((hottest + 1).saturating_sub(6) as f32 * intensity).round() as usize).min(hottest);
+        let source = row(0);
+        for (column, x) in (area.left()..area.right()).enumerate() {
+            let rand = pseudo_rand(tick, x, source);
+            let jitter = (rand & 1) as usize;
+            self.heat[column] = source_index.saturating_sub(jitter) as u8;
+        }
+
+        // Propagate away from the source by cooling slightly from the previous row. Sampling
+        // against the wind carries heat with it, so the flames lean downwind; what the wind
+        // blows in from past the edge is cold, so the upwind side burns low. A hotter fire
+        // cools less often, so its flames reach further; at 1.0 a cell cools by two one time in
+        // five and by one another time in five.
+        let spread = self.spread as i32;
+        let cool_chance = 20.0 / intensity;
+        for step in 1..area.height as usize {
+            let y = row(step as u16);
+            let (done, rest) = self.heat.split_at_mut(step * width);
+            let previous = &done[(step - 1) * width..];
+            for (column, x) in (area.left()..area.right()).enumerate() {
+                let rand = pseudo_rand(tick, x, y);
+                let x_offset = (rand as i32 % (spread * 2 + 1)) - spread;
+                let sample = (column as i32 + x_offset).clamp(0, width as i32 - 1) - self.wind;
+                let below = if (0..width as i32).contains(&sample) {
+                    previous[sample as usize] as usize
+                } else {
+                    0
+                };
+                let roll = ((rand % 5) * 20 + (rand / 5) % 20) as f32;
+                let cool_step = if roll < cool_chance {
+                    2
+                } else if roll < cool_chance * 2.0 {
+                    1
+                } else {
+                    0
+                };
+                let cooled = below.saturating_sub(cool_step);
+                rest[column] = if rand & 4 == 0 {
+                    cooled
+                } else {
+                    (below * 2 + cooled) / 3
+                } as u8;
+            }
+        }
+        self.simulated = Some((tick, area));
+    }
+}
+
+impl Background for Fire {
+    fn draw(&mut self, f: &mut Frame, tick: u64, palette: &[Color]) {
+        let area = f.area();
+        if area.is_empty() || palette.is_empty() {
+            return;
+        }
+        // monitors sharing a size share the simulation
+        if self.simulated != Some((tick, area)) {
+            self.simulate(tick, area, palette.len().min(256) - 1);
+        }
+
+        let buf = f.buffer_mut();
+        let width = area.width as usize;
+        for (step, heat) in self.heat.chunks_exact(width).enumerate() {
+            let y = self.direction.row(area, step as u16);
+            for (x, &index) in (area.left()..area.right()).zip(heat) {
+                buf[(x, y)]
+                    .set_char('▒')
+                    .set_style(Style::default().fg(palette[index as usize]));
+            }
+        }
+    }
+}
+
+// longest gap a paused animation catches up on, so a stalled frame doesn't wrap every column
+const MAX_CATCH_UP_TICKS: u64 = 64;
+
+/// Columns of falling trails. Every column has its own drop, which picks a new speed and
+/// length each time it falls past the bottom edge.
+#[derive(Debug, Default)]
+pub struct MatrixRain {
+    trails: Vec<Trail>,
+    // the tallest frame drawn so far, which is where drops fall off on every monitor
+    rows: u16,
+    last_tick: Option<u64>,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Trail {
+    // the leading cell in half rows, negative while still above the top edge
+    head: i32,
+    // half rows per tick
+    speed: i32,
+    length: i32,
+}
+
+impl Trail {
+    fn spawn(tick: u64, column: u16) -> Self {
+        let rand = pseudo_rand(tick, column, 0) as i32;
+        Self {
+            head: -(rand % 64),
+            speed: 1 + rand % 3,
+            length: 6 + (rand >> 4) % 12,
+        }
+    }
+}
+
+impl MatrixRain {
+    fn advance(&mut self, tick: u64) {
+        let steps = match self.last_tick {
+            Some(last) => tick.saturating_sub(last).min(MAX_CATCH_UP_TICKS) as i32,
+            None => 0,
+        };
+        self.last_tick = Some(tick);
+        for (column, trail) in self.trails.iter_mut().enumerate() {
+            trail.head += trail.speed * steps;
+            if trail.head / 2 - trail.length > self.rows as i32 {
+                *trail = Trail::spawn(tick, column as u16);
+            }
+        }
+    }
+}
+
+impl Background for MatrixRain {
+    fn draw(&mut self, f: &mut Frame, tick: u64, palette: &[Color]) {
+        let area = f.area();
+        let buf = f.buffer_mut();
+
+        let columns = area.width as usize;
+        while self.trails.len() < columns {
+            let column = self.trails.len() as u16;
+            self.trails.push(Trail::spawn(tick, column));
+        }
+        self.rows = self.rows.max(area.height);
+        if self.last_tick != Some(tick) {
+            self.advance(tick);
+        }
+
+        let hottest = palette.len() as i32 - 1;
+        for (column, trail) in self.trails.iter().take(columns).enumerate() {
+            let x = area.left() + column as u16;
+            for row in 0..area.height {
+                let distance = trail.head.div_euclid(2) - row as i32;
+                let index = if (0..trail.length).contains(&distance) {
+                    hottest * (trail.length - distance) / trail.length
+                } else {
+                    0
+                };
+                buf[(x, area.top() + row)]
+                    .set_char('▒')
+                    .set_style(Style::default().fg(palette[index as usize]));
+            }
+        }
+    }
+}
+
+// Paints every cell from a stateless `(tick, x, y) -> palette index` function.
+#[derive(Debug)]
+struct Generated(fn(u64, u16, u16) -> usize);
+
+impl Background for Generated {
+    fn draw(&mut self, f: &mut Frame, tick: u64, palette: &[Color]) {
+        let area = f.area();
+        let buf = f.buffer_mut();
+        for y in area.top()..area.bottom() {
+            for x in area.left()..area.right() {
+                let index = (self.0)(tick, x, y).min(palette.len() - 1);
+                buf[(x, y)]
+                    .set_char('▒')
+                    .set_style(Style::default().fg(palette[index]));
+            }
+        }
+    }
+}
+
+// Classic sum-of-sines plasma.
+fn plasma_index(tick: u64, x: u16, y: u16) -> usize {
+    let t = (tick % 100_000) as f32 * 0.05;
+    let (x, y) = (x as f32, y as f32);
+    let v = (x / 8.0 + t).sin()
+        + (y / 6.0 + t * 0.7).sin()
+        + ((x + y) / 10.0 + t * 0.5).sin()
+        + ((x * x + y * y).sqrt() / 8.0 - t).sin();
+    let normalized = ((v + 4.0) / 8.0).clamp(0.0, 1.0);
+    (normalized * (FIRE_PALETTE.len() - 1) as f32) as usize
+}
+
+// Sparse stars that slowly twinkle, each on its own phase.
+fn starfield_index(tick: u64, x: u16, y: u16) -> usize {
+    let seed = pseudo_rand(0, x, y) as u64;
+    if !seed.is_multiple_of(97) {
+        return 0;
+    }
+    let phase = (tick + seed) % 64;
+    let brightness = 32 - (phase as i64 - 32).unsigned_abs();
+    let hottest = (FIRE_PALETTE.len() - 1) as u64;
+    (hottest / 2 + (hottest / 2) * brightness / 32) as usize
+}
+
+// A splitmix64 finalizer over the whole tick. The position is spread by the golden ratio
+// constant first, so it never shares bits with the tick: xoring a truncated tick into the same
+// bits as `x` made a column repeat its neighbour every 65536 ticks (about 36 minutes at 30fps).
+fn pseudo_rand(tick: u64, x: u16, y: u16) -> u16 {
+    let position = ((x as u64) << 16) | y as u64;
+    let mut v = tick.wrapping_add(position.wrapping_mul(0x9e37_79b9_7f4a_7c15));
+    v = (v ^ (v >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    v = (v ^ (v >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    v ^= v >> 31;
+    (v >> 48) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use ratatui::{Terminal, backend::TestBackend};
+
+    use super::*;
+
+    // Where `color` sits in the default palette, coldest first.
+    fn heat_of(color: Color) -> usize {
+        FIRE_PALETTE
+            .iter()
+            .position(|&entry| entry == color)
+            .unwrap()
+    }
+
+    #[test]
+    fn pseudo_rand_is_uniform_across_the_u32_boundary() {
+        const BUCKETS: usize = 16;
+        let mut counts = [0u32; BUCKETS];
+        let ticks = u32::MAX as u64 - 256..u32::MAX as u64 + 256;
+        for tick in ticks.clone() {
+            for x in 0..16 {
+                for y in 0..16 {
+                    counts[(pseudo_rand(tick, x, y) as usize * BUCKETS) >> 16] += 1;
+                }
+            }
+        }
+        let expected = (ticks.count() * 16 * 16 / BUCKETS) as u32;
+        for count in counts {
+            assert!(count.abs_diff(expected) < expected / 20, "{counts:?}");
+        }
+    }
+
+    #[test]
+    fn pseudo_rand_does_not_repeat_after_the_tick_wraps() {
+        let cell = |start: u64, x: u16| -> Vec<u16> {
+            (start..start + 64)
+                .map(|tick| pseudo_rand(tick, x, 3))
+                .collect()
+        };
+        assert_ne!(cell(1_000, 5), cell(1_000 + (1 << 32), 5));
+        // nor does a column take over its neighbour's sequence later on
+        assert_ne!(cell(1_000, 5), cell(1_000 + (1 << 16), 4));
+        assert_ne!(cell(1_000, 5), cell(1_000 + (1 << 16), 6));
+    }
+
+    // Mean column of the fire's heat over the whole area, weighted by palette index.
+    fn heat_center(fire: &Fire, tick: u64) -> f64 {
+        let mut fire = fire.clone();
+        let mut terminal = Terminal::new(TestBackend::new(80, 24)).unwrap();
+        terminal
+            .draw(|frame| fire.draw(frame, tick, &FIRE_PALETTE))
+            .unwrap();
+        let buffer = terminal.backend().buffer();
+        let (mut weighted, mut total) = (0.0, 0.0);
+        for y in 0..buffer.area.height {
+            for x in 0..buffer.area.width {
+                let heat = buffer[(x, y)].style().fg.map_or(0, heat_of) as f64;
+                weighted += heat * x as f64;
+                total += heat;
+            }
+        }
+        weighted / total
+    }
+
+    #[test]
+    fn a_strong_wind_pushes_the_heat_right() {
+        let calm = Fire::default();
+        let windy = Fire {
+            wind: 3,
+            ..Fire::default()
+        };
+        for tick in [0, 1, 1000] {
+            assert!(
+                heat_center(&windy, tick) > heat_center(&calm, tick) + 1.0,
+                "tick {tick}"
+            );
+        }
+    }
+
+    #[test]
+    fn downward_fire_burns_from_the_top_edge() {
+        let mut fire = Fire {
+            direction: FireDirection::Down,
+            ..Fire::default()
+        };
+        let mut terminal = Terminal::new(TestBackend::new(20, 10)).unwrap();
+        terminal
+            .draw(|frame| fire.draw(frame, 0, &FIRE_PALETTE))
+            .unwrap();
+        let buffer = terminal.backend().buffer();
+        let heat = |y| heat_of(buffer[(0, y)].style().fg.unwrap());
+        assert!(heat(0) >= FIRE_PALETTE.len() - 7);
+        assert!(heat(0) > heat(9));
+        assert_eq!("down".parse(), Ok(FireDirection::Down));
+        assert!("sideways".parse::<FireDirection>().is_err());
+    }
+
+    #[test]
+    fn a_hotter_fire_burns_higher() {
+        let total = |intensity: f32| -> usize {
+            let mut fire = Fire {
+                intensity,
+                ..Fire::default()
+            };
+            heat_map(&mut fire, 7, 40, 30).iter().sum()
+        };
+        assert!(total(2.0) > total(1.0));
+        assert!(total(1.0) > total(0.5));
+        assert_eq!(parse_intensity("1.5"), Ok(1.5));
+        for invalid in ["0", "-1", "NaN", "hot"] {
+            assert!(parse_intensity(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn a_palette_that_repeats_colors_still_burns() {
+        // every heat above the coldest looks the same, so nothing can be read back from colors
+        let mut palette = vec![Color::Red; FIRE_PALETTE.len()];
+        palette[0] = Color::Black;
+        let mut fire = Fire::default();
+        let mut terminal = Terminal::new(TestBackend::new(20, 10)).unwrap();
+        terminal
+            .draw(|frame| fire.draw(frame, 0, &palette))
+            .unwrap();
+        let buffer = terminal.backend().buffer();
+        let burning = (0..20)
+            .filter(|&x| buffer[(x, 4)].style().fg == Some(Color::Red))
+            .count();
+        assert!(burning > 15, "{burning}");
+    }
+
+    // The palette index of every cell of a `width` by `height` frame `background` draws.
+    fn heat_map(background: &mut dyn Background, tick: u64, width: u16, height: u16) -> Vec<usize> {
+        let mut terminal = Terminal::new(TestBackend::new(width, height)).unwrap();
+        terminal
+            .draw(|frame| background.draw(frame, tick, &FIRE_PALETTE))
+            .unwrap();
+        let buffer = terminal.backend().buffer();
+        buffer
+            .content()
+            .iter()
+            .map(|cell| heat_of(cell.style().fg.unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn the_heat_grid_is_kept_until_the_tick_or_area_changes() {
+        let mut fire = Fire::default();
+        let first = heat_map(&mut fire, 3, 20, 10);
+        assert_eq!(fire.simulated, Some((3, Rect::new(0, 0, 20, 10))));
+        assert_eq!(heat_map(&mut fire, 3, 20, 10), first);
+        // a monitor of another size gets its own simulation, not a stretched copy
+        let wide = heat_map(&mut fire, 3, 30, 10);
+        assert_eq!(wide, heat_map(&mut Fire::default(), 3, 30, 10));
+        assert_eq!(fire.heat.len(), 300);
+    }
+
+    #[test]
+    fn matrix_rain_drops_fall_between_ticks() {
+        let mut rain = MatrixRain::default();
+        let first = heat_map(&mut rain, 0, 20, 30);
+        // drawing another monitor in the same tick doesn't move the drops
+        assert_eq!(heat_map(&mut rain, 0, 20, 30), first);
+        let mut later = first.clone();
+        for tick in 1..40 {
+            later = heat_map(&mut rain, tick, 20, 30);
+        }
+        assert_ne!(later, first);
+        assert!(later.iter().any(|&heat| heat > 0));
+    }
+
+    #[test]
+    fn every_background_fills_the_frame() {
+        for name in ["fire", "matrix", "plasma", "starfield"] {
+            let kind: BackgroundKind = name.parse().unwrap();
+            let mut background = kind.create(Fire::default());
+            assert_eq!(heat_map(background.as_mut(), 1, 8, 4).len(), 32, "{name}");
+        }
+    }
+}
@@ -1,11 +1,29 @@
+use std::time::{Duration, Instant};
+
+use crate::{
+    auth::{AuthError, Prompt, Secret},
+    media::NowPlaying,
+    power::Battery,
+    render::{Tint, to_argb},
+};
+
 use ratatui::{
     Frame, Terminal,
     backend::TestBackend,
-    buffer::Buffer,
-    layout::{Constraint, Direction, Layout, Position},
-    style::{Color, Style},
-    text::{Line, Text},
-    widgets::{Block, Borders, Paragraph},
+    buffer::{Buffer, Cell},
+    layout::{Alignment, Constraint, Direction, Layout, Position, Rect},
+    style::{Color, Modifier, Style},
+    text::{Line, Span, Text},
+    widgets::{Block, BorderType, Borders, Paragraph},
+};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+use zeroize::Zeroizing;
+
+mod background;
+
+pub use background::{
+    Background, BackgroundKind, Fire, FireDirection, MatrixRain, parse_intensity,
 };
 
 pub static FIRE_PALETTE: [Color; 36] = [
@@ -50,16 +68,240 @@ pub static FIRE_PALETTE: [Color; 36] = [
 #[derive(Debug, Default)]
 pub struct AppState {
     pub username: String,
-    pub password: String,
+    pub password: Secret,
     pub error_message: Option<String>,
     pub focused: FocusTarget,
+    // an unlock button on the box's bottom border, which joins the focus order
+    pub submit_button: bool,
+    // a power off button on the other end of the bottom border, also in the focus order
+    pub power_button: bool,
+    // the users to pick from with Left and Right in place of typing a username; empty to type
+    pub user_list: Vec<String>,
     pub tick: u64,
+    // drop every key without touching the fields, e.g. while the lock isn't confirmed yet and
+    // unlocked content may still be on screen
+    pub ignore_input: bool,
+    // an attempt is being checked; the fields are locked and a spinner replaces the info line
+    pub authenticating: bool,
+    // mirrors the keyboard's Caps Lock, which gets a warning in the box while on
+    pub caps_lock: bool,
+    // show the password as typed while its field is focused; back to masked on submit
+    pub reveal_password: bool,
+    // when the session lock was confirmed, if it has been
+    pub locked_at: Option<Instant>,
+    pub lock_reminder: LockReminder,
+    // right after locking, Enter unlocks without a password for this long; zero disables it
+    pub grace_period: Duration,
+    // the animation freezes after this long without input; None keeps it running
+    pub idle_after: Option<Duration>,
+    // when a key was last pressed or the pointer last moved, if ever
+    pub last_activity: Option<Instant>,
+    pub lockout: Lockout,
+    // flash the box border red for a moment after a failed attempt
+    pub flash_on_failure: bool,
+    // until when the border is drawn red, cleared by `tick` once it has passed
+    pub error_flash_until: Option<Instant>,
+    // ring the bell after a failed attempt, see `take_bell`
+    pub bell_on_failure: bool,
+    pub bell_pending: bool,
+    // text an input method is still composing, drawn underlined after the focused field until
+    // it's committed into it
+    pub preedit: Option<String>,
+    // the PAM prompt the password field is currently answering
+    pub prompt: Option<Prompt>,
+    // the last informational or error message PAM sent during the attempt, on the info line
+    pub pam_message: Option<String>,
+    // shown under the box when a media player is active
+    pub now_playing: Option<NowPlaying>,
+    // shown in the top right corner on machines with a battery
+    pub battery: Option<Battery>,
+    // multi-line ASCII art centered above the box
+    pub logo: Option<String>,
+    // replace the animated fire with a static gradient
+    pub reduced_motion: bool,
+    // on-screen numeric keypad for touchscreens without a keyboard
+    pub pin_pad: bool,
+    // how much a masked password field gives away about what was typed
+    pub password_mode: PasswordDisplay,
+    pub background: Box<dyn Background>,
+    // replaces FIRE_PALETTE for every background, see `palette_from_stops`
+    pub palette: Option<Vec<Color>>,
+    pub theme: Theme,
+}
+
+#[derive(Debug, Clone)]
+pub struct Theme {
+    pub box_fg: Color,
+    pub box_bg: Color,
+    // 255 is an opaque box; anything lower lets the fire show through it
+    pub box_alpha: u8,
+    // render the field labels and title in bold, leaving typed text regular
+    pub bold_labels: bool,
+    pub border: BoxBorder,
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum BoxBorder {
+    #[default]
+    Plain,
+    Rounded,
+    Double,
+    Thick,
+    None,
+}
+
+impl std::str::FromStr for BoxBorder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "plain" => Ok(Self::Plain),
+            "rounded" => Ok(Self::Rounded),
+            "double" => Ok(Self::Double),
+            "thick" => Ok(Self::Thick),
+            "none" => Ok(Self::None),
+            _ => Err(format!(
+                "unknown border {s:?}, expected plain, rounded, double, thick or none"
+            )),
+        }
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            box_fg: Color::White,
+            box_bg: Color::from_u32(0x00333333),
+            box_alpha: u8::MAX,
+            bold_labels: false,
+            border: BoxBorder::default(),
+        }
+    }
+}
+
+/// How a masked password field is rendered. Prompts PAM asked to echo are always shown as typed.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum PasswordDisplay {
+    // one `*` per character
+    #[default]
+    Masked,
+    // the same run of `*` once anything is typed, so the length isn't shown
+    FixedDots,
+    // nothing at all while typing; backspace still edits the hidden content
+    Hidden,
+}
+
+// length of the mask shown by `PasswordDisplay::FixedDots`
+const FIXED_DOTS: usize = 8;
+
+impl PasswordDisplay {
+    fn mask(self, password: &str) -> String {
+        match self {
+            Self::Masked => "*".repeat(password.chars().count()),
+            Self::FixedDots if password.is_empty() => String::new(),
+            Self::FixedDots => "*".repeat(FIXED_DOTS),
+            Self::Hidden => String::new(),
+        }
+    }
+}
+
+impl std::str::FromStr for PasswordDisplay {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "masked" => Ok(Self::Masked),
+            "fixed-dots" => Ok(Self::FixedDots),
+            "hidden" => Ok(Self::Hidden),
+            _ => Err(format!(
+                "unknown password display {s:?}, expected masked, fixed-dots or hidden"
+            )),
+        }
+    }
+}
+
+/// A subtle "Locked for 2h" notice shown once the session has been locked for a while, so a
+/// returning user on a shared machine knows it's been some time.
+#[derive(Debug, Clone)]
+pub struct LockReminder {
+    // None disables the reminder
+    pub after: Option<Duration>,
+}
+
+impl Default for LockReminder {
+    fn default() -> Self {
+        Self {
+            after: Some(Duration::from_secs(2 * 60 * 60)),
+        }
+    }
+}
+
+/// Slows down guessing. Once `threshold` attempts in a row have failed, every further failure
+/// blocks submitting for a delay that doubles each time, starting at `base_delay` and capped at
+/// `max_delay`. A successful attempt starts the count over.
+#[derive(Debug, Clone)]
+pub struct Lockout {
+    pub threshold: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    failures: u32,
+    locked_until: Option<Instant>,
+}
+
+impl Default for Lockout {
+    fn default() -> Self {
+        Self {
+            threshold: 3,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+            failures: 0,
+            locked_until: None,
+        }
+    }
+}
+
+impl Lockout {
+    /// Consecutive failed attempts since the last success.
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+
+    /// Counts a failed attempt made at `now`, starting or extending the lockout once past the
+    /// threshold.
+    pub fn record_failure(&mut self, now: Instant) {
+        self.failures = self.failures.saturating_add(1);
+        let Some(over) = self.failures.checked_sub(self.threshold) else {
+            return;
+        };
+        let delay = self
+            .base_delay
+            .checked_mul(1 << over.min(31))
+            .map_or(self.max_delay, |delay| delay.min(self.max_delay));
+        self.locked_until = Some(now + delay);
+    }
+
+    pub fn reset(&mut self) {
+        self.failures = 0;
+        self.locked_until = None;
+    }
+
+    /// How long submitting stays blocked, if it is at `now`.
+    pub fn remaining(&self, now: Instant) -> Option<Duration> {
+        let remaining = self.locked_until?.saturating_duration_since(now);
+        (!remaining.is_zero()).then_some(remaining)
+    }
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum FocusTarget {
     Username,
     Password,
+    // the unlock button on the bottom border of the box
+    Submit,
+    // the power off button opposite it
+    Power,
+    // the username line while it picks from `AppState::user_list`
+    UserList,
 }
 
 impl Default for FocusTarget {
@@ -70,220 +312,1809 @@ impl Default for FocusTarget {
 
 impl AppState {
     pub fn handle_input(&mut self, key: KeyInput) -> Option<AppAction> {
+        if self.ignore_input {
+            return None;
+        }
+        if self.authenticating {
+            // the attempt can't be edited once sent, only abandoned
+            return matches!(key, KeyInput::Esc).then_some(AppAction::Cancel);
+        }
+        if matches!(key, KeyInput::Enter)
+            && self.focused != FocusTarget::Power
+            && self.in_grace_period(Instant::now())
+        {
+            return Some(AppAction::Dismiss);
+        }
         match key {
-            KeyInput::Char(ch) => match self.focused {
-                FocusTarget::Username => self.username.push(ch),
-                FocusTarget::Password => self.password.push(ch),
-            },
-            KeyInput::Backspace => match self.focused {
-                FocusTarget::Username => {
-                    self.username.pop();
+            KeyInput::Char(ch) => {
+                if let Some(field) = self.focused_field() {
+                    field.push(ch);
                 }
-                FocusTarget::Password => {
-                    self.password.pop();
+            }
+            KeyInput::Backspace => {
+                if let Some(field) = self.focused_field() {
+                    field.pop();
                 }
-            },
-            KeyInput::Tab => {
-                self.focused = match self.focused {
-                    FocusTarget::Username => FocusTarget::Password,
-                    FocusTarget::Password => FocusTarget::Username,
-                };
             }
-            KeyInput::Up => {
-                self.focused = FocusTarget::Username;
+            KeyInput::Tab => self.move_focus(1, true),
+            KeyInput::BackTab => self.move_focus(-1, true),
+            KeyInput::Up => self.move_focus(-1, false),
+            KeyInput::Down => self.move_focus(1, false),
+            KeyInput::Left if self.focused == FocusTarget::UserList => self.pick_user(-1),
+            KeyInput::Right if self.focused == FocusTarget::UserList => self.pick_user(1),
+            KeyInput::Left | KeyInput::Right => {}
+            KeyInput::Enter if self.focused == FocusTarget::Power => {
+                return Some(AppAction::PowerOff);
             }
-            KeyInput::Down => {
-                self.focused = FocusTarget::Password;
+            KeyInput::Enter if self.focused == FocusTarget::Submit => {
+                if self.username.is_empty() {
+                    self.focused = self.username_target();
+                } else {
+                    return self.submit();
+                }
             }
             KeyInput::Enter => {
                 let username_empty = self.username.is_empty();
                 let password_empty = self.password.is_empty();
                 if username_empty && password_empty {
-                    self.focused = FocusTarget::Username;
+                    self.focused = self.username_target();
                 } else if !username_empty && password_empty {
                     self.focused = FocusTarget::Password;
                 } else if !username_empty && !password_empty {
-                    return Some(AppAction::Submit {
-                        username: self.username.clone(),
-                        password: self.password.clone(),
-                    });
+                    return self.submit();
                 }
             }
+            // gives up on a PAM prompt, which fails the attempt
+            KeyInput::Esc if self.prompt.is_some() => return Some(AppAction::Cancel),
             KeyInput::Esc => {
                 // TODO: clear or cancel.
             }
+            KeyInput::Clear => {
+                if let Some(field) = self.focused_field() {
+                    field.clear();
+                }
+            }
+            KeyInput::ToggleReveal => self.reveal_password = !self.reveal_password,
+            KeyInput::Paste => {}
         }
         None
     }
 
+    /// Types pasted text into the focused field, as if each character had been typed. Control
+    /// characters such as a trailing newline are dropped rather than submitting anything.
+    pub fn paste(&mut self, text: &str) {
+        if self.ignore_input || self.authenticating {
+            return;
+        }
+        if let Some(field) = self.focused_field() {
+            for ch in text.chars().filter(|ch| !ch.is_control()) {
+                field.push(ch);
+            }
+        }
+    }
+
+    // Nothing is sent while a lockout is running; the fields keep their contents so the
+    // attempt can be sent as is once it ends. Otherwise the password moves into the action,
+    // leaving the field empty.
+    fn submit(&mut self) -> Option<AppAction> {
+        if self.lockout.remaining(Instant::now()).is_some() {
+            return None;
+        }
+        self.reveal_password = false;
+        Some(AppAction::Submit {
+            username: self.username.clone(),
+            password: self.password.take(),
+        })
+    }
+
+    /// Moves focus straight to `target`, e.g. a clicked field. Ignored while the fields are
+    /// locked, like every key.
+    pub fn focus(&mut self, target: FocusTarget) {
+        if self.ignore_input || self.authenticating {
+            return;
+        }
+        self.focused = target;
+    }
+
+    /// What Tab, Shift-Tab, Up and Down move between, in the order they're drawn: the username
+    /// line, the password, then whichever buttons are shown. The pin pad is only ever tapped,
+    /// so it has no stop.
+    pub fn focus_order(&self) -> Vec<FocusTarget> {
+        let mut order = vec![self.username_target(), FocusTarget::Password];
+        if self.submit_button {
+            order.push(FocusTarget::Submit);
+        }
+        if self.power_button {
+            order.push(FocusTarget::Power);
+        }
+        order
+    }
+
+    // The username line's target: typed into, or picked from the user list.
+    fn username_target(&self) -> FocusTarget {
+        if self.user_list.is_empty() {
+            FocusTarget::Username
+        } else {
+            FocusTarget::UserList
+        }
+    }
+
+    // Steps `delta` places through the user list, wrapping around. A username that isn't in
+    // the list starts from its first entry.
+    fn pick_user(&mut self, delta: isize) {
+        let len = self.user_list.len() as isize;
+        if len == 0 {
+            return;
+        }
+        let current = self
+            .user_list
+            .iter()
+            .position(|user| *user == self.username);
+        let next = match current {
+            Some(current) => (current as isize + delta).rem_euclid(len),
+            None => 0,
+        };
+        self.username = self.user_list[next as usize].clone();
+    }
+
+    // Steps `delta` places through the focus order. Tab wraps around; Up and Down stop at the
+    // ends. Focus outside the order (e.g. set by a PAM prompt) moves to its first entry.
+    fn move_focus(&mut self, delta: isize, wrap: bool) {
+        let order = self.focus_order();
+        let Some(current) = order.iter().position(|target| *target == self.focused) else {
+            self.focused = order[0];
+            return;
+        };
+        let len = order.len() as isize;
+        let next = current as isize + delta;
+        let next = if wrap {
+            next.rem_euclid(len)
+        } else {
+            next.clamp(0, len - 1)
+        };
+        self.focused = order[next as usize];
+    }
+
+    // The text field typed characters go into, if focus is on one.
+    fn focused_field(&mut self) -> Option<&mut dyn TextField> {
+        match self.focused {
+            FocusTarget::Username => Some(&mut self.username),
+            FocusTarget::Password => Some(&mut self.password),
+            FocusTarget::Submit | FocusTarget::Power | FocusTarget::UserList => None,
+        }
+    }
+
     pub fn tick(&mut self) {
         self.tick = self.tick.saturating_add(1);
+        if !self.is_flashing(Instant::now()) {
+            self.error_flash_until = None;
+        }
     }
 
-    pub fn draw_background(f: &mut Frame, tick: u64) {
-        let area = f.area();
-        let buf = f.buffer_mut();
+    /// Whether the box border is still flashing red for a failed attempt at `now`.
+    pub fn is_flashing(&self, now: Instant) -> bool {
+        self.error_flash_until.is_some_and(|until| now < until)
+    }
 
-        let source_index = FIRE_PALETTE.len().saturating_sub(6);
-        // Seed the bottom row with a hot (but not max) color.
-        for x in area.left()..area.right() {
-            let rand = pseudo_rand(tick, x, area.bottom() - 1);
-            let jitter = (rand & 1) as usize;
-            let seed_index = source_index.saturating_sub(jitter);
-            buf[(x, area.bottom() - 1)]
-                .set_char('▒')
-                .set_style(Style::default().fg(FIRE_PALETTE[seed_index]));
+    /// Whether a failed attempt is waiting for the bell, which only rings once per failure.
+    pub fn take_bell(&mut self) -> bool {
+        std::mem::take(&mut self.bell_pending)
+    }
+
+    /// Points the password field at a new PAM prompt, relabelling it and masking the answer
+    /// only when PAM asked for a hidden response.
+    pub fn apply_prompt(&mut self, prompt: Prompt) {
+        self.prompt = Some(prompt);
+        self.password.clear();
+        self.focused = FocusTarget::Password;
+    }
+
+    /// Shows why an attempt failed and readies the password field for another try. Only a
+    /// rejected password counts towards the lockout, not PAM being unusable.
+    pub fn apply_auth_error(&mut self, error: &AuthError) {
+        if *error == AuthError::Failed {
+            self.lockout.record_failure(Instant::now());
+        }
+        self.error_message = Some(error.message().to_string());
+        self.password.clear();
+        self.focused = FocusTarget::Password;
+        if self.flash_on_failure {
+            self.error_flash_until = Some(Instant::now() + ERROR_FLASH_DURATION);
+        }
+        self.bell_pending = self.bell_on_failure;
+    }
+
+    pub fn password_label(&self) -> String {
+        let label = self
+            .prompt
+            .as_ref()
+            .map(Prompt::label)
+            .unwrap_or_else(|| "Password".to_string());
+        format!("{label}: ")
+    }
+
+    /// The password field's text as drawn, zeroed once dropped since a revealed code is copied
+    /// into it as is.
+    ///
+    /// Whatever is drawn also ends up in ratatui's cells and in the shm buffers the compositor
+    /// reads, which can't be zeroed: the compositor keeps its own copies as textures.
+    pub fn password_display(&self) -> Zeroizing<String> {
+        Zeroizing::new(if self.password_masked() {
+            self.password_mode.mask(self.password.as_str())
+        } else {
+            self.password.as_str().to_string()
+        })
+    }
+
+    /// Whether the password field hides what's typed, as it does unless PAM asked with echo on
+    /// or the password is revealed.
+    pub fn password_masked(&self) -> bool {
+        let masked = self.prompt.as_ref().is_none_or(Prompt::is_masked);
+        let revealed = self.reveal_password && self.focused == FocusTarget::Password;
+        masked && !revealed
+    }
+
+    /// What an input method is composing into `field`, if it's the focused one. Never shown
+    /// in a masked password field, where it would give the password away.
+    pub fn preedit_in(&self, field: FocusTarget) -> &str {
+        let hidden = field == FocusTarget::Password && self.password_masked();
+        match self.preedit.as_deref() {
+            Some(preedit) if self.focused == field && !hidden => preedit,
+            _ => "",
         }
+    }
+
+    /// Whether the lock can still be dismissed without a password at `now`.
+    pub fn in_grace_period(&self, now: Instant) -> bool {
+        self.locked_at
+            .is_some_and(|locked_at| now.saturating_duration_since(locked_at) < self.grace_period)
+    }
 
-        // Propagate upward by cooling slightly from the cell below.
-        for y in (area.top()..area.bottom() - 1).rev() {
+    pub fn record_activity(&mut self, now: Instant) {
+        self.last_activity = Some(now);
+    }
+
+    /// Whether the animation can freeze at `now`: nothing was typed or moved for `idle_after`,
+    /// and nothing on screen is spinning, flashing or counting down. Counts from the lock until the first
+    /// input.
+    pub fn is_idle(&self, now: Instant) -> bool {
+        let (Some(after), Some(since)) = (self.idle_after, self.last_activity.or(self.locked_at))
+        else {
+            return false;
+        };
+        if self.authenticating || self.lockout.remaining(now).is_some() || self.is_flashing(now) {
+            return false;
+        }
+        now.saturating_duration_since(since) >= after
+    }
+
+    /// How long the session has been locked, once that exceeds the reminder threshold.
+    pub fn lock_reminder_due(&self, now: Instant) -> Option<Duration> {
+        let after = self.lock_reminder.after?;
+        let elapsed = now.saturating_duration_since(self.locked_at?);
+        (elapsed >= after).then_some(elapsed)
+    }
+
+    /// Same palette as the fire, but a fixed gradient that never changes between ticks.
+    pub fn draw_static_background(f: &mut Frame, palette: &[Color]) {
+        let area = f.area();
+        let buf = f.buffer_mut();
+
+        let hottest = palette.len().saturating_sub(6);
+        let height = area.height.max(1) as usize;
+        for y in area.top()..area.bottom() {
+            let depth = (y - area.top()) as usize + 1;
+            let index = hottest * depth * depth / (height * height);
             for x in area.left()..area.right() {
-                let rand = pseudo_rand(tick, x, y);
-                let x_offset = (rand % 5) as i32 - 2;
-                let sample_x = (x as i32 + x_offset)
-                    .clamp(area.left() as i32, (area.right() - 1) as i32) as u16;
-                let below = buf[(sample_x, y + 1)].style().fg;
-                let below_index = palette_index(below.unwrap_or(Color::Black)).unwrap_or(0);
-                let cool_step = match rand % 5 {
-                    0 => 2,
-                    1 => 1,
-                    _ => 0,
-                };
-                let cooled = below_index.saturating_sub(cool_step);
-                let target_index = if rand & 4 == 0 {
-                    cooled
-                } else {
-                    (below_index * 2 + cooled) / 3
-                };
-                let current = buf[(x, y)].style().fg;
-                let current_index = palette_index(current.unwrap_or(Color::Black)).unwrap_or(0);
-                let next_index = if target_index < current_index {
-                    current_index.saturating_sub(1)
-                } else {
-                    target_index
-                };
                 buf[(x, y)]
                     .set_char('▒')
-                    .set_style(Style::default().fg(FIRE_PALETTE[next_index]));
+                    .set_style(Style::default().fg(palette[index]));
             }
         }
     }
 }
 
+// The editing the username and password fields share, so keys can go to whichever is focused.
+trait TextField {
+    fn push(&mut self, ch: char);
+    fn pop(&mut self) -> Option<char>;
+    fn clear(&mut self);
+}
+
+impl TextField for String {
+    fn push(&mut self, ch: char) {
+        String::push(self, ch);
+    }
+
+    fn pop(&mut self) -> Option<char> {
+        String::pop(self)
+    }
+
+    fn clear(&mut self) {
+        String::clear(self);
+    }
+}
+
+impl TextField for Secret {
+    fn push(&mut self, ch: char) {
+        Secret::push(self, ch);
+    }
+
+    fn pop(&mut self) -> Option<char> {
+        Secret::pop(self)
+    }
+
+    fn clear(&mut self) {
+        Secret::clear(self);
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum KeyInput {
     Char(char),
     Enter,
     Backspace,
     Tab,
+    // Shift-Tab
+    BackTab,
     Up,
     Down,
+    // pick the previous or next entry of the user list
+    Left,
+    Right,
     Esc,
+    // empties the focused field
+    Clear,
+    // Ctrl+R: flips between showing the password as typed and masking it
+    ToggleReveal,
+    // Ctrl+V or Shift+Insert; the caller fetches the clipboard and hands it to `paste`
+    Paste,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub enum AppAction {
-    Submit { username: String, password: String },
+    Submit { username: String, password: Secret },
+    // stop waiting for the attempt in flight, or abandon the PAM prompt it's asking, and give
+    // the fields back
+    Cancel,
+    // unlock without a password, within the grace period
+    Dismiss,
+    // the power button was pressed
+    PowerOff,
+}
+
+/// Renders the login screen into a cell buffer for rasterization. Uses `draw`, so the result
+/// never depends on where the terminal cursor would go; see `caret_position` for that.
+pub fn render_to_buffer(state: &mut AppState, width_cells: u16, height_cells: u16) -> Buffer {
+    let backend = TestBackend::new(width_cells, height_cells);
+    let mut terminal = Terminal::new(backend).expect("failed to create ratatui terminal");
+    let _ = terminal.draw(|frame| draw(frame, state));
+    terminal.backend().buffer().clone()
+}
+
+/// Draws the login screen and places the terminal cursor in the focused field. This is the
+/// entry point for real terminals, where the cursor doubles as the caret.
+pub fn view(frame: &mut Frame, state: &mut AppState) {
+    draw(frame, state);
+    if let Some(tint) = box_tint(frame.area(), state) {
+        tint_cells(frame.buffer_mut(), tint);
+    }
+    if let Some((x, y)) = caret_position(frame.area(), state) {
+        frame.set_cursor_position(Position { x, y });
+    }
 }
 
-pub fn render_to_buffer(state: &AppState, width_cells: u16, height_cells: u16) -> Buffer {
+/// The cell the caret belongs in for a frame covering `area`. Pixel renderers have no terminal
+/// cursor, so they draw their own caret here instead.
+pub fn caret_position(area: Rect, state: &AppState) -> Option<(u16, u16)> {
+    let layout = LoginLayout::new(area, state);
+    let block = centered_block("", state.theme.border);
+    cursor_position(block.inner(layout.box_area), state)
+}
+
+/// Renders only the background, for outputs that don't show the login box.
+pub fn render_background(state: &mut AppState, width_cells: u16, height_cells: u16) -> Buffer {
     let backend = TestBackend::new(width_cells, height_cells);
     let mut terminal = Terminal::new(backend).expect("failed to create ratatui terminal");
-    let _ = terminal.draw(|frame| view(frame, state));
+    let _ = terminal.draw(|frame| draw_backdrop(frame, state));
     terminal.backend().buffer().clone()
 }
 
-pub fn view(frame: &mut Frame, state: &AppState) {
-    AppState::draw_background(frame, state.tick);
+fn draw_backdrop(frame: &mut Frame, state: &mut AppState) {
+    let palette = state.palette.as_deref().unwrap_or(&FIRE_PALETTE);
+    if state.reduced_motion {
+        AppState::draw_static_background(frame, palette);
+    } else {
+        state.background.draw(frame, state.tick, palette);
+    }
+}
+
+/// Draws the login screen without touching the cursor.
+pub fn draw(frame: &mut Frame, state: &mut AppState) {
+    draw_backdrop(frame, state);
+    let state = &*state;
     let area = frame.area();
     let title = "Lilac";
+    let layout = LoginLayout::new(area, state);
+    let box_area = layout.box_area;
 
-    let vert = Layout::default()
-        .direction(Direction::Vertical)
-        .constraints([
-            Constraint::Min(0),
-            Constraint::Length(7),
-            Constraint::Min(0),
-        ])
-        .split(area);
-    let horiz = Layout::default()
-        .direction(Direction::Horizontal)
-        .constraints([
-            Constraint::Min(0),
-            Constraint::Length(36),
-            Constraint::Min(0),
-        ])
-        .split(vert[1]);
-    let box_area = horiz[1];
-
-    let box_style = Style::default()
-        .fg(Color::White)
-        .bg(Color::from_u32(0x00333333));
+    let theme = &state.theme;
+    let opaque = theme.box_alpha == u8::MAX;
+    // A translucent box's cells keep the color of the backdrop they cover, for `box_tint` to
+    // be blended over, so the widgets drawn on top must not overwrite it with a flat color.
+    let box_style = if opaque {
+        Style::default().fg(theme.box_fg).bg(theme.box_bg)
+    } else {
+        Style::default().fg(theme.box_fg)
+    };
     {
         let buf = frame.buffer_mut();
         for y in box_area.top()..box_area.bottom() {
             for x in box_area.left()..box_area.right() {
-                buf[(x, y)].set_char(' ').set_style(box_style);
+                let cell = &mut buf[(x, y)];
+                let bg = if opaque {
+                    theme.box_bg
+                } else {
+                    backdrop_color(cell)
+                };
+                cell.set_char(' ').set_style(box_style.bg(bg));
             }
         }
     }
-    let block = centered_block(title).style(box_style);
+    let label_style = if theme.bold_labels {
+        box_style.add_modifier(Modifier::BOLD)
+    } else {
+        box_style
+    };
+    let reminder = state.lock_reminder_due(Instant::now());
+    let mut block = centered_block(title, theme.border).style(box_style);
+    if theme.bold_labels {
+        block = block.title_style(Style::default().add_modifier(Modifier::BOLD));
+    }
+    if state.is_flashing(Instant::now()) {
+        block = block.border_style(box_style.fg(ERROR_FLASH_COLOR));
+    } else if reminder.is_some() {
+        block = block.border_style(box_style.fg(reminder_pulse(state.tick)));
+    }
+    if state.submit_button {
+        let style = if state.focused == FocusTarget::Submit {
+            label_style.add_modifier(Modifier::REVERSED)
+        } else {
+            label_style
+        };
+        block = block.title_bottom(Line::styled("[ Unlock ]", style).right_aligned());
+    }
+    if state.power_button {
+        let style = if state.focused == FocusTarget::Power {
+            label_style.add_modifier(Modifier::REVERSED)
+        } else {
+            label_style
+        };
+        block = block.title_bottom(Line::styled("[ Power ]", style).left_aligned());
+    }
     frame.render_widget(block.clone(), box_area);
 
-    let masked = "*".repeat(state.password.len());
-
-    let info = if let Some(message) = state.error_message.as_ref() {
+    let info = if state.authenticating {
+        let frame = SPINNER[(state.tick / 3) as usize % SPINNER.len()];
+        let message = state.pam_message.as_deref().unwrap_or("Authenticating...");
+        format!(" {frame} {message}")
+    } else if let Some(message) = state.pam_message.as_ref() {
+        format!(" {message}")
+    } else if let Some(remaining) = state.lockout.remaining(Instant::now()) {
+        format!(
+            " Too many attempts, wait {}s",
+            remaining.as_millis().div_ceil(1000)
+        )
+    } else if let Some(message) = state.error_message.as_ref() {
         format!("Error: {message}")
     } else {
         "".to_string()
     };
+    let password = state.password_display();
+    let username = if state.user_list.is_empty() {
+        Line::from(vec![
+            Span::styled(" Username: ", label_style),
+            Span::styled(state.username.as_str(), box_style),
+            Span::styled(
+                state.preedit_in(FocusTarget::Username),
+                box_style.add_modifier(Modifier::UNDERLINED),
+            ),
+        ])
+    } else {
+        let style = if state.focused == FocusTarget::UserList {
+            box_style.add_modifier(Modifier::REVERSED)
+        } else {
+            box_style
+        };
+        Line::from(vec![
+            Span::styled(" User: ", label_style),
+            Span::styled(format!("< {} >", state.username), style),
+        ])
+    };
     let paragraph = Paragraph::new(Text::from(vec![
         Line::styled(info, box_style),
-        Line::styled(format!(" Username: {}", state.username), box_style),
+        username,
         Line::styled("", box_style),
-        Line::styled(format!(" Password: {}", masked), box_style),
+        Line::from(vec![
+            Span::styled(format!(" {}", state.password_label()), label_style),
+            Span::styled(password.as_str(), box_style),
+            Span::styled(
+                state.preedit_in(FocusTarget::Password),
+                box_style.add_modifier(Modifier::UNDERLINED),
+            ),
+        ]),
+        // a typo in the password is more pressing than how long we've been locked
+        if state.caps_lock {
+            Line::styled(" ⚠ Caps Lock is on", box_style.fg(CAPS_LOCK_COLOR))
+        } else {
+            Line::styled(
+                reminder
+                    .map(|elapsed| format!(" Locked for {}", format_elapsed(elapsed)))
+                    .unwrap_or_default(),
+                box_style.fg(Color::Gray),
+            )
+        },
     ]))
     .style(box_style);
     let inner = block.inner(box_area);
     frame.render_widget(paragraph, inner);
 
-    if let Some((x, y)) = cursor_position(inner, state) {
-        frame.set_cursor_position(Position { x, y });
+    if let Some(logo) = state.logo.as_deref() {
+        let area = logo_area(layout.above, logo);
+        // keep the bottom of the art, nearest the box, when it doesn't all fit
+        let lines: Vec<_> = logo.lines().collect();
+        let visible = &lines[lines.len() - area.height as usize..];
+        let art: Vec<_> = visible.iter().map(|line| Line::raw(*line)).collect();
+        let art = Paragraph::new(art)
+            .alignment(Alignment::Center)
+            .style(Style::default().fg(theme.box_fg));
+        frame.render_widget(art, area);
     }
+
+    if let Some(pad) = layout.pin_pad {
+        let button_style = Style::default().fg(theme.box_fg).bg(theme.box_bg);
+        for (rect, label) in pin_buttons(pad) {
+            let button = Paragraph::new(label)
+                .alignment(Alignment::Center)
+                .style(button_style);
+            frame.render_widget(button, rect);
+        }
+    }
+
+    if let Some(now_playing) = state.now_playing.as_ref() {
+        let below = layout.below;
+        let line = Line::styled(now_playing.line(), Style::default().fg(Color::White))
+            .alignment(Alignment::Center);
+        if below.height > 1 {
+            let area = Rect::new(below.x, below.y + 1, below.width, 1);
+            frame.render_widget(Paragraph::new(line), area);
+        }
+    }
+
+    if let Some(battery) = state.battery.as_ref() {
+        let line = battery.line();
+        let width = display_width(&line).min(area.width);
+        let mut style = Style::default().fg(theme.box_fg).bg(theme.box_bg);
+        if battery.is_low() {
+            style = style.add_modifier(Modifier::REVERSED);
+        }
+        let corner = Rect::new(area.right() - width, area.y, width, 1);
+        frame.render_widget(Paragraph::new(line).style(style), corner);
+    }
+}
+
+/// Where the pieces of the login screen land in a frame. Shared by `view` and pointer
+/// hit-testing so a tap always lands on what was drawn.
+pub struct LoginLayout {
+    // whatever is left above the box
+    pub above: Rect,
+    pub box_area: Rect,
+    pub pin_pad: Option<Rect>,
+    // whatever is left below the box (and the pin pad, if shown)
+    pub below: Rect,
+}
+
+// the amber of the fire's upper flames, FIRE_PALETTE[24]
+const CAPS_LOCK_COLOR: Color = Color::from_u32(0x00FFA000);
+
+// the box border after a failed attempt, for about a dozen frames
+const ERROR_FLASH_COLOR: Color = Color::from_u32(0x00E03030);
+const ERROR_FLASH_DURATION: Duration = Duration::from_millis(400);
+
+// advanced every third tick while an attempt is being checked
+const SPINNER: [char; 4] = ['|', '/', '-', '\\'];
+
+const PIN_BUTTON_WIDTH: u16 = 5;
+const PIN_PAD_WIDTH: u16 = PIN_BUTTON_WIDTH * 3 + 2;
+const PIN_PAD_HEIGHT: u16 = 7;
+const PIN_KEYS: [[&str; 3]; 4] = [
+    ["1", "2", "3"],
+    ["4", "5", "6"],
+    ["7", "8", "9"],
+    ["C", "0", "OK"],
+];
+
+impl LoginLayout {
+    pub fn new(area: Rect, state: &AppState) -> Self {
+        let vert = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Min(0),
+                Constraint::Length(7),
+                Constraint::Min(0),
+            ])
+            .split(area);
+        let horiz = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([
+                Constraint::Min(0),
+                Constraint::Length(36),
+                Constraint::Min(0),
+            ])
+            .split(vert[1]);
+
+        let mut below = vert[2];
+        let mut pin_pad = None;
+        if state.pin_pad && below.height > PIN_PAD_HEIGHT && below.width >= PIN_PAD_WIDTH {
+            let x = below.x + (below.width - PIN_PAD_WIDTH) / 2;
+            pin_pad = Some(Rect::new(x, below.y + 1, PIN_PAD_WIDTH, PIN_PAD_HEIGHT));
+            below.y += PIN_PAD_HEIGHT + 1;
+            below.height -= PIN_PAD_HEIGHT + 1;
+        }
+
+        Self {
+            above: vert[0],
+            box_area: horiz[1],
+            pin_pad,
+            below,
+        }
+    }
+}
+
+// The rows of `above` the logo is drawn in: as many of its lines as fit, sitting one row above
+// the box when there's room for the gap.
+fn logo_area(above: Rect, logo: &str) -> Rect {
+    let lines = logo.lines().count().min(above.height as usize) as u16;
+    let gap = u16::from(above.height > lines);
+    Rect::new(above.x, above.bottom() - lines - gap, above.width, lines)
 }
 
-fn centered_block(title: &str) -> Block<'_> {
-    Block::default().title(title).borders(Borders::ALL)
+// The pin pad buttons, laid out 3 wide with a blank row between each row of keys.
+fn pin_buttons(pad: Rect) -> impl Iterator<Item = (Rect, &'static str)> {
+    PIN_KEYS.iter().enumerate().flat_map(move |(row, keys)| {
+        keys.iter().enumerate().map(move |(col, label)| {
+            let x = pad.x + col as u16 * (PIN_BUTTON_WIDTH + 1);
+            let y = pad.y + row as u16 * 2;
+            (Rect::new(x, y, PIN_BUTTON_WIDTH, 1), *label)
+        })
+    })
+}
+
+/// Maps a tap at cell `(column, row)` of a frame covering `area` to the pin pad key under it.
+pub fn pin_pad_hit(area: Rect, state: &AppState, column: u16, row: u16) -> Option<KeyInput> {
+    let pad = LoginLayout::new(area, state).pin_pad?;
+    let position = Position::new(column, row);
+    let (_, label) = pin_buttons(pad).find(|(rect, _)| rect.contains(position))?;
+    match label {
+        "C" => Some(KeyInput::Clear),
+        "OK" => Some(KeyInput::Enter),
+        digit => digit.chars().next().map(KeyInput::Char),
+    }
 }
 
-fn cursor_position(inner: ratatui::layout::Rect, state: &AppState) -> Option<(u16, u16)> {
+/// Maps a click at cell `(column, row)` of a frame covering `area` to the field on that line of
+/// the box.
+pub fn field_hit(area: Rect, state: &AppState, column: u16, row: u16) -> Option<FocusTarget> {
+    let layout = LoginLayout::new(area, state);
+    let inner = centered_block("", state.theme.border).inner(layout.box_area);
+    if !inner.contains(Position::new(column, row)) {
+        return None;
+    }
+    // the lines `draw` puts the fields on, below the info line
+    match row - inner.y {
+        1 => Some(state.username_target()),
+        3 => Some(FocusTarget::Password),
+        _ => None,
+    }
+}
+
+fn centered_block(title: &str, border: BoxBorder) -> Block<'_> {
+    let border_type = match border {
+        BoxBorder::None => return Block::default().title(title).borders(Borders::NONE),
+        BoxBorder::Plain => BorderType::Plain,
+        BoxBorder::Rounded => BorderType::Rounded,
+        BoxBorder::Double => BorderType::Double,
+        BoxBorder::Thick => BorderType::Thick,
+    };
+    Block::default()
+        .title(title)
+        .borders(Borders::ALL)
+        .border_type(border_type)
+}
+
+fn cursor_position(inner: Rect, state: &AppState) -> Option<(u16, u16)> {
+    if state.authenticating {
+        return None;
+    }
     let base_x = inner.x + 1;
     let user_label = "Username: ";
-    let pass_label = "Password: ";
+    let pass_label = state.password_label();
     let base_y = inner.y + 1;
 
     match state.focused {
+        // after whatever an input method is still composing
         FocusTarget::Username => Some((
-            base_x + user_label.len() as u16 + state.username.len() as u16,
+            base_x
+                + display_width(user_label)
+                + display_width(&state.username)
+                + display_width(state.preedit_in(FocusTarget::Username)),
             base_y,
         )),
+        // follows the rendered text, so a hidden field keeps the caret at its start
         FocusTarget::Password => Some((
-            base_x + pass_label.len() as u16 + state.password.len() as u16,
+            base_x
+                + display_width(&pass_label)
+                + display_width(&state.password_display())
+                + display_width(state.preedit_in(FocusTarget::Password)),
             base_y + 2,
         )),
+        // buttons and the user list show focus by highlighting instead of a caret
+        FocusTarget::Submit | FocusTarget::Power | FocusTarget::UserList => None,
+    }
+}
+
+// The columns `text` takes up once drawn: wide characters such as CJK take two, combining
+// marks none, and every grapheme cluster is measured as the single cell ratatui draws it in.
+fn display_width(text: &str) -> u16 {
+    let width: usize = text.graphemes(true).map(UnicodeWidthStr::width).sum();
+    width.min(u16::MAX as usize) as u16
+}
+
+// Slowly fades the border between white and a dim gray, one cycle every ~4s at 30fps.
+fn reminder_pulse(tick: u64) -> Color {
+    let phase = (tick % 128) as i32;
+    let level = (0x7F + (64 - (phase - 64).abs()) * 2) as u8;
+    Color::Rgb(level, level, level)
+}
+
+fn format_elapsed(elapsed: Duration) -> String {
+    let minutes = elapsed.as_secs() / 60;
+    match (minutes / 60, minutes % 60) {
+        (0, m) => format!("{m}m"),
+        (h, 0) => format!("{h}h"),
+        (h, m) => format!("{h}h {m}m"),
+    }
+}
+
+/// Asks the desktop whether animations are disabled. Only GNOME's `enable-animations` setting
+/// is consulted; anything unreadable counts as "no preference".
+pub fn desktop_prefers_reduced_motion() -> bool {
+    std::process::Command::new("gsettings")
+        .args(["get", "org.gnome.desktop.interface", "enable-animations"])
+        .stderr(std::process::Stdio::null())
+        .output()
+        .map(|output| output.status.success() && output.stdout.trim_ascii() == b"false")
+        .unwrap_or(false)
+}
+
+/// The translucent box background the rasterizer blends over the box's cells in a frame
+/// covering `area`, or `None` for an opaque box, which is in the cells already.
+pub fn box_tint(area: Rect, state: &AppState) -> Option<Tint> {
+    let theme = &state.theme;
+    if theme.box_alpha == u8::MAX {
+        return None;
+    }
+    let rgb = to_argb(theme.box_bg).unwrap_or(0) & 0x00FF_FFFF;
+    Some(Tint {
+        cells: LoginLayout::new(area, state).box_area,
+        argb: (theme.box_alpha as u32) << 24 | rgb,
+    })
+}
+
+// The color a backdrop cell shows: block elements such as the fire's `▒` fill the whole cell
+// in their foreground, anything else leaves its background around it.
+fn backdrop_color(cell: &Cell) -> Color {
+    let block = cell
+        .symbol()
+        .chars()
+        .next()
+        .is_some_and(|ch| ('\u{2580}'..='\u{259F}').contains(&ch));
+    if block { cell.fg } else { cell.bg }
+}
+
+// A terminal's cells have no alpha, so there the tint is mixed into their colors.
+fn tint_cells(buf: &mut Buffer, tint: Tint) {
+    let area = tint.cells.intersection(buf.area);
+    for y in area.top()..area.bottom() {
+        for x in area.left()..area.right() {
+            let cell = &mut buf[(x, y)];
+            let bg = to_argb(cell.bg).unwrap_or(0xFF00_0000);
+            cell.set_bg(Color::from_u32(tint.over(bg) & 0x00FF_FFFF));
+        }
     }
 }
 
-fn palette_index(color: Color) -> Option<usize> {
-    FIRE_PALETTE.iter().position(|entry| *entry == color)
+/// The built-in fire palettes, selectable by name. The variants recolor `FIRE_PALETTE` by
+/// swapping its channels, so they keep its brightness curve.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum PalettePreset {
+    #[default]
+    Fire,
+    Blue,
+    Green,
 }
 
-fn pseudo_rand(tick: u64, x: u16, y: u16) -> u16 {
-    let mut v = tick as u32 ^ ((x as u32) << 16) ^ (y as u32);
-    v ^= v >> 16;
-    v = v.wrapping_mul(0x7feb_352d);
-    v ^= v >> 15;
-    v = v.wrapping_mul(0x846c_a68b);
-    v ^= v >> 16;
-    (v & 0xFFFF) as u16
+impl PalettePreset {
+    pub fn colors(self) -> Vec<Color> {
+        FIRE_PALETTE
+            .iter()
+            .map(|&color| match (self, color) {
+                (Self::Blue, Color::Rgb(r, g, b)) => Color::Rgb(b, g, r),
+                (Self::Green, Color::Rgb(r, g, b)) => Color::Rgb(g, r, b),
+                _ => color,
+            })
+            .collect()
+    }
+}
+
+impl std::str::FromStr for PalettePreset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fire" => Ok(Self::Fire),
+            "blue" => Ok(Self::Blue),
+            "green" => Ok(Self::Green),
+            _ => Err(format!(
+                "unknown palette {s:?}, expected fire, blue or green"
+            )),
+        }
+    }
+}
+
+/// Stretches a few `0xRRGGBB` gradient stops, coldest first, over as many entries as
+/// `FIRE_PALETTE` has, so the fire and the generated backgrounds keep their shape with the
+/// new colors. Needs at least two stops.
+pub fn palette_from_stops(stops: &[u32]) -> Option<Vec<Color>> {
+    if stops.len() < 2 {
+        return None;
+    }
+    let last = FIRE_PALETTE.len() - 1;
+    let palette = (0..=last)
+        .map(|index| {
+            // position along the stops in 1/last steps
+            let position = index * (stops.len() - 1);
+            let (from, offset) = (position / last, position % last);
+            let to = (from + 1).min(stops.len() - 1);
+            let [_, r0, g0, b0] = stops[from].to_be_bytes();
+            let [_, r1, g1, b1] = stops[to].to_be_bytes();
+            let mix = |a: u8, b: u8| {
+                ((a as usize * (last - offset) + b as usize * offset + last / 2) / last) as u8
+            };
+            Color::Rgb(mix(r0, r1), mix(g0, g1), mix(b0, b1))
+        })
+        .collect();
+    Some(palette)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        auth::PromptStyle,
+        render::{PixelBuffer, Rasterizer},
+    };
+
+    #[test]
+    fn lock_reminder_shows_from_its_threshold() {
+        let locked_at = Instant::now();
+        let after = Duration::from_secs(2 * 60 * 60);
+        let mut state = AppState {
+            locked_at: Some(locked_at),
+            lock_reminder: LockReminder { after: Some(after) },
+            ..AppState::default()
+        };
+        let just_before = locked_at + after - Duration::from_secs(1);
+        assert_eq!(state.lock_reminder_due(just_before), None);
+        assert_eq!(state.lock_reminder_due(locked_at + after), Some(after));
+
+        // off, or before the lock was confirmed
+        state.lock_reminder.after = None;
+        assert_eq!(state.lock_reminder_due(locked_at + after), None);
+        state.lock_reminder.after = Some(after);
+        state.locked_at = None;
+        assert_eq!(state.lock_reminder_due(locked_at + after), None);
+    }
+
+    #[test]
+    fn the_ui_goes_idle_without_input_unless_something_is_moving() {
+        let locked_at = Instant::now();
+        let after = Duration::from_secs(60);
+        let mut state = AppState {
+            locked_at: Some(locked_at),
+            idle_after: Some(after),
+            ..AppState::default()
+        };
+        assert!(!state.is_idle(locked_at + after - Duration::from_secs(1)));
+        assert!(state.is_idle(locked_at + after));
+
+        // input starts the count over
+        let typed_at = locked_at + after;
+        state.record_activity(typed_at);
+        assert!(!state.is_idle(typed_at + Duration::from_secs(1)));
+        assert!(state.is_idle(typed_at + after));
+
+        // the spinner and the lockout countdown keep it awake
+        let later = typed_at + after * 2;
+        state.authenticating = true;
+        assert!(!state.is_idle(later));
+        state.authenticating = false;
+        state.lockout.threshold = 0;
+        state.lockout.base_delay = after;
+        state.lockout.record_failure(later);
+        assert!(!state.is_idle(later));
+        assert!(state.is_idle(later + after * 2));
+
+        state.idle_after = None;
+        assert!(!state.is_idle(later + after * 2));
+    }
+
+    #[test]
+    fn reduced_motion_renders_the_same_every_tick() {
+        let mut state = AppState {
+            reduced_motion: true,
+            ..AppState::default()
+        };
+        state.tick = 1;
+        let first = render_to_buffer(&mut state, 60, 20);
+        let background = render_background(&mut state, 60, 20);
+        for _ in 0..100 {
+            state.tick();
+        }
+        assert_eq!(render_to_buffer(&mut state, 60, 20), first);
+        assert_eq!(render_background(&mut state, 60, 20), background);
+    }
+
+    #[test]
+    fn translucent_box_blends_over_the_fire() {
+        let mut state = AppState {
+            reduced_motion: true,
+            theme: Theme {
+                box_bg: Color::Rgb(200, 100, 0),
+                box_alpha: 128,
+                ..Theme::default()
+            },
+            ..AppState::default()
+        };
+        let backdrop = render_background(&mut state, 60, 20);
+        let cells = render_to_buffer(&mut state, 60, 20);
+        let tint = box_tint(cells.area, &state).unwrap();
+        assert_eq!(tint.argb, 0x80C8_6400);
+
+        // a blank cell inside the box, just left of its right border
+        let (x, y) = (tint.cells.right() - 2, tint.cells.top() + 1);
+        assert_eq!(cells[(x, y)].symbol(), " ");
+        let pixel = |cells: &Buffer, tint: Option<Tint>| {
+            let mut bytes = vec![0; 60 * 20 * 4];
+            let mut target = PixelBuffer::new(&mut bytes, 60, 20, 60 * 4);
+            Rasterizer::new(1, 1).rasterize_tinted(cells, tint, &mut target);
+            target.pixel(x as u32, y as u32).unwrap()
+        };
+        let fire = pixel(&backdrop, None).to_be_bytes();
+        let blended = pixel(&cells, Some(tint)).to_be_bytes();
+
+        // opaque, and half the box color, half the fire behind it, give or take rounding
+        assert_eq!(blended[0], 0xFF);
+        for (channel, box_channel) in [(1, 200), (2, 100), (3, 0)] {
+            let half = (box_channel + fire[channel] as i32) / 2;
+            let diff = (blended[channel] as i32 - half).abs();
+            assert!(diff <= 1, "channel {channel}: {blended:?} over {fire:?}");
+        }
+        assert_ne!(blended, fire);
+    }
+
+    #[test]
+    fn an_expired_password_says_so_and_clears_the_field() {
+        let mut state = AppState {
+            password: Secret::from("hunter2"),
+            ..AppState::default()
+        };
+        state.apply_auth_error(&AuthError::PasswordExpired);
+        assert!(state.password.is_empty());
+        assert_eq!(state.focused, FocusTarget::Password);
+
+        let screen = render_to_buffer(&mut state, 60, 20);
+        let text: String = screen.content().iter().map(|cell| cell.symbol()).collect();
+        assert!(text.contains("Error: Expired; change elsewhere."));
+    }
+
+    // tall enough for the pin pad under the box
+    const PAD_AREA: Rect = Rect {
+        x: 0,
+        y: 0,
+        width: 40,
+        height: 24,
+    };
+
+    fn with_pin_pad() -> AppState {
+        AppState {
+            pin_pad: true,
+            username: "alice".to_string(),
+            ..AppState::default()
+        }
+    }
+
+    // The middle of the button labeled `label`.
+    fn pin_key(state: &AppState, label: &str) -> (u16, u16) {
+        let pad = LoginLayout::new(PAD_AREA, state).pin_pad.unwrap();
+        let (rect, _) = pin_buttons(pad).find(|(_, key)| *key == label).unwrap();
+        (rect.x + rect.width / 2, rect.y)
+    }
+
+    #[test]
+    fn clicks_hit_the_field_on_their_line() {
+        let mut state = AppState::default();
+        let area = Rect::new(0, 0, 60, 20);
+        let inner =
+            centered_block("", state.theme.border).inner(LoginLayout::new(area, &state).box_area);
+        let hit = |state: &AppState, row| field_hit(area, state, inner.x + 2, inner.y + row);
+        assert_eq!(hit(&state, 1), Some(FocusTarget::Username));
+        assert_eq!(hit(&state, 3), Some(FocusTarget::Password));
+        assert_eq!(hit(&state, 0), None);
+        assert_eq!(field_hit(area, &state, 0, 0), None);
+
+        state.focus(FocusTarget::Password);
+        assert_eq!(state.focused, FocusTarget::Password);
+        state.authenticating = true;
+        state.focus(FocusTarget::Username);
+        assert_eq!(state.focused, FocusTarget::Password);
+    }
+
+    #[test]
+    fn pin_pad_keys_map_to_input() {
+        let state = with_pin_pad();
+        let hit = |label: &str| {
+            let (column, row) = pin_key(&state, label);
+            pin_pad_hit(PAD_AREA, &state, column, row)
+        };
+        for digit in '0'..='9' {
+            assert!(
+                matches!(hit(digit.to_string().as_str()), Some(KeyInput::Char(ch)) if ch == digit)
+            );
+        }
+        assert!(matches!(hit("C"), Some(KeyInput::Clear)));
+        assert!(matches!(hit("OK"), Some(KeyInput::Enter)));
+    }
+
+    #[test]
+    fn pin_pad_misses_between_keys() {
+        let state = with_pin_pad();
+        let layout = LoginLayout::new(PAD_AREA, &state);
+        let pad = layout.pin_pad.unwrap();
+        // the column between "1" and "2", and the row between "1" and "4"
+        assert!(pin_pad_hit(PAD_AREA, &state, pad.x + PIN_BUTTON_WIDTH, pad.y).is_none());
+        assert!(pin_pad_hit(PAD_AREA, &state, pad.x, pad.y + 1).is_none());
+        // the box above it
+        let box_area = layout.box_area;
+        assert!(pin_pad_hit(PAD_AREA, &state, box_area.x + 1, box_area.y + 1).is_none());
+    }
+
+    #[test]
+    fn pin_pad_only_hits_when_shown() {
+        let mut state = with_pin_pad();
+        let (column, row) = pin_key(&state, "5");
+        state.pin_pad = false;
+        assert!(pin_pad_hit(PAD_AREA, &state, column, row).is_none());
+
+        // no room under the box
+        state.pin_pad = true;
+        let cramped = Rect::new(0, 0, 40, 12);
+        assert!(LoginLayout::new(cramped, &state).pin_pad.is_none());
+    }
+
+    #[test]
+    fn pin_pad_taps_type_and_submit() {
+        let mut state = with_pin_pad();
+        state.focused = FocusTarget::Password;
+        let mut action = None;
+        for label in ["4", "2", "C", "1", "2", "OK"] {
+            let (column, row) = pin_key(&state, label);
+            let key = pin_pad_hit(PAD_AREA, &state, column, row).unwrap();
+            action = state.handle_input(key);
+        }
+        match action {
+            Some(AppAction::Submit { username, password }) => {
+                assert_eq!(username, "alice");
+                assert_eq!(password.as_str(), "12");
+            }
+            other => panic!("expected a submit, got {other:?}"),
+        }
+        // the password moved into the action
+        assert!(state.password.is_empty());
+    }
+
+    const BACKGROUNDS: [BackgroundKind; 4] = [
+        BackgroundKind::Fire,
+        BackgroundKind::Matrix,
+        BackgroundKind::Plasma,
+        BackgroundKind::Starfield,
+    ];
+
+    #[test]
+    fn every_background_is_stable_at_a_fixed_tick() {
+        let mut frames = Vec::new();
+        for background in BACKGROUNDS {
+            let mut state = AppState {
+                background: background.create(Fire::default()),
+                tick: 42,
+                ..AppState::default()
+            };
+            let frame = render_to_buffer(&mut state, 60, 20);
+            assert_eq!(
+                render_to_buffer(&mut state, 60, 20),
+                frame,
+                "{background:?}"
+            );
+            frames.push(frame);
+        }
+        // and each one draws something of its own
+        for (i, frame) in frames.iter().enumerate() {
+            assert!(!frames[i + 1..].contains(frame), "{:?}", BACKGROUNDS[i]);
+        }
+    }
+
+    #[test]
+    fn palette_presets_recolor_the_fire() {
+        let blue: PalettePreset = "blue".parse().unwrap();
+        let colors = blue.colors();
+        assert_eq!(colors.len(), FIRE_PALETTE.len());
+        let hottest_fire = FIRE_PALETTE.iter().rev().find(|&&c| c != Color::Black);
+        let Some(&Color::Rgb(r, g, b)) = hottest_fire else {
+            panic!("the fire palette is RGB");
+        };
+        let hottest_blue = colors.iter().rev().find(|&&c| c != Color::Black);
+        assert_eq!(hottest_blue, Some(&Color::Rgb(b, g, r)));
+        assert_eq!(PalettePreset::Fire.colors(), FIRE_PALETTE);
+        assert!("purple".parse::<PalettePreset>().is_err());
+    }
+
+    #[test]
+    fn backgrounds_are_selected_by_name() {
+        let names = ["fire", "matrix", "plasma", "starfield"];
+        for (name, background) in names.into_iter().zip(BACKGROUNDS) {
+            assert_eq!(name.parse(), Ok(background));
+        }
+        assert!("lava".parse::<BackgroundKind>().is_err());
+    }
+
+    #[test]
+    fn render_to_buffer_ignores_the_cursor() {
+        for focused in [FocusTarget::Username, FocusTarget::Password] {
+            let mut state = AppState {
+                username: "alice".into(),
+                password: "hunter2".into(),
+                focused,
+                ..AppState::default()
+            };
+            let before = render_to_buffer(&mut state, 60, 20);
+
+            // a terminal that places its cursor in the focused field draws the same cells
+            let mut terminal = Terminal::new(TestBackend::new(60, 20)).unwrap();
+            terminal.draw(|frame| view(frame, &mut state)).unwrap();
+            let (x, y) = caret_position(before.area, &state).unwrap();
+            assert_eq!(terminal.get_cursor_position().unwrap(), Position { x, y });
+            assert_eq!(terminal.backend().buffer(), &before);
+
+            assert_eq!(render_to_buffer(&mut state, 60, 20), before);
+        }
+    }
+
+    #[test]
+    fn the_caret_moves_by_display_width() {
+        let caret_x = |username: &str| {
+            let state = AppState {
+                username: username.to_string(),
+                ..AppState::default()
+            };
+            caret_position(Rect::new(0, 0, 60, 20), &state).unwrap().0
+        };
+        let start = caret_x("");
+        assert_eq!(caret_x("alice") - start, 5);
+        // two columns per CJK character, none for a combining accent
+        assert_eq!(caret_x("李明") - start, 4);
+        assert_eq!(caret_x("re\u{301}ne\u{301}") - start, 4);
+        assert_eq!(display_width("👩‍👩‍👧"), 2);
+    }
+
+    // The box's corners and the middle of its bottom edge, in that order.
+    fn box_edges(border: BoxBorder) -> String {
+        let mut state = AppState::default();
+        state.theme.border = border;
+        let buffer = render_to_buffer(&mut state, 60, 20);
+        let area = LoginLayout::new(buffer.area, &state).box_area;
+        let (left, right) = (area.left(), area.right() - 1);
+        let (top, bottom) = (area.top(), area.bottom() - 1);
+        [(left, top), (right, top), (left, bottom), (right, bottom)]
+            .into_iter()
+            .chain([(left + area.width / 2, bottom)])
+            .map(|(x, y)| buffer[(x, y)].symbol())
+            .collect()
+    }
+
+    #[test]
+    fn border_styles_change_the_box_corners() {
+        assert_eq!(box_edges(BoxBorder::Plain), "┌┐└┘─");
+        assert_eq!(box_edges(BoxBorder::Rounded), "╭╮╰╯─");
+        assert_eq!(box_edges(BoxBorder::Double), "╔╗╚╝═");
+        assert_eq!(box_edges(BoxBorder::Thick), "┏┓┗┛━");
+        // with no border the title starts right in the corner
+        assert_eq!(box_edges(BoxBorder::None), "L    ");
+    }
+
+    #[test]
+    fn border_styles_are_selected_by_name() {
+        assert_eq!("rounded".parse(), Ok(BoxBorder::Rounded));
+        assert_eq!("none".parse(), Ok(BoxBorder::None));
+        assert!("dotted".parse::<BoxBorder>().is_err());
+    }
+
+    // The password field's row as rendered, and the caret's column from the start of its label.
+    fn password_row(mode: PasswordDisplay, password: &str) -> (String, usize) {
+        let mut state = AppState {
+            username: "alice".to_string(),
+            password: Secret::from(password),
+            focused: FocusTarget::Password,
+            password_mode: mode,
+            ..AppState::default()
+        };
+        let buffer = render_to_buffer(&mut state, 60, 20);
+        let (caret_x, y) = caret_position(buffer.area, &state).unwrap();
+        let inner = centered_block("", state.theme.border)
+            .inner(LoginLayout::new(buffer.area, &state).box_area);
+        let row: String = (inner.left()..inner.right())
+            .map(|x| buffer[(x, y)].symbol())
+            .collect();
+        let start = row.find("Password").unwrap();
+        let caret = (caret_x - inner.x) as usize - start;
+        (row.trim().to_string(), caret)
+    }
+
+    #[test]
+    fn masked_passwords_show_one_star_per_character() {
+        let (row, caret) = password_row(PasswordDisplay::Masked, "hunter2");
+        assert_eq!(row, "Password: *******");
+        assert_eq!(caret, row.len());
+    }
+
+    #[test]
+    fn fixed_dots_hide_the_length() {
+        for password in ["hi", "correct horse battery"] {
+            let (row, caret) = password_row(PasswordDisplay::FixedDots, password);
+            assert_eq!(row, "Password: ********");
+            assert_eq!(caret, row.len());
+        }
+        assert_eq!(password_row(PasswordDisplay::FixedDots, "").0, "Password:");
+    }
+
+    #[test]
+    fn hidden_passwords_show_nothing_and_still_edit() {
+        let (row, caret) = password_row(PasswordDisplay::Hidden, "hunter2");
+        assert_eq!(row, "Password:");
+        assert_eq!(caret, "Password: ".len());
+
+        let mut state = AppState {
+            password: Secret::from("hunter2"),
+            focused: FocusTarget::Password,
+            password_mode: PasswordDisplay::Hidden,
+            ..AppState::default()
+        };
+        state.handle_input(KeyInput::Backspace);
+        assert_eq!(state.password.as_str(), "hunter");
+    }
+
+    #[test]
+    fn ctrl_r_reveals_the_password_while_its_field_is_focused() {
+        let mut state = AppState {
+            focused: FocusTarget::Password,
+            reduced_motion: true,
+            ..typed("alice", "hunter2")
+        };
+        let password_row = |state: &mut AppState| {
+            let buffer = render_to_buffer(state, 60, 20);
+            let box_area = LoginLayout::new(buffer.area, state).box_area;
+            let rows = rows(&buffer, box_area.y, box_area.bottom());
+            let row = rows.into_iter().find(|row| row.contains("Password:"));
+            row.unwrap().trim_matches('│').trim().to_string()
+        };
+        assert_eq!(password_row(&mut state), "Password: *******");
+        state.handle_input(KeyInput::ToggleReveal);
+        assert_eq!(password_row(&mut state), "Password: hunter2");
+
+        // masked again while another field is focused, revealed again on return
+        state.focused = FocusTarget::Username;
+        assert_eq!(password_row(&mut state), "Password: *******");
+        state.focused = FocusTarget::Password;
+        assert_eq!(password_row(&mut state), "Password: hunter2");
+
+        // submitting puts the mask back for the next attempt
+        assert!(state.handle_input(KeyInput::Enter).is_some());
+        state.password = Secret::from("hunter3");
+        assert_eq!(password_row(&mut state), "Password: *******");
+    }
+
+    #[test]
+    fn pasting_types_into_the_focused_field_without_control_characters() {
+        let mut state = AppState {
+            focused: FocusTarget::Password,
+            ..typed("alice", "hun")
+        };
+        state.paste("ter\t2\n");
+        assert_eq!(state.password.as_str(), "hunter2");
+        assert_eq!(state.username, "alice");
+
+        // nothing lands while an attempt is being checked
+        state.authenticating = true;
+        state.paste("x");
+        assert_eq!(state.password.as_str(), "hunter2");
+    }
+
+    #[test]
+    fn password_display_modes_are_selected_by_name() {
+        assert_eq!("fixed-dots".parse(), Ok(PasswordDisplay::FixedDots));
+        assert_eq!("hidden".parse(), Ok(PasswordDisplay::Hidden));
+        assert!("stars".parse::<PasswordDisplay>().is_err());
+    }
+
+    fn typed(username: &str, password: &str) -> AppState {
+        AppState {
+            username: username.to_string(),
+            password: Secret::from(password),
+            ..AppState::default()
+        }
+    }
+
+    #[test]
+    fn masking_follows_each_prompt_of_a_conversation() {
+        let mut state = typed("alice", "");
+        let answer = |state: &mut AppState, style, text: &str, typed: &str| {
+            state.apply_prompt(Prompt::new(style, text));
+            for ch in typed.chars() {
+                state.handle_input(KeyInput::Char(ch));
+            }
+            (state.password_label(), state.password_display().to_string())
+        };
+
+        let password = answer(&mut state, PromptStyle::EchoOff, "Password: ", "hunter2");
+        assert_eq!(password, ("Password: ".to_string(), "*******".to_string()));
+        let code = answer(&mut state, PromptStyle::EchoOn, "OTP: ", "123456");
+        assert_eq!(code, ("Code: ".to_string(), "123456".to_string()));
+        let pin = answer(&mut state, PromptStyle::EchoOff, "PIN: ", "0000");
+        assert_eq!(pin, ("PIN: ".to_string(), "****".to_string()));
+    }
+
+    fn focus_after(state: &mut AppState, key: KeyInput) -> FocusTarget {
+        state.handle_input(key);
+        state.focused
+    }
+
+    #[test]
+    fn tab_cycles_through_the_focus_order() {
+        for enabled in 0..8 {
+            let mut state = AppState {
+                submit_button: enabled & 1 != 0,
+                power_button: enabled & 2 != 0,
+                user_list: if enabled & 4 != 0 {
+                    vec!["alice".to_string()]
+                } else {
+                    Vec::new()
+                },
+                ..AppState::default()
+            };
+            let expected = state.focus_order();
+            state.focused = expected[0];
+            // twice around, wrapping back to the start
+            for step in 1..=expected.len() * 2 {
+                let target = focus_after(&mut state, KeyInput::Tab);
+                assert_eq!(target, expected[step % expected.len()]);
+            }
+            for step in (0..expected.len()).rev() {
+                let target = focus_after(&mut state, KeyInput::BackTab);
+                assert_eq!(target, expected[step]);
+            }
+        }
+    }
+
+    #[test]
+    fn the_focus_order_follows_the_enabled_elements() {
+        use FocusTarget::*;
+        let mut state = AppState::default();
+        assert_eq!(state.focus_order(), [Username, Password]);
+        state.submit_button = true;
+        assert_eq!(state.focus_order(), [Username, Password, Submit]);
+        state.power_button = true;
+        assert_eq!(state.focus_order(), [Username, Password, Submit, Power]);
+        state.user_list = vec!["alice".to_string()];
+        assert_eq!(state.focus_order(), [UserList, Password, Submit, Power]);
+    }
+
+    #[test]
+    fn focus_outside_the_order_moves_to_its_start() {
+        let mut state = AppState {
+            focused: FocusTarget::Submit,
+            ..AppState::default()
+        };
+        assert_eq!(
+            focus_after(&mut state, KeyInput::Tab),
+            FocusTarget::Username
+        );
+    }
+
+    #[test]
+    fn enter_on_submit_submits() {
+        let mut state = typed("alice", "hunter2");
+        state.submit_button = true;
+        state.focused = FocusTarget::Submit;
+        match state.handle_input(KeyInput::Enter) {
+            Some(AppAction::Submit { username, password }) => {
+                assert_eq!(username, "alice");
+                assert_eq!(password.as_str(), "hunter2");
+            }
+            other => panic!("expected a submit, got {other:?}"),
+        }
+
+        // with nobody to submit for, it goes back to the username instead
+        let mut state = typed("", "hunter2");
+        state.focused = FocusTarget::Submit;
+        assert!(state.handle_input(KeyInput::Enter).is_none());
+        assert_eq!(state.focused, FocusTarget::Username);
+    }
+
+    #[test]
+    fn enter_on_power_powers_off() {
+        let mut state = typed("alice", "hunter2");
+        state.power_button = true;
+        state.focused = FocusTarget::Power;
+        // even within the grace period, which only Enter on the fields dismisses
+        state.locked_at = Some(Instant::now());
+        state.grace_period = Duration::from_secs(10);
+        assert!(matches!(
+            state.handle_input(KeyInput::Enter),
+            Some(AppAction::PowerOff)
+        ));
+    }
+
+    #[test]
+    fn left_and_right_pick_from_the_user_list() {
+        let mut state = AppState {
+            user_list: vec!["alice".to_string(), "bob".to_string(), "carol".to_string()],
+            username: "alice".to_string(),
+            focused: FocusTarget::UserList,
+            ..AppState::default()
+        };
+        let pick = |state: &mut AppState, key| {
+            assert!(state.handle_input(key).is_none());
+            state.username.clone()
+        };
+        assert_eq!(pick(&mut state, KeyInput::Right), "bob");
+        assert_eq!(pick(&mut state, KeyInput::Right), "carol");
+        assert_eq!(pick(&mut state, KeyInput::Right), "alice");
+        assert_eq!(pick(&mut state, KeyInput::Left), "carol");
+
+        // typing doesn't change the pick, and neither do the arrows elsewhere
+        assert_eq!(pick(&mut state, KeyInput::Char('x')), "carol");
+        state.focused = FocusTarget::Password;
+        assert_eq!(pick(&mut state, KeyInput::Right), "carol");
+        assert_eq!(state.password.as_str(), "");
+    }
+
+    #[test]
+    fn the_user_list_and_power_button_are_drawn() {
+        let mut state = AppState {
+            power_button: true,
+            user_list: vec!["alice".to_string(), "bob".to_string()],
+            username: "alice".to_string(),
+            focused: FocusTarget::UserList,
+            ..AppState::default()
+        };
+        let text = rows(&render_to_buffer(&mut state, 36, 7), 0, 7).join("\n");
+        assert!(text.contains("User: < alice >"), "{text}");
+        assert!(text.contains("[ Power ]"), "{text}");
+    }
+
+    #[test]
+    fn enter_dismisses_only_within_the_grace_period() {
+        let locked_at = Instant::now();
+        let mut state = AppState {
+            locked_at: Some(locked_at),
+            grace_period: Duration::from_secs(10),
+            ..typed("alice", "hunter2")
+        };
+        assert!(state.in_grace_period(locked_at + Duration::from_secs(9)));
+        assert!(!state.in_grace_period(locked_at + Duration::from_secs(10)));
+        assert!(matches!(
+            state.handle_input(KeyInput::Enter),
+            Some(AppAction::Dismiss)
+        ));
+
+        // off by default, and never before the lock is confirmed
+        state.grace_period = Duration::ZERO;
+        assert!(!state.in_grace_period(locked_at));
+        state.grace_period = Duration::from_secs(10);
+        state.locked_at = None;
+        assert!(matches!(
+            state.handle_input(KeyInput::Enter),
+            Some(AppAction::Submit { .. })
+        ));
+    }
+
+    // The rows of `buffer` from `top` up to (not including) `bottom`, without the static
+    // background around them.
+    fn rows(buffer: &Buffer, top: u16, bottom: u16) -> Vec<String> {
+        (top..bottom)
+            .map(|y| {
+                let row: String = (0..buffer.area.width)
+                    .map(|x| buffer[(x, y)].symbol())
+                    .collect();
+                row.trim_matches([' ', '▒']).to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn the_battery_shows_in_the_top_right_corner() {
+        let mut state = AppState {
+            reduced_motion: true,
+            ..AppState::default()
+        };
+        let top_row = |state: &mut AppState| rows(&render_to_buffer(state, 60, 20), 0, 1).remove(0);
+        assert_eq!(top_row(&mut state), "");
+
+        state.battery = Some(Battery {
+            percent: 12,
+            charging: false,
+        });
+        let buffer = render_to_buffer(&mut state, 60, 20);
+        assert_eq!(rows(&buffer, 0, 1), ["Battery 12%"]);
+        let corner = &buffer[(59, 0)];
+        assert_eq!(corner.symbol(), "%");
+        assert_eq!(corner.bg, state.theme.box_bg);
+        assert!(corner.modifier.contains(Modifier::REVERSED));
+    }
+
+    #[test]
+    fn caps_lock_warns_in_the_box_only_while_on() {
+        let mut state = AppState {
+            reduced_motion: true,
+            ..AppState::default()
+        };
+        let warned = |state: &mut AppState| {
+            let buffer = render_to_buffer(state, 60, 20);
+            let box_area = LoginLayout::new(buffer.area, state).box_area;
+            rows(&buffer, box_area.y, box_area.bottom())
+                .iter()
+                .any(|row| row.contains("⚠ Caps Lock is on"))
+        };
+        assert!(!warned(&mut state));
+        state.caps_lock = true;
+        assert!(warned(&mut state));
+    }
+
+    #[test]
+    fn the_logo_sits_above_the_box() {
+        let mut state = AppState {
+            logo: Some(" /\\\n/  \\\n----".to_string()),
+            reduced_motion: true,
+            ..AppState::default()
+        };
+        let buffer = render_to_buffer(&mut state, 60, 20);
+        let box_area = LoginLayout::new(buffer.area, &state).box_area;
+        // one blank row between the art and the box
+        let art = rows(&buffer, box_area.y - 4, box_area.y);
+        assert_eq!(art, ["/\\", "/  \\", "----", ""]);
+
+        // nothing of it inside the box
+        let inside = rows(&buffer, box_area.y, box_area.bottom());
+        assert!(inside.iter().all(|row| !row.contains("----")));
+    }
+
+    #[test]
+    fn a_logo_taller_than_the_space_keeps_its_bottom_lines() {
+        let logo: Vec<String> = (0..30).map(|line| format!("line {line}")).collect();
+        let mut state = AppState {
+            logo: Some(logo.join("\n")),
+            reduced_motion: true,
+            ..AppState::default()
+        };
+        let buffer = render_to_buffer(&mut state, 60, 20);
+        let box_area = LoginLayout::new(buffer.area, &state).box_area;
+        // every row above the box is art, with no room left for the gap
+        let art = rows(&buffer, 0, box_area.y);
+        let first = 30 - art.len();
+        assert_eq!(art[0], format!("line {first}"));
+        assert_eq!(art.last().unwrap(), "line 29");
+    }
+
+    fn lockout() -> Lockout {
+        Lockout {
+            threshold: 2,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(5),
+            ..Lockout::default()
+        }
+    }
+
+    #[test]
+    fn lockout_starts_at_the_threshold() {
+        let now = Instant::now();
+        let mut lockout = lockout();
+        lockout.record_failure(now);
+        assert_eq!(lockout.remaining(now), None);
+        lockout.record_failure(now);
+        assert_eq!(lockout.remaining(now), Some(Duration::from_secs(1)));
+        assert_eq!(lockout.failures(), 2);
+    }
+
+    #[test]
+    fn lockout_doubles_up_to_the_cap() {
+        let now = Instant::now();
+        let mut lockout = lockout();
+        let mut delays = Vec::new();
+        for _ in 0..6 {
+            lockout.record_failure(now);
+            delays.push(lockout.remaining(now).map_or(0, |delay| delay.as_secs()));
+        }
+        assert_eq!(delays, [0, 1, 2, 4, 5, 5]);
+
+        // however long the streak, the shift never overflows
+        for _ in 0..100 {
+            lockout.record_failure(now);
+        }
+        assert_eq!(lockout.remaining(now), Some(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn lockout_runs_out_and_resets() {
+        let now = Instant::now();
+        let mut lockout = lockout();
+        lockout.record_failure(now);
+        lockout.record_failure(now);
+        assert_eq!(lockout.remaining(now + Duration::from_secs(1)), None);
+
+        lockout.reset();
+        assert_eq!(lockout.failures(), 0);
+        assert_eq!(lockout.remaining(now), None);
+        lockout.record_failure(now);
+        assert_eq!(lockout.remaining(now), None);
+    }
+
+    #[test]
+    fn submitting_waits_out_the_lockout() {
+        let mut state = typed("alice", "hunter2");
+        state.focused = FocusTarget::Password;
+        state.lockout = Lockout {
+            threshold: 0,
+            base_delay: Duration::from_secs(60),
+            ..Lockout::default()
+        };
+        state.lockout.record_failure(Instant::now());
+
+        assert!(state.handle_input(KeyInput::Enter).is_none());
+        // kept for when the lockout ends
+        assert_eq!(state.password.as_str(), "hunter2");
+    }
+
+    #[test]
+    fn only_rejected_passwords_count_towards_the_lockout() {
+        let mut state = typed("alice", "hunter2");
+        state.apply_auth_error(&AuthError::Unavailable("no PAM".to_string()));
+        assert_eq!(state.lockout.failures(), 0);
+        state.apply_auth_error(&AuthError::Failed);
+        assert_eq!(state.lockout.failures(), 1);
+        assert!(state.password.is_empty());
+        assert_eq!(state.focused, FocusTarget::Password);
+    }
+
+    #[test]
+    fn a_preedit_is_drawn_underlined_after_the_focused_field() {
+        let mut state = typed("ab", "hunter2");
+        state.preedit = Some("日本".to_string());
+        let buffer = render_to_buffer(&mut state, 36, 7);
+        // " Username: " starts at the inner edge, and each ideograph takes two cells
+        let cell = &buffer[(14, 2)];
+        assert_eq!(cell.symbol(), "日");
+        assert!(cell.modifier.contains(Modifier::UNDERLINED));
+        assert_eq!(buffer[(16, 2)].symbol(), "本");
+        assert!(!buffer[(12, 2)].modifier.contains(Modifier::UNDERLINED));
+        assert_eq!(caret_position(buffer.area, &state), Some((18, 2)));
+
+        // a masked password keeps what's being composed to itself
+        state.focused = FocusTarget::Password;
+        assert_eq!(state.preedit_in(FocusTarget::Password), "");
+        assert_eq!(state.preedit_in(FocusTarget::Username), "");
+        state.reveal_password = true;
+        assert_eq!(state.preedit_in(FocusTarget::Password), "日本");
+    }
+
+    #[test]
+    fn a_failure_flashes_the_border_and_rings_the_bell_once() {
+        let mut state = typed("alice", "hunter2");
+        state.flash_on_failure = true;
+        state.bell_on_failure = true;
+        state.apply_auth_error(&AuthError::Failed);
+        let now = Instant::now();
+        assert!(state.is_flashing(now));
+        assert!(!state.is_idle(now));
+        let border = render_to_buffer(&mut state, 36, 7)[(0, 0)].fg;
+        assert_eq!(border, ERROR_FLASH_COLOR);
+        assert!(state.take_bell());
+        assert!(!state.take_bell());
+
+        // the tick loop lets it decay on its own
+        state.error_flash_until = Some(now);
+        state.tick();
+        assert_eq!(state.error_flash_until, None);
+        let border = render_to_buffer(&mut state, 36, 7)[(0, 0)].fg;
+        assert_ne!(border, ERROR_FLASH_COLOR);
+
+        // both can be turned off for quiet environments
+        state.flash_on_failure = false;
+        state.bell_on_failure = false;
+        state.apply_auth_error(&AuthError::Failed);
+        assert_eq!(state.error_flash_until, None);
+        assert!(!state.take_bell());
+    }
+
+    // The login box rendered at exactly its own size, so no background shows around it, row by
+    // row with the caret drawn as `_`. The tick is fixed, which keeps the frame the same every
+    // run.
+    fn login_box(state: &mut AppState) -> String {
+        state.tick = 42;
+        let buffer = render_to_buffer(state, 36, 7);
+        let caret = caret_position(buffer.area, state);
+        let mut text = String::new();
+        for y in 0..buffer.area.height {
+            for x in 0..buffer.area.width {
+                if caret == Some((x, y)) {
+                    text.push('_');
+                } else {
+                    text.push_str(buffer[(x, y)].symbol());
+                }
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    #[test]
+    fn snapshot_empty() {
+        insta::assert_snapshot!("empty", login_box(&mut AppState::default()));
+    }
+
+    #[test]
+    fn snapshot_username_typed() {
+        insta::assert_snapshot!("username_typed", login_box(&mut typed("alice", "")));
+    }
+
+    #[test]
+    fn snapshot_password_typed() {
+        let mut state = typed("alice", "hunter2");
+        state.focused = FocusTarget::Password;
+        insta::assert_snapshot!("password_typed", login_box(&mut state));
+    }
+
+    #[test]
+    fn snapshot_error_message() {
+        let mut state = typed("alice", "");
+        state.error_message = Some(AuthError::Failed.message().to_string());
+        state.focused = FocusTarget::Password;
+        insta::assert_snapshot!("error_message", login_box(&mut state));
+    }
+
+    #[test]
+    fn snapshot_submit_focused() {
+        let mut state = typed("alice", "hunter2");
+        state.submit_button = true;
+        state.focused = FocusTarget::Submit;
+        insta::assert_snapshot!("submit_focused", login_box(&mut state));
+    }
 }